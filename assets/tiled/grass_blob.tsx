@@ -0,0 +1,56 @@
+<?xml version="1.0" encoding="UTF-8"?>
+<tileset version="1.9" tiledversion="1.9.2" name="grass_blob" tilewidth="16" tileheight="16" tilecount="80" columns="10">
+ <image source="../sprites/Sprout Lands - Sprites - Basic pack/Tilesets/ground tiles/old tiles/Grass.png" width="160" height="128"/>
+ <wangsets>
+  <wangset name="Grass" type="mixed" tile="-1">
+   <wangcolor name="Grass" color="#3f9a2c" tile="-1" probability="1"/>
+   <wangcolor name="Empty" color="#000000" tile="-1" probability="1"/>
+   <wangtile tileid="2" wangid="1,1,1,1,1,1,1,1"/>
+   <wangtile tileid="20" wangid="2,2,2,2,1,2,2,2"/>
+   <wangtile tileid="26" wangid="1,2,1,1,1,2,1,2"/>
+   <wangtile tileid="27" wangid="1,2,1,2,1,1,1,2"/>
+   <wangtile tileid="28" wangid="1,2,2,2,1,2,1,2"/>
+   <wangtile tileid="29" wangid="1,2,1,2,2,2,1,2"/>
+   <wangtile tileid="30" wangid="1,2,2,2,1,2,2,2"/>
+   <wangtile tileid="31" wangid="2,2,1,1,1,2,2,2"/>
+   <wangtile tileid="32" wangid="2,2,1,1,1,1,1,2"/>
+   <wangtile tileid="33" wangid="2,2,2,2,1,1,1,2"/>
+   <wangtile tileid="36" wangid="1,1,1,2,1,2,1,2"/>
+   <wangtile tileid="37" wangid="1,2,1,2,1,2,1,1"/>
+   <wangtile tileid="38" wangid="2,2,1,2,1,2,1,2"/>
+   <wangtile tileid="39" wangid="1,2,1,2,1,2,2,2"/>
+   <wangtile tileid="41" wangid="1,1,1,1,1,2,2,2"/>
+   <wangtile tileid="43" wangid="1,2,2,2,1,1,1,1"/>
+   <wangtile tileid="44" wangid="1,1,1,2,1,1,1,1"/>
+   <wangtile tileid="45" wangid="1,1,1,1,1,2,1,1"/>
+   <wangtile tileid="46" wangid="1,2,1,1,1,2,2,2"/>
+   <wangtile tileid="47" wangid="2,2,1,2,1,1,1,2"/>
+   <wangtile tileid="48" wangid="2,2,1,1,1,2,1,2"/>
+   <wangtile tileid="49" wangid="1,2,2,2,1,1,1,2"/>
+   <wangtile tileid="50" wangid="1,2,2,2,2,2,2,2"/>
+   <wangtile tileid="51" wangid="1,1,1,2,2,2,2,2"/>
+   <wangtile tileid="52" wangid="1,1,1,2,2,2,1,1"/>
+   <wangtile tileid="53" wangid="1,2,2,2,2,2,1,1"/>
+   <wangtile tileid="54" wangid="1,2,1,1,1,1,1,1"/>
+   <wangtile tileid="55" wangid="1,1,1,1,1,1,1,2"/>
+   <wangtile tileid="56" wangid="1,1,1,2,2,2,1,2"/>
+   <wangtile tileid="57" wangid="1,2,2,2,1,2,1,1"/>
+   <wangtile tileid="58" wangid="1,1,1,2,1,2,2,2"/>
+   <wangtile tileid="59" wangid="1,2,1,2,2,2,1,1"/>
+   <wangtile tileid="60" wangid="2,2,1,2,2,2,2,2"/>
+   <wangtile tileid="61" wangid="2,2,1,2,2,2,1,2"/>
+   <wangtile tileid="63" wangid="2,2,2,2,2,2,1,2"/>
+   <wangtile tileid="64" wangid="2,2,1,2,1,2,2,2"/>
+   <wangtile tileid="65" wangid="2,2,2,2,1,2,1,2"/>
+   <wangtile tileid="66" wangid="1,1,1,1,1,2,1,2"/>
+   <wangtile tileid="67" wangid="1,2,1,1,1,1,1,2"/>
+   <wangtile tileid="71" wangid="1,2,1,2,1,2,1,2"/>
+   <wangtile tileid="72" wangid="1,2,1,1,1,2,1,1"/>
+   <wangtile tileid="73" wangid="1,1,1,2,1,1,1,2"/>
+   <wangtile tileid="74" wangid="1,2,1,2,2,2,2,2"/>
+   <wangtile tileid="75" wangid="1,2,2,2,2,2,1,2"/>
+   <wangtile tileid="76" wangid="1,1,1,2,1,2,1,1"/>
+   <wangtile tileid="77" wangid="1,2,1,2,1,1,1,1"/>
+  </wangset>
+ </wangsets>
+</tileset>
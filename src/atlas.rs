@@ -0,0 +1,282 @@
+use image::RgbaImage;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+pub const ATLAS_MANIFEST_PATH: &str = "sprites/atlas.ron";
+
+// === Structs ===
+// Describes how the sprite atlas is assembled from the source sheets, see `assets/sprites/atlas.ron`.
+#[derive(Debug, Deserialize)]
+pub struct AtlasManifest {
+    pub tile_size: u32,
+    // The width of the packed atlas in tiles.
+    pub columns: u32,
+    pub sheets: Vec<SheetManifest>,
+    // Names of fully transparent tiles, packed after every sheet.
+    #[serde(default)]
+    pub empty_tiles: Vec<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct SheetManifest {
+    // Relative to the assets folder, like the paths given to the `AssetServer`. Sheets of imported
+    // tilesets and projects are absolute instead.
+    pub path: String,
+    pub tiles: Vec<TileManifest>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct TileManifest {
+    pub name: String,
+    pub column: u32,
+    pub row: u32,
+}
+
+pub struct Atlas {
+    pub image: RgbaImage,
+    pub tile_size: u32,
+    // The atlas index of every tile, keyed by the name given in the manifest.
+    pub indices: HashMap<String, u32>,
+}
+
+#[derive(Debug)]
+pub enum AtlasError {
+    Io {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+    Manifest {
+        path: PathBuf,
+        message: String,
+    },
+    Image {
+        path: PathBuf,
+        error: image::ImageError,
+    },
+    TileOutOfBounds {
+        sheet: String,
+        name: String,
+    },
+    DuplicateName(String),
+}
+
+impl fmt::Display for AtlasError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AtlasError::Io { path, error } => write!(f, "could not read {:?}: {}", path, error),
+            AtlasError::Manifest { path, message } => {
+                write!(f, "invalid atlas manifest {:?}: {}", path, message)
+            }
+            AtlasError::Image { path, error } => {
+                write!(f, "could not decode {:?}: {}", path, error)
+            }
+            AtlasError::TileOutOfBounds { sheet, name } => {
+                write!(f, "tile {:?} lies outside of the sheet {:?}", name, sheet)
+            }
+            AtlasError::DuplicateName(name) => {
+                write!(f, "tile {:?} is listed more than once", name)
+            }
+        }
+    }
+}
+
+impl std::error::Error for AtlasError {}
+
+// === Helper Functions ===
+// Mirrors where bevy looks for assets: next to the manifest when running through cargo, next to the
+// executable otherwise.
+pub fn get_asset_root() -> PathBuf {
+    if let Ok(manifest_dir) = std::env::var("CARGO_MANIFEST_DIR") {
+        PathBuf::from(manifest_dir).join("assets")
+    } else {
+        std::env::current_exe()
+            .ok()
+            .and_then(|exe| exe.parent().map(Path::to_path_buf))
+            .unwrap_or_default()
+            .join("assets")
+    }
+}
+
+pub fn load_atlas_manifest(asset_root: &Path) -> Result<AtlasManifest, AtlasError> {
+    let path = asset_root.join(ATLAS_MANIFEST_PATH);
+    let contents = std::fs::read_to_string(&path).map_err(|error| AtlasError::Io {
+        path: path.clone(),
+        error,
+    })?;
+    let manifest: AtlasManifest =
+        ron::de::from_str(&contents).map_err(|error| AtlasError::Manifest {
+            path: path.clone(),
+            message: error.to_string(),
+        })?;
+    // Tiles are packed row by row, an atlas without columns or pixels has nowhere to put them.
+    if manifest.columns == 0 || manifest.tile_size == 0 {
+        return Err(AtlasError::Manifest {
+            path,
+            message: "columns and tile_size have to be greater than 0".to_string(),
+        });
+    }
+    Ok(manifest)
+}
+
+// Packs every tile listed in the manifest into a single image, left to right and top to bottom,
+// in the order the manifest lists them.
+pub fn build_atlas(manifest: &AtlasManifest, asset_root: &Path) -> Result<Atlas, AtlasError> {
+    let tile_size = manifest.tile_size;
+    let tile_count = manifest
+        .sheets
+        .iter()
+        .map(|sheet| sheet.tiles.len())
+        .sum::<usize>()
+        + manifest.empty_tiles.len();
+    let rows = (tile_count as u32 + manifest.columns - 1) / manifest.columns;
+    let mut image = RgbaImage::new(manifest.columns * tile_size, rows.max(1) * tile_size);
+    let mut indices: HashMap<String, u32> = HashMap::new();
+
+    let mut next_index = 0;
+    for sheet in &manifest.sheets {
+        // Imported tilesets without any tile the rules use, such as the layers of an exported map,
+        // are not read at all.
+        if sheet.tiles.is_empty() {
+            continue;
+        }
+        let path = asset_root.join(&sheet.path);
+        let sheet_image = image::open(&path)
+            .map_err(|error| AtlasError::Image {
+                path: path.clone(),
+                error,
+            })?
+            .into_rgba8();
+        for tile in &sheet.tiles {
+            if (tile.column + 1) * tile_size > sheet_image.width()
+                || (tile.row + 1) * tile_size > sheet_image.height()
+            {
+                return Err(AtlasError::TileOutOfBounds {
+                    sheet: sheet.path.clone(),
+                    name: tile.name.clone(),
+                });
+            }
+            let target_x = (next_index % manifest.columns) * tile_size;
+            let target_y = (next_index / manifest.columns) * tile_size;
+            for y in 0..tile_size {
+                for x in 0..tile_size {
+                    let pixel = sheet_image
+                        .get_pixel(tile.column * tile_size + x, tile.row * tile_size + y);
+                    image.put_pixel(target_x + x, target_y + y, *pixel);
+                }
+            }
+            if indices.insert(tile.name.clone(), next_index).is_some() {
+                return Err(AtlasError::DuplicateName(tile.name.clone()));
+            }
+            next_index += 1;
+        }
+    }
+    // The image starts out transparent, empty tiles only need an index.
+    for name in &manifest.empty_tiles {
+        if indices.insert(name.clone(), next_index).is_some() {
+            return Err(AtlasError::DuplicateName(name.clone()));
+        }
+        next_index += 1;
+    }
+
+    Ok(Atlas {
+        image,
+        tile_size,
+        indices,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    // A folder of its own for each test, as tests run in parallel.
+    fn get_test_asset_root(test_name: &str) -> PathBuf {
+        let asset_root = std::env::temp_dir().join(format!(
+            "autotile_atlas_{}_{}",
+            test_name,
+            std::process::id()
+        ));
+        std::fs::create_dir_all(asset_root.join("sprites")).unwrap();
+        asset_root
+    }
+
+    // A sheet of 2x1 tiles of `tile_size`, each filled with its own color.
+    fn write_sheet(asset_root: &Path, path: &str, tile_size: u32, colors: [Rgba<u8>; 2]) {
+        let sheet_image = RgbaImage::from_fn(tile_size * 2, tile_size, |x, _| {
+            colors[(x / tile_size) as usize]
+        });
+        sheet_image.save(asset_root.join(path)).unwrap();
+    }
+
+    fn get_sheet(path: &str, names: [&str; 2]) -> SheetManifest {
+        SheetManifest {
+            path: path.to_string(),
+            tiles: names
+                .iter()
+                .enumerate()
+                .map(|(column, name)| TileManifest {
+                    name: name.to_string(),
+                    column: column as u32,
+                    row: 0,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn named_indices_stay_the_same_when_a_sheet_is_added() {
+        let asset_root = get_test_asset_root("indices");
+        let red = Rgba([255, 0, 0, 255]);
+        let green = Rgba([0, 255, 0, 255]);
+        let blue = Rgba([0, 0, 255, 255]);
+        write_sheet(&asset_root, "grass.png", 4, [red, green]);
+        write_sheet(&asset_root, "dirt.png", 4, [blue, red]);
+        let mut manifest = AtlasManifest {
+            tile_size: 4,
+            columns: 3,
+            sheets: vec![get_sheet("grass.png", ["grass_0", "grass_1"])],
+            empty_tiles: vec!["blank".to_string()],
+        };
+        let atlas = build_atlas(&manifest, &asset_root).unwrap();
+
+        manifest
+            .sheets
+            .push(get_sheet("dirt.png", ["dirt_0", "dirt_1"]));
+        let larger_atlas = build_atlas(&manifest, &asset_root).unwrap();
+        for name in ["grass_0", "grass_1"] {
+            assert_eq!(larger_atlas.indices[name], atlas.indices[name]);
+        }
+        assert_eq!(larger_atlas.indices["dirt_0"], 2);
+        assert_eq!(larger_atlas.indices["dirt_1"], 3);
+        // The fourth tile wraps onto the second row of the 3 columns.
+        assert_eq!(*larger_atlas.image.get_pixel(4, 0), green);
+        assert_eq!(*larger_atlas.image.get_pixel(8, 0), blue);
+        assert_eq!(*larger_atlas.image.get_pixel(0, 4), red);
+        std::fs::remove_dir_all(asset_root).unwrap();
+    }
+
+    #[test]
+    fn manifests_without_columns_or_tile_size_are_rejected() {
+        let asset_root = get_test_asset_root("manifest");
+        for manifest in [
+            "(tile_size: 16, columns: 0, sheets: [])",
+            "(tile_size: 0, columns: 8, sheets: [])",
+        ] {
+            std::fs::write(asset_root.join(ATLAS_MANIFEST_PATH), manifest).unwrap();
+            assert!(matches!(
+                load_atlas_manifest(&asset_root),
+                Err(AtlasError::Manifest { .. })
+            ));
+        }
+        std::fs::write(
+            asset_root.join(ATLAS_MANIFEST_PATH),
+            "(tile_size: 16, columns: 8, sheets: [])",
+        )
+        .unwrap();
+        assert!(load_atlas_manifest(&asset_root).is_ok());
+        std::fs::remove_dir_all(asset_root).unwrap();
+    }
+}
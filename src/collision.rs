@@ -0,0 +1,541 @@
+use crate::debug::DebugOverlay;
+use crate::properties::TerrainPropertyTable;
+use crate::resolver::{get_drawn_rule_index, AutotileResolvers};
+use crate::structures::StructureFootprint;
+use crate::terrain::TerrainGrid;
+use crate::{
+    is_typing, GroundLayer, Rules, Slot, SpriteRegistry, TerrainChanged, BLANK_SPRITE, MAP_HEIGHT,
+    MAP_WIDTH,
+};
+use bevy::prelude::*;
+use bevy_ecs_tilemap::prelude::*;
+use bevy_inspector_egui::bevy_egui::EguiContext;
+use bevy_inspector_egui::egui;
+use std::collections::{HashMap, HashSet};
+
+// Shapes are generated per chunk of this many tiles square, so an edit only regenerates the chunks
+// around it. Shapes do not cross chunks.
+pub const COLLISION_CHUNK_SIZE: i32 = 16;
+
+// === Components ===
+// A collision shape around tiles that cannot be walked on, in world coordinates. Physics plugins
+// can build their colliders from these, watching for them being added and removed.
+#[derive(Component, Clone, Debug, PartialEq)]
+pub enum CollisionShape {
+    Rectangle { min: Vec2, max: Vec2 },
+    // Follows the edges the sprites around the blocked tiles are drawn with, counterclockwise
+    // around the blocked tiles. Outlines are open where they are cut by the edge of a chunk, or
+    // where a sprite continues onto the blocked tile.
+    Polyline { points: Vec<Vec2>, is_closed: bool },
+}
+
+// The chunk a collision shape was generated for, in chunks rather than tiles.
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CollisionChunk {
+    pub chunk: IVec2,
+}
+
+// === Enums ===
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CollisionShapeKind {
+    // Blocked tiles merged into as few rectangles as the greedy meshing finds.
+    Rectangles,
+    // The outlines of the blocked areas.
+    Outlines,
+}
+
+// === Resources ===
+pub struct CollisionGeometry {
+    pub kind: CollisionShapeKind,
+    // Its own copy of the terrain, kept up to date from `TerrainChanged` and the structure
+    // footprints.
+    terrain_grid: TerrainGrid,
+    dirty_chunks: HashSet<IVec2>,
+}
+
+// === Startup Systems ===
+pub fn setup_collision_geometry(mut commands: Commands) {
+    commands.insert_resource(CollisionGeometry {
+        kind: CollisionShapeKind::Rectangles,
+        terrain_grid: TerrainGrid::new(MAP_WIDTH as u32, MAP_HEIGHT as u32),
+        dirty_chunks: HashSet::new(),
+    });
+}
+
+// === Systems ===
+// F8 switches between rectangles and outlines.
+pub fn update_collision_geometry(
+    mut commands: Commands,
+    mut terrain_changed_event_reader: EventReader<TerrainChanged>,
+    mut collision_geometry: ResMut<CollisionGeometry>,
+    keyboard: Res<Input<KeyCode>>,
+    mut egui_context: ResMut<EguiContext>,
+    property_table: Res<TerrainPropertyTable>,
+    rules: Res<Rules>,
+    autotile_resolvers: Res<AutotileResolvers>,
+    sprite_registry: Res<SpriteRegistry>,
+    removed_footprints: RemovedComponents<StructureFootprint>,
+    ground_layer_query: Query<(&TilemapGridSize, &Transform), With<GroundLayer>>,
+    added_footprints_query: Query<Entity, Added<StructureFootprint>>,
+    footprint_tiles_query: Query<(&TilePos, Option<&StructureFootprint>)>,
+    shapes_query: Query<(Entity, &CollisionChunk), With<CollisionShape>>,
+) {
+    let mut is_everything_dirty =
+        property_table.is_changed() || rules.is_changed() || autotile_resolvers.is_changed();
+    if keyboard.just_pressed(KeyCode::F8) && !is_typing(&mut egui_context) {
+        collision_geometry.kind = match collision_geometry.kind {
+            CollisionShapeKind::Rectangles => CollisionShapeKind::Outlines,
+            CollisionShapeKind::Outlines => CollisionShapeKind::Rectangles,
+        };
+        println!("Collision Shapes Updated: {:?}", collision_geometry.kind);
+        is_everything_dirty = true;
+    }
+    for terrain_changed in terrain_changed_event_reader.iter() {
+        let tile_position = terrain_changed.pos;
+        collision_geometry
+            .terrain_grid
+            .set(tile_position.x, tile_position.y, terrain_changed.new);
+        mark_dirty_chunks(&mut collision_geometry.dirty_chunks, &tile_position);
+    }
+    // Structures count as more of the terrain around them, so they change the sprites around
+    // them too. Loading a map removes footprints and adds them again in the same frame.
+    for tile_entity in removed_footprints
+        .iter()
+        .chain(added_footprints_query.iter())
+    {
+        if let Ok((tile_position, structure_footprint)) = footprint_tiles_query.get(tile_entity) {
+            collision_geometry.terrain_grid.set_structure(
+                tile_position.x,
+                tile_position.y,
+                structure_footprint.is_some(),
+            );
+            mark_dirty_chunks(&mut collision_geometry.dirty_chunks, tile_position);
+        }
+    }
+    if is_everything_dirty {
+        let chunk_count = get_chunk(IVec2::new(MAP_WIDTH - 1, MAP_HEIGHT - 1)) + IVec2::ONE;
+        for y in 0..chunk_count.y {
+            for x in 0..chunk_count.x {
+                collision_geometry.dirty_chunks.insert(IVec2::new(x, y));
+            }
+        }
+    }
+    if collision_geometry.dirty_chunks.is_empty() {
+        return;
+    }
+    let (grid_size, map_transform) = match ground_layer_query.get_single() {
+        Ok(ground_layer) => ground_layer,
+        Err(_) => return,
+    };
+
+    let dirty_chunks: HashSet<IVec2> = collision_geometry.dirty_chunks.drain().collect();
+    for (shape_entity, collision_chunk) in shapes_query.iter() {
+        if dirty_chunks.contains(&collision_chunk.chunk) {
+            commands.entity(shape_entity).despawn();
+        }
+    }
+    let terrain_grid = &collision_geometry.terrain_grid;
+    let is_blocked = |cell: IVec2| {
+        // Outside the map counts as blocked, so the edge of the map gets no shapes.
+        if cell.x < 0 || cell.y < 0 || cell.x >= MAP_WIDTH || cell.y >= MAP_HEIGHT {
+            return true;
+        }
+        !property_table.is_walkable(terrain_grid.get(cell.x, cell.y))
+    };
+    let is_edge = |cell: IVec2, offset: IVec2| {
+        let neighbor = cell + offset;
+        !is_blocked(neighbor)
+            && has_edge_sprite(
+                terrain_grid,
+                neighbor,
+                -offset,
+                &rules,
+                &autotile_resolvers,
+                &sprite_registry,
+            )
+    };
+    // Corners of tiles to world positions, tiles are centered on their grid position.
+    let get_world_position = |corner: IVec2| {
+        let position = (corner.as_vec2() - Vec2::splat(0.5)) * Vec2::new(grid_size.x, grid_size.y);
+        map_transform.mul_vec3(position.extend(0.0)).truncate()
+    };
+    for chunk in &dirty_chunks {
+        let min = *chunk * COLLISION_CHUNK_SIZE;
+        let max = (min + IVec2::splat(COLLISION_CHUNK_SIZE)).min(IVec2::new(MAP_WIDTH, MAP_HEIGHT));
+        let shapes: Vec<CollisionShape> = match collision_geometry.kind {
+            CollisionShapeKind::Rectangles => get_rectangles(&is_blocked, min, max)
+                .into_iter()
+                .map(|(rectangle_min, rectangle_max)| CollisionShape::Rectangle {
+                    min: get_world_position(rectangle_min),
+                    max: get_world_position(rectangle_max),
+                })
+                .collect(),
+            CollisionShapeKind::Outlines => get_outlines(&is_blocked, &is_edge, min, max)
+                .into_iter()
+                .map(|(corners, is_closed)| CollisionShape::Polyline {
+                    points: corners.into_iter().map(get_world_position).collect(),
+                    is_closed,
+                })
+                .collect(),
+        };
+        for shape in shapes {
+            commands
+                .spawn()
+                .insert(shape)
+                .insert(CollisionChunk { chunk: *chunk })
+                .insert(Name::new("Collision Shape"));
+        }
+    }
+}
+
+// Draws the collision shapes in orange while the debug overlay is shown.
+pub fn draw_collision_shapes(
+    mut egui_context: ResMut<EguiContext>,
+    debug_overlay: Res<DebugOverlay>,
+    windows: Res<Windows>,
+    camera_query: Query<(&Camera, &GlobalTransform, &OrthographicProjection)>,
+    shapes_query: Query<&CollisionShape>,
+) {
+    if !debug_overlay.is_visible {
+        return;
+    }
+    let (camera, camera_transform, projection) = match camera_query.get_single() {
+        Ok(camera) => camera,
+        Err(_) => return,
+    };
+    let window = match windows.get_primary() {
+        Some(window) => window,
+        None => return,
+    };
+
+    let get_screen_position = |world_position: Vec2| {
+        let viewport_position =
+            camera.world_to_viewport(camera_transform, world_position.extend(0.0))?;
+        // Viewport positions count up from the bottom of the window, egui counts down from the top.
+        Some(egui::pos2(
+            viewport_position.x,
+            window.height() - viewport_position.y,
+        ))
+    };
+    let stroke = egui::Stroke::new(
+        (2.0 / projection.scale).max(1.0),
+        egui::Color32::from_rgb(255, 140, 0),
+    );
+    let painter = egui_context
+        .ctx_mut()
+        .layer_painter(egui::LayerId::background());
+    for shape in shapes_query.iter() {
+        match shape {
+            CollisionShape::Rectangle { min, max } => {
+                if let (Some(first), Some(second)) =
+                    (get_screen_position(*min), get_screen_position(*max))
+                {
+                    painter.rect_stroke(egui::Rect::from_two_pos(first, second), 0.0, stroke);
+                }
+            }
+            CollisionShape::Polyline { points, is_closed } => {
+                let mut screen_points: Vec<egui::Pos2> = points
+                    .iter()
+                    .filter_map(|point| get_screen_position(*point))
+                    .collect();
+                if *is_closed {
+                    if let Some(first) = screen_points.first().copied() {
+                        screen_points.push(first);
+                    }
+                }
+                painter.add(egui::Shape::line(screen_points, stroke));
+            }
+        }
+    }
+}
+
+// === Helper Functions ===
+pub fn get_chunk(cell: IVec2) -> IVec2 {
+    IVec2::new(
+        cell.x.div_euclid(COLLISION_CHUNK_SIZE),
+        cell.y.div_euclid(COLLISION_CHUNK_SIZE),
+    )
+}
+
+// Outlines depend on the sprites of the tiles around the blocked ones, which depend on the tiles
+// around them in turn, and can be in the next chunk.
+fn mark_dirty_chunks(dirty_chunks: &mut HashSet<IVec2>, tile_position: &TilePos) {
+    let cell = IVec2::new(tile_position.x as i32, tile_position.y as i32);
+    for y in -2..=2 {
+        for x in -2..=2 {
+            dirty_chunks.insert(get_chunk(cell + IVec2::new(x, y)));
+        }
+    }
+}
+
+// Merges the blocked tiles from `min` up to, but not including, `max` into rectangles. Each
+// rectangle grows as wide as it can along its first row, then as tall as the rows above allow.
+// Rectangles are returned as their lowest and highest tile corners.
+pub fn get_rectangles(
+    is_blocked: &dyn Fn(IVec2) -> bool,
+    min: IVec2,
+    max: IVec2,
+) -> Vec<(IVec2, IVec2)> {
+    let mut covered = HashSet::new();
+    let mut rectangles = Vec::new();
+    for y in min.y..max.y {
+        for x in min.x..max.x {
+            let start = IVec2::new(x, y);
+            if covered.contains(&start) || !is_blocked(start) {
+                continue;
+            }
+            let is_free = |cell: IVec2| !covered.contains(&cell) && is_blocked(cell);
+            let mut end_x = x + 1;
+            while end_x < max.x && is_free(IVec2::new(end_x, y)) {
+                end_x += 1;
+            }
+            let mut end_y = y + 1;
+            while end_y < max.y && (x..end_x).all(|row_x| is_free(IVec2::new(row_x, end_y))) {
+                end_y += 1;
+            }
+            for covered_y in y..end_y {
+                for covered_x in x..end_x {
+                    covered.insert(IVec2::new(covered_x, covered_y));
+                }
+            }
+            rectangles.push((start, IVec2::new(end_x, end_y)));
+        }
+    }
+    rectangles
+}
+
+// Traces the edges around the blocked tiles from `min` up to, but not including, `max`.
+// `is_edge` tells whether the side of a blocked tile towards an offset is an edge. Returns the
+// corners of each outline, counterclockwise around the blocked tiles, and whether it is closed.
+pub fn get_outlines(
+    is_blocked: &dyn Fn(IVec2) -> bool,
+    is_edge: &dyn Fn(IVec2, IVec2) -> bool,
+    min: IVec2,
+    max: IVec2,
+) -> Vec<(Vec<IVec2>, bool)> {
+    // Edges go from corner to corner with the blocked tile on their left.
+    let mut edges: HashMap<IVec2, Vec<IVec2>> = HashMap::new();
+    let mut edge_count = 0;
+    for y in min.y..max.y {
+        for x in min.x..max.x {
+            let cell = IVec2::new(x, y);
+            if !is_blocked(cell) {
+                continue;
+            }
+            for (offset, start, end) in [
+                (IVec2::NEG_Y, IVec2::new(0, 0), IVec2::new(1, 0)),
+                (IVec2::X, IVec2::new(1, 0), IVec2::new(1, 1)),
+                (IVec2::Y, IVec2::new(1, 1), IVec2::new(0, 1)),
+                (IVec2::NEG_X, IVec2::new(0, 1), IVec2::new(0, 0)),
+            ] {
+                if is_edge(cell, offset) {
+                    edges.entry(cell + start).or_default().push(cell + end);
+                    edge_count += 1;
+                }
+            }
+        }
+    }
+
+    // Open outlines have to start where no edge ends, so they are traced first.
+    let ends: HashSet<IVec2> = edges.values().flatten().copied().collect();
+    let mut starts: Vec<IVec2> = edges.keys().copied().collect();
+    starts.sort_by_key(|corner| (ends.contains(corner), corner.y, corner.x));
+    let mut outlines = Vec::new();
+    let mut traced_count = 0;
+    for start in starts {
+        while traced_count < edge_count {
+            let mut corners = vec![start];
+            let mut corner = start;
+            while let Some(next) = edges.get_mut(&corner).and_then(Vec::pop) {
+                traced_count += 1;
+                corners.push(next);
+                corner = next;
+                if corner == start {
+                    break;
+                }
+            }
+            if corners.len() == 1 {
+                break;
+            }
+            let is_closed = corners.last() == Some(&start);
+            if is_closed {
+                corners.pop();
+            }
+            outlines.push((get_simplified_corners(&corners, is_closed), is_closed));
+        }
+    }
+    outlines
+}
+
+// Whether the sprite the tile at `cell` is drawn with has an edge on its side towards `offset`,
+// going by the rule with that sprite. Only a slot that wants the terrain of the tile itself
+// carries the sprite on across that side. Tiles left blank, or drawn with a sprite no rule has,
+// have an edge on every side.
+pub fn has_edge_sprite(
+    terrain_grid: &TerrainGrid,
+    cell: IVec2,
+    offset: IVec2,
+    rules: &Rules,
+    autotile_resolvers: &AutotileResolvers,
+    sprite_registry: &SpriteRegistry,
+) -> bool {
+    let sprite_type = match terrain_grid.get(cell.x, cell.y) {
+        Some(sprite_type) => sprite_type,
+        None => return true,
+    };
+    let possible_rules = match rules.rules.get(&sprite_type) {
+        Some(possible_rules) => possible_rules,
+        None => return true,
+    };
+    let drawn_rule = terrain_grid
+        .resolve_tile(
+            cell.x as u32,
+            cell.y as u32,
+            rules,
+            autotile_resolvers,
+            sprite_registry,
+        )
+        .and_then(|tile_output| get_drawn_rule_index(&tile_output, possible_rules, sprite_registry))
+        .map(|drawn_index| &possible_rules[drawn_index])
+        .filter(|(_, sprite_name)| sprite_name != BLANK_SPRITE);
+    let (rule, _) = match drawn_rule {
+        Some(drawn_rule) => drawn_rule,
+        None => return true,
+    };
+    let slot = match (offset.x, offset.y) {
+        (0, 1) => rule.n_slot,
+        (1, 0) => rule.e_slot,
+        (0, -1) => rule.s_slot,
+        (-1, 0) => rule.w_slot,
+        _ => return true,
+    };
+    slot != Slot::Filled { sprite_type }
+}
+
+// Drops the corners in the middle of straight lines.
+fn get_simplified_corners(corners: &[IVec2], is_closed: bool) -> Vec<IVec2> {
+    let count = corners.len();
+    corners
+        .iter()
+        .enumerate()
+        .filter(|(index, corner)| {
+            let (previous, next) = if is_closed {
+                (
+                    corners[(index + count - 1) % count],
+                    corners[(index + 1) % count],
+                )
+            } else if *index == 0 || *index == count - 1 {
+                return true;
+            } else {
+                (corners[index - 1], corners[index + 1])
+            };
+            **corner - previous != next - **corner
+        })
+        .map(|(_, corner)| *corner)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::get_builtin_rules;
+    use crate::resolver::ResolverKind;
+
+    fn get_is_blocked(blocked_cells: &[IVec2]) -> impl Fn(IVec2) -> bool + '_ {
+        |cell: IVec2| blocked_cells.contains(&cell)
+    }
+
+    #[test]
+    fn an_l_shape_meshes_into_two_rectangles() {
+        let blocked_cells = [
+            IVec2::new(1, 1),
+            IVec2::new(2, 1),
+            IVec2::new(3, 1),
+            IVec2::new(1, 2),
+            IVec2::new(1, 3),
+        ];
+        let is_blocked = get_is_blocked(&blocked_cells);
+        let rectangles = get_rectangles(&is_blocked, IVec2::ZERO, IVec2::splat(5));
+        assert_eq!(
+            rectangles,
+            vec![
+                (IVec2::new(1, 1), IVec2::new(4, 2)),
+                (IVec2::new(1, 2), IVec2::new(2, 4)),
+            ]
+        );
+    }
+
+    #[test]
+    fn a_single_blocked_tile_has_a_closed_outline() {
+        let blocked_cells = [IVec2::new(1, 1)];
+        let is_blocked = get_is_blocked(&blocked_cells);
+        let is_edge = |cell: IVec2, offset: IVec2| !is_blocked(cell + offset);
+        let outlines = get_outlines(&is_blocked, &is_edge, IVec2::ZERO, IVec2::splat(3));
+        assert_eq!(outlines.len(), 1);
+        let (corners, is_closed) = &outlines[0];
+        assert!(is_closed);
+        assert_eq!(corners.len(), 4);
+        for corner in [
+            IVec2::new(1, 1),
+            IVec2::new(2, 1),
+            IVec2::new(2, 2),
+            IVec2::new(1, 2),
+        ] {
+            assert!(corners.contains(&corner));
+        }
+    }
+
+    #[test]
+    fn edge_sprites_only_have_edges_where_their_terrain_ends() {
+        let rules = get_builtin_rules();
+        let sprite_registry = SpriteRegistry::from_rules(&rules);
+        let terrain_grid = TerrainGrid::from_ascii(&["ggg"; 3]);
+        for resolver_kind in [
+            ResolverKind::FirstMatch,
+            ResolverKind::Bitmask,
+            ResolverKind::BestScore,
+        ] {
+            let autotile_resolvers = AutotileResolvers::new(resolver_kind, &rules);
+            let has_edge = |cell: IVec2, offset: IVec2| {
+                has_edge_sprite(
+                    &terrain_grid,
+                    cell,
+                    offset,
+                    &rules,
+                    &autotile_resolvers,
+                    &sprite_registry,
+                )
+            };
+            let center = IVec2::new(1, 1);
+            for offset in [IVec2::Y, IVec2::X, IVec2::NEG_Y, IVec2::NEG_X] {
+                assert!(!has_edge(center, offset));
+            }
+            let corner = IVec2::new(0, 0);
+            assert!(has_edge(corner, IVec2::NEG_X));
+            assert!(has_edge(corner, IVec2::NEG_Y));
+            assert!(!has_edge(corner, IVec2::X));
+            assert!(!has_edge(corner, IVec2::Y));
+        }
+    }
+
+    #[test]
+    fn structures_hide_the_edges_towards_them() {
+        let rules = get_builtin_rules();
+        let sprite_registry = SpriteRegistry::from_rules(&rules);
+        let autotile_resolvers = AutotileResolvers::new(ResolverKind::Bitmask, &rules);
+        let mut terrain_grid = TerrainGrid::from_ascii(&["ggg", "g.g", "ggg"]);
+        let has_edge = |terrain_grid: &TerrainGrid| {
+            has_edge_sprite(
+                terrain_grid,
+                IVec2::new(1, 2),
+                IVec2::NEG_Y,
+                &rules,
+                &autotile_resolvers,
+                &sprite_registry,
+            )
+        };
+        assert!(has_edge(&terrain_grid));
+        terrain_grid.set_structure(1, 1, true);
+        assert!(!has_edge(&terrain_grid));
+    }
+}
@@ -0,0 +1,419 @@
+use crate::resolver::{get_drawn_rule_index, AutotileResolvers, Neighborhood, TileOutput};
+use crate::rule_editor::get_slot_label;
+use crate::{
+    is_typing, world_position_to_tile_position, ActiveRules, GroundLayer, Mouse, Rule, Rules, Slot,
+    SpriteRegistry, TILE_SIZE,
+};
+use bevy::prelude::*;
+use bevy_ecs_tilemap::prelude::*;
+use bevy_inspector_egui::bevy_egui::EguiContext;
+use bevy_inspector_egui::egui;
+
+// How many near-misses are printed when a tile is explained.
+pub const NEAR_MISS_COUNT: usize = 3;
+
+// Tiles smaller than this on screen only show their mask, the rule index would not fit.
+const MIN_LABEL_SIZE: f32 = 24.0;
+const SLOT_NAMES: [&str; 9] = ["nw", "n", "ne", "w", "c", "e", "sw", "s", "se"];
+
+// === Resources ===
+pub struct DebugOverlay {
+    pub is_visible: bool,
+}
+
+// === Startup Systems ===
+pub fn setup_debug_overlay(mut commands: Commands) {
+    commands.insert_resource(DebugOverlay { is_visible: false });
+}
+
+// === Systems ===
+// F3 toggles the overlay. Every autotiled tile shows its neighbor mask in its top-left corner, white
+// for the same terrain, yellow for another terrain and dark for empty, and the index of the rule
+// whose sprite its resolver drew, or `*` for a sprite no rule has. Tiles the resolver left blank
+// are outlined in red.
+pub fn update_debug_overlay(
+    keyboard: Res<Input<KeyCode>>,
+    mut debug_overlay: ResMut<DebugOverlay>,
+    mut egui_context: ResMut<EguiContext>,
+    windows: Res<Windows>,
+    active_rules: Res<ActiveRules>,
+    rules: Res<Rules>,
+    autotile_resolvers: Res<AutotileResolvers>,
+    sprite_registry: Res<SpriteRegistry>,
+    camera_query: Query<(&Camera, &GlobalTransform, &OrthographicProjection)>,
+    ground_layer_query: Query<(&TilemapGridSize, &TilemapType, &Transform), With<GroundLayer>>,
+) {
+    if keyboard.just_pressed(KeyCode::F3) && !is_typing(&mut egui_context) {
+        debug_overlay.is_visible = !debug_overlay.is_visible;
+        println!("Debug Overlay Updated: {:?}", debug_overlay.is_visible);
+    }
+    if !debug_overlay.is_visible {
+        return;
+    }
+    let (camera, camera_transform, projection) = match camera_query.get_single() {
+        Ok(camera) => camera,
+        Err(_) => return,
+    };
+    let (grid_size, map_type, map_transform) = match ground_layer_query.get_single() {
+        Ok(ground_layer) => ground_layer,
+        Err(_) => return,
+    };
+    let window = match windows.get_primary() {
+        Some(window) => window,
+        None => return,
+    };
+
+    let screen = egui::Rect::from_min_size(
+        egui::Pos2::ZERO,
+        egui::vec2(window.width(), window.height()),
+    );
+    let tile_size = TILE_SIZE as f32 / projection.scale;
+    let slot_size = tile_size / 8.0;
+    // Drawn behind the editor panel and windows.
+    let painter = egui_context
+        .ctx_mut()
+        .layer_painter(egui::LayerId::background());
+    for (tile_position, active_rule) in &active_rules.active_rules {
+        let center = match get_screen_position(
+            tile_position,
+            grid_size,
+            map_type,
+            map_transform,
+            camera,
+            camera_transform,
+            window.height(),
+        ) {
+            Some(center) => center,
+            None => continue,
+        };
+        let tile_rect = egui::Rect::from_center_size(center, egui::vec2(tile_size, tile_size));
+        if !screen.intersects(tile_rect) {
+            continue;
+        }
+
+        let tile_output = get_tile_output(
+            tile_position,
+            active_rule,
+            &rules,
+            &autotile_resolvers,
+            &sprite_registry,
+        );
+        if tile_output.is_none() {
+            painter.rect_filled(
+                tile_rect,
+                0.0,
+                egui::Color32::from_rgba_unmultiplied(255, 0, 0, 64),
+            );
+            painter.rect_stroke(
+                tile_rect.shrink(1.0),
+                0.0,
+                egui::Stroke::new(2.0, egui::Color32::RED),
+            );
+        }
+
+        for (index, slot) in get_slots(active_rule).iter().enumerate() {
+            let color = match slot {
+                Slot::Filled { .. } if *slot == active_rule.c_slot => egui::Color32::WHITE,
+                Slot::Filled { .. } => egui::Color32::YELLOW,
+                _ => egui::Color32::from_black_alpha(160),
+            };
+            let offset = egui::vec2((index % 3) as f32, (index / 3) as f32) * slot_size;
+            let slot_rect = egui::Rect::from_min_size(
+                tile_rect.min + egui::vec2(1.0, 1.0) + offset,
+                egui::vec2(slot_size, slot_size),
+            );
+            painter.rect_filled(slot_rect, 0.0, color);
+        }
+
+        if tile_size >= MIN_LABEL_SIZE {
+            let drawn_index = tile_output.and_then(|tile_output| {
+                get_drawn_rule_index(
+                    &tile_output,
+                    get_possible_rules(active_rule, &rules),
+                    &sprite_registry,
+                )
+            });
+            let label = match (tile_output, drawn_index) {
+                (Some(_), Some(drawn_index)) => drawn_index.to_string(),
+                (Some(_), None) => "*".to_string(),
+                (None, _) => "?".to_string(),
+            };
+            painter.text(
+                tile_rect.right_bottom() - egui::vec2(2.0, 2.0),
+                egui::Align2::RIGHT_BOTTOM,
+                label,
+                egui::FontId::monospace(tile_size / 3.0),
+                egui::Color32::WHITE,
+            );
+        }
+    }
+}
+
+// Right clicking a tile while the overlay is shown prints how it was matched, and which rule the
+// resolver drew when it is not the first match.
+pub fn explain_tile(
+    keyboard: Res<Input<KeyCode>>,
+    debug_overlay: Res<DebugOverlay>,
+    mouse: Res<Mouse>,
+    mouse_input: Res<Input<MouseButton>>,
+    active_rules: Res<ActiveRules>,
+    rules: Res<Rules>,
+    autotile_resolvers: Res<AutotileResolvers>,
+    sprite_registry: Res<SpriteRegistry>,
+    ground_layer_query: Query<
+        (&TilemapSize, &TilemapGridSize, &TilemapType, &Transform),
+        With<GroundLayer>,
+    >,
+) {
+    let is_shift_pressed = keyboard.pressed(KeyCode::LShift) || keyboard.pressed(KeyCode::RShift);
+    // Shift+right click picks the ends of the debug path instead, see `pathfinding.rs`.
+    if !debug_overlay.is_visible
+        || !mouse_input.just_pressed(MouseButton::Right)
+        || is_shift_pressed
+        || mouse.is_over_ui
+    {
+        return;
+    }
+    let (map_size, grid_size, map_type, map_transform) = match ground_layer_query.get_single() {
+        Ok(ground_layer) => ground_layer,
+        Err(_) => return,
+    };
+    let tile_position = match world_position_to_tile_position(
+        mouse.world_position,
+        map_size,
+        grid_size,
+        map_type,
+        map_transform,
+    ) {
+        Some(tile_position) => tile_position,
+        None => return,
+    };
+    let active_rule = match active_rules.active_rules.get(&tile_position) {
+        Some(active_rule) => active_rule,
+        None => {
+            println!(
+                "Tile ({}, {}) is not autotiled",
+                tile_position.x, tile_position.y
+            );
+            return;
+        }
+    };
+    let possible_rules = get_possible_rules(active_rule, &rules);
+    let mut explanation = get_explanation(&tile_position, active_rule, possible_rules);
+    let tile_output = get_tile_output(
+        &tile_position,
+        active_rule,
+        &rules,
+        &autotile_resolvers,
+        &sprite_registry,
+    );
+    let drawn_index = tile_output.and_then(|tile_output| {
+        get_drawn_rule_index(&tile_output, possible_rules, &sprite_registry)
+    });
+    match (tile_output, drawn_index) {
+        (Some(_), Some(drawn_index))
+            if Some(drawn_index) != get_matched_index(active_rule, possible_rules) =>
+        {
+            explanation += &format!(
+                "  The resolver drew rule {} \"{}\" instead\n",
+                drawn_index, possible_rules[drawn_index].1
+            );
+        }
+        (Some(_), None) => explanation += "  The resolver drew a sprite no rule has\n",
+        (None, _) if get_matched_index(active_rule, possible_rules).is_some() => {
+            explanation += "  The resolver left the tile blank instead\n";
+        }
+        _ => {}
+    }
+    println!("{}", explanation);
+}
+
+// === Helper Functions ===
+// Where the center of a tile is in the window, in egui coordinates.
+pub fn get_screen_position(
+    tile_position: &TilePos,
+    grid_size: &TilemapGridSize,
+    map_type: &TilemapType,
+    map_transform: &Transform,
+    camera: &Camera,
+    camera_transform: &GlobalTransform,
+    window_height: f32,
+) -> Option<egui::Pos2> {
+    let tile_center = tile_position.center_in_world(grid_size, map_type);
+    let world_position = map_transform.mul_vec3(tile_center.extend(0.0));
+    let viewport_position = camera.world_to_viewport(camera_transform, world_position)?;
+    // Viewport positions count up from the bottom of the window, egui counts down from the top.
+    Some(egui::pos2(
+        viewport_position.x,
+        window_height - viewport_position.y,
+    ))
+}
+
+// The rules of the terrain at the center of `active_rule`.
+pub fn get_possible_rules<'a>(active_rule: &Rule, rules: &'a Rules) -> &'a [(Rule, String)] {
+    match active_rule.c_slot {
+        Slot::Filled { sprite_type } => rules
+            .rules
+            .get(&sprite_type)
+            .map(Vec::as_slice)
+            .unwrap_or_default(),
+        _ => &[],
+    }
+}
+
+// What the resolver of the terrain draws the tile with, as the ground layer shows it.
+pub fn get_tile_output(
+    tile_position: &TilePos,
+    active_rule: &Rule,
+    rules: &Rules,
+    autotile_resolvers: &AutotileResolvers,
+    sprite_registry: &SpriteRegistry,
+) -> Option<TileOutput> {
+    let sprite_type = match active_rule.c_slot {
+        Slot::Filled { sprite_type } => sprite_type,
+        _ => return None,
+    };
+    let neighborhood = Neighborhood {
+        tile_position: *tile_position,
+        active_rule: *active_rule,
+    };
+    autotile_resolvers
+        .get(sprite_type)
+        .resolve(&neighborhood, sprite_type, rules, sprite_registry)
+}
+
+// The slots of a rule from north to south, west to east, as they are laid out on the map.
+pub fn get_slots(rule: &Rule) -> [Slot; 9] {
+    [
+        rule.nw_slot,
+        rule.n_slot,
+        rule.ne_slot,
+        rule.w_slot,
+        rule.c_slot,
+        rule.e_slot,
+        rule.sw_slot,
+        rule.s_slot,
+        rule.se_slot,
+    ]
+}
+
+// Slots match the same way `Rule` compares them, `Slot::Any` matches everything.
+pub fn is_slot_match(slot: Slot, other: Slot) -> bool {
+    slot == other || slot == Slot::Any || other == Slot::Any
+}
+
+// The index of the first rule matching `active_rule`, the one that picks its sprite.
+pub fn get_matched_index(active_rule: &Rule, possible_rules: &[(Rule, String)]) -> Option<usize> {
+    possible_rules
+        .iter()
+        .position(|(rule, _)| active_rule == rule)
+}
+
+// For each slot, whether it differs between the tile and the rule.
+pub fn get_mismatches(active_rule: &Rule, rule: &Rule) -> [bool; 9] {
+    let mut mismatches = [false; 9];
+    for (index, (slot, other)) in get_slots(active_rule)
+        .iter()
+        .zip(get_slots(rule).iter())
+        .enumerate()
+    {
+        mismatches[index] = !is_slot_match(*slot, *other);
+    }
+    mismatches
+}
+
+// The rules that did not match, with the fewest differing slots first. Ties keep the rule order.
+pub fn get_near_misses(active_rule: &Rule, possible_rules: &[(Rule, String)]) -> Vec<usize> {
+    let mut near_misses: Vec<(usize, usize)> = possible_rules
+        .iter()
+        .enumerate()
+        .map(|(index, (rule, _))| {
+            let mismatch_count = get_mismatches(active_rule, rule)
+                .iter()
+                .filter(|mismatch| **mismatch)
+                .count();
+            (mismatch_count, index)
+        })
+        .filter(|(mismatch_count, _)| *mismatch_count > 0)
+        .collect();
+    near_misses.sort();
+    near_misses
+        .into_iter()
+        .take(NEAR_MISS_COUNT)
+        .map(|(_, index)| index)
+        .collect()
+}
+
+// Lays out the slots of the tile next to those of a rule, marking the slots that differ with `x`.
+pub fn get_comparison(active_rule: &Rule, rule: &Rule) -> String {
+    let actual_slots = get_slots(active_rule);
+    let rule_slots = get_slots(rule);
+    let mismatches = get_mismatches(active_rule, rule);
+    let mut comparison = String::new();
+    for row in 0..3 {
+        let get_row = |get_label: &dyn Fn(usize) -> String| {
+            (row * 3..row * 3 + 3)
+                .map(get_label)
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        comparison += &format!(
+            "    {}    {}    {}\n",
+            get_row(&|index| get_slot_label(&actual_slots[index])),
+            get_row(&|index| get_slot_label(&rule_slots[index])),
+            get_row(&|index| if mismatches[index] { "x" } else { "=" }.to_string()),
+        );
+    }
+    comparison
+}
+
+pub fn get_explanation(
+    tile_position: &TilePos,
+    active_rule: &Rule,
+    possible_rules: &[(Rule, String)],
+) -> String {
+    let terrain = match active_rule.c_slot {
+        Slot::Filled { sprite_type } => format!("{:?}", sprite_type),
+        _ => "Unknown".to_string(),
+    };
+    let mut explanation = format!(
+        "Tile ({}, {}) {}, {} rules\n",
+        tile_position.x,
+        tile_position.y,
+        terrain,
+        possible_rules.len()
+    );
+    match get_matched_index(active_rule, possible_rules) {
+        Some(matched_index) => {
+            let (rule, sprite_name) = &possible_rules[matched_index];
+            explanation += &format!("  Matched rule {} \"{}\"\n", matched_index, sprite_name);
+            explanation += "    tile     rule     diff\n";
+            explanation += &get_comparison(active_rule, rule);
+        }
+        None => {
+            explanation += "  No rule matched, the tile is left blank\n";
+            explanation += "    tile\n";
+            for row in get_slots(active_rule).chunks(3) {
+                let labels: Vec<_> = row.iter().map(get_slot_label).collect();
+                explanation += &format!("    {}\n", labels.join(" "));
+            }
+        }
+    }
+    for index in get_near_misses(active_rule, possible_rules) {
+        let (rule, sprite_name) = &possible_rules[index];
+        let mismatched_slots: Vec<_> = get_mismatches(active_rule, rule)
+            .iter()
+            .zip(SLOT_NAMES)
+            .filter(|(mismatch, _)| **mismatch)
+            .map(|(_, slot_name)| slot_name)
+            .collect();
+        explanation += &format!(
+            "  Near miss rule {} \"{}\", differs at {}\n",
+            index,
+            sprite_name,
+            mismatched_slots.join(", ")
+        );
+        explanation += &get_comparison(active_rule, rule);
+    }
+    explanation
+}
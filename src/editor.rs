@@ -0,0 +1,364 @@
+use crate::debug::DebugOverlay;
+use crate::generator::{GenerateTerrainEvent, Generator};
+use crate::legalize::{LegalizeEvent, Legalizer};
+use crate::morphology::{ApplyTerrainOperationEvent, Morphology, TerrainOperation};
+use crate::objects::{ConnectableType, ObjectLayer};
+use crate::rule_editor::RuleEditor;
+use crate::selection::{is_valid_stamp_name, Clipboard};
+use crate::structures::{Stamps, StructureLayer};
+use crate::tools::{BrushShape, Tool, ToolState, MAX_BRUSH_RADIUS};
+use crate::wfc::FillSelectionEvent;
+use crate::{
+    get_matching_sprite, GameState, GroundLayer, Rule, Rules, Slot, SpriteRegistry, SpriteType,
+    TERRAIN_TYPES,
+};
+use bevy::prelude::*;
+use bevy_inspector_egui::bevy_egui::EguiContext;
+use bevy_inspector_egui::egui;
+
+// The number keys select palette entries in order.
+pub const PALETTE_KEYS: [KeyCode; 9] = [
+    KeyCode::Key1,
+    KeyCode::Key2,
+    KeyCode::Key3,
+    KeyCode::Key4,
+    KeyCode::Key5,
+    KeyCode::Key6,
+    KeyCode::Key7,
+    KeyCode::Key8,
+    KeyCode::Key9,
+];
+
+const PREVIEW_SIZE: f32 = 32.0;
+
+// === Structs ===
+pub struct PaletteEntry {
+    pub sprite_type: SpriteType,
+    pub name: String,
+    // The sprite a tile surrounded by more of the same terrain resolves to, `None` for objects,
+    // structures and terrains without rules.
+    pub preview_sprite: Option<String>,
+}
+
+// === Resources ===
+// Everything that can be selected for placing, in the order it is shown and bound to keys.
+pub struct Palette {
+    pub entries: Vec<PaletteEntry>,
+}
+
+// === Startup Systems ===
+// Runs once the rules and stamps have been set up, so imported terrains get their own previews.
+pub fn setup_palette(mut commands: Commands, rules: Res<Rules>, stamps: Res<Stamps>) {
+    let mut entries = vec![PaletteEntry {
+        sprite_type: SpriteType::Blank,
+        name: "Erase".to_string(),
+        preview_sprite: None,
+    }];
+    for sprite_type in TERRAIN_TYPES {
+        entries.push(PaletteEntry {
+            sprite_type,
+            name: format!("{:?}", sprite_type),
+            preview_sprite: get_preview_sprite(&rules, sprite_type),
+        });
+    }
+    // Listed top to bottom, so fences keep 5 and paths 6 like before the palette.
+    for connectable_type in ConnectableType::ALL.into_iter().rev() {
+        entries.push(PaletteEntry {
+            sprite_type: connectable_type.to_sprite_type(),
+            name: format!("{:?}", connectable_type),
+            preview_sprite: None,
+        });
+    }
+    let mut stamp_types: Vec<_> = stamps.stamps.keys().copied().collect();
+    stamp_types.sort_by_key(|stamp_type| format!("{:?}", stamp_type));
+    for stamp_type in stamp_types {
+        entries.push(PaletteEntry {
+            sprite_type: stamp_type.to_sprite_type(),
+            name: format!("{:?}", stamp_type),
+            preview_sprite: None,
+        });
+    }
+    commands.insert_resource(Palette { entries });
+}
+
+// === Systems ===
+pub fn update_editor_panel(
+    mut egui_context: ResMut<EguiContext>,
+    mut generate_terrain_event_writer: EventWriter<GenerateTerrainEvent>,
+    mut fill_selection_event_writer: EventWriter<FillSelectionEvent>,
+    mut apply_terrain_operation_event_writer: EventWriter<ApplyTerrainOperationEvent>,
+    mut legalize_event_writer: EventWriter<LegalizeEvent>,
+    mut game_state: ResMut<GameState>,
+    mut tool_state: ResMut<ToolState>,
+    mut rule_editor: ResMut<RuleEditor>,
+    mut debug_overlay: ResMut<DebugOverlay>,
+    mut generator: ResMut<Generator>,
+    mut morphology: ResMut<Morphology>,
+    mut legalizer: ResMut<Legalizer>,
+    mut clipboard: ResMut<Clipboard>,
+    sprite_registry: Res<SpriteRegistry>,
+    palette: Res<Palette>,
+    mut layers_query: Query<
+        (&mut Visibility, Option<&GroundLayer>, Option<&ObjectLayer>),
+        Or<(With<GroundLayer>, With<ObjectLayer>, With<StructureLayer>)>,
+    >,
+) {
+    let atlas_texture = egui_context.add_image(sprite_registry.texture.clone_weak());
+    let mut selection = game_state.selection;
+    let mut tool = tool_state.tool;
+    let mut brush_radius = tool_state.brush_radius;
+    let mut brush_shape = tool_state.brush_shape;
+    let mut filled = tool_state.filled;
+    let mut is_rule_editor_open = rule_editor.is_open;
+    let mut is_debug_overlay_visible = debug_overlay.is_visible;
+    let mut generator_options = generator.options;
+    let mut fill_border = generator.fill_border;
+    let mut is_generate_clicked = false;
+    let mut is_fill_selection_clicked = false;
+    let mut island_size = morphology.island_size;
+    let mut terrain_operation = None;
+    let mut is_legalizer_enabled = legalizer.is_enabled;
+    let mut is_legalize_clicked = false;
+    let mut stamp_name = clipboard.stamp.as_ref().map(|stamp| stamp.name.clone());
+    // Layers are listed from the ground up.
+    let mut layers = Vec::new();
+    for (visibility, ground_layer, object_layer) in layers_query.iter_mut() {
+        let (name, order) = match (ground_layer, object_layer) {
+            (Some(_), _) => ("Ground".to_string(), 0),
+            (_, Some(object_layer)) => {
+                let connectable_type = object_layer.connectable_type;
+                let order = ConnectableType::ALL
+                    .iter()
+                    .position(|other| *other == connectable_type)
+                    .unwrap_or_default();
+                (format!("{:?}", connectable_type), 1 + order)
+            }
+            _ => ("Structures".to_string(), 1 + ConnectableType::ALL.len()),
+        };
+        layers.push((name, order, visibility));
+    }
+    layers.sort_by_key(|(_, order, _)| *order);
+
+    egui::SidePanel::right("editor_panel").show(egui_context.ctx_mut(), |ui| {
+        ui.heading("Palette");
+        for (index, entry) in palette.entries.iter().enumerate() {
+            let is_selected = selection == entry.sprite_type;
+            ui.horizontal(|ui| {
+                let preview = entry
+                    .preview_sprite
+                    .as_ref()
+                    .and_then(|sprite_name| get_atlas_uv(&sprite_registry, sprite_name));
+                let preview_clicked = match preview {
+                    Some(uv) => ui
+                        .add(
+                            egui::ImageButton::new(atlas_texture, [PREVIEW_SIZE, PREVIEW_SIZE])
+                                .uv(uv)
+                                .selected(is_selected),
+                        )
+                        .clicked(),
+                    None => {
+                        ui.add_space(PREVIEW_SIZE + ui.spacing().button_padding.x * 2.0);
+                        false
+                    }
+                };
+                let label = match PALETTE_KEYS.get(index) {
+                    Some(_) => format!("{} {}", index + 1, entry.name),
+                    None => entry.name.clone(),
+                };
+                if ui.selectable_label(is_selected, label).clicked() || preview_clicked {
+                    selection = entry.sprite_type;
+                }
+            });
+        }
+
+        ui.separator();
+        ui.heading("Tool");
+        for option in Tool::ALL {
+            ui.radio_value(&mut tool, option, format!("{:?}", option));
+        }
+        ui.add(egui::Slider::new(&mut brush_radius, 0..=MAX_BRUSH_RADIUS).text("Brush radius"));
+        ui.horizontal(|ui| {
+            ui.radio_value(&mut brush_shape, BrushShape::Square, "Square");
+            ui.radio_value(&mut brush_shape, BrushShape::Round, "Round");
+        });
+        ui.checkbox(&mut filled, "Filled shapes");
+
+        ui.separator();
+        ui.heading("Layers");
+        for (name, _, visibility) in &mut layers {
+            let mut is_visible = visibility.is_visible;
+            if ui.checkbox(&mut is_visible, name.as_str()).changed() {
+                visibility.is_visible = is_visible;
+            }
+        }
+
+        ui.separator();
+        ui.heading("Generate");
+        ui.horizontal(|ui| {
+            ui.label("Seed");
+            ui.add(egui::DragValue::new(&mut generator_options.seed));
+        });
+        ui.checkbox(&mut generator_options.island, "Island");
+        // Filling the selection can keep a ring of one terrain around what it collapses.
+        egui::ComboBox::from_label("Fill border")
+            .selected_text(match fill_border {
+                Some(sprite_type) => format!("{:?}", sprite_type),
+                None => "None".to_string(),
+            })
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut fill_border, None, "None");
+                for terrain in TERRAIN_TYPES {
+                    ui.selectable_value(&mut fill_border, Some(terrain), format!("{:?}", terrain));
+                }
+            });
+        ui.horizontal(|ui| {
+            is_generate_clicked = ui.button("Generate").clicked();
+            is_fill_selection_clicked = ui.button("Fill selection").clicked();
+        });
+
+        ui.separator();
+        ui.heading("Clean up");
+        ui.label("On the selection, or the whole map");
+        ui.horizontal_wrapped(|ui| {
+            if ui.button("Smooth").clicked() {
+                terrain_operation = Some(TerrainOperation::Smooth);
+            }
+            if ui.button("Erode").clicked() {
+                terrain_operation = Some(TerrainOperation::Erode);
+            }
+            if ui.button("Dilate").clicked() {
+                terrain_operation = Some(TerrainOperation::Dilate);
+            }
+            if ui.button("Open").clicked() {
+                terrain_operation = Some(TerrainOperation::Open);
+            }
+            if ui.button("Close").clicked() {
+                terrain_operation = Some(TerrainOperation::Close);
+            }
+        });
+        ui.horizontal(|ui| {
+            if ui.button("Remove islands under").clicked() {
+                terrain_operation = Some(TerrainOperation::RemoveIslands {
+                    min_size: island_size,
+                });
+            }
+            ui.add(egui::DragValue::new(&mut island_size).clamp_range(1..=256));
+        });
+        ui.horizontal(|ui| {
+            is_legalize_clicked = ui.button("Legalize").clicked();
+            ui.checkbox(&mut is_legalizer_enabled, "Legalize strokes");
+        });
+
+        // Only shown once something was copied or loaded, as there is no stamp to name before.
+        if let Some(stamp_name) = &mut stamp_name {
+            ui.separator();
+            ui.heading("Stamp");
+            ui.horizontal(|ui| {
+                ui.label("Name");
+                ui.add(egui::TextEdit::singleline(stamp_name).hint_text("stamp_001"));
+            });
+            if is_valid_stamp_name(stamp_name) {
+                ui.label("F6 saves it, F7 loads the next saved one");
+            } else {
+                ui.colored_label(egui::Color32::RED, "Only letters, digits, spaces, - and _");
+            }
+        }
+
+        ui.separator();
+        ui.checkbox(&mut is_rule_editor_open, "Rule editor");
+        ui.checkbox(&mut is_debug_overlay_visible, "Debug overlay");
+    });
+
+    // Only write back what changed, so change detection keeps working for the other systems.
+    if selection != game_state.selection {
+        game_state.selection = selection;
+    }
+    // Switching tools halfway through a stroke would paint a shape the stroke was not drawn for.
+    if tool != tool_state.tool && tool_state.stroke_start.is_none() {
+        tool_state.tool = tool;
+    }
+    if brush_radius != tool_state.brush_radius {
+        tool_state.brush_radius = brush_radius;
+    }
+    if brush_shape != tool_state.brush_shape {
+        tool_state.brush_shape = brush_shape;
+    }
+    if filled != tool_state.filled {
+        tool_state.filled = filled;
+    }
+    if is_rule_editor_open != rule_editor.is_open {
+        rule_editor.is_open = is_rule_editor_open;
+    }
+    if is_debug_overlay_visible != debug_overlay.is_visible {
+        debug_overlay.is_visible = is_debug_overlay_visible;
+    }
+    if generator_options != generator.options {
+        generator.options = generator_options;
+    }
+    if fill_border != generator.fill_border {
+        generator.fill_border = fill_border;
+    }
+    if is_generate_clicked {
+        generate_terrain_event_writer.send(GenerateTerrainEvent { is_startup: false });
+    }
+    if island_size != morphology.island_size {
+        morphology.island_size = island_size;
+    }
+    if let Some(operation) = terrain_operation {
+        apply_terrain_operation_event_writer.send(ApplyTerrainOperationEvent { operation });
+    }
+    if is_legalizer_enabled != legalizer.is_enabled {
+        legalizer.is_enabled = is_legalizer_enabled;
+        println!("Legalizer Updated: {:?}", legalizer.is_enabled);
+    }
+    if is_legalize_clicked {
+        legalize_event_writer.send(LegalizeEvent {});
+    }
+    if let Some(stamp_name) = stamp_name {
+        let is_renamed = clipboard
+            .stamp
+            .as_ref()
+            .map_or(false, |stamp| stamp.name != stamp_name);
+        if is_renamed {
+            if let Some(stamp) = &mut clipboard.stamp {
+                stamp.name = stamp_name;
+            }
+        }
+    }
+    if is_fill_selection_clicked {
+        fill_selection_event_writer.send(FillSelectionEvent {
+            keep_painted: false,
+        });
+    }
+}
+
+// === Helper Functions ===
+// The sprite picked for a tile of `sprite_type` with the same terrain all around it.
+pub fn get_preview_sprite(rules: &Rules, sprite_type: SpriteType) -> Option<String> {
+    let filled = Slot::Filled { sprite_type };
+    let active_rule = Rule {
+        nw_slot: filled,
+        n_slot: filled,
+        ne_slot: filled,
+        w_slot: filled,
+        c_slot: filled,
+        e_slot: filled,
+        sw_slot: filled,
+        s_slot: filled,
+        se_slot: filled,
+    };
+    get_matching_sprite(&active_rule, rules.rules.get(&sprite_type)?).map(str::to_string)
+}
+
+// The part of the atlas texture showing the sprite, in the 0 to 1 range egui expects.
+pub fn get_atlas_uv(sprite_registry: &SpriteRegistry, sprite_name: &str) -> Option<egui::Rect> {
+    let atlas_index = sprite_registry.get_atlas_index(sprite_name)?;
+    let column = (atlas_index % sprite_registry.atlas_columns) as f32;
+    let row = (atlas_index / sprite_registry.atlas_columns) as f32;
+    let columns = sprite_registry.atlas_columns as f32;
+    let rows = sprite_registry.atlas_rows as f32;
+    Some(egui::Rect::from_min_max(
+        egui::pos2(column / columns, row / rows),
+        egui::pos2((column + 1.0) / columns, (row + 1.0) / rows),
+    ))
+}
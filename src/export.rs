@@ -0,0 +1,525 @@
+use crate::atlas::get_asset_root;
+use crate::objects::{ConnectableType, ObjectLayer};
+use crate::structures::{StructureLayer, STRUCTURE_TEXTURE_PATH};
+use crate::{
+    is_typing, DirtTile, GrassTile, GroundLayer, SpriteRegistry, SpriteType, WaterTile,
+    TERRAIN_TYPES, TILE_SIZE,
+};
+use bevy::prelude::*;
+use bevy_ecs_tilemap::prelude::*;
+use bevy_inspector_egui::bevy_egui::EguiContext;
+use image::{Rgba, RgbaImage};
+use std::fmt;
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+
+pub const EXPORT_DIRECTORY: &str = "exports";
+pub const EXPORT_MAP_FILE_NAME: &str = "map.tmx";
+
+// How a tile is flipped, stored by Tiled in the upper bits of its gid.
+const GID_FLIPPED_HORIZONTALLY: u32 = 0x8000_0000;
+const GID_FLIPPED_VERTICALLY: u32 = 0x4000_0000;
+const GID_FLIPPED_DIAGONALLY: u32 = 0x2000_0000;
+
+// === Enums ===
+#[derive(Debug)]
+pub enum ExportError {
+    Io {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+    Image {
+        path: PathBuf,
+        error: image::ImageError,
+    },
+}
+
+impl fmt::Display for ExportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExportError::Io { path, error } => write!(f, "could not write {:?}: {}", path, error),
+            ExportError::Image { path, error } => {
+                write!(f, "could not read or write {:?}: {}", path, error)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ExportError {}
+
+// === Structs ===
+pub struct MapExport {
+    pub width: u32,
+    pub height: u32,
+    pub tilesets: Vec<TilesetExport>,
+    // Ordered bottom to top.
+    pub layers: Vec<LayerExport>,
+}
+
+pub struct TilesetExport {
+    // Also the file name of the tileset and of its image.
+    pub name: String,
+    pub image: RgbaImage,
+    // Tiles holding a `terrain` property.
+    pub tile_terrain: Vec<(u32, SpriteType)>,
+}
+
+pub struct LayerExport {
+    pub name: String,
+    pub tileset_index: usize,
+    // Tile indices into the tileset along with how they are flipped, row by row starting from the
+    // top-left corner of the map.
+    pub tiles: Vec<Option<(u32, TileFlip)>>,
+    // The terrain layer is hidden and marked with a `terrain` property.
+    pub is_terrain: bool,
+}
+
+// === Systems ===
+// Ctrl+E writes the map as it is drawn, along with its terrain, to `EXPORT_DIRECTORY`.
+pub fn export_map(
+    keyboard: Res<Input<KeyCode>>,
+    mut egui_context: ResMut<EguiContext>,
+    images: Res<Assets<Image>>,
+    sprite_registry: Res<SpriteRegistry>,
+    ground_layer_query: Query<(&TilemapSize, &TileStorage), With<GroundLayer>>,
+    object_layers_query: Query<(&ObjectLayer, &TileStorage)>,
+    structure_layer_query: Query<&TileStorage, With<StructureLayer>>,
+    tiles_query: Query<(&TileTexture, &TileFlip, &TileVisible)>,
+    terrain_query: Query<(Option<&GrassTile>, Option<&DirtTile>, Option<&WaterTile>)>,
+) {
+    let is_control_pressed =
+        keyboard.pressed(KeyCode::LControl) || keyboard.pressed(KeyCode::RControl);
+    if !is_control_pressed || !keyboard.just_pressed(KeyCode::E) || is_typing(&mut egui_context) {
+        return;
+    }
+    let (map_size, ground_storage) = match ground_layer_query.get_single() {
+        Ok(ground_layer) => ground_layer,
+        Err(_) => return,
+    };
+    let atlas_image = match images.get(&sprite_registry.texture) {
+        Some(atlas_image) => atlas_image,
+        None => return,
+    };
+
+    // Rows are written from the top, tile positions count them from the bottom.
+    let get_tiles = |tile_storage: &TileStorage,
+                     get_tile: &dyn Fn(Entity) -> Option<(u32, TileFlip)>| {
+        let mut tiles = Vec::new();
+        for y in (0..map_size.y).rev() {
+            for x in 0..map_size.x {
+                let tile = tile_storage.get(&TilePos { x, y }).and_then(get_tile);
+                tiles.push(tile);
+            }
+        }
+        tiles
+    };
+    let get_visible_texture = |tile_entity: Entity| match tiles_query.get(tile_entity) {
+        Ok((tile_texture, tile_flip, tile_visible)) if tile_visible.0 => {
+            Some((tile_texture.0, *tile_flip))
+        }
+        _ => None,
+    };
+
+    let mut tilesets = Vec::new();
+    let mut layers = Vec::new();
+
+    tilesets.push(TilesetExport {
+        name: "terrain".to_string(),
+        image: get_terrain_image(),
+        tile_terrain: (0..).zip(TERRAIN_TYPES).collect(),
+    });
+    layers.push(LayerExport {
+        name: "Terrain".to_string(),
+        tileset_index: 0,
+        tiles: get_tiles(ground_storage, &|tile_entity| {
+            let sprite_type = match terrain_query.get(tile_entity) {
+                Ok((Some(_), _, _)) => SpriteType::Grass,
+                Ok((_, Some(_), _)) => SpriteType::Dirt,
+                Ok((_, _, Some(_))) => SpriteType::Water,
+                _ => return None,
+            };
+            TERRAIN_TYPES
+                .iter()
+                .position(|terrain_type| *terrain_type == sprite_type)
+                .map(|tile_id| (tile_id as u32, TileFlip::default()))
+        }),
+        is_terrain: true,
+    });
+
+    let atlas_size = atlas_image.texture_descriptor.size;
+    let atlas = match RgbaImage::from_raw(
+        atlas_size.width,
+        atlas_size.height,
+        atlas_image.data.clone(),
+    ) {
+        Some(atlas) => atlas,
+        None => return,
+    };
+    let blank_index = sprite_registry.get_blank_index();
+    tilesets.push(TilesetExport {
+        name: "atlas".to_string(),
+        image: atlas,
+        tile_terrain: Vec::new(),
+    });
+    layers.push(LayerExport {
+        name: "Ground".to_string(),
+        tileset_index: tilesets.len() - 1,
+        tiles: get_tiles(ground_storage, &|tile_entity| {
+            get_visible_texture(tile_entity).filter(|(atlas_index, _)| *atlas_index != blank_index)
+        }),
+        is_terrain: false,
+    });
+
+    let mut object_layers: Vec<(&ObjectLayer, &TileStorage)> = object_layers_query.iter().collect();
+    object_layers.sort_by_key(|(object_layer, _)| {
+        ConnectableType::ALL
+            .iter()
+            .position(|connectable_type| *connectable_type == object_layer.connectable_type)
+    });
+    let mut other_layers: Vec<(String, &str, &TileStorage)> = object_layers
+        .into_iter()
+        .map(|(object_layer, tile_storage)| {
+            (
+                format!("{:?}", object_layer.connectable_type),
+                object_layer.connectable_type.texture_path(),
+                tile_storage,
+            )
+        })
+        .collect();
+    if let Ok(structure_storage) = structure_layer_query.get_single() {
+        other_layers.push((
+            "Structures".to_string(),
+            STRUCTURE_TEXTURE_PATH,
+            structure_storage,
+        ));
+    }
+    let asset_root = get_asset_root();
+    for (name, texture_path, tile_storage) in other_layers {
+        let path = asset_root.join(texture_path);
+        let image = match image::open(&path) {
+            Ok(image) => image.into_rgba8(),
+            Err(error) => {
+                println!(
+                    "Failed to export the map: {}",
+                    ExportError::Image { path, error }
+                );
+                return;
+            }
+        };
+        tilesets.push(TilesetExport {
+            name: name.to_lowercase(),
+            image,
+            tile_terrain: Vec::new(),
+        });
+        layers.push(LayerExport {
+            name,
+            tileset_index: tilesets.len() - 1,
+            tiles: get_tiles(tile_storage, &get_visible_texture),
+            is_terrain: false,
+        });
+    }
+
+    let map_export = MapExport {
+        width: map_size.x,
+        height: map_size.y,
+        tilesets,
+        layers,
+    };
+    match write_map_export(Path::new(EXPORT_DIRECTORY), &map_export) {
+        Ok(path) => println!("Exported the map to {:?}", path),
+        Err(error) => println!("Failed to export the map: {}", error),
+    }
+}
+
+// === Helper Functions ===
+// Writes the map, its tilesets and their images to `directory`, and returns the path of the map.
+pub fn write_map_export(directory: &Path, map_export: &MapExport) -> Result<PathBuf, ExportError> {
+    std::fs::create_dir_all(directory).map_err(|error| ExportError::Io {
+        path: directory.to_path_buf(),
+        error,
+    })?;
+
+    let mut first_gids = Vec::new();
+    let mut next_gid = 1;
+    for tileset in &map_export.tilesets {
+        let image_path = directory.join(format!("{}.png", tileset.name));
+        tileset
+            .image
+            .save(&image_path)
+            .map_err(|error| ExportError::Image {
+                path: image_path,
+                error,
+            })?;
+        write_file(
+            &directory.join(format!("{}.tsx", tileset.name)),
+            &get_tsx(tileset),
+        )?;
+        first_gids.push(next_gid);
+        next_gid += get_tile_count(tileset);
+    }
+
+    let map_path = directory.join(EXPORT_MAP_FILE_NAME);
+    write_file(&map_path, &get_tmx(map_export, &first_gids))?;
+    Ok(map_path)
+}
+
+fn get_tsx(tileset: &TilesetExport) -> String {
+    let tile_size = TILE_SIZE as u32;
+    let mut tsx = String::new();
+    let _ = writeln!(tsx, r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+    let _ = writeln!(
+        tsx,
+        r#"<tileset version="1.9" name="{}" tilewidth="{}" tileheight="{}" tilecount="{}" columns="{}">"#,
+        tileset.name,
+        tile_size,
+        tile_size,
+        get_tile_count(tileset),
+        tileset.image.width() / tile_size
+    );
+    let _ = writeln!(
+        tsx,
+        r#" <image source="{}.png" width="{}" height="{}"/>"#,
+        tileset.name,
+        tileset.image.width(),
+        tileset.image.height()
+    );
+    for (tile_id, sprite_type) in &tileset.tile_terrain {
+        let terrain_name = format!("{:?}", sprite_type).to_lowercase();
+        let _ = writeln!(tsx, r#" <tile id="{}">"#, tile_id);
+        let _ = writeln!(tsx, "  <properties>");
+        let _ = writeln!(
+            tsx,
+            r#"   <property name="terrain" value="{}"/>"#,
+            terrain_name
+        );
+        let _ = writeln!(tsx, "  </properties>");
+        let _ = writeln!(tsx, " </tile>");
+    }
+    let _ = writeln!(tsx, "</tileset>");
+    tsx
+}
+
+fn get_tmx(map_export: &MapExport, first_gids: &[u32]) -> String {
+    let tile_size = TILE_SIZE as u32;
+    let mut tmx = String::new();
+    let _ = writeln!(tmx, r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+    let _ = writeln!(
+        tmx,
+        r#"<map version="1.9" orientation="orthogonal" renderorder="right-down" width="{}" height="{}" tilewidth="{}" tileheight="{}" infinite="0" nextlayerid="{}" nextobjectid="1">"#,
+        map_export.width,
+        map_export.height,
+        tile_size,
+        tile_size,
+        map_export.layers.len() + 1
+    );
+    for (tileset, first_gid) in map_export.tilesets.iter().zip(first_gids) {
+        let _ = writeln!(
+            tmx,
+            r#" <tileset firstgid="{}" source="{}.tsx"/>"#,
+            first_gid, tileset.name
+        );
+    }
+    for (layer_id, layer) in map_export.layers.iter().enumerate() {
+        let _ = writeln!(
+            tmx,
+            r#" <layer id="{}" name="{}" width="{}" height="{}"{}>"#,
+            layer_id + 1,
+            layer.name,
+            map_export.width,
+            map_export.height,
+            if layer.is_terrain {
+                r#" visible="0""#
+            } else {
+                ""
+            }
+        );
+        if layer.is_terrain {
+            let _ = writeln!(tmx, "  <properties>");
+            let _ = writeln!(
+                tmx,
+                r#"   <property name="terrain" type="bool" value="true"/>"#
+            );
+            let _ = writeln!(tmx, "  </properties>");
+        }
+        let first_gid = first_gids[layer.tileset_index];
+        let rows: Vec<String> = layer
+            .tiles
+            .chunks(map_export.width as usize)
+            .map(|row| {
+                row.iter()
+                    .map(|tile| {
+                        tile.map_or(0, |(tile_id, tile_flip)| {
+                            (first_gid + tile_id) | get_gid_flags(&tile_flip)
+                        })
+                        .to_string()
+                    })
+                    .collect::<Vec<String>>()
+                    .join(",")
+            })
+            .collect();
+        let _ = writeln!(tmx, r#"  <data encoding="csv">"#);
+        let _ = writeln!(tmx, "{}", rows.join(",\n"));
+        let _ = writeln!(tmx, "</data>");
+        let _ = writeln!(tmx, " </layer>");
+    }
+    let _ = writeln!(tmx, "</map>");
+    tmx
+}
+
+fn get_gid_flags(tile_flip: &TileFlip) -> u32 {
+    let mut flags = 0;
+    if tile_flip.x {
+        flags |= GID_FLIPPED_HORIZONTALLY;
+    }
+    if tile_flip.y {
+        flags |= GID_FLIPPED_VERTICALLY;
+    }
+    if tile_flip.d {
+        flags |= GID_FLIPPED_DIAGONALLY;
+    }
+    flags
+}
+
+fn get_tile_count(tileset: &TilesetExport) -> u32 {
+    let tile_size = TILE_SIZE as u32;
+    (tileset.image.width() / tile_size) * (tileset.image.height() / tile_size)
+}
+
+// One flat tile per terrain, so the terrain layer can be looked at in Tiled.
+fn get_terrain_image() -> RgbaImage {
+    let tile_size = TILE_SIZE as u32;
+    let colors = [
+        Rgba([96, 168, 72, 255]),
+        Rgba([164, 116, 72, 255]),
+        Rgba([80, 144, 200, 255]),
+    ];
+    RgbaImage::from_fn(tile_size * colors.len() as u32, tile_size, |x, _| {
+        colors[(x / tile_size) as usize]
+    })
+}
+
+fn write_file(path: &Path, contents: &str) -> Result<(), ExportError> {
+    std::fs::write(path, contents).map_err(|error| ExportError::Io {
+        path: path.to_path_buf(),
+        error,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tiled::load_tiled_map;
+
+    // A 2x2 map with its terrain, and atlas sprites flipped every way Tiled stores.
+    fn get_map_export() -> MapExport {
+        let tile_size = TILE_SIZE as u32;
+        let flipped_x = TileFlip {
+            x: true,
+            ..default()
+        };
+        let flipped_y_d = TileFlip {
+            y: true,
+            d: true,
+            ..default()
+        };
+        MapExport {
+            width: 2,
+            height: 2,
+            tilesets: vec![
+                TilesetExport {
+                    name: "terrain".to_string(),
+                    image: get_terrain_image(),
+                    tile_terrain: (0..).zip(TERRAIN_TYPES).collect(),
+                },
+                TilesetExport {
+                    name: "atlas".to_string(),
+                    image: RgbaImage::new(tile_size * 4, tile_size),
+                    tile_terrain: Vec::new(),
+                },
+            ],
+            layers: vec![
+                LayerExport {
+                    name: "Terrain".to_string(),
+                    tileset_index: 0,
+                    tiles: vec![
+                        Some((0, TileFlip::default())),
+                        Some((1, TileFlip::default())),
+                        Some((2, TileFlip::default())),
+                        None,
+                    ],
+                    is_terrain: true,
+                },
+                LayerExport {
+                    name: "Ground".to_string(),
+                    tileset_index: 1,
+                    tiles: vec![
+                        Some((3, flipped_x)),
+                        Some((0, flipped_y_d)),
+                        None,
+                        Some((1, TileFlip::default())),
+                    ],
+                    is_terrain: false,
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn exported_maps_import_with_their_gids_flips_and_terrain() {
+        let directory =
+            std::env::temp_dir().join(format!("autotile_export_{}", std::process::id()));
+        let map_path = write_map_export(&directory, &get_map_export()).unwrap();
+        let tiled_map = load_tiled_map(&map_path).unwrap();
+        assert_eq!((tiled_map.width, tiled_map.height), (2, 2));
+
+        // The atlas starts right after the 3 terrain tiles.
+        let first_gids: Vec<u32> = tiled_map
+            .tilesets
+            .iter()
+            .map(|(first_gid, _)| *first_gid)
+            .collect();
+        assert_eq!(first_gids, vec![1, 4]);
+        assert_eq!(tiled_map.tilesets[1].1.name, "atlas");
+        assert_eq!(tiled_map.tilesets[1].1.columns, 4);
+
+        assert_eq!(tiled_map.layers[0].gids, vec![1, 2, 3, 0]);
+        assert_eq!(
+            tiled_map.layers[1].gids,
+            vec![
+                7 | GID_FLIPPED_HORIZONTALLY,
+                4 | GID_FLIPPED_VERTICALLY | GID_FLIPPED_DIAGONALLY,
+                0,
+                5,
+            ]
+        );
+        // Only the terrain layer has terrain, the atlas sprites leave it alone.
+        assert_eq!(
+            tiled_map.get_terrain(),
+            vec![
+                Some(SpriteType::Grass),
+                Some(SpriteType::Dirt),
+                Some(SpriteType::Water),
+                None,
+            ]
+        );
+
+        // The importer does not read how layers are shown, so the map is checked as XML.
+        let contents = std::fs::read_to_string(&map_path).unwrap();
+        let document = roxmltree::Document::parse(&contents).unwrap();
+        let layer_nodes: Vec<roxmltree::Node> = document
+            .root_element()
+            .children()
+            .filter(|node| node.has_tag_name("layer"))
+            .collect();
+        assert_eq!(layer_nodes[0].attribute("name"), Some("Terrain"));
+        assert_eq!(layer_nodes[0].attribute("visible"), Some("0"));
+        let terrain_property = layer_nodes[0]
+            .descendants()
+            .find(|node| node.has_tag_name("property"))
+            .unwrap();
+        assert_eq!(terrain_property.attribute("name"), Some("terrain"));
+        assert_eq!(terrain_property.attribute("value"), Some("true"));
+        assert_eq!(layer_nodes[1].attribute("visible"), None);
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,264 @@
+use crate::history::{set_tile_terrain, EditHistory};
+use crate::ldtk::LdtkImport;
+use crate::terrain::TerrainGrid;
+use crate::tiled::TiledImport;
+use crate::{
+    get_tile_terrain, DirtTile, GrassTile, GroundLayer, SpriteRegistry, SpriteType,
+    UpdateTilemapEvent, WaterTile,
+};
+use bevy::prelude::*;
+use bevy_ecs_tilemap::prelude::*;
+
+// === Events ===
+// Replaces the whole ground with terrain generated from the options in `Generator`.
+pub struct GenerateTerrainEvent {
+    // The map generated at startup is where the history begins, rather than a stroke to undo.
+    pub is_startup: bool,
+}
+
+// === Structs ===
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GeneratorOptions {
+    pub seed: u32,
+    // The size in tiles of the largest hills and lakes.
+    pub feature_size: f32,
+    // Each octave adds detail at `lacunarity` times the frequency and `persistence` times the
+    // strength of the one before.
+    pub octaves: u32,
+    pub persistence: f32,
+    pub lacunarity: f32,
+    // Heights go from 0 to 1. Water is below `water_level`, dirt below `dirt_level` and grass above.
+    pub water_level: f32,
+    pub dirt_level: f32,
+    // Lowers the edges of the map, so the land ends up surrounded by water.
+    pub island: bool,
+}
+
+impl Default for GeneratorOptions {
+    fn default() -> GeneratorOptions {
+        GeneratorOptions {
+            seed: 0,
+            feature_size: 24.0,
+            octaves: 4,
+            persistence: 0.5,
+            lacunarity: 2.0,
+            water_level: 0.35,
+            dirt_level: 0.45,
+            island: false,
+        }
+    }
+}
+
+// === Resources ===
+pub struct Generator {
+    pub options: GeneratorOptions,
+    // Whether the editor starts with generated terrain rather than an empty map.
+    pub generate_on_startup: bool,
+    // The terrain filling the selection keeps along its edge, see `CollapseOptions::border`.
+    pub fill_border: Option<SpriteType>,
+}
+
+impl Generator {
+    // Reads `--seed <seed>`, `--island` and `--blank` from the command line.
+    pub fn from_args(mut args: impl Iterator<Item = String>) -> Generator {
+        let mut generator = Generator {
+            options: GeneratorOptions::default(),
+            generate_on_startup: true,
+            fill_border: None,
+        };
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--seed" => {
+                    if let Some(seed) = args.next().and_then(|seed| seed.parse().ok()) {
+                        generator.options.seed = seed;
+                    }
+                }
+                "--island" => generator.options.island = true,
+                "--blank" => generator.generate_on_startup = false,
+                _ => {}
+            }
+        }
+        generator
+    }
+}
+
+// === Startup Systems ===
+// Imported maps are loaded instead of generating one.
+pub fn apply_startup_generation(
+    mut generate_terrain_event_writer: EventWriter<GenerateTerrainEvent>,
+    generator: Res<Generator>,
+    tiled_import: Res<TiledImport>,
+    ldtk_import: Res<LdtkImport>,
+) {
+    if generator.generate_on_startup && tiled_import.map.is_none() && ldtk_import.terrain.is_none()
+    {
+        generate_terrain_event_writer.send(GenerateTerrainEvent { is_startup: true });
+    }
+}
+
+// === Systems ===
+// The whole ground is painted at once, then autotiled once. It is recorded as a single stroke, so
+// it can be undone, apart from the terrain generated at startup.
+pub fn apply_generated_terrain(
+    mut commands: Commands,
+    mut generate_terrain_event_reader: EventReader<GenerateTerrainEvent>,
+    mut update_tilemap_event_writer: EventWriter<UpdateTilemapEvent>,
+    mut edit_history: ResMut<EditHistory>,
+    generator: Res<Generator>,
+    sprite_registry: Res<SpriteRegistry>,
+    ground_layer_query: Query<(&TilemapSize, &TileStorage), With<GroundLayer>>,
+    terrain_query: Query<(Option<&GrassTile>, Option<&DirtTile>, Option<&WaterTile>)>,
+) {
+    let is_startup = match generate_terrain_event_reader.iter().last() {
+        Some(generate_terrain_event) => generate_terrain_event.is_startup,
+        None => return,
+    };
+    let (map_size, tile_storage) = match ground_layer_query.get_single() {
+        Ok(ground_layer) => ground_layer,
+        Err(_) => return,
+    };
+
+    let terrain_grid = generate_terrain(&generator.options, map_size.x, map_size.y);
+    edit_history.finish_stroke();
+    for y in 0..map_size.y {
+        for x in 0..map_size.x {
+            let tile_position = TilePos { x, y };
+            if let Some(tile_entity) = tile_storage.get(&tile_position) {
+                let sprite_type = terrain_grid.get(x as i32, y as i32);
+                let previous_sprite_type = get_tile_terrain(tile_entity, &terrain_query);
+                edit_history.record(tile_position, previous_sprite_type, sprite_type);
+                set_tile_terrain(&mut commands, tile_entity, sprite_type, &sprite_registry);
+            }
+        }
+    }
+    edit_history.finish_stroke();
+    if is_startup {
+        edit_history.clear();
+    }
+    update_tilemap_event_writer.send(UpdateTilemapEvent {});
+    println!("Terrain Generated: {:?}", generator.options);
+}
+
+// === Helper Functions ===
+// Generates the terrain of a `width` by `height` map. The same options always give the same map.
+pub fn generate_terrain(options: &GeneratorOptions, width: u32, height: u32) -> TerrainGrid {
+    let mut terrain_grid = TerrainGrid::new(width, height);
+    for y in 0..height {
+        for x in 0..width {
+            let mut terrain_height = get_fractal_noise(options, x as f32, y as f32);
+            if options.island {
+                // The distance from the center, 1 at the middle of each edge.
+                let offset = Vec2::new(
+                    (x as f32 + 0.5) / width as f32 * 2.0 - 1.0,
+                    (y as f32 + 0.5) / height as f32 * 2.0 - 1.0,
+                );
+                terrain_height *= (1.0 - offset.length_squared()).max(0.0);
+            }
+            let sprite_type = if terrain_height < options.water_level {
+                SpriteType::Water
+            } else if terrain_height < options.dirt_level {
+                SpriteType::Dirt
+            } else {
+                SpriteType::Grass
+            };
+            terrain_grid.set(x, y, Some(sprite_type));
+        }
+    }
+    terrain_grid
+}
+
+// Sums octaves of value noise, scaled back into the 0 to 1 range.
+pub fn get_fractal_noise(options: &GeneratorOptions, x: f32, y: f32) -> f32 {
+    let mut frequency = 1.0 / options.feature_size.max(1.0);
+    let mut amplitude = 1.0;
+    let mut total = 0.0;
+    let mut total_amplitude = 0.0;
+    for octave in 0..options.octaves.max(1) {
+        let seed = options.seed.wrapping_add(octave);
+        total += amplitude * get_value_noise(seed, x * frequency, y * frequency);
+        total_amplitude += amplitude;
+        frequency *= options.lacunarity;
+        amplitude *= options.persistence;
+    }
+    total / total_amplitude
+}
+
+// Smoothly blends the random values at the four corners of the unit square around the position.
+pub fn get_value_noise(seed: u32, x: f32, y: f32) -> f32 {
+    let x0 = x.floor();
+    let y0 = y.floor();
+    let smooth = |t: f32| t * t * (3.0 - 2.0 * t);
+    let tx = smooth(x - x0);
+    let ty = smooth(y - y0);
+    let (x0, y0) = (x0 as i32, y0 as i32);
+    let south = lerp(
+        get_lattice_value(seed, x0, y0),
+        get_lattice_value(seed, x0 + 1, y0),
+        tx,
+    );
+    let north = lerp(
+        get_lattice_value(seed, x0, y0 + 1),
+        get_lattice_value(seed, x0 + 1, y0 + 1),
+        tx,
+    );
+    lerp(south, north, ty)
+}
+
+// A random value from 0 to 1 for each whole position, hashed so no state has to be kept.
+pub fn get_lattice_value(seed: u32, x: i32, y: i32) -> f32 {
+    let mut hash = seed
+        .wrapping_mul(0x9E37_79B9)
+        .wrapping_add((x as u32).wrapping_mul(0x85EB_CA6B))
+        .wrapping_add((y as u32).wrapping_mul(0xC2B2_AE35));
+    hash ^= hash >> 15;
+    hash = hash.wrapping_mul(0x2C1B_3C6D);
+    hash ^= hash >> 12;
+    hash = hash.wrapping_mul(0x297A_2D39);
+    hash ^= hash >> 15;
+    (hash & 0x00FF_FFFF) as f32 / 0x00FF_FFFF as f32
+}
+
+fn lerp(start: f32, end: f32, t: f32) -> f32 {
+    start + (end - start) * t
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generate_terrain_is_deterministic_per_seed() {
+        let options = GeneratorOptions {
+            seed: 42,
+            ..default()
+        };
+        let terrain_grid = generate_terrain(&options, 32, 32);
+        assert_eq!(terrain_grid, generate_terrain(&options, 32, 32));
+
+        let other_options = GeneratorOptions {
+            seed: 43,
+            ..default()
+        };
+        assert_ne!(terrain_grid, generate_terrain(&other_options, 32, 32));
+    }
+
+    #[test]
+    fn island_mode_puts_water_along_the_border() {
+        for seed in 0..8 {
+            let options = GeneratorOptions {
+                seed,
+                island: true,
+                ..default()
+            };
+            let terrain_grid = generate_terrain(&options, 64, 48);
+            for x in 0..64 {
+                assert_eq!(terrain_grid.get(x, 0), Some(SpriteType::Water));
+                assert_eq!(terrain_grid.get(x, 47), Some(SpriteType::Water));
+            }
+            for y in 0..48 {
+                assert_eq!(terrain_grid.get(0, y), Some(SpriteType::Water));
+                assert_eq!(terrain_grid.get(63, y), Some(SpriteType::Water));
+            }
+        }
+    }
+}
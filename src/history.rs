@@ -0,0 +1,302 @@
+use crate::{
+    is_typing, DirtTile, GrassTile, GroundLayer, Mouse, SpriteRegistry, SpriteType,
+    UpdateTilesEvent, WaterTile,
+};
+use bevy::prelude::*;
+use bevy_ecs_tilemap::prelude::*;
+use bevy_inspector_egui::bevy_egui::EguiContext;
+use std::collections::{HashMap, VecDeque};
+
+// How much memory the undo history may hold before the oldest strokes are forgotten.
+pub const HISTORY_MEMORY_BUDGET: usize = 4 * 1024 * 1024;
+
+// === Structs ===
+// The terrain of a cell before and after a stroke.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CellEdit {
+    pub tile_position: TilePos,
+    pub before: Option<SpriteType>,
+    pub after: Option<SpriteType>,
+}
+
+// Every cell changed from mouse-down to mouse-up, undone and redone as one operation.
+#[derive(Clone, Debug, Default)]
+pub struct Stroke {
+    pub cell_edits: Vec<CellEdit>,
+}
+
+impl Stroke {
+    pub fn get_memory_size(&self) -> usize {
+        std::mem::size_of::<Stroke>() + self.cell_edits.capacity() * std::mem::size_of::<CellEdit>()
+    }
+}
+
+// === Resources ===
+#[derive(Default)]
+pub struct EditHistory {
+    undo_strokes: VecDeque<Stroke>,
+    redo_strokes: Vec<Stroke>,
+    // The stroke being painted, it joins the history once the mouse is released.
+    current_stroke: Option<Stroke>,
+    // Where each cell of the stroke being painted is in its edits, so strokes covering the whole
+    // map are recorded in linear time.
+    current_indices: HashMap<TilePos, usize>,
+    memory_size: usize,
+}
+
+impl EditHistory {
+    // Records that a cell was painted. Only the first `before` of a cell is kept within a stroke,
+    // so painting over the same cell twice still undoes to what it was before the stroke.
+    pub fn record(
+        &mut self,
+        tile_position: TilePos,
+        before: Option<SpriteType>,
+        after: Option<SpriteType>,
+    ) {
+        let stroke = self.current_stroke.get_or_insert_with(Stroke::default);
+        match self.current_indices.get(&tile_position).copied() {
+            Some(index) => stroke.cell_edits[index].after = after,
+            None => {
+                if before != after {
+                    self.current_indices
+                        .insert(tile_position, stroke.cell_edits.len());
+                    stroke.cell_edits.push(CellEdit {
+                        tile_position,
+                        before,
+                        after,
+                    });
+                }
+            }
+        }
+    }
+
+    // Moves the stroke being painted into the history. A new stroke discards whatever was undone.
+    pub fn finish_stroke(&mut self) {
+        let mut stroke = match self.current_stroke.take() {
+            Some(stroke) => stroke,
+            None => return,
+        };
+        self.current_indices.clear();
+        stroke
+            .cell_edits
+            .retain(|cell_edit| cell_edit.before != cell_edit.after);
+        if stroke.cell_edits.is_empty() {
+            return;
+        }
+        stroke.cell_edits.shrink_to_fit();
+        for redo_stroke in self.redo_strokes.drain(..) {
+            self.memory_size -= redo_stroke.get_memory_size();
+        }
+        self.memory_size += stroke.get_memory_size();
+        self.undo_strokes.push_back(stroke);
+        // The latest stroke is always kept, even when it is larger than the whole budget.
+        while self.memory_size > HISTORY_MEMORY_BUDGET && self.undo_strokes.len() > 1 {
+            if let Some(oldest_stroke) = self.undo_strokes.pop_front() {
+                self.memory_size -= oldest_stroke.get_memory_size();
+            }
+        }
+    }
+
+    pub fn undo(&mut self) -> Option<&Stroke> {
+        let stroke = self.undo_strokes.pop_back()?;
+        self.redo_strokes.push(stroke);
+        self.redo_strokes.last()
+    }
+
+    pub fn redo(&mut self) -> Option<&Stroke> {
+        let stroke = self.redo_strokes.pop()?;
+        self.undo_strokes.push_back(stroke);
+        self.undo_strokes.back()
+    }
+
+    pub fn is_painting(&self) -> bool {
+        self.current_stroke.is_some()
+    }
+
+    // The cells painted so far in the stroke being painted.
+    pub fn get_current_edits(&self) -> &[CellEdit] {
+        match &self.current_stroke {
+            Some(stroke) => &stroke.cell_edits,
+            None => &[],
+        }
+    }
+
+    // Forgets everything, for when the whole map is replaced.
+    pub fn clear(&mut self) {
+        *self = EditHistory::default();
+    }
+}
+
+// === Startup Systems ===
+pub fn setup_edit_history(mut commands: Commands) {
+    commands.insert_resource(EditHistory::default());
+}
+
+// === Systems ===
+// Closes the stroke once the mouse button is released.
+pub fn update_edit_history(mouse: Res<Mouse>, mut edit_history: ResMut<EditHistory>) {
+    if !mouse.holding_lmb {
+        edit_history.finish_stroke();
+    }
+}
+
+// Ctrl+Z undoes the last stroke, Ctrl+Shift+Z redoes it. Only the cells of the stroke and the ones
+// around them are autotiled again.
+pub fn undo_redo(
+    mut commands: Commands,
+    mut update_tiles_event_writer: EventWriter<UpdateTilesEvent>,
+    keyboard: Res<Input<KeyCode>>,
+    sprite_registry: Res<SpriteRegistry>,
+    mut edit_history: ResMut<EditHistory>,
+    mut egui_context: ResMut<EguiContext>,
+    ground_layer_query: Query<&TileStorage, With<GroundLayer>>,
+) {
+    if is_typing(&mut egui_context) {
+        return;
+    }
+    let is_control_pressed =
+        keyboard.pressed(KeyCode::LControl) || keyboard.pressed(KeyCode::RControl);
+    if !is_control_pressed || !keyboard.just_pressed(KeyCode::Z) {
+        return;
+    }
+    // Undoing halfway through a stroke would leave the history out of step with the map.
+    if edit_history.is_painting() {
+        return;
+    }
+    let ground_storage = match ground_layer_query.get_single() {
+        Ok(ground_storage) => ground_storage,
+        Err(_) => return,
+    };
+
+    let is_redo = keyboard.pressed(KeyCode::LShift) || keyboard.pressed(KeyCode::RShift);
+    let stroke = if is_redo {
+        edit_history.redo()
+    } else {
+        edit_history.undo()
+    };
+    let stroke = match stroke {
+        Some(stroke) => stroke,
+        None => return,
+    };
+    for cell_edit in &stroke.cell_edits {
+        let sprite_type = if is_redo {
+            cell_edit.after
+        } else {
+            cell_edit.before
+        };
+        if let Some(tile_entity) = ground_storage.get(&cell_edit.tile_position) {
+            set_tile_terrain(&mut commands, tile_entity, sprite_type, &sprite_registry);
+        }
+    }
+    update_tiles_event_writer.send(UpdateTilesEvent {
+        tile_positions: stroke
+            .cell_edits
+            .iter()
+            .map(|cell_edit| cell_edit.tile_position)
+            .collect(),
+    });
+}
+
+// === Helper Functions ===
+// Only the changed cells are touched, the event sent along decides which tiles are autotiled again.
+// Erased cells are reset to blank, as autotiling leaves tiles without terrain as they were.
+pub fn set_tile_terrain(
+    commands: &mut Commands,
+    tile_entity: Entity,
+    sprite_type: Option<SpriteType>,
+    sprite_registry: &SpriteRegistry,
+) {
+    let mut tile = commands.entity(tile_entity);
+    tile.remove::<GrassTile>()
+        .remove::<DirtTile>()
+        .remove::<WaterTile>();
+    match sprite_type {
+        Some(SpriteType::Grass) => {
+            tile.insert(GrassTile {});
+        }
+        Some(SpriteType::Dirt) => {
+            tile.insert(DirtTile {});
+        }
+        Some(SpriteType::Water) => {
+            tile.insert(WaterTile {});
+        }
+        _ => {
+            tile.insert(TileTexture(sprite_registry.get_blank_index()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record_stroke(edit_history: &mut EditHistory, cell_count: u32, after: SpriteType) {
+        for index in 0..cell_count {
+            let tile_position = TilePos {
+                x: index % 1024,
+                y: index / 1024,
+            };
+            edit_history.record(tile_position, None, Some(after));
+        }
+        edit_history.finish_stroke();
+    }
+
+    fn get_undo_count(edit_history: &mut EditHistory) -> usize {
+        let mut undo_count = 0;
+        while edit_history.undo().is_some() {
+            undo_count += 1;
+        }
+        undo_count
+    }
+
+    #[test]
+    fn recording_a_cell_again_keeps_the_first_before() {
+        let mut edit_history = EditHistory::default();
+        let tile_position = TilePos { x: 3, y: 4 };
+        edit_history.record(tile_position, None, Some(SpriteType::Grass));
+        edit_history.record(
+            tile_position,
+            Some(SpriteType::Grass),
+            Some(SpriteType::Dirt),
+        );
+        edit_history.finish_stroke();
+
+        let stroke = edit_history.undo().unwrap();
+        assert_eq!(
+            stroke.cell_edits,
+            vec![CellEdit {
+                tile_position,
+                before: None,
+                after: Some(SpriteType::Dirt),
+            }]
+        );
+    }
+
+    #[test]
+    fn a_new_stroke_discards_what_was_undone() {
+        let mut edit_history = EditHistory::default();
+        record_stroke(&mut edit_history, 1, SpriteType::Grass);
+        assert!(edit_history.undo().is_some());
+        record_stroke(&mut edit_history, 1, SpriteType::Dirt);
+        assert!(edit_history.redo().is_none());
+        assert_eq!(get_undo_count(&mut edit_history), 1);
+    }
+
+    #[test]
+    fn the_budget_forgets_the_oldest_strokes_but_keeps_the_latest() {
+        let budget_cells = (HISTORY_MEMORY_BUDGET / std::mem::size_of::<CellEdit>()) as u32;
+        let mut edit_history = EditHistory::default();
+        for _ in 0..3 {
+            record_stroke(&mut edit_history, budget_cells * 2 / 5, SpriteType::Grass);
+        }
+        assert!(edit_history.memory_size <= HISTORY_MEMORY_BUDGET);
+        assert_eq!(get_undo_count(&mut edit_history), 2);
+
+        let mut edit_history = EditHistory::default();
+        record_stroke(&mut edit_history, 1, SpriteType::Grass);
+        record_stroke(&mut edit_history, budget_cells * 2, SpriteType::Dirt);
+        let stroke = edit_history.undo().unwrap();
+        assert_eq!(stroke.cell_edits.len(), (budget_cells * 2) as usize);
+        assert!(edit_history.undo().is_none());
+    }
+}
@@ -0,0 +1,777 @@
+use crate::atlas::{SheetManifest, TileManifest};
+use crate::tiled::get_absolute_path;
+use crate::{
+    get_sprite_type_by_name, paint_terrain, GroundLayer, Rule, Slot, SpriteType,
+    UpdateTilemapEvent, TILE_SIZE,
+};
+use bevy::prelude::*;
+use bevy_ecs_tilemap::prelude::*;
+use serde::Deserialize;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+// Pattern values matching any IntGrid value, negated it matches cells without one.
+const PATTERN_ANYTHING: i64 = 1_000_001;
+
+// === Enums ===
+#[derive(Debug)]
+pub enum LdtkError {
+    Io {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+    Json {
+        path: PathBuf,
+        message: String,
+    },
+    Unsupported {
+        path: PathBuf,
+        what: String,
+    },
+}
+
+impl fmt::Display for LdtkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LdtkError::Io { path, error } => write!(f, "could not read {:?}: {}", path, error),
+            LdtkError::Json { path, message } => {
+                write!(f, "invalid LDtk project {:?}: {}", path, message)
+            }
+            LdtkError::Unsupported { path, what } => {
+                write!(f, "{:?} uses {}, which is not supported", path, what)
+            }
+        }
+    }
+}
+
+impl std::error::Error for LdtkError {}
+
+// === Structs ===
+// The parts of an `.ldtk` project the importer reads, every other field is ignored.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LdtkProject {
+    pub defs: LdtkDefinitions,
+    #[serde(default)]
+    pub levels: Vec<LdtkLevel>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LdtkDefinitions {
+    pub layers: Vec<LdtkLayerDefinition>,
+    pub tilesets: Vec<LdtkTilesetDefinition>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LdtkLayerDefinition {
+    pub uid: i64,
+    pub identifier: String,
+    #[serde(default)]
+    pub int_grid_values: Vec<LdtkIntGridValue>,
+    #[serde(default)]
+    pub auto_rule_groups: Vec<LdtkRuleGroup>,
+    pub tileset_def_uid: Option<i64>,
+    // Used by projects saved before LDtk 1.0.
+    pub auto_tileset_def_uid: Option<i64>,
+    // Auto layers read the IntGrid values of another layer.
+    pub auto_source_layer_def_uid: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LdtkIntGridValue {
+    pub value: i64,
+    pub identifier: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LdtkRuleGroup {
+    pub name: String,
+    pub active: bool,
+    pub rules: Vec<LdtkRule>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LdtkRule {
+    pub uid: i64,
+    pub active: bool,
+    pub size: usize,
+    // Values of the cells around the tile, row by row from the top-left corner.
+    pub pattern: Vec<i64>,
+    // Used by projects saved before LDtk 1.4.
+    #[serde(default)]
+    pub tile_ids: Vec<u32>,
+    #[serde(default)]
+    pub tile_rects_ids: Vec<Vec<u32>>,
+    pub chance: Option<f32>,
+    pub break_on_match: Option<bool>,
+    #[serde(default)]
+    pub flip_x: bool,
+    #[serde(default)]
+    pub flip_y: bool,
+    pub x_modulo: Option<i64>,
+    pub y_modulo: Option<i64>,
+    pub checker: Option<String>,
+    pub tile_mode: Option<String>,
+    #[serde(default)]
+    pub perlin_active: bool,
+    pub out_of_bounds_value: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LdtkTilesetDefinition {
+    pub uid: i64,
+    pub identifier: String,
+    pub rel_path: Option<String>,
+    pub tile_grid_size: i32,
+    #[serde(default)]
+    pub spacing: i32,
+    #[serde(default)]
+    pub padding: i32,
+    #[serde(rename = "__cWid")]
+    pub columns: u32,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LdtkLevel {
+    pub identifier: String,
+    // Missing when levels are saved in separate files.
+    pub layer_instances: Option<Vec<LdtkLayerInstance>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LdtkLayerInstance {
+    #[serde(rename = "__identifier")]
+    pub identifier: String,
+    #[serde(rename = "__cWid")]
+    pub width: u32,
+    #[serde(rename = "__cHei")]
+    pub height: u32,
+    // Row by row, starting from the top-left corner of the level. 0 is an empty cell.
+    #[serde(default)]
+    pub int_grid_csv: Vec<i64>,
+    pub layer_def_uid: i64,
+}
+
+pub struct LdtkTerrain {
+    pub width: u32,
+    pub height: u32,
+    // Row by row, starting from the top-left corner of the level.
+    pub terrain: Vec<Option<SpriteType>>,
+}
+
+// === Resources ===
+pub struct LdtkImport {
+    // The file name of the project, without its extension.
+    pub name: String,
+    pub sheets: Vec<SheetManifest>,
+    pub rules: HashMap<SpriteType, Vec<(Rule, String)>>,
+    pub terrain: Option<LdtkTerrain>,
+}
+
+impl LdtkImport {
+    // Reads `--ldtk <project>` from the command line.
+    pub fn from_args(mut args: impl Iterator<Item = String>) -> Result<LdtkImport, LdtkError> {
+        let mut ldtk_import = LdtkImport {
+            name: String::new(),
+            sheets: Vec::new(),
+            rules: HashMap::new(),
+            terrain: None,
+        };
+        while let Some(arg) = args.next() {
+            if arg == "--ldtk" {
+                if let Some(path) = args.next() {
+                    ldtk_import = load_ldtk_project(Path::new(&path))?;
+                }
+            }
+        }
+        Ok(ldtk_import)
+    }
+}
+
+// === Startup Systems ===
+// Paints the IntGrid values of the imported level onto the ground.
+pub fn apply_ldtk_terrain(
+    mut commands: Commands,
+    mut update_tilemap_event_writer: EventWriter<UpdateTilemapEvent>,
+    ldtk_import: Res<LdtkImport>,
+    ground_layer_query: Query<(&TilemapSize, &TileStorage), With<GroundLayer>>,
+) {
+    let ldtk_terrain = match &ldtk_import.terrain {
+        Some(ldtk_terrain) => ldtk_terrain,
+        None => return,
+    };
+    let (map_size, tile_storage) = match ground_layer_query.get_single() {
+        Ok(ground_layer) => ground_layer,
+        Err(_) => return,
+    };
+    paint_terrain(
+        &mut commands,
+        map_size,
+        tile_storage,
+        ldtk_terrain.width,
+        ldtk_terrain.height,
+        &ldtk_terrain.terrain,
+    );
+    update_tilemap_event_writer.send(UpdateTilemapEvent {});
+}
+
+// === Helper Functions ===
+// Converts the auto-layer rules and the first level of a project. Rule features that have no
+// equivalent are reported and the rules using them are left out.
+pub fn load_ldtk_project(path: &Path) -> Result<LdtkImport, LdtkError> {
+    let contents = std::fs::read_to_string(path).map_err(|error| LdtkError::Io {
+        path: path.to_path_buf(),
+        error,
+    })?;
+    let project: LdtkProject =
+        serde_json::from_str(&contents).map_err(|error| LdtkError::Json {
+            path: path.to_path_buf(),
+            message: error.to_string(),
+        })?;
+
+    let mut unsupported: Vec<String> = Vec::new();
+    let mut rules: HashMap<SpriteType, Vec<(Rule, String)>> = HashMap::new();
+    let mut used_tiles: HashMap<i64, BTreeSet<u32>> = HashMap::new();
+    for layer in &project.defs.layers {
+        if layer.auto_rule_groups.is_empty() {
+            continue;
+        }
+        let tileset = layer
+            .tileset_def_uid
+            .or(layer.auto_tileset_def_uid)
+            .and_then(|uid| {
+                project
+                    .defs
+                    .tilesets
+                    .iter()
+                    .find(|tileset| tileset.uid == uid)
+            });
+        let tileset = match tileset {
+            Some(tileset) => tileset,
+            None => {
+                unsupported.push(format!(
+                    "layer {} has rules but no tileset",
+                    layer.identifier
+                ));
+                continue;
+            }
+        };
+        let source_layer = match layer.auto_source_layer_def_uid {
+            Some(uid) => project.defs.layers.iter().find(|source| source.uid == uid),
+            None => Some(layer),
+        };
+        let terrain_values = match source_layer {
+            Some(source_layer) => get_terrain_values(source_layer),
+            None => continue,
+        };
+
+        for group in layer.auto_rule_groups.iter().filter(|group| group.active) {
+            for ldtk_rule in group.rules.iter().filter(|ldtk_rule| ldtk_rule.active) {
+                let context = format!(
+                    "rule {} in {}/{}",
+                    ldtk_rule.uid, layer.identifier, group.name
+                );
+                match convert_rule(ldtk_rule, &terrain_values, &context, &mut unsupported) {
+                    Some((sprite_type, rule, tile_id)) => {
+                        used_tiles.entry(tileset.uid).or_default().insert(tile_id);
+                        rules
+                            .entry(sprite_type)
+                            .or_default()
+                            .push((rule, get_sprite_name(tileset, tile_id)));
+                    }
+                    None => continue,
+                }
+            }
+        }
+    }
+
+    let mut sheets = Vec::new();
+    for (tileset_uid, tile_ids) in used_tiles {
+        let tileset = project
+            .defs
+            .tilesets
+            .iter()
+            .find(|tileset| tileset.uid == tileset_uid)
+            .expect("Tilesets were looked up by uid");
+        sheets.push(get_sheet_manifest(path, tileset, &tile_ids)?);
+    }
+
+    let terrain = match project.levels.first() {
+        Some(level) => {
+            if project.levels.len() > 1 {
+                unsupported.push(format!(
+                    "only the first level, {}, is loaded",
+                    level.identifier
+                ));
+            }
+            Some(get_level_terrain(path, &project, level, &mut unsupported)?)
+        }
+        None => None,
+    };
+
+    for what in &unsupported {
+        println!("LDtk import of {:?}: {}", path, what);
+    }
+
+    Ok(LdtkImport {
+        name: path
+            .file_stem()
+            .map(|file_stem| file_stem.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+        sheets,
+        rules,
+        terrain,
+    })
+}
+
+// Maps the IntGrid values of a layer whose identifier names a terrain.
+fn get_terrain_values(layer: &LdtkLayerDefinition) -> HashMap<i64, SpriteType> {
+    layer
+        .int_grid_values
+        .iter()
+        .filter_map(|int_grid_value| {
+            let sprite_type = get_sprite_type_by_name(int_grid_value.identifier.as_deref()?)?;
+            Some((int_grid_value.value, sprite_type))
+        })
+        .collect()
+}
+
+fn convert_rule(
+    ldtk_rule: &LdtkRule,
+    terrain_values: &HashMap<i64, SpriteType>,
+    context: &str,
+    unsupported: &mut Vec<String>,
+) -> Option<(SpriteType, Rule, u32)> {
+    let mut report = |what: &str| unsupported.push(format!("{}: {}", context, what));
+
+    if ldtk_rule.size != 1 && ldtk_rule.size != 3 {
+        report("patterns larger than 3x3 are left out");
+        return None;
+    }
+    if ldtk_rule.chance.unwrap_or(1.0) < 1.0 {
+        report("random chances are left out");
+        return None;
+    }
+    // Rules that only apply to some of the cells would apply everywhere, they are left out.
+    if ldtk_rule.x_modulo.unwrap_or(1) != 1
+        || ldtk_rule.y_modulo.unwrap_or(1) != 1
+        || ldtk_rule.checker.as_deref().unwrap_or("None") != "None"
+    {
+        report("modulos and checker patterns are left out");
+        return None;
+    }
+    if ldtk_rule.perlin_active {
+        report("perlin noise is left out");
+        return None;
+    }
+    if !ldtk_rule.break_on_match.unwrap_or(true) {
+        report("rules drawn underneath later matches are left out");
+        return None;
+    }
+
+    let tile_groups: Vec<Vec<u32>> = if ldtk_rule.tile_rects_ids.is_empty() {
+        ldtk_rule
+            .tile_ids
+            .iter()
+            .map(|tile_id| vec![*tile_id])
+            .collect()
+    } else {
+        ldtk_rule.tile_rects_ids.clone()
+    };
+    let tile_id = match tile_groups
+        .first()
+        .and_then(|tile_group| tile_group.first())
+    {
+        Some(tile_id) => *tile_id,
+        None => {
+            report("the rule has no tile");
+            return None;
+        }
+    };
+
+    // Rules belong to the terrain of the cell they are drawn on.
+    let center_value = ldtk_rule.pattern.get(ldtk_rule.pattern.len() / 2).copied();
+    let sprite_type = match center_value.and_then(|value| terrain_values.get(&value)) {
+        Some(sprite_type) => *sprite_type,
+        None => {
+            report("the center of the pattern has to be a terrain");
+            return None;
+        }
+    };
+    let center_value = center_value.unwrap_or_default();
+
+    let mut slots = [Slot::Any; 9];
+    if ldtk_rule.size == 3 {
+        if ldtk_rule.pattern.len() != 9 {
+            report("the pattern does not match its size");
+            return None;
+        }
+        for (slot, value) in slots.iter_mut().zip(&ldtk_rule.pattern) {
+            *slot = match *value {
+                0 => Slot::Any,
+                value if value == -PATTERN_ANYTHING => Slot::Empty,
+                // Slots have no negation, "not this terrain" is what an empty slot stands for in
+                // the built in rules.
+                value if value == -center_value => Slot::Empty,
+                value if value > 0 && value < PATTERN_ANYTHING => {
+                    match terrain_values.get(&value) {
+                        Some(sprite_type) => Slot::Filled {
+                            sprite_type: *sprite_type,
+                        },
+                        None => {
+                            report(&format!("IntGrid value {} is not a terrain", value));
+                            return None;
+                        }
+                    }
+                }
+                value if value == PATTERN_ANYTHING => {
+                    report("matching any IntGrid value is left out");
+                    return None;
+                }
+                value if value.abs() > PATTERN_ANYTHING => {
+                    report("IntGrid value groups are left out");
+                    return None;
+                }
+                value => {
+                    report(&format!(
+                        "negating IntGrid value {}, other than the center, is left out",
+                        -value
+                    ));
+                    return None;
+                }
+            };
+        }
+    }
+
+    // Rules using the features below are kept, without that part of them.
+    if ldtk_rule.flip_x || ldtk_rule.flip_y {
+        report("flipped variants are not generated");
+    }
+    if ldtk_rule.out_of_bounds_value.is_some() {
+        report("out of bounds values are ignored, the edge of the map counts as empty");
+    }
+    if tile_groups.len() > 1 {
+        report("only the first of the random tiles is used");
+    }
+    if ldtk_rule.tile_mode.as_deref() == Some("Stamp") || tile_groups[0].len() > 1 {
+        report("only the first tile of the stamp is used");
+    }
+
+    let rule = Rule {
+        nw_slot: slots[0],
+        n_slot: slots[1],
+        ne_slot: slots[2],
+        w_slot: slots[3],
+        c_slot: Slot::Filled { sprite_type },
+        e_slot: slots[5],
+        sw_slot: slots[6],
+        s_slot: slots[7],
+        se_slot: slots[8],
+    };
+    Some((sprite_type, rule, tile_id))
+}
+
+fn get_sprite_name(tileset: &LdtkTilesetDefinition, tile_id: u32) -> String {
+    format!("{}/{}", tileset.identifier, tile_id)
+}
+
+fn get_sheet_manifest(
+    path: &Path,
+    tileset: &LdtkTilesetDefinition,
+    tile_ids: &BTreeSet<u32>,
+) -> Result<SheetManifest, LdtkError> {
+    let unsupported = |what: &str| LdtkError::Unsupported {
+        path: path.to_path_buf(),
+        what: format!("{} in tileset {}", what, tileset.identifier),
+    };
+    if tileset.tile_grid_size != TILE_SIZE {
+        return Err(unsupported("a tile size other than the one of the map"));
+    }
+    if tileset.spacing != 0 || tileset.padding != 0 {
+        return Err(unsupported("spacing or padding between tiles"));
+    }
+    let rel_path = tileset
+        .rel_path
+        .as_ref()
+        .ok_or_else(|| unsupported("an embedded image"))?;
+
+    Ok(SheetManifest {
+        // Images are relative to the project, the atlas needs them absolute.
+        path: get_absolute_path(path, rel_path)
+            .to_string_lossy()
+            .into_owned(),
+        tiles: tile_ids
+            .iter()
+            .map(|tile_id| TileManifest {
+                name: get_sprite_name(tileset, *tile_id),
+                column: tile_id % tileset.columns,
+                row: tile_id / tileset.columns,
+            })
+            .collect(),
+    })
+}
+
+// Combines the IntGrid layers of a level into a single terrain per cell. Layers are listed from the
+// top, so they are painted in reverse.
+fn get_level_terrain(
+    path: &Path,
+    project: &LdtkProject,
+    level: &LdtkLevel,
+    unsupported: &mut Vec<String>,
+) -> Result<LdtkTerrain, LdtkError> {
+    let layer_instances = level
+        .layer_instances
+        .as_ref()
+        .ok_or_else(|| LdtkError::Unsupported {
+            path: path.to_path_buf(),
+            what: "levels saved in separate files".to_string(),
+        })?;
+    let int_grid_layers: Vec<&LdtkLayerInstance> = layer_instances
+        .iter()
+        .filter(|layer_instance| !layer_instance.int_grid_csv.is_empty())
+        .collect();
+    let (width, height) = int_grid_layers
+        .last()
+        .map(|layer_instance| (layer_instance.width, layer_instance.height))
+        .unwrap_or_default();
+
+    let mut terrain = vec![None; (width * height) as usize];
+    for layer_instance in int_grid_layers.into_iter().rev() {
+        if layer_instance.width != width
+            || layer_instance.height != height
+            || layer_instance.int_grid_csv.len() != terrain.len()
+        {
+            unsupported.push(format!(
+                "layer {} has a different grid than the level and is left out",
+                layer_instance.identifier
+            ));
+            continue;
+        }
+        let terrain_values = match project
+            .defs
+            .layers
+            .iter()
+            .find(|layer| layer.uid == layer_instance.layer_def_uid)
+        {
+            Some(layer) => get_terrain_values(layer),
+            None => continue,
+        };
+        for (index, value) in layer_instance.int_grid_csv.iter().enumerate() {
+            if let Some(sprite_type) = terrain_values.get(value) {
+                terrain[index] = Some(*sprite_type);
+            }
+        }
+    }
+
+    Ok(LdtkTerrain {
+        width,
+        height,
+        terrain,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::debug::get_slots;
+    use serde_json::json;
+
+    const GRASS: Slot = Slot::Filled {
+        sprite_type: SpriteType::Grass,
+    };
+    const DIRT: Slot = Slot::Filled {
+        sprite_type: SpriteType::Dirt,
+    };
+
+    // Grass is 1 and dirt is 2, 3 is named but not after a terrain and 4 has no name.
+    fn get_layer_definition() -> LdtkLayerDefinition {
+        serde_json::from_value(json!({
+            "uid": 1,
+            "identifier": "Ground",
+            "intGridValues": [
+                { "value": 1, "identifier": "grass" },
+                { "value": 2, "identifier": "Dirt" },
+                { "value": 3, "identifier": "lava" },
+                { "value": 4, "identifier": null },
+            ],
+            "tilesetDefUid": null,
+            "autoTilesetDefUid": null,
+            "autoSourceLayerDefUid": null,
+        }))
+        .unwrap()
+    }
+
+    // A rule drawing tile 7 with `pattern`, along with the fields of `overrides`.
+    fn get_ldtk_rule(pattern: [i64; 9], overrides: serde_json::Value) -> LdtkRule {
+        let mut value = json!({
+            "uid": 10,
+            "active": true,
+            "size": 3,
+            "pattern": pattern,
+            "tileRectsIds": [[7]],
+            "chance": 1.0,
+            "breakOnMatch": true,
+            "xModulo": 1,
+            "yModulo": 1,
+            "checker": "None",
+            "tileMode": "Single",
+            "outOfBoundsValue": null,
+        });
+        for (key, override_value) in overrides.as_object().unwrap() {
+            value[key] = override_value.clone();
+        }
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn convert(ldtk_rule: &LdtkRule) -> (Option<(SpriteType, [Slot; 9], u32)>, Vec<String>) {
+        let terrain_values = get_terrain_values(&get_layer_definition());
+        let mut unsupported = Vec::new();
+        let converted = convert_rule(ldtk_rule, &terrain_values, "rule", &mut unsupported)
+            .map(|(sprite_type, rule, tile_id)| (sprite_type, get_slots(&rule), tile_id));
+        (converted, unsupported)
+    }
+
+    #[test]
+    fn only_int_grid_values_named_after_a_terrain_are_terrain() {
+        let terrain_values = get_terrain_values(&get_layer_definition());
+        assert_eq!(
+            terrain_values,
+            HashMap::from([(1, SpriteType::Grass), (2, SpriteType::Dirt)])
+        );
+    }
+
+    #[test]
+    fn wildcards_and_values_become_slots() {
+        let ldtk_rule = get_ldtk_rule([0, 1, 0, 2, 1, 0, 0, 0, 1], json!({}));
+        let (converted, unsupported) = convert(&ldtk_rule);
+        assert_eq!(
+            converted,
+            Some((
+                SpriteType::Grass,
+                [
+                    Slot::Any,
+                    GRASS,
+                    Slot::Any,
+                    DIRT,
+                    GRASS,
+                    Slot::Any,
+                    Slot::Any,
+                    Slot::Any,
+                    GRASS
+                ],
+                7
+            ))
+        );
+        assert!(unsupported.is_empty());
+    }
+
+    #[test]
+    fn negating_the_center_or_anything_becomes_empty() {
+        let ldtk_rule = get_ldtk_rule([0, -1, 0, 0, 1, 0, 0, 0, -PATTERN_ANYTHING], json!({}));
+        let (converted, unsupported) = convert(&ldtk_rule);
+        let (_, slots, _) = converted.unwrap();
+        assert_eq!(slots[1], Slot::Empty);
+        assert_eq!(slots[8], Slot::Empty);
+        assert!(unsupported.is_empty());
+    }
+
+    #[test]
+    fn rules_with_unsupported_features_are_left_out_and_reported() {
+        let pattern = [0, 0, 0, 0, 1, 0, 0, 0, 0];
+        for (ldtk_rule, reason) in [
+            (
+                get_ldtk_rule(pattern, json!({ "chance": 0.5 })),
+                "random chances",
+            ),
+            (
+                get_ldtk_rule(pattern, json!({ "checker": "Horizontal" })),
+                "checker",
+            ),
+            (get_ldtk_rule(pattern, json!({ "xModulo": 2 })), "modulos"),
+            (
+                get_ldtk_rule(pattern, json!({ "perlinActive": true })),
+                "perlin",
+            ),
+            (
+                get_ldtk_rule([0, -2, 0, 0, 1, 0, 0, 0, 0], json!({})),
+                "negating IntGrid value 2",
+            ),
+            (
+                get_ldtk_rule([0, 3, 0, 0, 1, 0, 0, 0, 0], json!({})),
+                "IntGrid value 3 is not a terrain",
+            ),
+            (
+                get_ldtk_rule([0, 0, 0, 0, 3, 0, 0, 0, 0], json!({})),
+                "has to be a terrain",
+            ),
+        ] {
+            let (converted, unsupported) = convert(&ldtk_rule);
+            assert!(converted.is_none(), "{}", reason);
+            assert_eq!(unsupported.len(), 1, "{}", reason);
+            assert!(unsupported[0].contains(reason), "{}", unsupported[0]);
+        }
+    }
+
+    #[test]
+    fn flips_and_random_tiles_are_reduced_and_reported() {
+        let ldtk_rule = get_ldtk_rule(
+            [0, 0, 0, 0, 1, 0, 0, 0, 0],
+            json!({ "flipX": true, "tileRectsIds": [[7], [8]] }),
+        );
+        let (converted, unsupported) = convert(&ldtk_rule);
+        let (_, _, tile_id) = converted.unwrap();
+        assert_eq!(tile_id, 7);
+        assert_eq!(unsupported.len(), 2);
+        assert!(unsupported[0].contains("flipped variants"));
+        assert!(unsupported[1].contains("random tiles"));
+    }
+
+    #[test]
+    fn int_grid_layers_are_painted_from_the_bottom_up() {
+        let project = LdtkProject {
+            defs: LdtkDefinitions {
+                layers: vec![get_layer_definition()],
+                tilesets: Vec::new(),
+            },
+            levels: Vec::new(),
+        };
+        // Layers are listed from the top, the empty cells and values that are not a terrain of the
+        // top layer let the one below show.
+        let level: LdtkLevel = serde_json::from_value(json!({
+            "identifier": "Level_0",
+            "layerInstances": [
+                { "__identifier": "Small", "__cWid": 1, "__cHei": 1, "intGridCsv": [2],
+                    "layerDefUid": 1 },
+                { "__identifier": "Top", "__cWid": 2, "__cHei": 2, "intGridCsv": [0, 2, 3, 0],
+                    "layerDefUid": 1 },
+                { "__identifier": "Bottom", "__cWid": 2, "__cHei": 2, "intGridCsv": [1, 1, 1, 4],
+                    "layerDefUid": 1 },
+            ],
+        }))
+        .unwrap();
+        let mut unsupported = Vec::new();
+        let level_terrain =
+            get_level_terrain(Path::new("test.ldtk"), &project, &level, &mut unsupported).unwrap();
+        assert_eq!((level_terrain.width, level_terrain.height), (2, 2));
+        assert_eq!(
+            level_terrain.terrain,
+            vec![
+                Some(SpriteType::Grass),
+                Some(SpriteType::Dirt),
+                Some(SpriteType::Grass),
+                None
+            ]
+        );
+        assert_eq!(unsupported.len(), 1);
+        assert!(unsupported[0].contains("layer Small has a different grid"));
+    }
+}
@@ -0,0 +1,376 @@
+use crate::history::{set_tile_terrain, EditHistory};
+use crate::selection::Selection;
+use crate::terrain::TerrainGrid;
+use crate::{
+    get_terrain_grid, DirtTile, GrassTile, GroundLayer, Mouse, Rules, SpriteRegistry, SpriteType,
+    UpdateTilemapEvent, WaterTile, BLANK_SPRITE,
+};
+use bevy::prelude::*;
+use bevy_ecs_tilemap::prelude::*;
+use std::collections::HashSet;
+
+// Stops a pass that keeps finding changes, so a rule set that can never be satisfied does not
+// rewrite the whole map.
+pub const MAX_LEGALIZE_CHANGES: usize = 256;
+
+// === Events ===
+// Legalizes the selection, or the whole map when nothing is selected.
+pub struct LegalizeEvent {}
+
+// === Structs ===
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CellChange {
+    pub cell: IVec2,
+    pub before: Option<SpriteType>,
+    pub after: Option<SpriteType>,
+}
+
+// === Resources ===
+pub struct Legalizer {
+    // Whether every stroke is legalized once the mouse button is released.
+    pub is_enabled: bool,
+}
+
+// === Startup Systems ===
+pub fn setup_legalizer(mut commands: Commands) {
+    commands.insert_resource(Legalizer { is_enabled: false });
+}
+
+// === Systems ===
+// Runs before the stroke is finished, so the fixes are undone along with the stroke.
+pub fn legalize_stroke(
+    mut commands: Commands,
+    mut update_tilemap_event_writer: EventWriter<UpdateTilemapEvent>,
+    mut edit_history: ResMut<EditHistory>,
+    legalizer: Res<Legalizer>,
+    mouse: Res<Mouse>,
+    rules: Res<Rules>,
+    sprite_registry: Res<SpriteRegistry>,
+    ground_layer_query: Query<(&TilemapSize, &TileStorage), With<GroundLayer>>,
+    terrain_query: Query<(Option<&GrassTile>, Option<&DirtTile>, Option<&WaterTile>)>,
+) {
+    if !legalizer.is_enabled || mouse.holding_lmb || !edit_history.is_painting() {
+        return;
+    }
+    let (map_size, tile_storage) = match ground_layer_query.get_single() {
+        Ok(ground_layer) => ground_layer,
+        Err(_) => return,
+    };
+
+    // The last cells of the stroke may have been painted this frame, their components are not
+    // inserted yet.
+    let mut terrain_grid = get_terrain_grid(map_size, tile_storage, &terrain_query);
+    let mut area = HashSet::new();
+    for cell_edit in edit_history.get_current_edits() {
+        let tile_position = cell_edit.tile_position;
+        terrain_grid.set(tile_position.x, tile_position.y, cell_edit.after);
+        let cell = IVec2::new(tile_position.x as i32, tile_position.y as i32);
+        for y in -1..=1 {
+            for x in -1..=1 {
+                area.insert(cell + IVec2::new(x, y));
+            }
+        }
+    }
+    let (_, cell_changes) = legalize(&terrain_grid, &area, &rules);
+    for cell_change in &cell_changes {
+        let tile_position = TilePos {
+            x: cell_change.cell.x as u32,
+            y: cell_change.cell.y as u32,
+        };
+        if let Some(tile_entity) = tile_storage.get(&tile_position) {
+            edit_history.record(tile_position, cell_change.before, cell_change.after);
+            set_tile_terrain(
+                &mut commands,
+                tile_entity,
+                cell_change.after,
+                &sprite_registry,
+            );
+        }
+    }
+    if !cell_changes.is_empty() {
+        report_cell_changes(&cell_changes);
+        update_tilemap_event_writer.send(UpdateTilemapEvent {});
+    }
+}
+
+pub fn apply_legalize(
+    mut commands: Commands,
+    mut legalize_event_reader: EventReader<LegalizeEvent>,
+    mut update_tilemap_event_writer: EventWriter<UpdateTilemapEvent>,
+    mut edit_history: ResMut<EditHistory>,
+    selection: Res<Selection>,
+    rules: Res<Rules>,
+    sprite_registry: Res<SpriteRegistry>,
+    ground_layer_query: Query<(&TilemapSize, &TileStorage), With<GroundLayer>>,
+    terrain_query: Query<(Option<&GrassTile>, Option<&DirtTile>, Option<&WaterTile>)>,
+) {
+    if legalize_event_reader.iter().last().is_none() {
+        return;
+    }
+    let (map_size, tile_storage) = match ground_layer_query.get_single() {
+        Ok(ground_layer) => ground_layer,
+        Err(_) => return,
+    };
+    let area: HashSet<IVec2> = if selection.cells.is_empty() {
+        (0..map_size.y as i32)
+            .flat_map(|y| (0..map_size.x as i32).map(move |x| IVec2::new(x, y)))
+            .collect()
+    } else {
+        selection
+            .cells
+            .iter()
+            .map(|tile_position| IVec2::new(tile_position.x as i32, tile_position.y as i32))
+            .collect()
+    };
+    let terrain_grid = get_terrain_grid(map_size, tile_storage, &terrain_query);
+    let (legal_grid, cell_changes) = legalize(&terrain_grid, &area, &rules);
+
+    edit_history.finish_stroke();
+    for cell_change in &cell_changes {
+        let tile_position = TilePos {
+            x: cell_change.cell.x as u32,
+            y: cell_change.cell.y as u32,
+        };
+        if let Some(tile_entity) = tile_storage.get(&tile_position) {
+            edit_history.record(tile_position, cell_change.before, cell_change.after);
+            set_tile_terrain(
+                &mut commands,
+                tile_entity,
+                cell_change.after,
+                &sprite_registry,
+            );
+        }
+    }
+    edit_history.finish_stroke();
+    report_cell_changes(&cell_changes);
+    let remaining = get_illegal_cells(&legal_grid, &area, &rules).len();
+    if remaining > 0 {
+        println!("{} tiles could not be legalized", remaining);
+    }
+    if !cell_changes.is_empty() {
+        update_tilemap_event_writer.send(UpdateTilemapEvent {});
+    }
+}
+
+// === Helper Functions ===
+// Tiles whose terrain has rules, but whose neighborhood none of them has a sprite for.
+pub fn is_illegal(terrain_grid: &TerrainGrid, cell: IVec2, rules: &Rules) -> bool {
+    if cell.x < 0
+        || cell.y < 0
+        || cell.x >= terrain_grid.width as i32
+        || cell.y >= terrain_grid.height as i32
+    {
+        return false;
+    }
+    let sprite_type = match terrain_grid.get(cell.x, cell.y) {
+        Some(sprite_type) => sprite_type,
+        None => return false,
+    };
+    if !rules.rules.contains_key(&sprite_type) {
+        return false;
+    }
+    terrain_grid
+        .match_tile(cell.x as u32, cell.y as u32, rules)
+        .map_or(true, |sprite_name| sprite_name == BLANK_SPRITE)
+}
+
+pub fn get_illegal_cells(
+    terrain_grid: &TerrainGrid,
+    area: &HashSet<IVec2>,
+    rules: &Rules,
+) -> Vec<IVec2> {
+    let mut illegal_cells: Vec<IVec2> = area
+        .iter()
+        .copied()
+        .filter(|cell| is_illegal(terrain_grid, *cell, rules))
+        .collect();
+    // Sorted so the same map is always legalized the same way.
+    illegal_cells.sort_by_key(|cell| (cell.y, cell.x));
+    illegal_cells
+}
+
+// Changes tiles of `area` until every one of them can be drawn, one tile at a time. Each step
+// grows a terrain onto a tile or shrinks it away from one, picking the change around an illegal
+// tile that leaves the fewest illegal tiles. This keeps the number of changes low, without trying
+// every combination. Stops when no single change helps any more.
+pub fn legalize(
+    terrain_grid: &TerrainGrid,
+    area: &HashSet<IVec2>,
+    rules: &Rules,
+) -> (TerrainGrid, Vec<CellChange>) {
+    let mut legal_grid = terrain_grid.clone();
+    let count_illegal = |grid: &TerrainGrid, cell: IVec2| {
+        let mut count = 0;
+        for y in -1..=1 {
+            for x in -1..=1 {
+                let neighbor = cell + IVec2::new(x, y);
+                if area.contains(&neighbor) && is_illegal(grid, neighbor, rules) {
+                    count += 1;
+                }
+            }
+        }
+        count
+    };
+
+    // Only the tiles around a change can become legal or illegal, so the illegal tiles are found
+    // once and kept up to date, rather than looked for in the whole area after every change.
+    let mut illegal_cells: HashSet<IVec2> = get_illegal_cells(&legal_grid, area, rules)
+        .into_iter()
+        .collect();
+    let mut changed_cells: Vec<IVec2> = Vec::new();
+    for _ in 0..MAX_LEGALIZE_CHANGES {
+        // Sorted so the same map is always legalized the same way.
+        let mut sorted_illegal_cells: Vec<IVec2> = illegal_cells.iter().copied().collect();
+        sorted_illegal_cells.sort_by_key(|cell| (cell.y, cell.x));
+        // The change that removes the most illegal tiles, as (removed tiles, cell, terrain).
+        let mut best_change: Option<(i32, IVec2, Option<SpriteType>)> = None;
+        for illegal_cell in sorted_illegal_cells {
+            for y in -1..=1 {
+                for x in -1..=1 {
+                    let cell = illegal_cell + IVec2::new(x, y);
+                    if !area.contains(&cell)
+                        || cell.x < 0
+                        || cell.y < 0
+                        || cell.x >= legal_grid.width as i32
+                        || cell.y >= legal_grid.height as i32
+                    {
+                        continue;
+                    }
+                    let current = legal_grid.get(cell.x, cell.y);
+                    let illegal_before = count_illegal(&legal_grid, cell);
+                    // Either grow the terrain of a neighbor onto the tile, or clear it. Clearing
+                    // comes last, so it only wins when no terrain does better.
+                    let mut candidates = Vec::new();
+                    for y in -1..=1 {
+                        for x in -1..=1 {
+                            candidates.push(legal_grid.get(cell.x + x, cell.y + y));
+                        }
+                    }
+                    candidates.push(None);
+                    for candidate in candidates {
+                        if candidate == current {
+                            continue;
+                        }
+                        // Tried in place and put back, copying the grid for every candidate is
+                        // too slow on a whole map.
+                        legal_grid.set(cell.x as u32, cell.y as u32, candidate);
+                        let removed = illegal_before - count_illegal(&legal_grid, cell);
+                        legal_grid.set(cell.x as u32, cell.y as u32, current);
+                        if removed > 0 && best_change.map_or(true, |(best, _, _)| removed > best) {
+                            best_change = Some((removed, cell, candidate));
+                        }
+                    }
+                }
+            }
+        }
+        let (_, cell, sprite_type) = match best_change {
+            Some(best_change) => best_change,
+            None => break,
+        };
+        legal_grid.set(cell.x as u32, cell.y as u32, sprite_type);
+        if !changed_cells.contains(&cell) {
+            changed_cells.push(cell);
+        }
+        for y in -1..=1 {
+            for x in -1..=1 {
+                let neighbor = cell + IVec2::new(x, y);
+                if area.contains(&neighbor) && is_illegal(&legal_grid, neighbor, rules) {
+                    illegal_cells.insert(neighbor);
+                } else {
+                    illegal_cells.remove(&neighbor);
+                }
+            }
+        }
+    }
+
+    // Clearing a tile can be the only single change that helps while a speck is removed, even
+    // though the terrain around it fits once the whole speck is gone. Those tiles are filled back
+    // in, rather than left as holes.
+    let mut is_filled = true;
+    while is_filled {
+        is_filled = false;
+        for cell in &changed_cells {
+            if legal_grid.get(cell.x, cell.y).is_some()
+                || terrain_grid.get(cell.x, cell.y).is_none()
+            {
+                continue;
+            }
+            let illegal_before = count_illegal(&legal_grid, *cell);
+            for y in -1..=1 {
+                for x in -1..=1 {
+                    let candidate = legal_grid.get(cell.x + x, cell.y + y);
+                    if candidate.is_none() {
+                        continue;
+                    }
+                    legal_grid.set(cell.x as u32, cell.y as u32, candidate);
+                    if count_illegal(&legal_grid, *cell) <= illegal_before {
+                        is_filled = true;
+                        break;
+                    }
+                    legal_grid.set(cell.x as u32, cell.y as u32, None);
+                }
+                if legal_grid.get(cell.x, cell.y).is_some() {
+                    break;
+                }
+            }
+        }
+    }
+
+    let cell_changes = changed_cells
+        .into_iter()
+        .map(|cell| CellChange {
+            cell,
+            before: terrain_grid.get(cell.x, cell.y),
+            after: legal_grid.get(cell.x, cell.y),
+        })
+        .filter(|cell_change| cell_change.before != cell_change.after)
+        .collect();
+    (legal_grid, cell_changes)
+}
+
+pub fn report_cell_changes(cell_changes: &[CellChange]) {
+    for cell_change in cell_changes {
+        println!(
+            "Legalized ({}, {}): {:?} -> {:?}",
+            cell_change.cell.x, cell_change.cell.y, cell_change.before, cell_change.after
+        );
+    }
+    println!("Legalized {} tiles", cell_changes.len());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::get_builtin_rules;
+
+    #[test]
+    fn legalize_fixes_a_checkerboard() {
+        let rules = get_builtin_rules();
+        let terrain_grid = TerrainGrid::from_ascii(&["dgdgdgdg", "gdgdgdgd"].repeat(4));
+        let area: HashSet<IVec2> = terrain_grid.cells().into_iter().collect();
+        assert!(!get_illegal_cells(&terrain_grid, &area, &rules).is_empty());
+
+        let (legal_grid, cell_changes) = legalize(&terrain_grid, &area, &rules);
+        assert!(get_illegal_cells(&legal_grid, &area, &rules).is_empty());
+        for cell_change in &cell_changes {
+            assert_eq!(
+                terrain_grid.get(cell_change.cell.x, cell_change.cell.y),
+                cell_change.before
+            );
+            assert_eq!(
+                legal_grid.get(cell_change.cell.x, cell_change.cell.y),
+                cell_change.after
+            );
+        }
+    }
+
+    #[test]
+    fn legalize_leaves_legal_terrain_alone() {
+        let rules = get_builtin_rules();
+        let terrain_grid = TerrainGrid::from_ascii(&["gggggggg"; 8]);
+        let area: HashSet<IVec2> = terrain_grid.cells().into_iter().collect();
+        let (legal_grid, cell_changes) = legalize(&terrain_grid, &area, &rules);
+        assert_eq!(legal_grid, terrain_grid);
+        assert!(cell_changes.is_empty());
+    }
+}
@@ -1,24 +1,125 @@
 #![warn(clippy::all, clippy::pedantic)]
 
+mod atlas;
+mod collision;
+mod debug;
+mod editor;
+mod export;
+mod generator;
+mod history;
+mod ldtk;
+mod legalize;
+mod morphology;
+mod objects;
+mod pathfinding;
+mod preview;
+mod properties;
+mod render;
+mod resolver;
+mod rule_editor;
+mod save;
+mod selection;
+mod structures;
+mod terrain;
+mod tiled;
+mod tools;
+mod wfc;
+
+use crate::atlas::{
+    build_atlas, get_asset_root, load_atlas_manifest, Atlas, AtlasError, ATLAS_MANIFEST_PATH,
+};
+use crate::collision::{
+    draw_collision_shapes, setup_collision_geometry, update_collision_geometry,
+};
+use crate::debug::{explain_tile, setup_debug_overlay, update_debug_overlay};
+use crate::editor::{setup_palette, update_editor_panel, Palette, PALETTE_KEYS};
+use crate::export::export_map;
+use crate::generator::{
+    apply_generated_terrain, apply_startup_generation, GenerateTerrainEvent, Generator,
+};
+use crate::history::{
+    set_tile_terrain, setup_edit_history, undo_redo, update_edit_history, EditHistory,
+};
+use crate::ldtk::{apply_ldtk_terrain, LdtkImport};
+use crate::legalize::{apply_legalize, legalize_stroke, setup_legalizer, LegalizeEvent};
+use crate::morphology::{apply_terrain_operation, setup_morphology, ApplyTerrainOperationEvent};
+use crate::objects::{
+    place_object, setup_connection_rules, setup_object_layers, setup_object_stroke,
+    update_object_layers, UpdateObjectLayerEvent,
+};
+use crate::pathfinding::{draw_debug_path, pick_path_cells, setup_pathfinder, update_pathfinder};
+use crate::preview::{is_same_flip, setup_preview_layer, update_brush_preview};
+use crate::properties::setup_terrain_properties;
+use crate::render::{render_map_file, RenderOptions};
+use crate::resolver::{
+    setup_autotile_resolvers, update_autotile_resolvers, AutotileResolvers, Neighborhood,
+    ResolverKind, TileOutput,
+};
+use crate::rule_editor::{read_rule_file, setup_rule_editor, update_rule_editor, RULE_FILE_PATH};
+use crate::save::{load_map, save_map};
+use crate::selection::{
+    edit_selection, is_selecting, paste_selection, select_region, setup_selection, Clipboard,
+};
+use crate::structures::{place_structure, setup_stamps, setup_structure_layer, StructureFootprint};
+use crate::terrain::TerrainGrid;
+use crate::tiled::{apply_tiled_map, TiledImport, TiledTileset};
+use crate::tools::{setup_tools, update_tool, Tool, ToolState};
+use crate::wfc::{fill_selection, FillSelectionEvent};
 use bevy::input::mouse::{MouseScrollUnit, MouseWheel};
 use bevy::math::Vec4Swizzles;
 use bevy::prelude::*;
+use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
 use bevy::render::texture::ImageSettings;
 use bevy::window::PresentMode;
 use bevy_ecs_tilemap::prelude::*;
+use bevy_inspector_egui::bevy_egui::EguiContext;
 use bevy_inspector_egui::WorldInspectorPlugin;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
 pub const MAP_WIDTH: i32 = 64;
 pub const MAP_HEIGHT: i32 = 64;
 pub const TILE_SIZE: i32 = 16;
 
+// Every terrain the ground can be painted with. Exported terrain tilesets list their tiles in this
+// order.
+pub const TERRAIN_TYPES: [SpriteType; 3] = [SpriteType::Grass, SpriteType::Dirt, SpriteType::Water];
+// The terrains `update_tilemap` picks sprites for, water tiles keep the texture they are painted
+// with.
+pub const AUTOTILED_TYPES: [SpriteType; 2] = [SpriteType::Grass, SpriteType::Dirt];
+
+// The sprite tiles resolve to when no rule matches.
+pub const BLANK_SPRITE: &str = "blank";
+// The rules written out in `setup_rules`.
+pub const BUILTIN_RULE_SET: &str = "builtin";
+
 pub const CAMERA_MIN_ZOOM: f32 = 0.1;
 pub const CAMERA_MAX_ZOOM: f32 = 2.5;
 pub const CAMERA_MOVEMENT_SPEED: f32 = 10.0;
 pub const CAMERA_SCROLL_SPEED: f32 = 0.1;
 
 fn main() {
+    let tiled_import = TiledImport::from_args(std::env::args().skip(1)).unwrap_or_else(|error| {
+        println!("Failed to import from Tiled: {}", error);
+        std::process::exit(1);
+    });
+    let ldtk_import = LdtkImport::from_args(std::env::args().skip(1)).unwrap_or_else(|error| {
+        println!("Failed to import from LDtk: {}", error);
+        std::process::exit(1);
+    });
+    let generator = Generator::from_args(std::env::args().skip(1));
+    let resolver_kind = ResolverKind::from_args(std::env::args().skip(1));
+    if let Some(render_options) = RenderOptions::from_args(std::env::args().skip(1)) {
+        match render_map_file(&render_options, &tiled_import, &ldtk_import, resolver_kind) {
+            Ok(()) => println!("Rendered {:?}", render_options.image_path),
+            Err(error) => {
+                println!("Failed to render the map: {}", error);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
     App::new()
         .insert_resource(ClearColor(Color::rgb(0.0, 0.0, 0.0)))
         .insert_resource(WindowDescriptor {
@@ -32,32 +133,119 @@ fn main() {
         .add_plugins(DefaultPlugins) // bevy
         .add_plugin(WorldInspectorPlugin::new()) // bevy_inspector_egui
         .add_plugin(TilemapPlugin) // bevy_ecs_tilemap
+        .insert_resource(tiled_import)
+        .insert_resource(ldtk_import)
+        .insert_resource(generator)
+        .insert_resource(resolver_kind)
         .add_event::<UpdateTilemapEvent>()
+        .add_event::<UpdateTilesEvent>()
+        .add_event::<UpdateObjectLayerEvent>()
+        .add_event::<GenerateTerrainEvent>()
+        .add_event::<FillSelectionEvent>()
+        .add_event::<ApplyTerrainOperationEvent>()
+        .add_event::<LegalizeEvent>()
+        .add_event::<TerrainChanged>()
+        .add_event::<TileResolved>()
         .add_startup_system(setup_camera)
         .add_startup_system(setup_mouse)
         .add_startup_system(setup_rules.label(Setup::Rules))
         .add_startup_system(setup_active_rules.label(Setup::ActiveRules))
-        .add_startup_system(setup_sprites.label(Setup::Sprites))
+        // The atlas has to exist before the tilemap is spawned.
+        .add_startup_system_to_stage(StartupStage::PreStartup, setup_sprites)
         .add_startup_system(setup_tilemap.label(Setup::Tilemap))
+        .add_startup_system(setup_connection_rules)
+        .add_startup_system(setup_object_layers)
+        .add_startup_system(setup_object_stroke)
+        .add_startup_system(setup_stamps)
+        .add_startup_system(setup_structure_layer)
+        .add_startup_system(setup_preview_layer)
+        .add_startup_system(setup_edit_history)
+        .add_startup_system(setup_tools)
+        .add_startup_system(setup_selection)
+        .add_startup_system(setup_rule_editor)
+        .add_startup_system(setup_debug_overlay)
+        .add_startup_system(setup_morphology)
+        .add_startup_system(setup_legalizer)
+        .add_startup_system(setup_terrain_properties)
+        .add_startup_system(setup_pathfinder)
+        .add_startup_system(setup_collision_geometry)
         .add_startup_system(
             setup_game
                 .label(Setup::Game)
                 .after(Setup::Rules)
                 .after(Setup::ActiveRules)
-                .after(Setup::Sprites)
                 .after(Setup::Tilemap),
         )
+        // Imported terrain is painted once the ground tilemap has been spawned.
+        .add_startup_system_to_stage(StartupStage::PostStartup, apply_tiled_map)
+        .add_startup_system_to_stage(StartupStage::PostStartup, apply_ldtk_terrain)
+        .add_startup_system_to_stage(StartupStage::PostStartup, apply_startup_generation)
+        .add_startup_system_to_stage(StartupStage::PostStartup, setup_palette)
+        .add_startup_system_to_stage(StartupStage::PostStartup, setup_autotile_resolvers)
         .add_system(update_camera_movement)
         .add_system(update_camera_zoom)
         .add_system(update_selection)
+        .add_system(update_editor_panel)
+        .add_system(update_rule_editor)
+        .add_system(update_debug_overlay)
+        .add_system(explain_tile)
+        .add_system(update_pathfinder)
+        .add_system(pick_path_cells.after(update_pathfinder))
+        .add_system(draw_debug_path.after(pick_path_cells))
+        .add_system(draw_collision_shapes)
+        .add_system(update_tool)
         .add_system(update_mouse)
         .add_system(place_tile)
-        .add_system(update_active_rules)
-        .add_system(update_tilemap)
+        .add_system(update_brush_preview)
+        // Strokes are legalized before they are closed, so the fixes are undone with them.
+        .add_system(
+            legalize_stroke
+                .before(History::FinishStroke)
+                .after(place_tile),
+        )
+        .add_system(update_edit_history.label(History::FinishStroke))
+        .add_system(undo_redo)
+        .add_system(apply_generated_terrain)
+        .add_system(place_object)
+        .add_system(place_structure)
+        .add_system(select_region)
+        .add_system(edit_selection)
+        .add_system(paste_selection)
+        .add_system(fill_selection)
+        .add_system(apply_terrain_operation)
+        .add_system(apply_legalize)
+        .add_system(export_map)
+        .add_system(save_map)
+        .add_system(load_map)
+        // Autotiling runs after the commands issued by the placement systems have been applied.
+        .add_system_to_stage(
+            CoreStage::PostUpdate,
+            update_active_rules.label(Autotile::ActiveRules),
+        )
+        .add_system_to_stage(
+            CoreStage::PostUpdate,
+            update_autotile_resolvers.label(Autotile::Resolvers),
+        )
+        .add_system_to_stage(
+            CoreStage::PostUpdate,
+            update_tilemap
+                .after(Autotile::ActiveRules)
+                .after(Autotile::Resolvers),
+        )
+        .add_system_to_stage(CoreStage::PostUpdate, update_object_layers)
+        .add_system_to_stage(CoreStage::PostUpdate, send_terrain_changes)
+        // Footprints removed by the placement systems are only seen by systems of later stages.
+        .add_system_to_stage(
+            CoreStage::PostUpdate,
+            update_collision_geometry.after(send_terrain_changes),
+        )
         .run();
 }
 
 // === Components ===
+#[derive(Component, Debug)]
+pub struct GroundLayer {}
+
 #[derive(Component, Debug)]
 pub struct GrassTile {}
 
@@ -70,142 +258,61 @@ pub struct WaterTile {}
 // === Events ===
 pub struct UpdateTilemapEvent {}
 
+// Autotiles only the given tiles and the ones around them, for edits too small to go over the
+// whole map, such as undoing a stroke.
+pub struct UpdateTilesEvent {
+    pub tile_positions: Vec<TilePos>,
+}
+
+// Sent once for every ground tile whose terrain changed, however it was changed. Painting,
+// undoing, imports and loading a map all send it.
+#[derive(Clone, Copy, Debug)]
+pub struct TerrainChanged {
+    pub tilemap: Entity,
+    pub pos: TilePos,
+    pub old: Option<SpriteType>,
+    pub new: Option<SpriteType>,
+}
+
+// Sent when an autotiled tile is drawn with another sprite, `sprite` is its atlas index.
+#[derive(Clone, Copy, Debug)]
+pub struct TileResolved {
+    pub pos: TilePos,
+    pub sprite: u32,
+}
+
 // === Enums ===
 #[derive(Debug, Clone, PartialEq, Eq, Hash, SystemLabel)]
 enum Setup {
     Rules,
     ActiveRules,
-    Sprites,
     Tilemap,
     Game,
 }
 
-#[allow(non_camel_case_types)]
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
-pub enum Sprite {
-    Blank,
-    // Grass Row 1
-    Grass_000_010_010,
-    Grass_000_011_010,
-    Grass_000_111_010,
-    Grass_000_110_010,
-    Grass_110_111_010,
-    Grass_000_111_011,
-    Grass_000_111_110,
-    Grass_011_111_010,
-    Grass_000_011_011,
-    Grass_010_111_111,
-    Grass_000_111_111,
-    Grass_000_110_110,
-    // Grass Row 2
-    Grass_010_010_010,
-    Grass_010_011_010,
-    Grass_010_111_010,
-    Grass_010_110_010,
-    Grass_010_011_011,
-    Grass_011_111_111,
-    Grass_110_111_111,
-    Grass_010_110_110,
-    Grass_011_011_011,
-    Grass_011_111_110,
-    Grass_000_000_000,
-    // Blank
-    Grass_110_111_110,
-    // Grass Row 3
-    Grass_010_010_000,
-    Grass_010_011_000,
-    Grass_010_111_000,
-    Grass_010_110_000,
-    Grass_011_011_010,
-    Grass_111_111_011,
-    Grass_111_111_110,
-    Grass_110_110_010,
-    Grass_011_111_011,
-    Grass_111_111_111,
-    Grass_110_111_011,
-    Grass_110_110_110,
-    // Grass Row 4
-    Grass_000_010_000,
-    Grass_000_011_000,
-    Grass_000_111_000,
-    Grass_000_110_000,
-    Grass_010_111_110,
-    Grass_011_111_000,
-    Grass_110_111_000,
-    Grass_010_111_011,
-    Grass_011_011_000,
-    Grass_111_111_000,
-    Grass_111_111_010,
-    Grass_110_110_000,
-    // Dirt Row 1
-    Dirt_000_010_010,
-    Dirt_000_011_010,
-    Dirt_000_111_010,
-    Dirt_000_110_010,
-    Dirt_110_111_010,
-    Dirt_000_111_011,
-    Dirt_000_111_110,
-    Dirt_011_111_010,
-    Dirt_000_011_011,
-    Dirt_010_111_111,
-    Dirt_000_111_111,
-    Dirt_000_110_110,
-    // Dirt Row 2
-    Dirt_010_010_010,
-    Dirt_010_011_010,
-    Dirt_010_111_010,
-    Dirt_010_110_010,
-    Dirt_010_011_011,
-    Dirt_011_111_111,
-    Dirt_110_111_111,
-    Dirt_010_110_110,
-    Dirt_011_011_011,
-    Dirt_011_111_110,
-    Dirt_000_000_000,
-    // Blank
-    Dirt_110_111_110,
-    // Dirt Row 3
-    Dirt_010_010_000,
-    Dirt_010_011_000,
-    Dirt_010_111_000,
-    Dirt_010_110_000,
-    Dirt_011_011_010,
-    Dirt_111_111_011,
-    Dirt_111_111_110,
-    Dirt_110_110_010,
-    Dirt_011_111_011,
-    Dirt_111_111_111,
-    Dirt_110_111_011,
-    Dirt_110_110_110,
-    // Dirt Row 4
-    Dirt_000_010_000,
-    Dirt_000_011_000,
-    Dirt_000_111_000,
-    Dirt_000_110_000,
-    Dirt_010_111_110,
-    Dirt_011_111_000,
-    Dirt_110_111_000,
-    Dirt_010_111_011,
-    Dirt_011_011_000,
-    Dirt_111_111_000,
-    Dirt_111_111_010,
-    Dirt_110_110_000,
-    // Water
-    Water_0,
-    Water_1,
-    Water_2,
-    Water_3,
+#[derive(Debug, Clone, PartialEq, Eq, Hash, SystemLabel)]
+enum Autotile {
+    ActiveRules,
+    Resolvers,
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, SystemLabel)]
+enum History {
+    FinishStroke,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum SpriteType {
     Blank,
     Grass,
     Dirt,
     Water,
+    Path,
+    Fence,
+    WoodenHouse,
 }
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub enum Slot {
     Empty,
     Filled { sprite_type: SpriteType },
@@ -213,7 +320,7 @@ pub enum Slot {
 }
 
 // === Struts ===
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub struct Rule {
     pub nw_slot: Slot,
     pub n_slot: Slot,
@@ -262,22 +369,74 @@ pub struct Mouse {
     pub window_position: Vec2,
     pub world_position: Vec3,
     pub holding_lmb: bool,
+    // Whether the cursor is over an egui window or panel, clicks there are not meant for the map.
+    pub is_over_ui: bool,
 }
 
 pub struct GameState {
     pub selection: SpriteType,
 }
 
-pub struct Sprites {
-    pub sprite_lookup_table: HashMap<Sprite, u32>,
+// Maps sprite names, such as "grass/000_010_010", to their index in the atlas. Names come from the
+// atlas manifest.
+pub struct SpriteRegistry {
+    pub atlas_indices: HashMap<String, u32>,
+    // The atlas packed from the source sheets listed in `ATLAS_MANIFEST_PATH`.
+    pub texture: Handle<Image>,
+    // The size of the atlas in tiles.
+    pub atlas_columns: u32,
+    pub atlas_rows: u32,
+}
+
+impl SpriteRegistry {
+    pub fn get_atlas_index(&self, name: &str) -> Option<u32> {
+        self.atlas_indices.get(name).copied()
+    }
+
+    pub fn get_blank_index(&self) -> u32 {
+        self.atlas_indices[BLANK_SPRITE]
+    }
+
+    // A registry with an atlas index for the blank sprite and every sprite of the rules, in the
+    // order they first appear, without a texture.
+    #[cfg(test)]
+    pub fn from_rules(rules: &Rules) -> SpriteRegistry {
+        let mut atlas_indices = HashMap::from([(BLANK_SPRITE.to_string(), 0)]);
+        let mut sprite_types: Vec<&SpriteType> = rules.rules.keys().collect();
+        sprite_types.sort_by_key(|sprite_type| format!("{:?}", sprite_type));
+        for sprite_type in sprite_types {
+            for (_, sprite_name) in &rules.rules[sprite_type] {
+                let atlas_index = atlas_indices.len() as u32;
+                atlas_indices
+                    .entry(sprite_name.clone())
+                    .or_insert(atlas_index);
+            }
+        }
+        let atlas_rows = atlas_indices.len() as u32;
+        SpriteRegistry {
+            atlas_indices,
+            texture: Handle::default(),
+            atlas_columns: 1,
+            atlas_rows,
+        }
+    }
 }
 
 pub struct Rules {
-    pub rules: HashMap<SpriteType, Vec<(Rule, Sprite)>>,
+    // Rules are matched in order, the first match picks the sprite. Sprites are referred to by
+    // their name in the `SpriteRegistry`.
+    pub rules: HashMap<SpriteType, Vec<(Rule, String)>>,
+    // Identifies where the rules came from, saved maps record it.
+    pub rule_set: String,
+    // The rules of the terrains an imported tileset or project replaced, as they were before. The
+    // rule file is saved with these, so imports do not end up in it.
+    pub replaced_rules: HashMap<SpriteType, Vec<(Rule, String)>>,
 }
 
 pub struct ActiveRules {
     pub active_rules: HashMap<TilePos, Rule>,
+    // The tiles whose rule was built again by the last update, `None` when every tile was.
+    pub changed_positions: Option<HashSet<TilePos>>,
 }
 
 // === Startup Systems ===
@@ -301,131 +460,89 @@ pub fn setup_mouse(mut commands: Commands) {
         window_position: Default::default(),
         world_position: Default::default(),
         holding_lmb: false,
+        is_over_ui: false,
     })
 }
 
-pub fn setup_sprites(mut commands: Commands) {
-    let sprites = Sprites {
-        sprite_lookup_table: HashMap::from([
-            // Grass Row 1
-            (Sprite::Grass_000_010_010, 0),
-            (Sprite::Grass_000_011_010, 1),
-            (Sprite::Grass_000_111_010, 2),
-            (Sprite::Grass_000_110_010, 3),
-            (Sprite::Grass_110_111_010, 4),
-            (Sprite::Grass_000_111_011, 5),
-            (Sprite::Grass_000_111_110, 6),
-            (Sprite::Grass_011_111_010, 7),
-            (Sprite::Grass_000_011_011, 8),
-            (Sprite::Grass_010_111_111, 9),
-            (Sprite::Grass_000_111_111, 10),
-            (Sprite::Grass_000_110_110, 11),
-            // Grass Row 2
-            (Sprite::Grass_010_010_010, 12),
-            (Sprite::Grass_010_011_010, 13),
-            (Sprite::Grass_010_111_010, 14),
-            (Sprite::Grass_010_110_010, 15),
-            (Sprite::Grass_010_011_011, 16),
-            (Sprite::Grass_011_111_111, 17),
-            (Sprite::Grass_110_111_111, 18),
-            (Sprite::Grass_010_110_110, 19),
-            (Sprite::Grass_011_011_011, 20),
-            (Sprite::Grass_011_111_110, 21),
-            (Sprite::Grass_000_000_000, 22),
-            (Sprite::Grass_110_111_110, 23),
-            // Grass Row 3
-            (Sprite::Grass_010_010_000, 24),
-            (Sprite::Grass_010_011_000, 25),
-            (Sprite::Grass_010_111_000, 26),
-            (Sprite::Grass_010_110_000, 27),
-            (Sprite::Grass_011_011_010, 28),
-            (Sprite::Grass_111_111_011, 29),
-            (Sprite::Grass_111_111_110, 30),
-            (Sprite::Grass_110_110_010, 31),
-            (Sprite::Grass_011_111_011, 32),
-            (Sprite::Grass_111_111_111, 33),
-            (Sprite::Grass_110_111_011, 34),
-            (Sprite::Grass_110_110_110, 35),
-            // Grass Row 4
-            (Sprite::Grass_000_010_000, 36),
-            (Sprite::Grass_000_011_000, 37),
-            (Sprite::Grass_000_111_000, 38),
-            (Sprite::Grass_000_110_000, 39),
-            (Sprite::Grass_010_111_110, 40),
-            (Sprite::Grass_011_111_000, 41),
-            (Sprite::Grass_110_111_000, 42),
-            (Sprite::Grass_010_111_011, 43),
-            (Sprite::Grass_011_011_000, 44),
-            (Sprite::Grass_111_111_000, 45),
-            (Sprite::Grass_111_111_010, 46),
-            (Sprite::Grass_110_110_000, 47),
-            // Dirt Row 1
-            (Sprite::Dirt_000_010_010, 48),
-            (Sprite::Dirt_000_011_010, 49),
-            (Sprite::Dirt_000_111_010, 50),
-            (Sprite::Dirt_000_110_010, 51),
-            (Sprite::Dirt_110_111_010, 52),
-            (Sprite::Dirt_000_111_011, 53),
-            (Sprite::Dirt_000_111_110, 54),
-            (Sprite::Dirt_011_111_010, 55),
-            (Sprite::Dirt_000_011_011, 56),
-            (Sprite::Dirt_010_111_111, 57),
-            (Sprite::Dirt_000_111_111, 58),
-            (Sprite::Dirt_000_110_110, 59),
-            // Dirt Row 2
-            (Sprite::Dirt_010_010_010, 60),
-            (Sprite::Dirt_010_011_010, 61),
-            (Sprite::Dirt_010_111_010, 62),
-            (Sprite::Dirt_010_110_010, 63),
-            (Sprite::Dirt_010_011_011, 64),
-            (Sprite::Dirt_011_111_111, 65),
-            (Sprite::Dirt_110_111_111, 66),
-            (Sprite::Dirt_010_110_110, 67),
-            (Sprite::Dirt_011_011_011, 68),
-            (Sprite::Dirt_011_111_110, 69),
-            (Sprite::Dirt_000_000_000, 70),
-            (Sprite::Dirt_110_111_110, 71),
-            // Dirt Row 3
-            (Sprite::Dirt_010_010_000, 72),
-            (Sprite::Dirt_010_011_000, 73),
-            (Sprite::Dirt_010_111_000, 74),
-            (Sprite::Dirt_010_110_000, 75),
-            (Sprite::Dirt_011_011_010, 76),
-            (Sprite::Dirt_111_111_011, 77),
-            (Sprite::Dirt_111_111_110, 78),
-            (Sprite::Dirt_110_110_010, 79),
-            (Sprite::Dirt_011_111_011, 80),
-            (Sprite::Dirt_111_111_111, 81),
-            (Sprite::Dirt_110_111_011, 82),
-            (Sprite::Dirt_110_110_110, 83),
-            // Dirt Row 4
-            (Sprite::Dirt_000_010_000, 84),
-            (Sprite::Dirt_000_011_000, 85),
-            (Sprite::Dirt_000_111_000, 86),
-            (Sprite::Dirt_000_110_000, 87),
-            (Sprite::Dirt_010_111_110, 88),
-            (Sprite::Dirt_011_111_000, 89),
-            (Sprite::Dirt_110_111_000, 90),
-            (Sprite::Dirt_010_111_011, 91),
-            (Sprite::Dirt_011_011_000, 92),
-            (Sprite::Dirt_111_111_000, 93),
-            (Sprite::Dirt_111_111_010, 94),
-            (Sprite::Dirt_110_110_000, 95),
-            // Water
-            (Sprite::Water_0, 96),
-            (Sprite::Water_1, 97),
-            (Sprite::Water_2, 98),
-            (Sprite::Water_3, 99),
-            // Blank
-            (Sprite::Blank, 102),
-        ]),
+pub fn setup_sprites(
+    mut commands: Commands,
+    mut images: ResMut<Assets<Image>>,
+    tiled_import: Res<TiledImport>,
+    ldtk_import: Res<LdtkImport>,
+) {
+    let atlas = build_sprite_atlas(&tiled_import, &ldtk_import)
+        .unwrap_or_else(|error| panic!("Failed to build the sprite atlas: {}", error));
+    if !atlas.indices.contains_key(BLANK_SPRITE) {
+        panic!(
+            "{} has to list a {:?} tile",
+            ATLAS_MANIFEST_PATH, BLANK_SPRITE
+        );
+    }
+
+    let atlas_columns = atlas.image.width() / atlas.tile_size;
+    let atlas_rows = atlas.image.height() / atlas.tile_size;
+    let size = Extent3d {
+        width: atlas.image.width(),
+        height: atlas.image.height(),
+        depth_or_array_layers: 1,
+    };
+    let texture = images.add(Image::new(
+        size,
+        TextureDimension::D2,
+        atlas.image.into_raw(),
+        TextureFormat::Rgba8UnormSrgb,
+    ));
+
+    let sprite_registry = SpriteRegistry {
+        atlas_indices: atlas.indices,
+        texture,
+        atlas_columns,
+        atlas_rows,
     };
 
-    commands.insert_resource(sprites);
+    commands.insert_resource(sprite_registry);
 }
 
-pub fn setup_rules(mut commands: Commands) {
-    let rules = Rules {
+pub fn setup_rules(
+    mut commands: Commands,
+    sprite_registry: Res<SpriteRegistry>,
+    tiled_import: Res<TiledImport>,
+    ldtk_import: Res<LdtkImport>,
+) {
+    let rules = get_rules(&tiled_import, &ldtk_import);
+    report_unknown_sprites(&rules, &sprite_registry);
+    commands.insert_resource(rules);
+}
+
+// The built in rules, or the rule file when there is one, with the terrains defined by imported
+// tilesets and projects replaced. Apart from `setup_rules` so that maps can be resolved without an
+// app, see `render.rs`.
+pub fn get_rules(tiled_import: &TiledImport, ldtk_import: &LdtkImport) -> Rules {
+    let mut rules = get_builtin_rules();
+    // Rules saved from the rule editor replace the built in ones.
+    let rule_file_path = get_asset_root().join(RULE_FILE_PATH);
+    if rule_file_path.exists() {
+        match read_rule_file(&rule_file_path) {
+            Ok(rule_file_rules) => rules = rule_file_rules,
+            Err(error) => println!(
+                "Failed to load the rules, using the built in ones: {}",
+                error
+            ),
+        }
+    }
+    // Terrains defined by an imported tileset or project replace the built in rules.
+    for tileset in &tiled_import.tilesets {
+        let rule_set = format!("tiled/{}", tileset.name);
+        replace_rules(&mut rules, tileset.to_rules(), &rule_set);
+    }
+    let rule_set = format!("ldtk/{}", ldtk_import.name);
+    replace_rules(&mut rules, ldtk_import.rules.clone(), &rule_set);
+    rules
+}
+
+// The rules of the bundled sprite sheets, whatever the rule file or imports say.
+pub fn get_builtin_rules() -> Rules {
+    Rules {
         rules: HashMap::from([
             // Grass
             (
@@ -450,7 +567,7 @@ pub fn setup_rules(mut commands: Commands) {
                             },
                             se_slot: Slot::Any,
                         },
-                        Sprite::Grass_000_010_010,
+                        "grass/000_010_010".to_string(),
                     ),
                     // 1
                     (
@@ -471,7 +588,7 @@ pub fn setup_rules(mut commands: Commands) {
                             },
                             se_slot: Slot::Empty,
                         },
-                        Sprite::Grass_000_011_010,
+                        "grass/000_011_010".to_string(),
                     ),
                     // 2
                     (
@@ -494,7 +611,7 @@ pub fn setup_rules(mut commands: Commands) {
                             },
                             se_slot: Slot::Empty,
                         },
-                        Sprite::Grass_000_111_010,
+                        "grass/000_111_010".to_string(),
                     ),
                     // 3
                     (
@@ -515,7 +632,7 @@ pub fn setup_rules(mut commands: Commands) {
                             },
                             se_slot: Slot::Any,
                         },
-                        Sprite::Grass_000_110_010,
+                        "grass/000_110_010".to_string(),
                     ),
                     // 4
                     (
@@ -542,7 +659,7 @@ pub fn setup_rules(mut commands: Commands) {
                             },
                             se_slot: Slot::Empty,
                         },
-                        Sprite::Grass_110_111_010,
+                        "grass/110_111_010".to_string(),
                     ),
                     // 5
                     (
@@ -567,7 +684,7 @@ pub fn setup_rules(mut commands: Commands) {
                                 sprite_type: SpriteType::Grass,
                             },
                         },
-                        Sprite::Grass_000_111_011,
+                        "grass/000_111_011".to_string(),
                     ),
                     // 6
                     (
@@ -592,7 +709,7 @@ pub fn setup_rules(mut commands: Commands) {
                             },
                             se_slot: Slot::Empty,
                         },
-                        Sprite::Grass_000_111_110,
+                        "grass/000_111_110".to_string(),
                     ),
                     // 7
                     (
@@ -619,7 +736,7 @@ pub fn setup_rules(mut commands: Commands) {
                             },
                             se_slot: Slot::Empty,
                         },
-                        Sprite::Grass_011_111_010,
+                        "grass/011_111_010".to_string(),
                     ),
                     // 8
                     (
@@ -642,7 +759,7 @@ pub fn setup_rules(mut commands: Commands) {
                                 sprite_type: SpriteType::Grass,
                             },
                         },
-                        Sprite::Grass_000_011_011,
+                        "grass/000_011_011".to_string(),
                     ),
                     // 9
                     (
@@ -671,7 +788,7 @@ pub fn setup_rules(mut commands: Commands) {
                                 sprite_type: SpriteType::Grass,
                             },
                         },
-                        Sprite::Grass_010_111_111,
+                        "grass/010_111_111".to_string(),
                     ),
                     // 10
                     (
@@ -698,7 +815,7 @@ pub fn setup_rules(mut commands: Commands) {
                                 sprite_type: SpriteType::Grass,
                             },
                         },
-                        Sprite::Grass_000_111_111,
+                        "grass/000_111_111".to_string(),
                     ),
                     // 11
                     (
@@ -721,7 +838,7 @@ pub fn setup_rules(mut commands: Commands) {
                             },
                             se_slot: Slot::Any,
                         },
-                        Sprite::Grass_000_110_110,
+                        "grass/000_110_110".to_string(),
                     ),
                     // === Row 2 ===
                     // 12
@@ -743,7 +860,7 @@ pub fn setup_rules(mut commands: Commands) {
                             },
                             se_slot: Slot::Any,
                         },
-                        Sprite::Grass_010_010_010,
+                        "grass/010_010_010".to_string(),
                     ),
                     // 13
                     (
@@ -766,7 +883,7 @@ pub fn setup_rules(mut commands: Commands) {
                             },
                             se_slot: Slot::Empty,
                         },
-                        Sprite::Grass_010_011_010,
+                        "grass/010_011_010".to_string(),
                     ),
                     // 14
                     (
@@ -791,7 +908,7 @@ pub fn setup_rules(mut commands: Commands) {
                             },
                             se_slot: Slot::Empty,
                         },
-                        Sprite::Grass_010_111_010,
+                        "grass/010_111_010".to_string(),
                     ),
                     // 15
                     (
@@ -814,7 +931,7 @@ pub fn setup_rules(mut commands: Commands) {
                             },
                             se_slot: Slot::Any,
                         },
-                        Sprite::Grass_010_110_010,
+                        "grass/010_110_010".to_string(),
                     ),
                     // 16
                     (
@@ -839,7 +956,7 @@ pub fn setup_rules(mut commands: Commands) {
                                 sprite_type: SpriteType::Grass,
                             },
                         },
-                        Sprite::Grass_010_011_011,
+                        "grass/010_011_011".to_string(),
                     ),
                     // 17
                     (
@@ -870,7 +987,7 @@ pub fn setup_rules(mut commands: Commands) {
                                 sprite_type: SpriteType::Grass,
                             },
                         },
-                        Sprite::Grass_011_111_111,
+                        "grass/011_111_111".to_string(),
                     ),
                     // 18
                     (
@@ -901,7 +1018,7 @@ pub fn setup_rules(mut commands: Commands) {
                                 sprite_type: SpriteType::Grass,
                             },
                         },
-                        Sprite::Grass_110_111_111,
+                        "grass/110_111_111".to_string(),
                     ),
                     // 19
                     (
@@ -926,7 +1043,7 @@ pub fn setup_rules(mut commands: Commands) {
                             },
                             se_slot: Slot::Any,
                         },
-                        Sprite::Grass_010_110_110,
+                        "grass/010_110_110".to_string(),
                     ),
                     // 20
                     (
@@ -953,7 +1070,7 @@ pub fn setup_rules(mut commands: Commands) {
                                 sprite_type: SpriteType::Grass,
                             },
                         },
-                        Sprite::Grass_011_011_011,
+                        "grass/011_011_011".to_string(),
                     ),
                     // 21
                     (
@@ -982,7 +1099,7 @@ pub fn setup_rules(mut commands: Commands) {
                             },
                             se_slot: Slot::Empty,
                         },
-                        Sprite::Grass_011_111_110,
+                        "grass/011_111_110".to_string(),
                     ),
                     // 22
                     // No Rule for blank tile.
@@ -1013,7 +1130,7 @@ pub fn setup_rules(mut commands: Commands) {
                             },
                             se_slot: Slot::Empty,
                         },
-                        Sprite::Grass_110_111_110,
+                        "grass/110_111_110".to_string(),
                     ),
                     // === Row 3 ===
                     // 24
@@ -1033,7 +1150,7 @@ pub fn setup_rules(mut commands: Commands) {
                             s_slot: Slot::Empty,
                             se_slot: Slot::Any,
                         },
-                        Sprite::Grass_010_010_000,
+                        "grass/010_010_000".to_string(),
                     ),
                     // 25
                     (
@@ -1054,7 +1171,7 @@ pub fn setup_rules(mut commands: Commands) {
                             s_slot: Slot::Empty,
                             se_slot: Slot::Any,
                         },
-                        Sprite::Grass_010_011_000,
+                        "grass/010_011_000".to_string(),
                     ),
                     // 26
                     (
@@ -1077,7 +1194,7 @@ pub fn setup_rules(mut commands: Commands) {
                             s_slot: Slot::Empty,
                             se_slot: Slot::Any,
                         },
-                        Sprite::Grass_010_111_000,
+                        "grass/010_111_000".to_string(),
                     ),
                     // 27
                     (
@@ -1098,7 +1215,7 @@ pub fn setup_rules(mut commands: Commands) {
                             s_slot: Slot::Empty,
                             se_slot: Slot::Any,
                         },
-                        Sprite::Grass_010_110_000,
+                        "grass/010_110_000".to_string(),
                     ),
                     // 28
                     (
@@ -1123,7 +1240,7 @@ pub fn setup_rules(mut commands: Commands) {
                             },
                             se_slot: Slot::Empty,
                         },
-                        Sprite::Grass_011_011_010,
+                        "grass/011_011_010".to_string(),
                     ),
                     // 29
                     (
@@ -1154,7 +1271,7 @@ pub fn setup_rules(mut commands: Commands) {
                                 sprite_type: SpriteType::Grass,
                             },
                         },
-                        Sprite::Grass_111_111_011,
+                        "grass/111_111_011".to_string(),
                     ),
                     // 30
                     (
@@ -1185,7 +1302,7 @@ pub fn setup_rules(mut commands: Commands) {
                             },
                             se_slot: Slot::Empty,
                         },
-                        Sprite::Grass_111_111_110,
+                        "grass/111_111_110".to_string(),
                     ),
                     // 31
                     (
@@ -1210,7 +1327,7 @@ pub fn setup_rules(mut commands: Commands) {
                             },
                             se_slot: Slot::Any,
                         },
-                        Sprite::Grass_110_110_010,
+                        "grass/110_110_010".to_string(),
                     ),
                     // 32
                     (
@@ -1239,7 +1356,7 @@ pub fn setup_rules(mut commands: Commands) {
                                 sprite_type: SpriteType::Grass,
                             },
                         },
-                        Sprite::Grass_011_111_011,
+                        "grass/011_111_011".to_string(),
                     ),
                     // 33
                     (
@@ -1272,7 +1389,7 @@ pub fn setup_rules(mut commands: Commands) {
                                 sprite_type: SpriteType::Grass,
                             },
                         },
-                        Sprite::Grass_111_111_111,
+                        "grass/111_111_111".to_string(),
                     ),
                     // 34
                     (
@@ -1301,7 +1418,7 @@ pub fn setup_rules(mut commands: Commands) {
                                 sprite_type: SpriteType::Grass,
                             },
                         },
-                        Sprite::Grass_110_111_011,
+                        "grass/110_111_011".to_string(),
                     ),
                     // 35
                     (
@@ -1328,7 +1445,7 @@ pub fn setup_rules(mut commands: Commands) {
                             },
                             se_slot: Slot::Any,
                         },
-                        Sprite::Grass_110_110_110,
+                        "grass/110_110_110".to_string(),
                     ),
                     // === Row 4 ===
                     // 36
@@ -1346,7 +1463,7 @@ pub fn setup_rules(mut commands: Commands) {
                             s_slot: Slot::Empty,
                             se_slot: Slot::Any,
                         },
-                        Sprite::Grass_000_010_000,
+                        "grass/000_010_000".to_string(),
                     ),
                     // 37
                     (
@@ -1365,7 +1482,7 @@ pub fn setup_rules(mut commands: Commands) {
                             s_slot: Slot::Empty,
                             se_slot: Slot::Any,
                         },
-                        Sprite::Grass_000_011_000,
+                        "grass/000_011_000".to_string(),
                     ),
                     // 38
                     (
@@ -1386,7 +1503,7 @@ pub fn setup_rules(mut commands: Commands) {
                             s_slot: Slot::Empty,
                             se_slot: Slot::Any,
                         },
-                        Sprite::Grass_000_111_000,
+                        "grass/000_111_000".to_string(),
                     ),
                     // 39
                     (
@@ -1405,7 +1522,7 @@ pub fn setup_rules(mut commands: Commands) {
                             s_slot: Slot::Empty,
                             se_slot: Slot::Any,
                         },
-                        Sprite::Grass_000_110_000,
+                        "grass/000_110_000".to_string(),
                     ),
                     // 40
                     (
@@ -1432,7 +1549,7 @@ pub fn setup_rules(mut commands: Commands) {
                             },
                             se_slot: Slot::Empty,
                         },
-                        Sprite::Grass_010_111_110,
+                        "grass/010_111_110".to_string(),
                     ),
                     // 41
                     (
@@ -1457,7 +1574,7 @@ pub fn setup_rules(mut commands: Commands) {
                             s_slot: Slot::Empty,
                             se_slot: Slot::Any,
                         },
-                        Sprite::Grass_011_111_000,
+                        "grass/011_111_000".to_string(),
                     ),
                     // 42
                     (
@@ -1482,7 +1599,7 @@ pub fn setup_rules(mut commands: Commands) {
                             s_slot: Slot::Empty,
                             se_slot: Slot::Any,
                         },
-                        Sprite::Grass_110_111_000,
+                        "grass/110_111_000".to_string(),
                     ),
                     // 43
                     (
@@ -1509,7 +1626,7 @@ pub fn setup_rules(mut commands: Commands) {
                                 sprite_type: SpriteType::Grass,
                             },
                         },
-                        Sprite::Grass_010_111_011,
+                        "grass/010_111_011".to_string(),
                     ),
                     // 44
                     (
@@ -1532,7 +1649,7 @@ pub fn setup_rules(mut commands: Commands) {
                             s_slot: Slot::Empty,
                             se_slot: Slot::Any,
                         },
-                        Sprite::Grass_011_011_000,
+                        "grass/011_011_000".to_string(),
                     ),
                     // 45
                     (
@@ -1559,7 +1676,7 @@ pub fn setup_rules(mut commands: Commands) {
                             s_slot: Slot::Empty,
                             se_slot: Slot::Any,
                         },
-                        Sprite::Grass_111_111_000,
+                        "grass/111_111_000".to_string(),
                     ),
                     // 46
                     (
@@ -1588,7 +1705,7 @@ pub fn setup_rules(mut commands: Commands) {
                             },
                             se_slot: Slot::Empty,
                         },
-                        Sprite::Grass_111_111_010,
+                        "grass/111_111_010".to_string(),
                     ),
                     // 47
                     (
@@ -1611,7 +1728,7 @@ pub fn setup_rules(mut commands: Commands) {
                             s_slot: Slot::Empty,
                             se_slot: Slot::Any,
                         },
-                        Sprite::Grass_110_110_000,
+                        "grass/110_110_000".to_string(),
                     ),
                     // Custom Rules
                 ]),
@@ -1639,7 +1756,7 @@ pub fn setup_rules(mut commands: Commands) {
                             },
                             se_slot: Slot::Any,
                         },
-                        Sprite::Dirt_000_010_010,
+                        "dirt/000_010_010".to_string(),
                     ),
                     // 1
                     (
@@ -1660,7 +1777,7 @@ pub fn setup_rules(mut commands: Commands) {
                             },
                             se_slot: Slot::Empty,
                         },
-                        Sprite::Dirt_000_011_010,
+                        "dirt/000_011_010".to_string(),
                     ),
                     // 2
                     (
@@ -1683,7 +1800,7 @@ pub fn setup_rules(mut commands: Commands) {
                             },
                             se_slot: Slot::Empty,
                         },
-                        Sprite::Dirt_000_111_010,
+                        "dirt/000_111_010".to_string(),
                     ),
                     // 3
                     (
@@ -1704,7 +1821,7 @@ pub fn setup_rules(mut commands: Commands) {
                             },
                             se_slot: Slot::Any,
                         },
-                        Sprite::Dirt_000_110_010,
+                        "dirt/000_110_010".to_string(),
                     ),
                     // 4
                     (
@@ -1731,7 +1848,7 @@ pub fn setup_rules(mut commands: Commands) {
                             },
                             se_slot: Slot::Empty,
                         },
-                        Sprite::Dirt_110_111_010,
+                        "dirt/110_111_010".to_string(),
                     ),
                     // 5
                     (
@@ -1756,7 +1873,7 @@ pub fn setup_rules(mut commands: Commands) {
                                 sprite_type: SpriteType::Dirt,
                             },
                         },
-                        Sprite::Dirt_000_111_011,
+                        "dirt/000_111_011".to_string(),
                     ),
                     // 6
                     (
@@ -1781,7 +1898,7 @@ pub fn setup_rules(mut commands: Commands) {
                             },
                             se_slot: Slot::Empty,
                         },
-                        Sprite::Dirt_000_111_110,
+                        "dirt/000_111_110".to_string(),
                     ),
                     // 7
                     (
@@ -1808,7 +1925,7 @@ pub fn setup_rules(mut commands: Commands) {
                             },
                             se_slot: Slot::Empty,
                         },
-                        Sprite::Dirt_011_111_010,
+                        "dirt/011_111_010".to_string(),
                     ),
                     // 8
                     (
@@ -1831,7 +1948,7 @@ pub fn setup_rules(mut commands: Commands) {
                                 sprite_type: SpriteType::Dirt,
                             },
                         },
-                        Sprite::Dirt_000_011_011,
+                        "dirt/000_011_011".to_string(),
                     ),
                     // 9
                     (
@@ -1860,7 +1977,7 @@ pub fn setup_rules(mut commands: Commands) {
                                 sprite_type: SpriteType::Dirt,
                             },
                         },
-                        Sprite::Dirt_010_111_111,
+                        "dirt/010_111_111".to_string(),
                     ),
                     // 10
                     (
@@ -1887,7 +2004,7 @@ pub fn setup_rules(mut commands: Commands) {
                                 sprite_type: SpriteType::Dirt,
                             },
                         },
-                        Sprite::Dirt_000_111_111,
+                        "dirt/000_111_111".to_string(),
                     ),
                     // 11
                     (
@@ -1910,7 +2027,7 @@ pub fn setup_rules(mut commands: Commands) {
                             },
                             se_slot: Slot::Any,
                         },
-                        Sprite::Dirt_000_110_110,
+                        "dirt/000_110_110".to_string(),
                     ),
                     // === Row 2 ===
                     // 12
@@ -1932,7 +2049,7 @@ pub fn setup_rules(mut commands: Commands) {
                             },
                             se_slot: Slot::Any,
                         },
-                        Sprite::Dirt_010_010_010,
+                        "dirt/010_010_010".to_string(),
                     ),
                     // 13
                     (
@@ -1955,7 +2072,7 @@ pub fn setup_rules(mut commands: Commands) {
                             },
                             se_slot: Slot::Empty,
                         },
-                        Sprite::Dirt_010_011_010,
+                        "dirt/010_011_010".to_string(),
                     ),
                     // 14
                     (
@@ -1980,7 +2097,7 @@ pub fn setup_rules(mut commands: Commands) {
                             },
                             se_slot: Slot::Empty,
                         },
-                        Sprite::Dirt_010_111_010,
+                        "dirt/010_111_010".to_string(),
                     ),
                     // 15
                     (
@@ -2003,7 +2120,7 @@ pub fn setup_rules(mut commands: Commands) {
                             },
                             se_slot: Slot::Any,
                         },
-                        Sprite::Dirt_010_110_010,
+                        "dirt/010_110_010".to_string(),
                     ),
                     // 16
                     (
@@ -2028,7 +2145,7 @@ pub fn setup_rules(mut commands: Commands) {
                                 sprite_type: SpriteType::Dirt,
                             },
                         },
-                        Sprite::Dirt_010_011_011,
+                        "dirt/010_011_011".to_string(),
                     ),
                     // 17
                     (
@@ -2059,7 +2176,7 @@ pub fn setup_rules(mut commands: Commands) {
                                 sprite_type: SpriteType::Dirt,
                             },
                         },
-                        Sprite::Dirt_011_111_111,
+                        "dirt/011_111_111".to_string(),
                     ),
                     // 18
                     (
@@ -2090,7 +2207,7 @@ pub fn setup_rules(mut commands: Commands) {
                                 sprite_type: SpriteType::Dirt,
                             },
                         },
-                        Sprite::Dirt_110_111_111,
+                        "dirt/110_111_111".to_string(),
                     ),
                     // 19
                     (
@@ -2115,7 +2232,7 @@ pub fn setup_rules(mut commands: Commands) {
                             },
                             se_slot: Slot::Any,
                         },
-                        Sprite::Dirt_010_110_110,
+                        "dirt/010_110_110".to_string(),
                     ),
                     // 20
                     (
@@ -2142,7 +2259,7 @@ pub fn setup_rules(mut commands: Commands) {
                                 sprite_type: SpriteType::Dirt,
                             },
                         },
-                        Sprite::Dirt_011_011_011,
+                        "dirt/011_011_011".to_string(),
                     ),
                     // 21
                     (
@@ -2171,7 +2288,7 @@ pub fn setup_rules(mut commands: Commands) {
                             },
                             se_slot: Slot::Empty,
                         },
-                        Sprite::Dirt_011_111_110,
+                        "dirt/011_111_110".to_string(),
                     ),
                     // 22
                     // No Rule for blank tile.
@@ -2202,7 +2319,7 @@ pub fn setup_rules(mut commands: Commands) {
                             },
                             se_slot: Slot::Empty,
                         },
-                        Sprite::Dirt_110_111_110,
+                        "dirt/110_111_110".to_string(),
                     ),
                     // === Row 3 ===
                     // 24
@@ -2222,7 +2339,7 @@ pub fn setup_rules(mut commands: Commands) {
                             s_slot: Slot::Empty,
                             se_slot: Slot::Any,
                         },
-                        Sprite::Dirt_010_010_000,
+                        "dirt/010_010_000".to_string(),
                     ),
                     // 25
                     (
@@ -2243,7 +2360,7 @@ pub fn setup_rules(mut commands: Commands) {
                             s_slot: Slot::Empty,
                             se_slot: Slot::Any,
                         },
-                        Sprite::Dirt_010_011_000,
+                        "dirt/010_011_000".to_string(),
                     ),
                     // 26
                     (
@@ -2266,7 +2383,7 @@ pub fn setup_rules(mut commands: Commands) {
                             s_slot: Slot::Empty,
                             se_slot: Slot::Any,
                         },
-                        Sprite::Dirt_010_111_000,
+                        "dirt/010_111_000".to_string(),
                     ),
                     // 27
                     (
@@ -2287,7 +2404,7 @@ pub fn setup_rules(mut commands: Commands) {
                             s_slot: Slot::Empty,
                             se_slot: Slot::Any,
                         },
-                        Sprite::Dirt_010_110_000,
+                        "dirt/010_110_000".to_string(),
                     ),
                     // 28
                     (
@@ -2312,7 +2429,7 @@ pub fn setup_rules(mut commands: Commands) {
                             },
                             se_slot: Slot::Empty,
                         },
-                        Sprite::Dirt_011_011_010,
+                        "dirt/011_011_010".to_string(),
                     ),
                     // 29
                     (
@@ -2343,7 +2460,7 @@ pub fn setup_rules(mut commands: Commands) {
                                 sprite_type: SpriteType::Dirt,
                             },
                         },
-                        Sprite::Dirt_111_111_011,
+                        "dirt/111_111_011".to_string(),
                     ),
                     // 30
                     (
@@ -2374,7 +2491,7 @@ pub fn setup_rules(mut commands: Commands) {
                             },
                             se_slot: Slot::Empty,
                         },
-                        Sprite::Dirt_111_111_110,
+                        "dirt/111_111_110".to_string(),
                     ),
                     // 31
                     (
@@ -2399,7 +2516,7 @@ pub fn setup_rules(mut commands: Commands) {
                             },
                             se_slot: Slot::Any,
                         },
-                        Sprite::Dirt_110_110_010,
+                        "dirt/110_110_010".to_string(),
                     ),
                     // 32
                     (
@@ -2428,7 +2545,7 @@ pub fn setup_rules(mut commands: Commands) {
                                 sprite_type: SpriteType::Dirt,
                             },
                         },
-                        Sprite::Dirt_011_111_011,
+                        "dirt/011_111_011".to_string(),
                     ),
                     // 33
                     (
@@ -2461,7 +2578,7 @@ pub fn setup_rules(mut commands: Commands) {
                                 sprite_type: SpriteType::Dirt,
                             },
                         },
-                        Sprite::Dirt_111_111_111,
+                        "dirt/111_111_111".to_string(),
                     ),
                     // 34
                     (
@@ -2490,7 +2607,7 @@ pub fn setup_rules(mut commands: Commands) {
                                 sprite_type: SpriteType::Dirt,
                             },
                         },
-                        Sprite::Dirt_110_111_011,
+                        "dirt/110_111_011".to_string(),
                     ),
                     // 35
                     (
@@ -2517,7 +2634,7 @@ pub fn setup_rules(mut commands: Commands) {
                             },
                             se_slot: Slot::Any,
                         },
-                        Sprite::Dirt_110_110_110,
+                        "dirt/110_110_110".to_string(),
                     ),
                     // === Row 4 ===
                     // 36
@@ -2535,7 +2652,7 @@ pub fn setup_rules(mut commands: Commands) {
                             s_slot: Slot::Empty,
                             se_slot: Slot::Any,
                         },
-                        Sprite::Dirt_000_010_000,
+                        "dirt/000_010_000".to_string(),
                     ),
                     // 37
                     (
@@ -2554,7 +2671,7 @@ pub fn setup_rules(mut commands: Commands) {
                             s_slot: Slot::Empty,
                             se_slot: Slot::Any,
                         },
-                        Sprite::Dirt_000_011_000,
+                        "dirt/000_011_000".to_string(),
                     ),
                     // 38
                     (
@@ -2575,7 +2692,7 @@ pub fn setup_rules(mut commands: Commands) {
                             s_slot: Slot::Empty,
                             se_slot: Slot::Any,
                         },
-                        Sprite::Dirt_000_111_000,
+                        "dirt/000_111_000".to_string(),
                     ),
                     // 39
                     (
@@ -2594,7 +2711,7 @@ pub fn setup_rules(mut commands: Commands) {
                             s_slot: Slot::Empty,
                             se_slot: Slot::Any,
                         },
-                        Sprite::Dirt_000_110_000,
+                        "dirt/000_110_000".to_string(),
                     ),
                     // 40
                     (
@@ -2621,7 +2738,7 @@ pub fn setup_rules(mut commands: Commands) {
                             },
                             se_slot: Slot::Empty,
                         },
-                        Sprite::Dirt_010_111_110,
+                        "dirt/010_111_110".to_string(),
                     ),
                     // 41
                     (
@@ -2646,7 +2763,7 @@ pub fn setup_rules(mut commands: Commands) {
                             s_slot: Slot::Empty,
                             se_slot: Slot::Any,
                         },
-                        Sprite::Dirt_011_111_000,
+                        "dirt/011_111_000".to_string(),
                     ),
                     // 42
                     (
@@ -2671,7 +2788,7 @@ pub fn setup_rules(mut commands: Commands) {
                             s_slot: Slot::Empty,
                             se_slot: Slot::Any,
                         },
-                        Sprite::Dirt_110_111_000,
+                        "dirt/110_111_000".to_string(),
                     ),
                     // 43
                     (
@@ -2698,7 +2815,7 @@ pub fn setup_rules(mut commands: Commands) {
                                 sprite_type: SpriteType::Dirt,
                             },
                         },
-                        Sprite::Dirt_010_111_011,
+                        "dirt/010_111_011".to_string(),
                     ),
                     // 44
                     (
@@ -2721,7 +2838,7 @@ pub fn setup_rules(mut commands: Commands) {
                             s_slot: Slot::Empty,
                             se_slot: Slot::Any,
                         },
-                        Sprite::Dirt_011_011_000,
+                        "dirt/011_011_000".to_string(),
                     ),
                     // 45
                     (
@@ -2748,7 +2865,7 @@ pub fn setup_rules(mut commands: Commands) {
                             s_slot: Slot::Empty,
                             se_slot: Slot::Any,
                         },
-                        Sprite::Dirt_111_111_000,
+                        "dirt/111_111_000".to_string(),
                     ),
                     // 46
                     (
@@ -2777,7 +2894,7 @@ pub fn setup_rules(mut commands: Commands) {
                             },
                             se_slot: Slot::Empty,
                         },
-                        Sprite::Dirt_111_111_010,
+                        "dirt/111_111_010".to_string(),
                     ),
                     // 47
                     (
@@ -2800,24 +2917,53 @@ pub fn setup_rules(mut commands: Commands) {
                             s_slot: Slot::Empty,
                             se_slot: Slot::Any,
                         },
-                        Sprite::Dirt_110_110_000,
+                        "dirt/110_110_000".to_string(),
                     ),
                     // Custom Rules
                 ]),
             ),
         ]),
-    };
-    commands.insert_resource(rules);
+        rule_set: BUILTIN_RULE_SET.to_string(),
+        replaced_rules: HashMap::new(),
+    }
+}
+
+// Replaces the rules of every autotiled terrain in `imported_rules`, remembering the rules it had
+// before the first import replaced them. Rules of the other terrains would never be used, as their
+// tiles keep the texture they are painted with, so they are reported and left out.
+fn replace_rules(
+    rules: &mut Rules,
+    imported_rules: HashMap<SpriteType, Vec<(Rule, String)>>,
+    rule_set: &str,
+) {
+    for (sprite_type, possible_rules) in imported_rules {
+        if !AUTOTILED_TYPES.contains(&sprite_type) {
+            println!(
+                "{} has {} rules for {:?}, which is not autotiled, they are ignored",
+                rule_set,
+                possible_rules.len(),
+                sprite_type
+            );
+            continue;
+        }
+        rules.rule_set = rule_set.to_string();
+        let previous_rules = rules.rules.insert(sprite_type, possible_rules);
+        rules
+            .replaced_rules
+            .entry(sprite_type)
+            .or_insert_with(|| previous_rules.unwrap_or_default());
+    }
 }
 
 pub fn setup_active_rules(mut commands: Commands) {
     let active_rules = ActiveRules {
         active_rules: HashMap::new(),
+        changed_positions: None,
     };
     commands.insert_resource(active_rules);
 }
 
-pub fn setup_tilemap(mut commands: Commands, asset_server: Res<AssetServer>) {
+pub fn setup_tilemap(mut commands: Commands, sprite_registry: Res<SpriteRegistry>) {
     let tilemap_size = TilemapSize {
         x: MAP_WIDTH as u32,
         y: MAP_HEIGHT as u32,
@@ -2833,7 +2979,7 @@ pub fn setup_tilemap(mut commands: Commands, asset_server: Res<AssetServer>) {
                 .spawn()
                 .insert_bundle(TileBundle {
                     position: tile_position,
-                    texture: TileTexture(102), // 102 is blank
+                    texture: TileTexture(sprite_registry.get_blank_index()),
                     tilemap_id: TilemapId(tilemap_entity),
                     ..default()
                 })
@@ -2850,8 +2996,7 @@ pub fn setup_tilemap(mut commands: Commands, asset_server: Res<AssetServer>) {
         x: TILE_SIZE as f32,
         y: TILE_SIZE as f32,
     };
-    let image_handle: Handle<Image> = asset_server.load("sprites/all_sprites.png");
-    let tilemap_texture = TilemapTexture::Single(image_handle);
+    let tilemap_texture = TilemapTexture::Single(sprite_registry.texture.clone());
 
     commands
         .entity(tilemap_entity)
@@ -2866,7 +3011,8 @@ pub fn setup_tilemap(mut commands: Commands, asset_server: Res<AssetServer>) {
             tile_size,
             transform: Transform::from_xyz(0.0, 0.0, 0.0),
             ..Default::default()
-        });
+        })
+        .insert(GroundLayer {});
 }
 
 pub fn setup_game(mut commands: Commands) {
@@ -2880,554 +3026,390 @@ pub fn setup_game(mut commands: Commands) {
 pub fn place_tile(
     mut commands: Commands,
     mut update_tilemap_event_writer: EventWriter<UpdateTilemapEvent>,
+    mut edit_history: ResMut<EditHistory>,
+    mut tool_state: ResMut<ToolState>,
+    clipboard: Res<Clipboard>,
     game_state: Res<GameState>,
     mouse: Res<Mouse>,
-    // mouse_input: Res<Input<MouseButton>>,
-    tilemap_query: Query<(
-        &TilemapSize,
-        &TilemapGridSize,
-        &TilemapType,
-        &TileStorage,
-        &Transform,
-    )>,
+    sprite_registry: Res<SpriteRegistry>,
+    tilemap_query: Query<
+        (
+            &TilemapSize,
+            &TilemapGridSize,
+            &TilemapType,
+            &TileStorage,
+            &Transform,
+        ),
+        With<GroundLayer>,
+    >,
+    terrain_query: Query<(Option<&GrassTile>, Option<&DirtTile>, Option<&WaterTile>)>,
 ) {
-    if mouse.holding_lmb {
-        // if mouse_input.just_pressed(MouseButton::Left) {
-        for (map_size, grid_size, map_type, tile_storage, map_transform) in tilemap_query.iter() {
-            // Grab the cursor position from the `Res<CursorPos>`
-            let cursor_pos: Vec3 = mouse.world_position;
-            // We need to make sure that the cursor's world position is correct relative to the map
-            // due to any map transformation.
-            let cursor_in_map_pos: Vec2 = {
-                // Extend the cursor_pos vec3 by 1.0
-                let cursor_pos = Vec4::from((cursor_pos, 1.0));
-                let cursor_in_map_pos = map_transform.compute_matrix().inverse() * cursor_pos;
-                cursor_in_map_pos.xy()
-            };
-            // Once we have a world position we can transform it into a possible tile position.
-            if let Some(tile_position) =
-            TilePos::from_world_pos(&cursor_in_map_pos, map_size, grid_size, map_type)
-            {
-                if let Some(tile_entity) = tile_storage.get(&tile_position) {
-                    commands.entity(tile_entity).remove::<GrassTile>();
-                    commands.entity(tile_entity).remove::<DirtTile>();
-                    commands.entity(tile_entity).remove::<WaterTile>();
-                    match game_state.selection {
-                        SpriteType::Grass => {
-                            commands.entity(tile_entity).insert(GrassTile {});
-                        }
-                        SpriteType::Dirt => {
-                            commands.entity(tile_entity).insert(DirtTile {});
-                        }
-                        SpriteType::Water => {
-                            commands.entity(tile_entity).insert(WaterTile {});
-                        }
-                        _ => {
-                            // Do Nothing
-                        }
-                    }
-                    update_tilemap_event_writer.send(UpdateTilemapEvent {});
-                }
-            }
+    if is_selecting(&tool_state, &clipboard) {
+        return;
+    }
+    // Fences, paths and structures are placed on top of the ground by their own systems.
+    let sprite_type = match game_state.selection {
+        SpriteType::Blank => None,
+        SpriteType::Grass | SpriteType::Dirt | SpriteType::Water => Some(game_state.selection),
+        _ => return,
+    };
+    let (map_size, grid_size, map_type, tile_storage, map_transform) =
+        match tilemap_query.get_single() {
+            Ok(ground_layer) => ground_layer,
+            Err(_) => return,
+        };
+    // Grab the cursor position from the `Res<CursorPos>`
+    let cursor_pos: Vec3 = mouse.world_position;
+    let tile_position =
+        world_position_to_tile_position(cursor_pos, map_size, grid_size, map_type, map_transform);
+
+    // Work out the stroke to paint this frame, if any.
+    let stroke = if mouse.holding_lmb {
+        let tile_position = match tile_position {
+            Some(tile_position) => tile_position,
+            None => return,
+        };
+        let is_stroke_start = tool_state.stroke_start.is_none();
+        if is_stroke_start {
+            tool_state.stroke_start = Some(tile_position);
+        }
+        let last_tile_position = tool_state.last_tile_position.replace(tile_position);
+        match tool_state.tool {
+            // Walk from the previous sample so fast drags do not leave gaps.
+            Tool::Freehand => Some((last_tile_position.unwrap_or(tile_position), tile_position)),
+            Tool::FloodFill if is_stroke_start => Some((tile_position, tile_position)),
+            _ => None,
+        }
+    } else {
+        // Shapes are painted once the mouse button is released, where the cursor was last seen on
+        // the map.
+        let stroke_start = tool_state.stroke_start.take();
+        let last_tile_position = tool_state.last_tile_position.take();
+        match (stroke_start, last_tile_position) {
+            (Some(start), Some(end)) if tool_state.is_shape() => Some((start, end)),
+            _ => None,
+        }
+    };
+    let (start, end) = match stroke {
+        Some(stroke) => stroke,
+        None => return,
+    };
+
+    let terrain_grid = if tool_state.tool == Tool::FloodFill {
+        get_terrain_grid(map_size, tile_storage, &terrain_query)
+    } else {
+        TerrainGrid::new(0, 0)
+    };
+    let cells = tool_state.get_cells(
+        IVec2::new(start.x as i32, start.y as i32),
+        IVec2::new(end.x as i32, end.y as i32),
+        &terrain_grid,
+    );
+
+    // The whole batch is painted at once, then autotiled once.
+    let mut is_painted = false;
+    for cell in cells {
+        if cell.x < 0 || cell.y < 0 || cell.x >= map_size.x as i32 || cell.y >= map_size.y as i32 {
+            continue;
+        }
+        let tile_position = TilePos {
+            x: cell.x as u32,
+            y: cell.y as u32,
+        };
+        if let Some(tile_entity) = tile_storage.get(&tile_position) {
+            let previous_sprite_type = get_tile_terrain(tile_entity, &terrain_query);
+            edit_history.record(tile_position, previous_sprite_type, sprite_type);
+            set_tile_terrain(&mut commands, tile_entity, sprite_type, &sprite_registry);
+            is_painted = true;
         }
     }
+    if is_painted {
+        update_tilemap_event_writer.send(UpdateTilemapEvent {});
+    }
 }
 
 pub fn update_active_rules(
     mut update_tilemap_event_reader: EventReader<UpdateTilemapEvent>,
+    mut update_tiles_event_reader: EventReader<UpdateTilesEvent>,
     grass_tiles_query: Query<&TilePos, (With<GrassTile>, Without<DirtTile>, Without<WaterTile>)>,
     dirt_tiles_query: Query<&TilePos, (With<DirtTile>, Without<GrassTile>, Without<WaterTile>)>,
     water_tiles_query: Query<&TilePos, (With<WaterTile>, Without<GrassTile>, Without<DirtTile>)>,
-    mut tilemap_query: Query<(&TileStorage, &TilemapType)>,
+    structure_footprints_query: Query<(), With<StructureFootprint>>,
+    mut tilemap_query: Query<(&TileStorage, &TilemapType), With<GroundLayer>>,
     mut active_rules: ResMut<ActiveRules>,
 ) {
-    for _ in update_tilemap_event_reader.iter() {
-        if let Ok((tile_storage, tilemap_type)) = tilemap_query.get_single_mut() {
-            // Clear Previous Active Rules
-            active_rules.active_rules.clear();
-
-            // Grass Tiles
-            for tile_position in grass_tiles_query.iter() {
-                let neighbors = get_tile_neighbors(tile_position, tile_storage, tilemap_type);
-
-                // NW
-                let north_west_slot: Slot;
-                if let Some(nw_neighbor) = neighbors.north_west {
-                    // Can also change this system to only check for Grass Tiles and resolve others to Any.
-                    if grass_tiles_query.contains(nw_neighbor) {
-                        north_west_slot = Slot::Filled {
-                            sprite_type: SpriteType::Grass,
-                        };
-                    } else if dirt_tiles_query.contains(nw_neighbor) {
-                        north_west_slot = Slot::Filled {
-                            sprite_type: SpriteType::Dirt,
-                        }
-                    } else if water_tiles_query.contains(nw_neighbor) {
-                        north_west_slot = Slot::Filled {
-                            sprite_type: SpriteType::Water,
-                        }
-                    } else {
-                        north_west_slot = Slot::Empty
-                    }
-                } else {
-                    north_west_slot = Slot::Empty
-                }
-
-                // N
-                let north_slot: Slot;
-                if let Some(north_neighbor_entity) = neighbors.north {
-                    // Can also change this system to only check for Grass Tiles and resolve others to Any.
-                    if grass_tiles_query.contains(north_neighbor_entity) {
-                        north_slot = Slot::Filled {
-                            sprite_type: SpriteType::Grass,
-                        }
-                    } else if dirt_tiles_query.contains(north_neighbor_entity) {
-                        north_slot = Slot::Filled {
-                            sprite_type: SpriteType::Dirt,
-                        }
-                    } else if water_tiles_query.contains(north_neighbor_entity) {
-                        north_slot = Slot::Filled {
-                            sprite_type: SpriteType::Water,
-                        }
-                    } else {
-                        north_slot = Slot::Empty
-                    }
-                } else {
-                    north_slot = Slot::Empty
-                }
-
-                // NE
-                let north_east_slot: Slot;
-                if let Some(north_east_neighbor_entity) = neighbors.north_east {
-                    // Can also change this system to only check for Grass Tiles and resolve others to Any.
-                    if grass_tiles_query.contains(north_east_neighbor_entity) {
-                        north_east_slot = Slot::Filled {
-                            sprite_type: SpriteType::Grass,
-                        }
-                    } else if dirt_tiles_query.contains(north_east_neighbor_entity) {
-                        north_east_slot = Slot::Filled {
-                            sprite_type: SpriteType::Dirt,
-                        }
-                    } else if water_tiles_query.contains(north_east_neighbor_entity) {
-                        north_east_slot = Slot::Filled {
-                            sprite_type: SpriteType::Water,
-                        }
-                    } else {
-                        north_east_slot = Slot::Empty
-                    }
-                } else {
-                    north_east_slot = Slot::Empty
-                }
-
-                // W
-                let west_slot: Slot;
-                if let Some(west_neighbor_entity) = neighbors.west {
-                    // Can also change this system to only check for Grass Tiles and resolve others to Any.
-                    if grass_tiles_query.contains(west_neighbor_entity) {
-                        west_slot = Slot::Filled {
-                            sprite_type: SpriteType::Grass,
-                        }
-                    } else if dirt_tiles_query.contains(west_neighbor_entity) {
-                        west_slot = Slot::Filled {
-                            sprite_type: SpriteType::Dirt,
-                        }
-                    } else if water_tiles_query.contains(west_neighbor_entity) {
-                        west_slot = Slot::Filled {
-                            sprite_type: SpriteType::Water,
-                        }
-                    } else {
-                        west_slot = Slot::Empty
-                    }
-                } else {
-                    west_slot = Slot::Empty
-                }
-
-                // C
-                let center_slot = Slot::Filled {
+    // Resolves the slot a neighbor fills from the point of view of a tile of `sprite_type`.
+    let get_neighbor_slot = |neighbor: Option<Entity>, sprite_type: SpriteType| -> Slot {
+        if let Some(neighbor_entity) = neighbor {
+            // Structures sit on top of the ground and count as more of the surrounding terrain,
+            // so the ground around them resolves as if it continued underneath.
+            if structure_footprints_query.contains(neighbor_entity) {
+                Slot::Filled { sprite_type }
+            } else if grass_tiles_query.contains(neighbor_entity) {
+                Slot::Filled {
                     sprite_type: SpriteType::Grass,
-                };
-
-                // E
-                let east_slot: Slot;
-                if let Some(east_neighbor_entity) = neighbors.east {
-                    // Can also change this system to only check for Grass Tiles and resolve others to Any.
-                    if grass_tiles_query.contains(east_neighbor_entity) {
-                        east_slot = Slot::Filled {
-                            sprite_type: SpriteType::Grass,
-                        }
-                    } else if dirt_tiles_query.contains(east_neighbor_entity) {
-                        east_slot = Slot::Filled {
-                            sprite_type: SpriteType::Dirt,
-                        }
-                    } else if water_tiles_query.contains(east_neighbor_entity) {
-                        east_slot = Slot::Filled {
-                            sprite_type: SpriteType::Water,
-                        }
-                    } else {
-                        east_slot = Slot::Empty
-                    }
-                } else {
-                    east_slot = Slot::Empty
                 }
-
-                // SW
-                let south_west_slot: Slot;
-                if let Some(south_west_neighbor_entity) = neighbors.south_west {
-                    // Can also change this system to only check for Grass Tiles and resolve others to Any.
-                    if grass_tiles_query.contains(south_west_neighbor_entity) {
-                        south_west_slot = Slot::Filled {
-                            sprite_type: SpriteType::Grass,
-                        }
-                    } else if dirt_tiles_query.contains(south_west_neighbor_entity) {
-                        south_west_slot = Slot::Filled {
-                            sprite_type: SpriteType::Dirt,
-                        }
-                    } else if water_tiles_query.contains(south_west_neighbor_entity) {
-                        south_west_slot = Slot::Filled {
-                            sprite_type: SpriteType::Water,
-                        }
-                    } else {
-                        south_west_slot = Slot::Empty
-                    }
-                } else {
-                    south_west_slot = Slot::Empty
-                }
-
-                // S
-                let south_slot: Slot;
-                if let Some(south_neighbor_entity) = neighbors.south {
-                    // Can also change this system to only check for Grass Tiles and resolve others to Any.
-                    if grass_tiles_query.contains(south_neighbor_entity) {
-                        south_slot = Slot::Filled {
-                            sprite_type: SpriteType::Grass,
-                        }
-                    } else if dirt_tiles_query.contains(south_neighbor_entity) {
-                        south_slot = Slot::Filled {
-                            sprite_type: SpriteType::Dirt,
-                        }
-                    } else if water_tiles_query.contains(south_neighbor_entity) {
-                        south_slot = Slot::Filled {
-                            sprite_type: SpriteType::Water,
-                        }
-                    } else {
-                        south_slot = Slot::Empty
-                    }
-                } else {
-                    south_slot = Slot::Empty
+            } else if dirt_tiles_query.contains(neighbor_entity) {
+                Slot::Filled {
+                    sprite_type: SpriteType::Dirt,
                 }
-
-                // SE
-                let south_east_slot: Slot;
-                if let Some(south_east_neighbor_entity) = neighbors.south_east {
-                    // Can also change this system to only check for Grass Tiles and resolve others to Any.
-                    if grass_tiles_query.contains(south_east_neighbor_entity) {
-                        south_east_slot = Slot::Filled {
-                            sprite_type: SpriteType::Grass,
-                        }
-                    } else if dirt_tiles_query.contains(south_east_neighbor_entity) {
-                        south_east_slot = Slot::Filled {
-                            sprite_type: SpriteType::Dirt,
-                        }
-                    } else if water_tiles_query.contains(south_east_neighbor_entity) {
-                        south_east_slot = Slot::Filled {
-                            sprite_type: SpriteType::Water,
-                        }
-                    } else {
-                        south_east_slot = Slot::Empty
-                    }
-                } else {
-                    south_east_slot = Slot::Empty
+            } else if water_tiles_query.contains(neighbor_entity) {
+                Slot::Filled {
+                    sprite_type: SpriteType::Water,
                 }
-
-                let current_rule = Rule {
-                    nw_slot: north_west_slot,
-                    n_slot: north_slot,
-                    ne_slot: north_east_slot,
-                    w_slot: west_slot,
-                    c_slot: center_slot,
-                    e_slot: east_slot,
-                    sw_slot: south_west_slot,
-                    s_slot: south_slot,
-                    se_slot: south_east_slot,
-                };
-
-                active_rules
-                    .active_rules
-                    .insert(*tile_position, current_rule);
+            } else {
+                Slot::Empty
             }
+        } else {
+            Slot::Empty
+        }
+    };
 
-            // Dirt Tiles
-            for tile_position in dirt_tiles_query.iter() {
-                let neighbors = get_tile_neighbors(tile_position, tile_storage, tilemap_type);
-
-                // NW
-                let north_west_slot: Slot;
-                if let Some(nw_neighbor) = neighbors.north_west {
-                    // Can also change this system to only check for Grass Tiles and resolve others to Any.
-                    if grass_tiles_query.contains(nw_neighbor) {
-                        north_west_slot = Slot::Filled {
-                            sprite_type: SpriteType::Grass,
-                        };
-                    } else if dirt_tiles_query.contains(nw_neighbor) {
-                        north_west_slot = Slot::Filled {
-                            sprite_type: SpriteType::Dirt,
-                        }
-                    } else if water_tiles_query.contains(nw_neighbor) {
-                        north_west_slot = Slot::Filled {
-                            sprite_type: SpriteType::Water,
-                        }
-                    } else {
-                        north_west_slot = Slot::Empty
-                    }
-                } else {
-                    north_west_slot = Slot::Empty
-                }
-
-                // N
-                let north_slot: Slot;
-                if let Some(north_neighbor_entity) = neighbors.north {
-                    // Can also change this system to only check for Grass Tiles and resolve others to Any.
-                    if grass_tiles_query.contains(north_neighbor_entity) {
-                        north_slot = Slot::Filled {
-                            sprite_type: SpriteType::Grass,
-                        }
-                    } else if dirt_tiles_query.contains(north_neighbor_entity) {
-                        north_slot = Slot::Filled {
-                            sprite_type: SpriteType::Dirt,
-                        }
-                    } else if water_tiles_query.contains(north_neighbor_entity) {
-                        north_slot = Slot::Filled {
-                            sprite_type: SpriteType::Water,
-                        }
-                    } else {
-                        north_slot = Slot::Empty
-                    }
-                } else {
-                    north_slot = Slot::Empty
-                }
-
-                // NE
-                let north_east_slot: Slot;
-                if let Some(north_east_neighbor_entity) = neighbors.north_east {
-                    // Can also change this system to only check for Grass Tiles and resolve others to Any.
-                    if grass_tiles_query.contains(north_east_neighbor_entity) {
-                        north_east_slot = Slot::Filled {
-                            sprite_type: SpriteType::Grass,
-                        }
-                    } else if dirt_tiles_query.contains(north_east_neighbor_entity) {
-                        north_east_slot = Slot::Filled {
-                            sprite_type: SpriteType::Dirt,
-                        }
-                    } else if water_tiles_query.contains(north_east_neighbor_entity) {
-                        north_east_slot = Slot::Filled {
-                            sprite_type: SpriteType::Water,
-                        }
-                    } else {
-                        north_east_slot = Slot::Empty
-                    }
-                } else {
-                    north_east_slot = Slot::Empty
-                }
-
-                // W
-                let west_slot: Slot;
-                if let Some(west_neighbor_entity) = neighbors.west {
-                    // Can also change this system to only check for Grass Tiles and resolve others to Any.
-                    if grass_tiles_query.contains(west_neighbor_entity) {
-                        west_slot = Slot::Filled {
-                            sprite_type: SpriteType::Grass,
-                        }
-                    } else if dirt_tiles_query.contains(west_neighbor_entity) {
-                        west_slot = Slot::Filled {
-                            sprite_type: SpriteType::Dirt,
-                        }
-                    } else if water_tiles_query.contains(west_neighbor_entity) {
-                        west_slot = Slot::Filled {
-                            sprite_type: SpriteType::Water,
-                        }
-                    } else {
-                        west_slot = Slot::Empty
-                    }
-                } else {
-                    west_slot = Slot::Empty
-                }
-
-                // C
-                let center_slot = Slot::Filled {
-                    sprite_type: SpriteType::Dirt,
-                };
-
-                // E
-                let east_slot: Slot;
-                if let Some(east_neighbor_entity) = neighbors.east {
-                    // Can also change this system to only check for Grass Tiles and resolve others to Any.
-                    if grass_tiles_query.contains(east_neighbor_entity) {
-                        east_slot = Slot::Filled {
-                            sprite_type: SpriteType::Grass,
-                        }
-                    } else if dirt_tiles_query.contains(east_neighbor_entity) {
-                        east_slot = Slot::Filled {
-                            sprite_type: SpriteType::Dirt,
-                        }
-                    } else if water_tiles_query.contains(east_neighbor_entity) {
-                        east_slot = Slot::Filled {
-                            sprite_type: SpriteType::Water,
-                        }
-                    } else {
-                        east_slot = Slot::Empty
-                    }
-                } else {
-                    east_slot = Slot::Empty
-                }
-
-                // SW
-                let south_west_slot: Slot;
-                if let Some(south_west_neighbor_entity) = neighbors.south_west {
-                    // Can also change this system to only check for Grass Tiles and resolve others to Any.
-                    if grass_tiles_query.contains(south_west_neighbor_entity) {
-                        south_west_slot = Slot::Filled {
-                            sprite_type: SpriteType::Grass,
-                        }
-                    } else if dirt_tiles_query.contains(south_west_neighbor_entity) {
-                        south_west_slot = Slot::Filled {
-                            sprite_type: SpriteType::Dirt,
-                        }
-                    } else if water_tiles_query.contains(south_west_neighbor_entity) {
-                        south_west_slot = Slot::Filled {
-                            sprite_type: SpriteType::Water,
-                        }
-                    } else {
-                        south_west_slot = Slot::Empty
-                    }
-                } else {
-                    south_west_slot = Slot::Empty
-                }
+    let is_everything_changed = update_tilemap_event_reader.iter().count() > 0;
+    let tile_positions: Vec<TilePos> = update_tiles_event_reader
+        .iter()
+        .flat_map(|update_tiles_event| update_tiles_event.tile_positions.iter().copied())
+        .collect();
+    if !is_everything_changed && tile_positions.is_empty() {
+        return;
+    }
+    let (tile_storage, tilemap_type) = match tilemap_query.get_single_mut() {
+        Ok(ground_layer) => ground_layer,
+        Err(_) => return,
+    };
+    let get_active_rule = |tile_position: &TilePos, sprite_type: SpriteType| {
+        let neighbors = get_tile_neighbors(tile_position, tile_storage, tilemap_type);
+        Rule {
+            nw_slot: get_neighbor_slot(neighbors.north_west, sprite_type),
+            n_slot: get_neighbor_slot(neighbors.north, sprite_type),
+            ne_slot: get_neighbor_slot(neighbors.north_east, sprite_type),
+            w_slot: get_neighbor_slot(neighbors.west, sprite_type),
+            c_slot: Slot::Filled { sprite_type },
+            e_slot: get_neighbor_slot(neighbors.east, sprite_type),
+            sw_slot: get_neighbor_slot(neighbors.south_west, sprite_type),
+            s_slot: get_neighbor_slot(neighbors.south, sprite_type),
+            se_slot: get_neighbor_slot(neighbors.south_east, sprite_type),
+        }
+    };
 
-                // S
-                let south_slot: Slot;
-                if let Some(south_neighbor_entity) = neighbors.south {
-                    // Can also change this system to only check for Grass Tiles and resolve others to Any.
-                    if grass_tiles_query.contains(south_neighbor_entity) {
-                        south_slot = Slot::Filled {
-                            sprite_type: SpriteType::Grass,
-                        }
-                    } else if dirt_tiles_query.contains(south_neighbor_entity) {
-                        south_slot = Slot::Filled {
-                            sprite_type: SpriteType::Dirt,
-                        }
-                    } else if water_tiles_query.contains(south_neighbor_entity) {
-                        south_slot = Slot::Filled {
-                            sprite_type: SpriteType::Water,
-                        }
-                    } else {
-                        south_slot = Slot::Empty
-                    }
-                } else {
-                    south_slot = Slot::Empty
-                }
+    if is_everything_changed {
+        // Clear Previous Active Rules
+        active_rules.active_rules.clear();
+        let grass_tiles = grass_tiles_query
+            .iter()
+            .map(|tile_position| (tile_position, SpriteType::Grass));
+        let dirt_tiles = dirt_tiles_query
+            .iter()
+            .map(|tile_position| (tile_position, SpriteType::Dirt));
+        for (tile_position, sprite_type) in grass_tiles.chain(dirt_tiles) {
+            let current_rule = get_active_rule(tile_position, sprite_type);
+            active_rules
+                .active_rules
+                .insert(*tile_position, current_rule);
+        }
+        active_rules.changed_positions = None;
+        return;
+    }
 
-                // SE
-                let south_east_slot: Slot;
-                if let Some(south_east_neighbor_entity) = neighbors.south_east {
-                    // Can also change this system to only check for Grass Tiles and resolve others to Any.
-                    if grass_tiles_query.contains(south_east_neighbor_entity) {
-                        south_east_slot = Slot::Filled {
-                            sprite_type: SpriteType::Grass,
-                        }
-                    } else if dirt_tiles_query.contains(south_east_neighbor_entity) {
-                        south_east_slot = Slot::Filled {
-                            sprite_type: SpriteType::Dirt,
-                        }
-                    } else if water_tiles_query.contains(south_east_neighbor_entity) {
-                        south_east_slot = Slot::Filled {
-                            sprite_type: SpriteType::Water,
-                        }
-                    } else {
-                        south_east_slot = Slot::Empty
-                    }
-                } else {
-                    south_east_slot = Slot::Empty
+    // Only the given tiles and their neighbors can see different terrain around them.
+    let mut changed_positions = HashSet::new();
+    for tile_position in &tile_positions {
+        for y in -1..=1 {
+            for x in -1..=1 {
+                let (neighbor_x, neighbor_y) =
+                    (tile_position.x as i32 + x, tile_position.y as i32 + y);
+                if neighbor_x >= 0
+                    && neighbor_y >= 0
+                    && neighbor_x < tile_storage.size.x as i32
+                    && neighbor_y < tile_storage.size.y as i32
+                {
+                    changed_positions.insert(TilePos {
+                        x: neighbor_x as u32,
+                        y: neighbor_y as u32,
+                    });
                 }
-
-                let current_rule = Rule {
-                    nw_slot: north_west_slot,
-                    n_slot: north_slot,
-                    ne_slot: north_east_slot,
-                    w_slot: west_slot,
-                    c_slot: center_slot,
-                    e_slot: east_slot,
-                    sw_slot: south_west_slot,
-                    s_slot: south_slot,
-                    se_slot: south_east_slot,
-                };
-
-                active_rules
-                    .active_rules
-                    .insert(*tile_position, current_rule);
             }
         }
     }
+    for tile_position in &changed_positions {
+        active_rules.active_rules.remove(tile_position);
+        let tile_entity = match tile_storage.get(tile_position) {
+            Some(tile_entity) => tile_entity,
+            None => continue,
+        };
+        let sprite_type = if grass_tiles_query.contains(tile_entity) {
+            SpriteType::Grass
+        } else if dirt_tiles_query.contains(tile_entity) {
+            SpriteType::Dirt
+        } else {
+            continue;
+        };
+        let current_rule = get_active_rule(tile_position, sprite_type);
+        active_rules
+            .active_rules
+            .insert(*tile_position, current_rule);
+    }
+    active_rules.changed_positions = Some(changed_positions);
 }
 
+// Every autotiled tile is drawn with what the resolver of its terrain picks, see `resolver.rs`.
 pub fn update_tilemap(
-    mut grass_tiles_query: Query<
-        (&TilePos, &mut TileTexture),
-        (With<GrassTile>, Without<DirtTile>, Without<WaterTile>),
-    >,
-    mut dirt_tiles_query: Query<
-        (&TilePos, &mut TileTexture),
-        (With<DirtTile>, Without<GrassTile>, Without<WaterTile>),
+    mut commands: Commands,
+    mut tile_resolved_event_writer: EventWriter<TileResolved>,
+    mut tiles_query: Query<
+        (
+            Entity,
+            &TilePos,
+            &mut TileTexture,
+            &mut TileFlip,
+            Option<&AnimatedTile>,
+            Option<&GrassTile>,
+            Option<&DirtTile>,
+        ),
+        (Or<(With<GrassTile>, With<DirtTile>)>, Without<WaterTile>),
     >,
-    sprites: Res<Sprites>,
+    ground_layer_query: Query<&TileStorage, With<GroundLayer>>,
+    sprite_registry: Res<SpriteRegistry>,
     active_rules: Res<ActiveRules>,
     rules: Res<Rules>,
+    autotile_resolvers: Res<AutotileResolvers>,
 ) {
     // Perform auto tiling based on neighbors and rules
-    if active_rules.is_changed() {
-        let possible_rules = rules.rules[&SpriteType::Grass].clone();
-        for (tile_position, mut tile_texture) in grass_tiles_query.iter_mut() {
-            if active_rules.active_rules.contains_key(tile_position) {
-                let active_rule = active_rules.active_rules[tile_position];
-                let mut new_sprite = Sprite::Blank;
-                for (rule, sprite) in possible_rules.iter() {
-                    if active_rule == *rule {
-                        new_sprite = sprite.clone();
-                        break;
-                    }
-                }
-                tile_texture.0 = sprites.sprite_lookup_table[&new_sprite];
+    if active_rules.is_changed() || autotile_resolvers.is_changed() {
+        // Only the tiles whose rule changed are visited, unless the resolvers changed too.
+        let tile_entities: Vec<Entity> = match (
+            &active_rules.changed_positions,
+            autotile_resolvers.is_changed(),
+            ground_layer_query.get_single(),
+        ) {
+            (Some(changed_positions), false, Ok(tile_storage)) => changed_positions
+                .iter()
+                .filter_map(|tile_position| tile_storage.get(tile_position))
+                .collect(),
+            _ => tiles_query
+                .iter()
+                .map(|(tile_entity, ..)| tile_entity)
+                .collect(),
+        };
+        for tile_entity in tile_entities {
+            let (
+                tile_entity,
+                tile_position,
+                mut tile_texture,
+                mut tile_flip,
+                animated_tile,
+                grass_tile,
+                dirt_tile,
+            ) = match tiles_query.get_mut(tile_entity) {
+                Ok(tile) => tile,
+                Err(_) => continue,
+            };
+            let sprite_type = match (grass_tile, dirt_tile) {
+                (Some(_), None) => SpriteType::Grass,
+                (None, Some(_)) => SpriteType::Dirt,
+                _ => continue,
+            };
+            let active_rule = match active_rules.active_rules.get(tile_position) {
+                Some(active_rule) => *active_rule,
+                None => continue,
+            };
+            let neighborhood = Neighborhood {
+                tile_position: *tile_position,
+                active_rule,
+            };
+            let tile_output = autotile_resolvers
+                .get(sprite_type)
+                .resolve(&neighborhood, sprite_type, &rules, &sprite_registry)
+                .unwrap_or_else(|| TileOutput::from_atlas_index(sprite_registry.get_blank_index()));
+            if tile_texture.0 != tile_output.atlas_index {
+                tile_texture.0 = tile_output.atlas_index;
+                tile_resolved_event_writer.send(TileResolved {
+                    pos: *tile_position,
+                    sprite: tile_output.atlas_index,
+                });
             }
-        }
-        let possible_rules = rules.rules[&SpriteType::Dirt].clone();
-        for (tile_position, mut tile_texture) in dirt_tiles_query.iter_mut() {
-            if active_rules.active_rules.contains_key(tile_position) {
-                let active_rule = active_rules.active_rules[tile_position];
-                let mut new_sprite = Sprite::Blank;
-                for (rule, sprite) in possible_rules.iter() {
-                    if active_rule == *rule {
-                        new_sprite = sprite.clone();
-                        break;
-                    }
+            if !is_same_flip(&tile_flip, &tile_output.flip) {
+                *tile_flip = tile_output.flip;
+            }
+            // Only tiles whose animation changed are touched, most tiles never have one.
+            match (tile_output.animation, animated_tile) {
+                (Some(animation), Some(animated_tile))
+                    if animation.start == animated_tile.start
+                        && animation.end == animated_tile.end
+                        && animation.speed == animated_tile.speed => {}
+                (Some(animation), _) => {
+                    commands.entity(tile_entity).insert(animation);
                 }
-                tile_texture.0 = sprites.sprite_lookup_table[&new_sprite];
+                (None, Some(_)) => {
+                    commands.entity(tile_entity).remove::<AnimatedTile>();
+                }
+                (None, None) => {}
             }
         }
     }
 }
 
-pub fn update_selection(keyboard: Res<Input<KeyCode>>, mut game_state: ResMut<GameState>) {
-    if keyboard.just_pressed(KeyCode::Key1) {
-        game_state.selection = SpriteType::Blank;
-        println!("Selection Updated: {:?}", game_state.selection);
-    } else if keyboard.just_pressed(KeyCode::Key2) {
-        game_state.selection = SpriteType::Grass;
-        println!("Selection Updated: {:?}", game_state.selection);
-    } else if keyboard.just_pressed(KeyCode::Key3) {
-        game_state.selection = SpriteType::Dirt;
-        println!("Selection Updated: {:?}", game_state.selection);
-    } else if keyboard.just_pressed(KeyCode::Key4) {
-        game_state.selection = SpriteType::Water;
-        println!("Selection Updated: {:?}", game_state.selection);
+// Compares the terrain of the ground tiles whose terrain components were added or removed with
+// what it was the last time, so every way of painting is covered without each of them sending the
+// event.
+pub fn send_terrain_changes(
+    mut terrain_changed_event_writer: EventWriter<TerrainChanged>,
+    mut terrains: Local<HashMap<Entity, SpriteType>>,
+    removed_grass_tiles: RemovedComponents<GrassTile>,
+    removed_dirt_tiles: RemovedComponents<DirtTile>,
+    removed_water_tiles: RemovedComponents<WaterTile>,
+    added_tiles_query: Query<Entity, Or<(Added<GrassTile>, Added<DirtTile>, Added<WaterTile>)>>,
+    tiles_query: Query<(&TilemapId, &TilePos)>,
+    terrain_query: Query<(Option<&GrassTile>, Option<&DirtTile>, Option<&WaterTile>)>,
+    ground_layer_query: Query<(), With<GroundLayer>>,
+) {
+    let mut tile_entities: Vec<Entity> = removed_grass_tiles
+        .iter()
+        .chain(removed_dirt_tiles.iter())
+        .chain(removed_water_tiles.iter())
+        .chain(added_tiles_query.iter())
+        .collect();
+    // A tile repainted in one frame is both removed and added.
+    tile_entities.sort();
+    tile_entities.dedup();
+    for tile_entity in tile_entities {
+        let (tilemap_id, pos) = match tiles_query.get(tile_entity) {
+            Ok(tile) => tile,
+            Err(_) => continue,
+        };
+        if !ground_layer_query.contains(tilemap_id.0) {
+            continue;
+        }
+        let new = get_tile_terrain(tile_entity, &terrain_query);
+        let old = match new {
+            Some(sprite_type) => terrains.insert(tile_entity, sprite_type),
+            None => terrains.remove(&tile_entity),
+        };
+        if old != new {
+            terrain_changed_event_writer.send(TerrainChanged {
+                tilemap: tilemap_id.0,
+                pos: *pos,
+                old,
+                new,
+            });
+        }
+    }
+}
+
+// The number keys select the entries of the palette in order.
+pub fn update_selection(
+    keyboard: Res<Input<KeyCode>>,
+    palette: Res<Palette>,
+    mut game_state: ResMut<GameState>,
+    mut egui_context: ResMut<EguiContext>,
+) {
+    if is_typing(&mut egui_context) {
+        return;
+    }
+    for (key, entry) in PALETTE_KEYS.iter().zip(&palette.entries) {
+        if keyboard.just_pressed(*key) {
+            game_state.selection = entry.sprite_type;
+        }
     }
 }
 
@@ -3437,7 +3419,9 @@ pub fn update_mouse(
     windows: Res<Windows>,
     camera_q: Query<(&Transform, &Camera)>,
     mut cursor_moved_events: EventReader<CursorMoved>,
+    mut egui_context: ResMut<EguiContext>,
 ) {
+    mouse.is_over_ui = egui_context.ctx_mut().is_pointer_over_area();
     for cursor_moved in cursor_moved_events.iter() {
         // To get the mouse's world position, we have to transform its window position by
         // any transforms on the camera. This is done by projecting the cursor position into
@@ -3448,7 +3432,7 @@ pub fn update_mouse(
         }
     }
     // Left Mouse Button Held
-    if mouse_input.just_pressed(MouseButton::Left) {
+    if mouse_input.just_pressed(MouseButton::Left) && !mouse.is_over_ui {
         mouse.holding_lmb = true;
     } else if mouse_input.just_released(MouseButton::Left) {
         mouse.holding_lmb = false;
@@ -3459,7 +3443,11 @@ pub fn update_camera_movement(
     keyboard: Res<Input<KeyCode>>,
     windows: ResMut<Windows>,
     mut camera_query: Query<&mut Transform, With<Camera>>,
+    mut egui_context: ResMut<EguiContext>,
 ) {
+    if is_typing(&mut egui_context) {
+        return;
+    }
     let mut camera_transform = camera_query.single_mut();
 
     // Update the camera position based on the keyboard input.
@@ -3544,12 +3532,173 @@ pub fn update_camera_zoom(
 }
 
 // === Helper Functions ===
+// Whether a field of the editor panel has the keyboard. Keys typed into it are not shortcuts, so
+// every system reading the keyboard skips them.
+pub fn is_typing(egui_context: &mut EguiContext) -> bool {
+    egui_context.ctx_mut().wants_keyboard_input()
+}
+
+// Returns the name of the sprite picked by the first rule matching `active_rule`.
+pub fn get_matching_sprite<'a>(
+    active_rule: &Rule,
+    possible_rules: &'a [(Rule, String)],
+) -> Option<&'a str> {
+    possible_rules
+        .iter()
+        .find(|(rule, _)| active_rule == rule)
+        .map(|(_, sprite_name)| sprite_name.as_str())
+}
+
+// Packs the atlas listed in `ATLAS_MANIFEST_PATH` along with the sprites of imported tilesets and
+// projects.
+pub fn build_sprite_atlas(
+    tiled_import: &TiledImport,
+    ldtk_import: &LdtkImport,
+) -> Result<Atlas, AtlasError> {
+    let asset_root = get_asset_root();
+    let mut manifest = load_atlas_manifest(&asset_root)?;
+    manifest.sheets.extend(
+        tiled_import
+            .tilesets
+            .iter()
+            .map(TiledTileset::to_sheet_manifest),
+    );
+    manifest.sheets.extend(ldtk_import.sheets.iter().cloned());
+    build_atlas(&manifest, &asset_root)
+}
+
+// Prints every rule that refers to a sprite missing from the registry, those rules would silently
+// resolve to blank otherwise. Returns how many were found.
+pub fn report_unknown_sprites(rules: &Rules, sprite_registry: &SpriteRegistry) -> usize {
+    let mut unknown_sprites = 0;
+    for (sprite_type, possible_rules) in &rules.rules {
+        for (index, (_, sprite_name)) in possible_rules.iter().enumerate() {
+            if sprite_registry.get_atlas_index(sprite_name).is_none() {
+                println!(
+                    "Rule {} of {:?} refers to an unknown sprite {:?}",
+                    index, sprite_type, sprite_name
+                );
+                unknown_sprites += 1;
+            }
+        }
+    }
+    unknown_sprites
+}
+
+// Terrain names used by imported maps and tilesets, matched regardless of case.
+pub fn get_sprite_type_by_name(name: &str) -> Option<SpriteType> {
+    match name.to_lowercase().as_str() {
+        "grass" => Some(SpriteType::Grass),
+        "dirt" => Some(SpriteType::Dirt),
+        "water" => Some(SpriteType::Water),
+        _ => None,
+    }
+}
+
+// Returns the terrain painted on a ground tile.
+pub fn get_tile_terrain(
+    tile_entity: Entity,
+    terrain_query: &Query<(Option<&GrassTile>, Option<&DirtTile>, Option<&WaterTile>)>,
+) -> Option<SpriteType> {
+    match terrain_query.get(tile_entity) {
+        Ok((Some(_), _, _)) => Some(SpriteType::Grass),
+        Ok((_, Some(_), _)) => Some(SpriteType::Dirt),
+        Ok((_, _, Some(_))) => Some(SpriteType::Water),
+        _ => None,
+    }
+}
+
+// Copies the terrain of the ground into a grid, for tools that look at more than one tile.
+pub fn get_terrain_grid(
+    map_size: &TilemapSize,
+    tile_storage: &TileStorage,
+    terrain_query: &Query<(Option<&GrassTile>, Option<&DirtTile>, Option<&WaterTile>)>,
+) -> TerrainGrid {
+    let mut terrain_grid = TerrainGrid::new(map_size.x, map_size.y);
+    for y in 0..map_size.y {
+        for x in 0..map_size.x {
+            if let Some(tile_entity) = tile_storage.get(&TilePos { x, y }) {
+                terrain_grid.set(x, y, get_tile_terrain(tile_entity, terrain_query));
+            }
+        }
+    }
+    terrain_grid
+}
+
+// Paints imported terrain onto the ground, `terrain` is listed row by row starting from the
+// top-left corner like editors store it. The autotiling systems take it from there.
+pub fn paint_terrain(
+    commands: &mut Commands,
+    map_size: &TilemapSize,
+    tile_storage: &TileStorage,
+    width: u32,
+    height: u32,
+    terrain: &[Option<SpriteType>],
+) {
+    if width > map_size.x || height > map_size.y {
+        println!(
+            "The imported map is {}x{} tiles, only the bottom-left {}x{} tiles are loaded",
+            width, height, map_size.x, map_size.y
+        );
+    }
+
+    for (index, sprite_type) in terrain.iter().enumerate() {
+        let column = index as u32 % width;
+        let row = index as u32 / width;
+        // Rows are counted from the top, tile positions count them from the bottom.
+        let tile_position = TilePos {
+            x: column,
+            y: height - 1 - row,
+        };
+        if tile_position.x >= map_size.x || tile_position.y >= map_size.y {
+            continue;
+        }
+        if let Some(tile_entity) = tile_storage.get(&tile_position) {
+            match sprite_type {
+                Some(SpriteType::Grass) => {
+                    commands.entity(tile_entity).insert(GrassTile {});
+                }
+                Some(SpriteType::Dirt) => {
+                    commands.entity(tile_entity).insert(DirtTile {});
+                }
+                Some(SpriteType::Water) => {
+                    commands.entity(tile_entity).insert(WaterTile {});
+                }
+                _ => {
+                    // Do Nothing
+                }
+            }
+        }
+    }
+}
+
 pub fn world_position_to_index(position: Vec2) -> (i32, i32) {
     let x_index = position.x / TILE_SIZE as f32;
     let y_index = position.y / TILE_SIZE as f32;
     (x_index as i32, y_index as i32)
 }
 
+// Converts a world position into the position of the tile underneath it, taking into account any
+// transforms applied to the tilemap.
+pub fn world_position_to_tile_position(
+    world_position: Vec3,
+    map_size: &TilemapSize,
+    grid_size: &TilemapGridSize,
+    map_type: &TilemapType,
+    map_transform: &Transform,
+) -> Option<TilePos> {
+    // We need to make sure that the world position is correct relative to the map due to any map
+    // transformation.
+    let position_in_map: Vec2 = {
+        // Extend the world_position vec3 by 1.0
+        let world_position = Vec4::from((world_position, 1.0));
+        let position_in_map = map_transform.compute_matrix().inverse() * world_position;
+        position_in_map.xy()
+    };
+    // Once we have a position in the map we can transform it into a possible tile position.
+    TilePos::from_world_pos(&position_in_map, map_size, grid_size, map_type)
+}
+
 // Converts the cursor position into a world position, taking into account any transforms applied
 // the camera.
 pub fn cursor_pos_in_world(
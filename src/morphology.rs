@@ -0,0 +1,346 @@
+use crate::history::{set_tile_terrain, EditHistory};
+use crate::selection::Selection;
+use crate::terrain::TerrainGrid;
+use crate::{
+    get_terrain_grid, get_tile_terrain, DirtTile, GameState, GrassTile, GroundLayer,
+    SpriteRegistry, SpriteType, UpdateTilemapEvent, WaterTile,
+};
+use bevy::prelude::*;
+use bevy_ecs_tilemap::prelude::*;
+use std::collections::{HashMap, HashSet};
+
+pub const DEFAULT_ISLAND_SIZE: u32 = 3;
+
+const NEIGHBOR_OFFSETS: [IVec2; 8] = [
+    IVec2::new(-1, 1),
+    IVec2::new(0, 1),
+    IVec2::new(1, 1),
+    IVec2::new(-1, 0),
+    IVec2::new(1, 0),
+    IVec2::new(-1, -1),
+    IVec2::new(0, -1),
+    IVec2::new(1, -1),
+];
+
+// === Events ===
+pub struct ApplyTerrainOperationEvent {
+    pub operation: TerrainOperation,
+}
+
+// === Enums ===
+// Clean ups for specks and thin bridges no rule has a sprite for. Erode, dilate, open and close
+// work on the terrain selected in the palette.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TerrainOperation {
+    // Every tile takes the terrain most of the tiles around it have.
+    Smooth,
+    // Tiles of the terrain that touch another terrain lose it.
+    Erode,
+    // Tiles that touch the terrain take it.
+    Dilate,
+    // Erodes then dilates, which removes specks and thin bridges.
+    Open,
+    // Dilates then erodes, which fills small gaps and holes.
+    Close,
+    // Patches of the same terrain smaller than `min_size` tiles take the terrain around them.
+    RemoveIslands { min_size: u32 },
+}
+
+impl TerrainOperation {
+    // Whether the operation works on the terrain selected in the palette.
+    pub fn uses_selected_terrain(self) -> bool {
+        matches!(
+            self,
+            TerrainOperation::Erode
+                | TerrainOperation::Dilate
+                | TerrainOperation::Open
+                | TerrainOperation::Close
+        )
+    }
+}
+
+// === Resources ===
+pub struct Morphology {
+    // The size under which `TerrainOperation::RemoveIslands` removes patches.
+    pub island_size: u32,
+}
+
+// === Startup Systems ===
+pub fn setup_morphology(mut commands: Commands) {
+    commands.insert_resource(Morphology {
+        island_size: DEFAULT_ISLAND_SIZE,
+    });
+}
+
+// === Systems ===
+// Applies to the selection, or to the whole map when nothing is selected, as one undoable stroke.
+pub fn apply_terrain_operation(
+    mut commands: Commands,
+    mut apply_terrain_operation_event_reader: EventReader<ApplyTerrainOperationEvent>,
+    mut update_tilemap_event_writer: EventWriter<UpdateTilemapEvent>,
+    mut edit_history: ResMut<EditHistory>,
+    game_state: Res<GameState>,
+    selection: Res<Selection>,
+    sprite_registry: Res<SpriteRegistry>,
+    ground_layer_query: Query<(&TilemapSize, &TileStorage), With<GroundLayer>>,
+    terrain_query: Query<(Option<&GrassTile>, Option<&DirtTile>, Option<&WaterTile>)>,
+) {
+    let operations: Vec<TerrainOperation> = apply_terrain_operation_event_reader
+        .iter()
+        .map(|apply_terrain_operation_event| apply_terrain_operation_event.operation)
+        .collect();
+    if operations.is_empty() {
+        return;
+    }
+    let (map_size, tile_storage) = match ground_layer_query.get_single() {
+        Ok(ground_layer) => ground_layer,
+        Err(_) => return,
+    };
+    // The eraser works on tiles without terrain, but paths, fences and houses are not terrain.
+    let (sprite_type, is_terrain_selected) = match game_state.selection {
+        SpriteType::Grass | SpriteType::Dirt | SpriteType::Water => {
+            (Some(game_state.selection), true)
+        }
+        SpriteType::Blank => (None, true),
+        _ => (None, false),
+    };
+
+    let cells: Vec<IVec2> = if selection.cells.is_empty() {
+        (0..map_size.y as i32)
+            .flat_map(|y| (0..map_size.x as i32).map(move |x| IVec2::new(x, y)))
+            .collect()
+    } else {
+        selection
+            .cells
+            .iter()
+            .map(|tile_position| IVec2::new(tile_position.x as i32, tile_position.y as i32))
+            .collect()
+    };
+    let terrain_grid = get_terrain_grid(map_size, tile_storage, &terrain_query);
+    let mut changed_grid = terrain_grid.clone();
+    for operation in operations {
+        if operation.uses_selected_terrain() && !is_terrain_selected {
+            println!(
+                "Select a terrain in the palette to apply {:?}, {:?} is not a terrain",
+                operation, game_state.selection
+            );
+            continue;
+        }
+        changed_grid = apply_operation(&changed_grid, &cells, operation, sprite_type);
+        println!("Terrain Operation Applied: {:?}", operation);
+    }
+
+    edit_history.finish_stroke();
+    let mut is_changed = false;
+    for cell in &cells {
+        let before = terrain_grid.get(cell.x, cell.y);
+        let after = changed_grid.get(cell.x, cell.y);
+        if before == after {
+            continue;
+        }
+        let tile_position = TilePos {
+            x: cell.x as u32,
+            y: cell.y as u32,
+        };
+        if let Some(tile_entity) = tile_storage.get(&tile_position) {
+            edit_history.record(tile_position, before, after);
+            set_tile_terrain(&mut commands, tile_entity, after, &sprite_registry);
+            is_changed = true;
+        }
+    }
+    edit_history.finish_stroke();
+    if is_changed {
+        update_tilemap_event_writer.send(UpdateTilemapEvent {});
+    }
+}
+
+// === Helper Functions ===
+// Only `cells` are changed, the tiles around them are read as they are.
+pub fn apply_operation(
+    terrain_grid: &TerrainGrid,
+    cells: &[IVec2],
+    operation: TerrainOperation,
+    sprite_type: Option<SpriteType>,
+) -> TerrainGrid {
+    match operation {
+        TerrainOperation::Smooth => smooth(terrain_grid, cells),
+        TerrainOperation::Erode => erode(terrain_grid, cells, sprite_type),
+        TerrainOperation::Dilate => dilate(terrain_grid, cells, sprite_type),
+        TerrainOperation::Open => {
+            dilate(&erode(terrain_grid, cells, sprite_type), cells, sprite_type)
+        }
+        TerrainOperation::Close => erode(
+            &dilate(terrain_grid, cells, sprite_type),
+            cells,
+            sprite_type,
+        ),
+        TerrainOperation::RemoveIslands { min_size } => {
+            remove_islands(terrain_grid, cells, min_size)
+        }
+    }
+}
+
+pub fn smooth(terrain_grid: &TerrainGrid, cells: &[IVec2]) -> TerrainGrid {
+    let mut smoothed_grid = terrain_grid.clone();
+    for cell in cells {
+        let mut neighborhood = get_neighbors(terrain_grid, *cell);
+        neighborhood.push(terrain_grid.get(cell.x, cell.y));
+        // Only a clear majority wins, so edges between two terrains stay where they are.
+        if let Some((sprite_type, count)) = get_most_common(&neighborhood) {
+            if count * 2 > neighborhood.len() {
+                smoothed_grid.set(cell.x as u32, cell.y as u32, sprite_type);
+            }
+        }
+    }
+    smoothed_grid
+}
+
+pub fn erode(
+    terrain_grid: &TerrainGrid,
+    cells: &[IVec2],
+    sprite_type: Option<SpriteType>,
+) -> TerrainGrid {
+    let mut eroded_grid = terrain_grid.clone();
+    for cell in cells {
+        if terrain_grid.get(cell.x, cell.y) != sprite_type {
+            continue;
+        }
+        let others: Vec<_> = get_neighbors(terrain_grid, *cell)
+            .into_iter()
+            .filter(|neighbor| *neighbor != sprite_type)
+            .collect();
+        if let Some((other, _)) = get_most_common(&others) {
+            eroded_grid.set(cell.x as u32, cell.y as u32, other);
+        }
+    }
+    eroded_grid
+}
+
+pub fn dilate(
+    terrain_grid: &TerrainGrid,
+    cells: &[IVec2],
+    sprite_type: Option<SpriteType>,
+) -> TerrainGrid {
+    let mut dilated_grid = terrain_grid.clone();
+    for cell in cells {
+        if get_neighbors(terrain_grid, *cell).contains(&sprite_type) {
+            dilated_grid.set(cell.x as u32, cell.y as u32, sprite_type);
+        }
+    }
+    dilated_grid
+}
+
+// Patches are tiles of the same terrain, or without terrain, connected through their north, east,
+// south and west neighbors. They take the terrain most common around their edge.
+pub fn remove_islands(terrain_grid: &TerrainGrid, cells: &[IVec2], min_size: u32) -> TerrainGrid {
+    let region: HashSet<IVec2> = cells.iter().copied().collect();
+    let mut visited = HashSet::new();
+    let mut cleaned_grid = terrain_grid.clone();
+    for start in cells {
+        if !is_inside(terrain_grid, *start) || !visited.insert(*start) {
+            continue;
+        }
+        let sprite_type = terrain_grid.get(start.x, start.y);
+        let mut patch = Vec::new();
+        let mut surroundings = Vec::new();
+        let mut pending = vec![*start];
+        while let Some(cell) = pending.pop() {
+            patch.push(cell);
+            for offset in [IVec2::Y, IVec2::X, -IVec2::Y, -IVec2::X] {
+                let neighbor = cell + offset;
+                if !is_inside(terrain_grid, neighbor) {
+                    continue;
+                }
+                let neighbor_sprite_type = terrain_grid.get(neighbor.x, neighbor.y);
+                if neighbor_sprite_type != sprite_type {
+                    surroundings.push(neighbor_sprite_type);
+                } else if visited.insert(neighbor) {
+                    pending.push(neighbor);
+                }
+            }
+        }
+        if patch.len() >= min_size as usize {
+            continue;
+        }
+        if let Some((other, _)) = get_most_common(&surroundings) {
+            for cell in patch.iter().filter(|cell| region.contains(cell)) {
+                cleaned_grid.set(cell.x as u32, cell.y as u32, other);
+            }
+        }
+    }
+    cleaned_grid
+}
+
+fn is_inside(terrain_grid: &TerrainGrid, cell: IVec2) -> bool {
+    cell.x >= 0
+        && cell.y >= 0
+        && cell.x < terrain_grid.width as i32
+        && cell.y < terrain_grid.height as i32
+}
+
+// The terrain of the 8 tiles around `cell` that are on the map.
+fn get_neighbors(terrain_grid: &TerrainGrid, cell: IVec2) -> Vec<Option<SpriteType>> {
+    NEIGHBOR_OFFSETS
+        .iter()
+        .map(|offset| cell + *offset)
+        .filter(|neighbor| is_inside(terrain_grid, *neighbor))
+        .map(|neighbor| terrain_grid.get(neighbor.x, neighbor.y))
+        .collect()
+}
+
+// The most common terrain and how often it comes up. Ties go to the terrain seen first.
+fn get_most_common(sprite_types: &[Option<SpriteType>]) -> Option<(Option<SpriteType>, usize)> {
+    let mut counts: HashMap<Option<SpriteType>, usize> = HashMap::new();
+    for sprite_type in sprite_types {
+        *counts.entry(*sprite_type).or_default() += 1;
+    }
+    let mut most_common: Option<(Option<SpriteType>, usize)> = None;
+    for sprite_type in sprite_types {
+        let count = counts[sprite_type];
+        if most_common.map_or(true, |(_, most_count)| count > most_count) {
+            most_common = Some((*sprite_type, count));
+        }
+    }
+    most_common
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn open_removes_specks_and_thin_bridges() {
+        let terrain_grid =
+            TerrainGrid::from_ascii(&["ggggggg", "gdggggg", "ggggggg", "ddddddd", "ggggggg"]);
+        let cells = terrain_grid.cells();
+        let opened_grid = apply_operation(
+            &terrain_grid,
+            &cells,
+            TerrainOperation::Open,
+            Some(SpriteType::Dirt),
+        );
+        assert_eq!(opened_grid, TerrainGrid::from_ascii(&["ggggggg"; 5]));
+    }
+
+    #[test]
+    fn dilate_grows_the_terrain_by_one_tile() {
+        let terrain_grid = TerrainGrid::from_ascii(&["ggggg", "ggggg", "ggdgg", "ggggg", "ggggg"]);
+        let cells = terrain_grid.cells();
+        let dilated_grid = dilate(&terrain_grid, &cells, Some(SpriteType::Dirt));
+        assert_eq!(
+            dilated_grid,
+            TerrainGrid::from_ascii(&["ggggg", "gdddg", "gdddg", "gdddg", "ggggg"])
+        );
+    }
+
+    #[test]
+    fn remove_islands_only_changes_the_given_cells() {
+        let terrain_grid = TerrainGrid::from_ascii(&["~~~~~~", "~gg~~~", "~~~~g~", "~~~~~~"]);
+        let cells = vec![IVec2::new(1, 2), IVec2::new(2, 2), IVec2::new(1, 1)];
+        let cleaned_grid = remove_islands(&terrain_grid, &cells, 3);
+        assert_eq!(
+            cleaned_grid,
+            TerrainGrid::from_ascii(&["~~~~~~", "~~~~~~", "~~~~g~", "~~~~~~"])
+        );
+    }
+}
@@ -0,0 +1,427 @@
+use crate::selection::{is_selecting, Clipboard};
+use crate::tools::ToolState;
+use crate::{
+    world_position_to_tile_position, GameState, Mouse, SpriteType, MAP_HEIGHT, MAP_WIDTH, TILE_SIZE,
+};
+use bevy::prelude::*;
+use bevy_ecs_tilemap::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+// Connection masks use one bit per cardinal neighbor, diagonals are ignored.
+pub const CONNECTION_NORTH: u8 = 0b0001;
+pub const CONNECTION_EAST: u8 = 0b0010;
+pub const CONNECTION_SOUTH: u8 = 0b0100;
+pub const CONNECTION_WEST: u8 = 0b1000;
+
+// === Components ===
+// Each connectable type gets its own tilemap (layer) on top of the ground, as each type uses its
+// own tileset texture.
+#[derive(Component, Debug)]
+pub struct ObjectLayer {
+    pub connectable_type: ConnectableType,
+}
+
+// Marks a tile of an object layer as occupied. `links` holds the connections laid down by strokes
+// and is only used by types that connect by stroke.
+#[derive(Component, Debug)]
+pub struct ConnectableTile {
+    pub links: u8,
+}
+
+// === Events ===
+pub struct UpdateObjectLayerEvent {}
+
+// === Enums ===
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ConnectableType {
+    Path,
+    Fence,
+}
+
+impl ConnectableType {
+    // Ordered bottom to top, paths are drawn beneath fences.
+    pub const ALL: [ConnectableType; 2] = [ConnectableType::Path, ConnectableType::Fence];
+
+    pub fn from_sprite_type(sprite_type: SpriteType) -> Option<ConnectableType> {
+        match sprite_type {
+            SpriteType::Path => Some(ConnectableType::Path),
+            SpriteType::Fence => Some(ConnectableType::Fence),
+            _ => None,
+        }
+    }
+
+    pub fn to_sprite_type(self) -> SpriteType {
+        match self {
+            ConnectableType::Path => SpriteType::Path,
+            ConnectableType::Fence => SpriteType::Fence,
+        }
+    }
+
+    // Fences connect to any adjacent fence, paths only connect to the cells they were drawn
+    // through so that parallel paths stay apart.
+    pub fn connects_by_stroke(self) -> bool {
+        match self {
+            ConnectableType::Path => true,
+            ConnectableType::Fence => false,
+        }
+    }
+
+    pub fn texture_path(self) -> &'static str {
+        match self {
+            ConnectableType::Path => {
+                "sprites/Sprout Lands - Sprites - Basic pack/Objects/Paths.png"
+            }
+            ConnectableType::Fence => {
+                "sprites/Sprout Lands - Sprites - Basic pack/Tilesets/Fences.png"
+            }
+        }
+    }
+}
+
+// === Resources ===
+pub struct ConnectionRules {
+    // The atlas index to use for each connection mask, indexed by the mask itself.
+    pub rules: HashMap<ConnectableType, [u32; 16]>,
+}
+
+// The tile painted last by the current stroke, `None` while the mouse button is up.
+pub struct ObjectStroke {
+    pub last_tile_position: Option<TilePos>,
+}
+
+// === Startup Systems ===
+pub fn setup_connection_rules(mut commands: Commands) {
+    let connection_rules = ConnectionRules {
+        rules: HashMap::from([
+            // Paths.png only has dead-ends and straights as whole tiles, plus a crossing that spans
+            // a 2x2 block. Corners, T-junctions and crossings use the quarter of that crossing
+            // which points towards the most of their connections.
+            (
+                ConnectableType::Path,
+                [
+                    4,  // ----
+                    8,  // N---
+                    13, // -E--
+                    6,  // NE--
+                    0,  // --S-
+                    4,  // N-S-
+                    10, // -ES-
+                    6,  // NES-
+                    15, // ---W
+                    5,  // N--W
+                    14, // -E-W
+                    5,  // NE-W
+                    9,  // --SW
+                    9,  // N-SW
+                    10, // -ESW
+                    5,  // NESW
+                ],
+            ),
+            // Fences.png is a 4x4 grid. Columns are: no rails, E rail, W and E rails, W rail.
+            // Rows are: S post, N and S posts, N post, no posts.
+            (
+                ConnectableType::Fence,
+                [
+                    12, // ----
+                    8,  // N---
+                    13, // -E--
+                    9,  // NE--
+                    0,  // --S-
+                    4,  // N-S-
+                    1,  // -ES-
+                    5,  // NES-
+                    15, // ---W
+                    11, // N--W
+                    14, // -E-W
+                    10, // NE-W
+                    3,  // --SW
+                    7,  // N-SW
+                    2,  // -ESW
+                    6,  // NESW
+                ],
+            ),
+        ]),
+    };
+    commands.insert_resource(connection_rules);
+}
+
+pub fn setup_object_stroke(mut commands: Commands) {
+    commands.insert_resource(ObjectStroke {
+        last_tile_position: None,
+    });
+}
+
+pub fn setup_object_layers(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let tilemap_size = TilemapSize {
+        x: MAP_WIDTH as u32,
+        y: MAP_HEIGHT as u32,
+    };
+
+    for (layer_index, connectable_type) in ConnectableType::ALL.iter().enumerate() {
+        let tilemap_entity = commands.spawn().id();
+        let mut tile_storage = TileStorage::empty(tilemap_size);
+
+        // Spawn the elements of the tilemap, hidden until something is placed on them.
+        for y in 0..tilemap_size.y {
+            for x in 0..tilemap_size.x {
+                let tile_position = TilePos { x, y };
+                let tile_entity = commands
+                    .spawn()
+                    .insert_bundle(TileBundle {
+                        position: tile_position,
+                        tilemap_id: TilemapId(tilemap_entity),
+                        visible: TileVisible(false),
+                        ..default()
+                    })
+                    .id();
+                tile_storage.set(&tile_position, tile_entity);
+            }
+        }
+
+        let grid_size = TilemapGridSize {
+            x: TILE_SIZE as f32,
+            y: TILE_SIZE as f32,
+        };
+        let tile_size = TilemapTileSize {
+            x: TILE_SIZE as f32,
+            y: TILE_SIZE as f32,
+        };
+        let image_handle: Handle<Image> = asset_server.load(connectable_type.texture_path());
+        let tilemap_texture = TilemapTexture::Single(image_handle);
+
+        commands
+            .entity(tilemap_entity)
+            .insert_bundle(TilemapBundle {
+                grid_size,
+                size: tilemap_size,
+                storage: tile_storage,
+                texture: tilemap_texture,
+                map_type: TilemapType::Square {
+                    diagonal_neighbors: false,
+                },
+                tile_size,
+                // Layers are stacked above the ground tilemap.
+                transform: Transform::from_xyz(0.0, 0.0, 1.0 + layer_index as f32),
+                ..Default::default()
+            })
+            .insert(ObjectLayer {
+                connectable_type: *connectable_type,
+            });
+    }
+}
+
+// === Systems ===
+pub fn place_object(
+    mut commands: Commands,
+    mut update_object_layer_event_writer: EventWriter<UpdateObjectLayerEvent>,
+    game_state: Res<GameState>,
+    mouse: Res<Mouse>,
+    mut object_stroke: ResMut<ObjectStroke>,
+    tool_state: Res<ToolState>,
+    clipboard: Res<Clipboard>,
+    object_layer_query: Query<(
+        &ObjectLayer,
+        &TilemapSize,
+        &TilemapGridSize,
+        &TilemapType,
+        &TileStorage,
+        &Transform,
+    )>,
+    connectable_tiles_query: Query<&ConnectableTile>,
+) {
+    if !mouse.holding_lmb || is_selecting(&tool_state, &clipboard) {
+        object_stroke.last_tile_position = None;
+        return;
+    }
+    // Blank erases objects the same way it erases terrain, any other terrain leaves them alone.
+    let selected_type = ConnectableType::from_sprite_type(game_state.selection);
+    if selected_type.is_none() && game_state.selection != SpriteType::Blank {
+        return;
+    }
+
+    let mut current_tile_position = None;
+    for (object_layer, map_size, grid_size, map_type, tile_storage, map_transform) in
+        object_layer_query.iter()
+    {
+        if let Some(tile_position) = world_position_to_tile_position(
+            mouse.world_position,
+            map_size,
+            grid_size,
+            map_type,
+            map_transform,
+        ) {
+            current_tile_position = Some(tile_position);
+            if let Some(tile_entity) = tile_storage.get(&tile_position) {
+                match selected_type {
+                    Some(connectable_type) if connectable_type == object_layer.connectable_type => {
+                        if connectable_type.connects_by_stroke() {
+                            // Walk from the previous sample so fast drags still lay a continuous
+                            // path, linking every pair of consecutive cells along the way.
+                            let start = object_stroke.last_tile_position.unwrap_or(tile_position);
+                            let stroke = get_cardinal_line(start, tile_position);
+                            let mut links: HashMap<Entity, u8> = HashMap::new();
+                            for tile_position in &stroke {
+                                if let Some(entity) = tile_storage.get(tile_position) {
+                                    let current_links = connectable_tiles_query
+                                        .get(entity)
+                                        .map_or(0, |tile| tile.links);
+                                    links.insert(entity, current_links);
+                                }
+                            }
+                            for pair in stroke.windows(2) {
+                                let connection = get_connection_between(&pair[0], &pair[1]);
+                                if let Some(entity) = tile_storage.get(&pair[0]) {
+                                    *links.entry(entity).or_default() |= connection;
+                                }
+                                if let Some(entity) = tile_storage.get(&pair[1]) {
+                                    *links.entry(entity).or_default() |=
+                                        get_opposite_connection(connection);
+                                }
+                            }
+                            for (entity, links) in links {
+                                commands.entity(entity).insert(ConnectableTile { links });
+                            }
+                        } else {
+                            commands
+                                .entity(tile_entity)
+                                .insert(ConnectableTile { links: 0 });
+                        }
+                    }
+                    None => {
+                        commands.entity(tile_entity).remove::<ConnectableTile>();
+                    }
+                    _ => continue,
+                }
+                update_object_layer_event_writer.send(UpdateObjectLayerEvent {});
+            }
+        }
+    }
+    object_stroke.last_tile_position = current_tile_position;
+}
+
+pub fn update_object_layers(
+    mut update_object_layer_event_reader: EventReader<UpdateObjectLayerEvent>,
+    object_layer_query: Query<(&ObjectLayer, &TilemapSize, &TileStorage, &TilemapType)>,
+    connectable_tiles_query: Query<&ConnectableTile>,
+    mut tiles_query: Query<(&mut TileTexture, &mut TileVisible)>,
+    connection_rules: Res<ConnectionRules>,
+) {
+    // Several events can arrive in the same frame, the layers only need to be resolved once.
+    if update_object_layer_event_reader.iter().count() == 0 {
+        return;
+    }
+
+    for (object_layer, map_size, tile_storage, tilemap_type) in object_layer_query.iter() {
+        let sprites = connection_rules.rules[&object_layer.connectable_type];
+        for y in 0..map_size.y {
+            for x in 0..map_size.x {
+                let tile_position = TilePos { x, y };
+                if let Some(tile_entity) = tile_storage.get(&tile_position) {
+                    if let Ok((mut tile_texture, mut tile_visible)) =
+                        tiles_query.get_mut(tile_entity)
+                    {
+                        let connectable_tile = connectable_tiles_query.get(tile_entity).ok();
+                        let is_occupied = connectable_tile.is_some();
+                        if let Some(connectable_tile) = connectable_tile {
+                            let mut connection_mask = get_connection_mask(
+                                &tile_position,
+                                tile_storage,
+                                tilemap_type,
+                                |neighbor| connectable_tiles_query.contains(neighbor),
+                            );
+                            // Links towards erased neighbors are dropped by the mask above.
+                            if object_layer.connectable_type.connects_by_stroke() {
+                                connection_mask &= connectable_tile.links;
+                            }
+                            let new_texture = sprites[connection_mask as usize];
+                            if tile_texture.0 != new_texture {
+                                tile_texture.0 = new_texture;
+                            }
+                        }
+                        // Only touch the visibility when it changes to avoid re-meshing the layer.
+                        if tile_visible.0 != is_occupied {
+                            tile_visible.0 = is_occupied;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+// === Helper Functions ===
+// Builds the 4-bit connection mask of a tile from the cardinal neighbors that `is_connected`.
+pub fn get_connection_mask(
+    tile_position: &TilePos,
+    tile_storage: &TileStorage,
+    tilemap_type: &TilemapType,
+    is_connected: impl Fn(Entity) -> bool,
+) -> u8 {
+    let neighbors = get_tile_neighbors(tile_position, tile_storage, tilemap_type);
+    let mut connection_mask = 0;
+    if neighbors.north.map_or(false, &is_connected) {
+        connection_mask |= CONNECTION_NORTH;
+    }
+    if neighbors.east.map_or(false, &is_connected) {
+        connection_mask |= CONNECTION_EAST;
+    }
+    if neighbors.south.map_or(false, &is_connected) {
+        connection_mask |= CONNECTION_SOUTH;
+    }
+    if neighbors.west.map_or(false, &is_connected) {
+        connection_mask |= CONNECTION_WEST;
+    }
+    connection_mask
+}
+
+// Returns the connection bit pointing from `from` towards its cardinal neighbor `to`.
+pub fn get_connection_between(from: &TilePos, to: &TilePos) -> u8 {
+    if to.y > from.y {
+        CONNECTION_NORTH
+    } else if to.y < from.y {
+        CONNECTION_SOUTH
+    } else if to.x > from.x {
+        CONNECTION_EAST
+    } else if to.x < from.x {
+        CONNECTION_WEST
+    } else {
+        0
+    }
+}
+
+pub fn get_opposite_connection(connection: u8) -> u8 {
+    ((connection << 2) | (connection >> 2)) & 0b1111
+}
+
+// Walks from `start` to `end` one cardinal step at a time, so consecutive positions are always
+// neighbors. Both ends are included.
+pub fn get_cardinal_line(start: TilePos, end: TilePos) -> Vec<TilePos> {
+    let total_x = end.x.abs_diff(start.x);
+    let total_y = end.y.abs_diff(start.y);
+    let (mut moved_x, mut moved_y) = (0, 0);
+    let mut position = start;
+    let mut line = vec![start];
+    while moved_x < total_x || moved_y < total_y {
+        // Step along whichever axis is furthest behind the straight line between both ends.
+        let step_x = moved_y == total_y
+            || (moved_x < total_x && (2 * moved_x + 1) * total_y < (2 * moved_y + 1) * total_x);
+        if step_x {
+            position.x = if end.x > start.x {
+                position.x + 1
+            } else {
+                position.x - 1
+            };
+            moved_x += 1;
+        } else {
+            position.y = if end.y > start.y {
+                position.y + 1
+            } else {
+                position.y - 1
+            };
+            moved_y += 1;
+        }
+        line.push(position);
+    }
+    line
+}
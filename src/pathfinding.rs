@@ -0,0 +1,435 @@
+use crate::debug::{get_screen_position, DebugOverlay};
+use crate::properties::TerrainPropertyTable;
+use crate::terrain::TerrainGrid;
+use crate::{
+    is_typing, world_position_to_tile_position, GroundLayer, Mouse, TerrainChanged, MAP_HEIGHT,
+    MAP_WIDTH, TILE_SIZE,
+};
+use bevy::prelude::*;
+use bevy_ecs_tilemap::prelude::*;
+use bevy_inspector_egui::bevy_egui::EguiContext;
+use bevy_inspector_egui::egui;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+
+// The cache is emptied once it holds this many paths, most callers ask for the same few again.
+pub const MAX_CACHED_PATHS: usize = 256;
+
+const ORTHOGONAL_OFFSETS: [IVec2; 4] = [IVec2::Y, IVec2::X, IVec2::NEG_Y, IVec2::NEG_X];
+const DIAGONAL_OFFSETS: [IVec2; 4] = [
+    IVec2::new(1, 1),
+    IVec2::new(1, -1),
+    IVec2::new(-1, -1),
+    IVec2::new(-1, 1),
+];
+
+// === Enums ===
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Movement {
+    // North, east, south and west.
+    FourWay,
+    // Diagonals as well, as long as both tiles beside the diagonal can be walked on.
+    EightWay,
+}
+
+// === Structs ===
+#[derive(Clone, Debug, PartialEq)]
+pub struct Path {
+    // From the start to the goal, both included.
+    pub cells: Vec<IVec2>,
+    pub cost: f32,
+}
+
+// A tile waiting to be visited, the one with the lowest estimate is visited first.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct OpenCell {
+    estimate: f32,
+    cost: f32,
+    cell: IVec2,
+}
+
+impl Eq for OpenCell {}
+
+impl Ord for OpenCell {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Reversed, `BinaryHeap` pops the largest first.
+        other
+            .estimate
+            .partial_cmp(&self.estimate)
+            .unwrap_or(Ordering::Equal)
+            .then_with(|| {
+                self.cost
+                    .partial_cmp(&other.cost)
+                    .unwrap_or(Ordering::Equal)
+            })
+    }
+}
+
+impl PartialOrd for OpenCell {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// === Resources ===
+// Finds paths over the ground. It keeps its own copy of the terrain, kept up to date from
+// `TerrainChanged`, so paths can be asked for from any system.
+pub struct Pathfinder {
+    pub movement: Movement,
+    terrain_grid: TerrainGrid,
+    cache: HashMap<(IVec2, IVec2, Movement), Option<Path>>,
+    // The ends of the path drawn by the debug overlay.
+    pub debug_start: Option<IVec2>,
+    pub debug_goal: Option<IVec2>,
+}
+
+impl Pathfinder {
+    // The cheapest path from `start` to `goal`, `None` when there is none.
+    pub fn find_path(
+        &mut self,
+        start: IVec2,
+        goal: IVec2,
+        property_table: &TerrainPropertyTable,
+    ) -> Option<Path> {
+        let key = (start, goal, self.movement);
+        if let Some(path) = self.cache.get(&key) {
+            return path.clone();
+        }
+        if self.cache.len() >= MAX_CACHED_PATHS {
+            self.cache.clear();
+        }
+        let path = find_path(
+            &self.terrain_grid,
+            start,
+            goal,
+            self.movement,
+            property_table,
+        );
+        self.cache.insert(key, path.clone());
+        path
+    }
+
+    pub fn clear_cache(&mut self) {
+        self.cache.clear();
+    }
+}
+
+// === Startup Systems ===
+pub fn setup_pathfinder(mut commands: Commands) {
+    commands.insert_resource(Pathfinder {
+        movement: Movement::FourWay,
+        terrain_grid: TerrainGrid::new(MAP_WIDTH as u32, MAP_HEIGHT as u32),
+        cache: HashMap::new(),
+        debug_start: None,
+        debug_goal: None,
+    });
+}
+
+// === Systems ===
+// Any change to the terrain or its properties may open a shorter path or block a cached one, so
+// the whole cache is dropped.
+pub fn update_pathfinder(
+    mut terrain_changed_event_reader: EventReader<TerrainChanged>,
+    mut pathfinder: ResMut<Pathfinder>,
+    property_table: Res<TerrainPropertyTable>,
+) {
+    let mut is_changed = property_table.is_changed();
+    for terrain_changed in terrain_changed_event_reader.iter() {
+        pathfinder.terrain_grid.set(
+            terrain_changed.pos.x,
+            terrain_changed.pos.y,
+            terrain_changed.new,
+        );
+        is_changed = true;
+    }
+    if is_changed {
+        pathfinder.clear_cache();
+    }
+}
+
+// While the debug overlay is shown, Shift+right click picks the start of the debug path, then its
+// goal. F4 switches between moving in 4 and 8 directions.
+pub fn pick_path_cells(
+    keyboard: Res<Input<KeyCode>>,
+    mut egui_context: ResMut<EguiContext>,
+    mouse_input: Res<Input<MouseButton>>,
+    mouse: Res<Mouse>,
+    debug_overlay: Res<DebugOverlay>,
+    property_table: Res<TerrainPropertyTable>,
+    mut pathfinder: ResMut<Pathfinder>,
+    ground_layer_query: Query<
+        (&TilemapSize, &TilemapGridSize, &TilemapType, &Transform),
+        With<GroundLayer>,
+    >,
+) {
+    if !debug_overlay.is_visible {
+        return;
+    }
+    if keyboard.just_pressed(KeyCode::F4) && !is_typing(&mut egui_context) {
+        pathfinder.movement = match pathfinder.movement {
+            Movement::FourWay => Movement::EightWay,
+            Movement::EightWay => Movement::FourWay,
+        };
+        println!("Movement Updated: {:?}", pathfinder.movement);
+    }
+    let is_shift_pressed = keyboard.pressed(KeyCode::LShift) || keyboard.pressed(KeyCode::RShift);
+    if !is_shift_pressed || !mouse_input.just_pressed(MouseButton::Right) || mouse.is_over_ui {
+        return;
+    }
+    let (map_size, grid_size, map_type, map_transform) = match ground_layer_query.get_single() {
+        Ok(ground_layer) => ground_layer,
+        Err(_) => return,
+    };
+    let tile_position = match world_position_to_tile_position(
+        mouse.world_position,
+        map_size,
+        grid_size,
+        map_type,
+        map_transform,
+    ) {
+        Some(tile_position) => tile_position,
+        None => return,
+    };
+    let cell = IVec2::new(tile_position.x as i32, tile_position.y as i32);
+    match (pathfinder.debug_start, pathfinder.debug_goal) {
+        (Some(start), None) => {
+            pathfinder.debug_goal = Some(cell);
+            match pathfinder.find_path(start, cell, &property_table) {
+                Some(path) => println!(
+                    "Path from ({}, {}) to ({}, {}): {} tiles, cost {}",
+                    start.x,
+                    start.y,
+                    cell.x,
+                    cell.y,
+                    path.cells.len(),
+                    path.cost
+                ),
+                None => println!(
+                    "No path from ({}, {}) to ({}, {})",
+                    start.x, start.y, cell.x, cell.y
+                ),
+            }
+        }
+        _ => {
+            pathfinder.debug_start = Some(cell);
+            pathfinder.debug_goal = None;
+        }
+    }
+}
+
+// Draws the debug path over the map, from a green start to a red goal.
+pub fn draw_debug_path(
+    mut egui_context: ResMut<EguiContext>,
+    mut pathfinder: ResMut<Pathfinder>,
+    debug_overlay: Res<DebugOverlay>,
+    property_table: Res<TerrainPropertyTable>,
+    windows: Res<Windows>,
+    camera_query: Query<(&Camera, &GlobalTransform, &OrthographicProjection)>,
+    ground_layer_query: Query<(&TilemapGridSize, &TilemapType, &Transform), With<GroundLayer>>,
+) {
+    if !debug_overlay.is_visible {
+        return;
+    }
+    let start = match pathfinder.debug_start {
+        Some(start) => start,
+        None => return,
+    };
+    let (camera, camera_transform, projection) = match camera_query.get_single() {
+        Ok(camera) => camera,
+        Err(_) => return,
+    };
+    let (grid_size, map_type, map_transform) = match ground_layer_query.get_single() {
+        Ok(ground_layer) => ground_layer,
+        Err(_) => return,
+    };
+    let window = match windows.get_primary() {
+        Some(window) => window,
+        None => return,
+    };
+
+    let get_position = |cell: IVec2| {
+        let tile_position = TilePos {
+            x: cell.x as u32,
+            y: cell.y as u32,
+        };
+        get_screen_position(
+            &tile_position,
+            grid_size,
+            map_type,
+            map_transform,
+            camera,
+            camera_transform,
+            window.height(),
+        )
+    };
+    let radius = TILE_SIZE as f32 / projection.scale / 4.0;
+    let painter = egui_context
+        .ctx_mut()
+        .layer_painter(egui::LayerId::background());
+    if let Some(goal) = pathfinder.debug_goal {
+        if let Some(path) = pathfinder.find_path(start, goal, &property_table) {
+            let points: Vec<egui::Pos2> = path
+                .cells
+                .iter()
+                .filter_map(|cell| get_position(*cell))
+                .collect();
+            painter.add(egui::Shape::line(
+                points,
+                egui::Stroke::new(radius / 2.0, egui::Color32::from_rgb(0, 160, 255)),
+            ));
+        }
+        if let Some(position) = get_position(goal) {
+            painter.circle_filled(position, radius, egui::Color32::RED);
+        }
+    }
+    if let Some(position) = get_position(start) {
+        painter.circle_filled(position, radius, egui::Color32::GREEN);
+    }
+}
+
+// === Helper Functions ===
+// A* over the terrain. Entering a tile costs the movement cost of its terrain, times the square
+// root of 2 for diagonal steps.
+pub fn find_path(
+    terrain_grid: &TerrainGrid,
+    start: IVec2,
+    goal: IVec2,
+    movement: Movement,
+    property_table: &TerrainPropertyTable,
+) -> Option<Path> {
+    let get_cost = |cell: IVec2| property_table.get_movement_cost(terrain_grid.get(cell.x, cell.y));
+    get_cost(start)?;
+    get_cost(goal)?;
+    let min_cost = property_table.get_min_movement_cost();
+    let get_estimate = |cell: IVec2| {
+        let distance = (goal - cell).abs();
+        let (long, short) = (distance.max_element(), distance.min_element());
+        let steps = match movement {
+            Movement::FourWay => (long + short) as f32,
+            Movement::EightWay => (long - short) as f32 + std::f32::consts::SQRT_2 * short as f32,
+        };
+        steps * min_cost
+    };
+
+    let mut open_cells = BinaryHeap::from([OpenCell {
+        estimate: get_estimate(start),
+        cost: 0.0,
+        cell: start,
+    }]);
+    let mut costs = HashMap::from([(start, 0.0)]);
+    let mut previous_cells: HashMap<IVec2, IVec2> = HashMap::new();
+    while let Some(open_cell) = open_cells.pop() {
+        if open_cell.cell == goal {
+            let mut cells = vec![goal];
+            while let Some(previous_cell) = previous_cells.get(cells.last()?) {
+                cells.push(*previous_cell);
+            }
+            cells.reverse();
+            return Some(Path {
+                cells,
+                cost: open_cell.cost,
+            });
+        }
+        // Visited already through a cheaper path.
+        if open_cell.cost > costs[&open_cell.cell] {
+            continue;
+        }
+        let mut steps: Vec<(IVec2, f32)> = ORTHOGONAL_OFFSETS
+            .iter()
+            .map(|offset| (*offset, 1.0))
+            .collect();
+        if movement == Movement::EightWay {
+            steps.extend(
+                DIAGONAL_OFFSETS
+                    .iter()
+                    // Diagonals cannot cut the corner of a tile that cannot be walked on.
+                    .filter(|offset| {
+                        get_cost(open_cell.cell + IVec2::new(offset.x, 0)).is_some()
+                            && get_cost(open_cell.cell + IVec2::new(0, offset.y)).is_some()
+                    })
+                    .map(|offset| (*offset, std::f32::consts::SQRT_2)),
+            );
+        }
+        for (offset, distance) in steps {
+            let neighbor = open_cell.cell + offset;
+            let cost = match get_cost(neighbor) {
+                Some(movement_cost) => open_cell.cost + movement_cost * distance,
+                None => continue,
+            };
+            if costs
+                .get(&neighbor)
+                .map_or(false, |previous_cost| cost >= *previous_cost)
+            {
+                continue;
+            }
+            costs.insert(neighbor, cost);
+            previous_cells.insert(neighbor, open_cell.cell);
+            open_cells.push(OpenCell {
+                estimate: cost + get_estimate(neighbor),
+                cost,
+                cell: neighbor,
+            });
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn eight_way_does_not_cut_corners() {
+        let property_table = TerrainPropertyTable::default();
+        let terrain_grid = TerrainGrid::from_ascii(&["~g", "g~"]);
+        let path = find_path(
+            &terrain_grid,
+            IVec2::new(0, 0),
+            IVec2::new(1, 1),
+            Movement::EightWay,
+            &property_table,
+        );
+        assert_eq!(path, None);
+
+        let terrain_grid = TerrainGrid::from_ascii(&["gg", "g~"]);
+        let path = find_path(
+            &terrain_grid,
+            IVec2::new(0, 0),
+            IVec2::new(1, 1),
+            Movement::EightWay,
+            &property_table,
+        )
+        .unwrap();
+        assert_eq!(
+            path.cells,
+            vec![IVec2::new(0, 0), IVec2::new(0, 1), IVec2::new(1, 1)]
+        );
+        assert_eq!(path.cost, 2.0);
+    }
+
+    #[test]
+    fn paths_cost_the_terrain_they_enter() {
+        let property_table = TerrainPropertyTable::default();
+        let terrain_grid = TerrainGrid::from_ascii(&["ggdgg"]);
+        let path = find_path(
+            &terrain_grid,
+            IVec2::new(0, 0),
+            IVec2::new(4, 0),
+            Movement::FourWay,
+            &property_table,
+        )
+        .unwrap();
+        assert_eq!(path.cells.len(), 5);
+        assert_eq!(path.cost, 5.0);
+
+        let terrain_grid = TerrainGrid::from_ascii(&["gg~gg"]);
+        let path = find_path(
+            &terrain_grid,
+            IVec2::new(0, 0),
+            IVec2::new(4, 0),
+            Movement::FourWay,
+            &property_table,
+        );
+        assert_eq!(path, None);
+    }
+}
@@ -0,0 +1,265 @@
+use crate::objects::ConnectableType;
+use crate::resolver::{AutotileResolvers, TileOutput};
+use crate::selection::{is_selecting, Clipboard};
+use crate::structures::StructureFootprint;
+use crate::terrain::TerrainGrid;
+use crate::tools::ToolState;
+use crate::{
+    get_terrain_grid, world_position_to_tile_position, DirtTile, GameState, GrassTile, GroundLayer,
+    Mouse, Rules, SpriteRegistry, SpriteType, WaterTile, MAP_HEIGHT, MAP_WIDTH, TILE_SIZE,
+};
+use bevy::prelude::*;
+use bevy_ecs_tilemap::prelude::*;
+use std::collections::{HashMap, HashSet};
+
+// Preview tiles are drawn see-through, so the ground they would replace still shows.
+pub const PREVIEW_COLOR: Color = Color::rgba(1.0, 1.0, 1.0, 0.6);
+
+// === Components ===
+#[derive(Component, Debug)]
+pub struct PreviewLayer {}
+
+// === Resources ===
+pub struct BrushPreview {
+    // The preview tiles currently shown, hidden again once the brush moves away.
+    pub tile_positions: Vec<TilePos>,
+}
+
+// === Startup Systems ===
+pub fn setup_preview_layer(mut commands: Commands, sprite_registry: Res<SpriteRegistry>) {
+    let tilemap_size = TilemapSize {
+        x: MAP_WIDTH as u32,
+        y: MAP_HEIGHT as u32,
+    };
+    let tilemap_entity = commands.spawn().id();
+    let mut tile_storage = TileStorage::empty(tilemap_size);
+
+    // Spawn the elements of the tilemap, hidden until the brush is over them.
+    for y in 0..tilemap_size.y {
+        for x in 0..tilemap_size.x {
+            let tile_position = TilePos { x, y };
+            let tile_entity = commands
+                .spawn()
+                .insert_bundle(TileBundle {
+                    position: tile_position,
+                    texture: TileTexture(sprite_registry.get_blank_index()),
+                    tilemap_id: TilemapId(tilemap_entity),
+                    visible: TileVisible(false),
+                    color: TileColor(PREVIEW_COLOR),
+                    ..default()
+                })
+                .id();
+            tile_storage.set(&tile_position, tile_entity);
+        }
+    }
+
+    let grid_size = TilemapGridSize {
+        x: TILE_SIZE as f32,
+        y: TILE_SIZE as f32,
+    };
+    let tile_size = TilemapTileSize {
+        x: TILE_SIZE as f32,
+        y: TILE_SIZE as f32,
+    };
+    let tilemap_texture = TilemapTexture::Single(sprite_registry.texture.clone());
+
+    commands
+        .entity(tilemap_entity)
+        .insert_bundle(TilemapBundle {
+            grid_size,
+            size: tilemap_size,
+            storage: tile_storage,
+            texture: tilemap_texture,
+            map_type: TilemapType::Square {
+                diagonal_neighbors: true,
+            },
+            tile_size,
+            // The preview is drawn above every other layer, so nothing hides it.
+            transform: Transform::from_xyz(0.0, 0.0, 2.0 + ConnectableType::ALL.len() as f32),
+            ..Default::default()
+        })
+        .insert(PreviewLayer {});
+
+    commands.insert_resource(BrushPreview {
+        tile_positions: Vec::new(),
+    });
+}
+
+// === Systems ===
+// Shows what clicking would paint, along with how the neighbors would be autotiled around it. The
+// terrain is resolved on a copy of the ground, the ground itself is left untouched.
+pub fn update_brush_preview(
+    mut brush_preview: ResMut<BrushPreview>,
+    clipboard: Res<Clipboard>,
+    game_state: Res<GameState>,
+    mouse: Res<Mouse>,
+    rules: Res<Rules>,
+    autotile_resolvers: Res<AutotileResolvers>,
+    sprite_registry: Res<SpriteRegistry>,
+    tool_state: Res<ToolState>,
+    ground_layer_query: Query<
+        (
+            &TilemapSize,
+            &TilemapGridSize,
+            &TilemapType,
+            &TileStorage,
+            &Transform,
+        ),
+        With<GroundLayer>,
+    >,
+    preview_layer_query: Query<&TileStorage, With<PreviewLayer>>,
+    terrain_query: Query<(Option<&GrassTile>, Option<&DirtTile>, Option<&WaterTile>)>,
+    structure_footprints_query: Query<&TilePos, With<StructureFootprint>>,
+    mut preview_tiles_query: Query<(&mut TileTexture, &mut TileFlip, &mut TileVisible)>,
+) {
+    let preview_storage = match preview_layer_query.get_single() {
+        Ok(preview_storage) => preview_storage,
+        Err(_) => return,
+    };
+
+    // Only terrain is previewed, and only where a click would paint it.
+    let sprite_type = match game_state.selection {
+        SpriteType::Blank => Some(None),
+        SpriteType::Grass | SpriteType::Dirt | SpriteType::Water => {
+            Some(Some(game_state.selection))
+        }
+        _ => None,
+    };
+    let mut preview_sprites = HashMap::new();
+    if let (Some(sprite_type), Ok(ground_layer)) = (sprite_type, ground_layer_query.get_single()) {
+        let (map_size, grid_size, map_type, tile_storage, map_transform) = ground_layer;
+        let tile_position = world_position_to_tile_position(
+            mouse.world_position,
+            map_size,
+            grid_size,
+            map_type,
+            map_transform,
+        );
+        // While the mouse button is held only shapes are previewed, everything else is painted as
+        // the cursor moves.
+        let stroke_start = match tool_state.stroke_start {
+            Some(stroke_start) if tool_state.is_shape() => Some(stroke_start),
+            Some(_) => None,
+            None if mouse.is_over_ui => None,
+            None => tile_position,
+        };
+        if let (Some(start), Some(end)) = (stroke_start, tile_position) {
+            if !is_selecting(&tool_state, &clipboard) {
+                let mut terrain_grid = get_terrain_grid(map_size, tile_storage, &terrain_query);
+                for tile_position in structure_footprints_query.iter() {
+                    terrain_grid.set_structure(tile_position.x, tile_position.y, true);
+                }
+                let cells = tool_state.get_cells(
+                    IVec2::new(start.x as i32, start.y as i32),
+                    IVec2::new(end.x as i32, end.y as i32),
+                    &terrain_grid,
+                );
+                for (cell, tile_output) in get_preview_sprites(
+                    &terrain_grid,
+                    &cells,
+                    sprite_type,
+                    &rules,
+                    &autotile_resolvers,
+                    &sprite_registry,
+                ) {
+                    let tile_position = TilePos {
+                        x: cell.x as u32,
+                        y: cell.y as u32,
+                    };
+                    preview_sprites.insert(tile_position, tile_output);
+                }
+            }
+        }
+    }
+
+    // Only tiles whose preview changed are written, so the tilemap is not rebuilt every frame.
+    for tile_position in &brush_preview.tile_positions {
+        if preview_sprites.contains_key(tile_position) {
+            continue;
+        }
+        if let Some(tile_entity) = preview_storage.get(tile_position) {
+            if let Ok((_, _, mut tile_visible)) = preview_tiles_query.get_mut(tile_entity) {
+                tile_visible.0 = false;
+            }
+        }
+    }
+    for (tile_position, tile_output) in &preview_sprites {
+        if let Some(tile_entity) = preview_storage.get(tile_position) {
+            if let Ok((mut tile_texture, mut tile_flip, mut tile_visible)) =
+                preview_tiles_query.get_mut(tile_entity)
+            {
+                if tile_texture.0 != tile_output.atlas_index {
+                    tile_texture.0 = tile_output.atlas_index;
+                }
+                if !is_same_flip(&tile_flip, &tile_output.flip) {
+                    *tile_flip = tile_output.flip;
+                }
+                if !tile_visible.0 {
+                    tile_visible.0 = true;
+                }
+            }
+        }
+    }
+    brush_preview.tile_positions = preview_sprites.into_keys().collect();
+}
+
+// === Helper Functions ===
+// Paints `cells` on a copy of `terrain_grid` and returns what the painted cells are drawn with,
+// along with the neighbors whose sprite changes because of them. Cells that resolve to nothing,
+// such as erased ones, are left out.
+pub fn get_preview_sprites(
+    terrain_grid: &TerrainGrid,
+    cells: &[IVec2],
+    sprite_type: Option<SpriteType>,
+    rules: &Rules,
+    autotile_resolvers: &AutotileResolvers,
+    sprite_registry: &SpriteRegistry,
+) -> Vec<(IVec2, TileOutput)> {
+    let is_inside = |cell: IVec2| {
+        cell.x >= 0
+            && cell.y >= 0
+            && cell.x < terrain_grid.width as i32
+            && cell.y < terrain_grid.height as i32
+    };
+    let mut painted_grid = terrain_grid.clone();
+    let mut painted_cells = HashSet::new();
+    for cell in cells {
+        if is_inside(*cell) {
+            painted_grid.set(cell.x as u32, cell.y as u32, sprite_type);
+            painted_cells.insert(*cell);
+        }
+    }
+
+    let mut seen = HashSet::new();
+    let mut preview_sprites = Vec::new();
+    for cell in &painted_cells {
+        for dy in -1..=1 {
+            for dx in -1..=1 {
+                let neighbor = *cell + IVec2::new(dx, dy);
+                if !is_inside(neighbor) || !seen.insert(neighbor) {
+                    continue;
+                }
+                let (x, y) = (neighbor.x as u32, neighbor.y as u32);
+                let resolve_tile = |grid: &TerrainGrid| {
+                    grid.resolve_tile(x, y, rules, autotile_resolvers, sprite_registry)
+                };
+                let tile_output = match resolve_tile(&painted_grid) {
+                    Some(tile_output) => tile_output,
+                    None => continue,
+                };
+                let is_changed = resolve_tile(terrain_grid).map_or(true, |previous_output| {
+                    previous_output.atlas_index != tile_output.atlas_index
+                        || !is_same_flip(&previous_output.flip, &tile_output.flip)
+                });
+                if painted_cells.contains(&neighbor) || is_changed {
+                    preview_sprites.push((neighbor, tile_output));
+                }
+            }
+        }
+    }
+    preview_sprites
+}
+
+pub fn is_same_flip(flip: &TileFlip, other: &TileFlip) -> bool {
+    flip.x == other.x && flip.y == other.y && flip.d == other.d
+}
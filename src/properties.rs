@@ -0,0 +1,81 @@
+use crate::SpriteType;
+use bevy::prelude::*;
+use std::collections::HashMap;
+
+// === Structs ===
+// What a terrain means for gameplay, as opposed to how it is drawn.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TerrainProperties {
+    pub walkable: bool,
+    // How much entering a tile of the terrain costs, 1 for normal ground.
+    pub movement_cost: f32,
+    // Free-form labels game code can check, such as "slow" or "swimmable".
+    pub tags: Vec<String>,
+}
+
+impl TerrainProperties {
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|other| other == tag)
+    }
+}
+
+// === Resources ===
+// The properties of each terrain. Tiles without terrain, or whose terrain is missing from the
+// table, cannot be walked on.
+pub struct TerrainPropertyTable {
+    pub properties: HashMap<SpriteType, TerrainProperties>,
+}
+
+impl TerrainPropertyTable {
+    pub fn get(&self, sprite_type: Option<SpriteType>) -> Option<&TerrainProperties> {
+        self.properties.get(&sprite_type?)
+    }
+
+    pub fn is_walkable(&self, sprite_type: Option<SpriteType>) -> bool {
+        self.get(sprite_type)
+            .map_or(false, |properties| properties.walkable)
+    }
+
+    // The cost of entering a tile, `None` when it cannot be walked on.
+    pub fn get_movement_cost(&self, sprite_type: Option<SpriteType>) -> Option<f32> {
+        self.get(sprite_type)
+            .filter(|properties| properties.walkable)
+            .map(|properties| properties.movement_cost)
+    }
+
+    // The lowest cost of entering any walkable tile, which keeps the pathfinding estimate from
+    // overshooting.
+    pub fn get_min_movement_cost(&self) -> f32 {
+        self.properties
+            .values()
+            .filter(|properties| properties.walkable)
+            .map(|properties| properties.movement_cost)
+            .fold(f32::INFINITY, f32::min)
+    }
+}
+
+impl Default for TerrainPropertyTable {
+    fn default() -> TerrainPropertyTable {
+        let get_properties =
+            |walkable: bool, movement_cost: f32, tags: &[&str]| TerrainProperties {
+                walkable,
+                movement_cost,
+                tags: tags.iter().map(|tag| tag.to_string()).collect(),
+            };
+        TerrainPropertyTable {
+            properties: HashMap::from([
+                (SpriteType::Grass, get_properties(true, 1.0, &["ground"])),
+                (
+                    SpriteType::Dirt,
+                    get_properties(true, 2.0, &["ground", "slow"]),
+                ),
+                (SpriteType::Water, get_properties(false, 1.0, &["water"])),
+            ]),
+        }
+    }
+}
+
+// === Startup Systems ===
+pub fn setup_terrain_properties(mut commands: Commands) {
+    commands.insert_resource(TerrainPropertyTable::default());
+}
@@ -0,0 +1,233 @@
+use crate::atlas::{Atlas, AtlasError};
+use crate::ldtk::LdtkImport;
+use crate::resolver::{AutotileResolvers, ResolverKind};
+use crate::save::{get_map_file_terrain, read_map_file, MapFileError, MapFormat};
+use crate::structures::Stamps;
+use crate::terrain::TerrainGrid;
+use crate::tiled::TiledImport;
+use crate::{build_sprite_atlas, get_rules, Rules, SpriteRegistry};
+use bevy::prelude::*;
+use bevy_ecs_tilemap::prelude::*;
+use image::{Rgba, RgbaImage};
+use std::fmt;
+use std::path::PathBuf;
+
+// === Constants ===
+const BACKGROUND_COLOR: Rgba<u8> = Rgba([0, 0, 0, 255]);
+const GRID_COLOR: Rgba<u8> = Rgba([64, 64, 64, 255]);
+
+// === Enums ===
+#[derive(Debug)]
+pub enum RenderError {
+    Map(MapFileError),
+    Atlas(AtlasError),
+    Image {
+        path: PathBuf,
+        error: image::ImageError,
+    },
+}
+
+impl fmt::Display for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RenderError::Map(error) => write!(f, "{}", error),
+            RenderError::Atlas(error) => write!(f, "{}", error),
+            RenderError::Image { path, error } => {
+                write!(f, "could not write {:?}: {}", path, error)
+            }
+        }
+    }
+}
+
+impl std::error::Error for RenderError {}
+
+// === Structs ===
+// Renders a saved map to a PNG instead of opening a window, so previews can be made without a GPU:
+// `--render <map> <png> [--scale <factor>] [--grid]`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RenderOptions {
+    pub map_path: PathBuf,
+    pub image_path: PathBuf,
+    pub scale: u32,
+    pub grid_lines: bool,
+}
+
+impl RenderOptions {
+    // Returns `None` when no render was asked for.
+    pub fn from_args(mut args: impl Iterator<Item = String>) -> Option<RenderOptions> {
+        let mut paths = None;
+        let mut scale = 1;
+        let mut grid_lines = false;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--render" => {
+                    if let (Some(map_path), Some(image_path)) = (args.next(), args.next()) {
+                        paths = Some((PathBuf::from(map_path), PathBuf::from(image_path)));
+                    }
+                }
+                "--scale" => {
+                    if let Some(factor) = args.next().and_then(|factor| factor.parse().ok()) {
+                        scale = u32::max(factor, 1);
+                    }
+                }
+                "--grid" => grid_lines = true,
+                _ => {}
+            }
+        }
+        let (map_path, image_path) = paths?;
+        Some(RenderOptions {
+            map_path,
+            image_path,
+            scale,
+            grid_lines,
+        })
+    }
+}
+
+// === Helper Functions ===
+// Loads the map and renders it with the same rules, resolvers and atlas the editor would use.
+pub fn render_map_file(
+    options: &RenderOptions,
+    tiled_import: &TiledImport,
+    ldtk_import: &LdtkImport,
+    resolver_kind: ResolverKind,
+) -> Result<(), RenderError> {
+    let map_format = MapFormat::from_path(&options.map_path);
+    let map_file = read_map_file(&options.map_path, map_format).map_err(RenderError::Map)?;
+    let terrain = get_map_file_terrain(&options.map_path, &map_file).map_err(RenderError::Map)?;
+    let mut terrain_grid = TerrainGrid::from_rows(map_file.width, map_file.height, &terrain);
+    // The ground around structures is drawn as if it continued underneath, like in the editor.
+    let stamps = Stamps::default();
+    for saved_structure in &map_file.structures {
+        if saved_structure.x >= map_file.width || saved_structure.y >= map_file.height {
+            continue;
+        }
+        let origin = TilePos {
+            x: saved_structure.x,
+            y: saved_structure.y,
+        };
+        for (cell_position, _) in stamps.stamps[&saved_structure.stamp_type].get_cells(&origin) {
+            terrain_grid.set_structure(cell_position.x, cell_position.y, true);
+        }
+    }
+
+    let rules = get_rules(tiled_import, ldtk_import);
+    if map_file.rule_set != rules.rule_set {
+        println!(
+            "{:?} was painted with the {:?} rules, it is rendered with the {:?} rules",
+            options.map_path, map_file.rule_set, rules.rule_set
+        );
+    }
+    let atlas = build_sprite_atlas(tiled_import, ldtk_import).map_err(RenderError::Atlas)?;
+    let autotile_resolvers = AutotileResolvers::new(resolver_kind, &rules);
+
+    let image = render_terrain(
+        &terrain_grid,
+        &rules,
+        &autotile_resolvers,
+        &atlas,
+        options.scale,
+        options.grid_lines,
+    );
+    image
+        .save(&options.image_path)
+        .map_err(|error| RenderError::Image {
+            path: options.image_path.clone(),
+            error,
+        })
+}
+
+// Autotiles the terrain and copies the matching atlas cells into an image, north side up, flipped
+// like the resolvers flip them. Tiles that resolve to nothing are left as background, like blank
+// tiles in the editor.
+pub fn render_terrain(
+    terrain_grid: &TerrainGrid,
+    rules: &Rules,
+    autotile_resolvers: &AutotileResolvers,
+    atlas: &Atlas,
+    scale: u32,
+    grid_lines: bool,
+) -> RgbaImage {
+    let tile_size = atlas.tile_size;
+    let columns = atlas.image.width() / tile_size;
+    let cell_size = tile_size * scale;
+    // The resolvers pick atlas indices by sprite name, the texture is not needed to render.
+    let sprite_registry = SpriteRegistry {
+        atlas_indices: atlas.indices.clone(),
+        texture: Handle::default(),
+        atlas_columns: columns,
+        atlas_rows: atlas.image.height() / tile_size,
+    };
+    let mut image = RgbaImage::from_pixel(
+        terrain_grid.width * cell_size,
+        terrain_grid.height * cell_size,
+        BACKGROUND_COLOR,
+    );
+
+    let tile_outputs = terrain_grid.resolve(rules, autotile_resolvers, &sprite_registry);
+    for y in 0..terrain_grid.height {
+        for x in 0..terrain_grid.width {
+            let tile_output = match tile_outputs[(y * terrain_grid.width + x) as usize] {
+                Some(tile_output) => tile_output,
+                None => continue,
+            };
+            let source_x = (tile_output.atlas_index % columns) * tile_size;
+            let source_y = (tile_output.atlas_index / columns) * tile_size;
+            // Rows count from the bottom of the map, but from the top of the image.
+            let target_x = x * cell_size;
+            let target_y = (terrain_grid.height - 1 - y) * cell_size;
+            for pixel_y in 0..cell_size {
+                for pixel_x in 0..cell_size {
+                    let (offset_x, offset_y) = get_flipped_offset(
+                        pixel_x / scale,
+                        pixel_y / scale,
+                        tile_size,
+                        &tile_output.flip,
+                    );
+                    let source = atlas
+                        .image
+                        .get_pixel(source_x + offset_x, source_y + offset_y);
+                    let target = image.get_pixel_mut(target_x + pixel_x, target_y + pixel_y);
+                    *target = blend(*target, *source);
+                }
+            }
+        }
+    }
+
+    if grid_lines {
+        for y in 0..image.height() {
+            for x in 0..image.width() {
+                if x % cell_size == 0 || y % cell_size == 0 {
+                    image.put_pixel(x, y, GRID_COLOR);
+                }
+            }
+        }
+    }
+    image
+}
+
+// The pixel of the sprite drawn at `x`, `y` of a flipped tile. Tiles are flipped along the
+// diagonal first, then horizontally and vertically, so the flips are undone in the opposite order.
+fn get_flipped_offset(x: u32, y: u32, tile_size: u32, flip: &TileFlip) -> (u32, u32) {
+    let y = if flip.y { tile_size - 1 - y } else { y };
+    let x = if flip.x { tile_size - 1 - x } else { x };
+    if flip.d {
+        (y, x)
+    } else {
+        (x, y)
+    }
+}
+
+// Draws `source` over an opaque `target`.
+fn blend(target: Rgba<u8>, source: Rgba<u8>) -> Rgba<u8> {
+    let alpha = source[3] as u32;
+    let mix = |target: u8, source: u8| {
+        ((source as u32 * alpha + target as u32 * (255 - alpha) + 127) / 255) as u8
+    };
+    Rgba([
+        mix(target[0], source[0]),
+        mix(target[1], source[1]),
+        mix(target[2], source[2]),
+        255,
+    ])
+}
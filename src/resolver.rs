@@ -0,0 +1,501 @@
+use crate::debug::get_slots;
+use crate::{get_matching_sprite, Rule, Rules, Slot, SpriteRegistry, SpriteType, TERRAIN_TYPES};
+use bevy::prelude::*;
+use bevy_ecs_tilemap::prelude::*;
+use std::collections::{HashMap, HashSet};
+
+// Bits of a neighbor mask, clockwise from north. A bit is set when that neighbor has the same
+// terrain as the tile.
+pub const MASK_N: u8 = 1;
+pub const MASK_NE: u8 = 2;
+pub const MASK_E: u8 = 4;
+pub const MASK_SE: u8 = 8;
+pub const MASK_S: u8 = 16;
+pub const MASK_SW: u8 = 32;
+pub const MASK_W: u8 = 64;
+pub const MASK_NW: u8 = 128;
+
+// === Structs ===
+// What a resolver sees of a tile: where it is and the terrain of the 8 tiles around it, laid out
+// like a rule.
+#[derive(Clone, Copy, Debug)]
+pub struct Neighborhood {
+    pub tile_position: TilePos,
+    pub active_rule: Rule,
+}
+
+impl Neighborhood {
+    pub fn get_mask(&self, sprite_type: SpriteType) -> u8 {
+        get_neighbor_mask(&self.active_rule, sprite_type)
+    }
+}
+
+// What a tile is drawn with.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TileOutput {
+    pub atlas_index: u32,
+    pub flip: TileFlip,
+    // Tiles without an animation keep showing `atlas_index`.
+    pub animation: Option<AnimatedTile>,
+}
+
+impl TileOutput {
+    pub fn from_atlas_index(atlas_index: u32) -> TileOutput {
+        TileOutput {
+            atlas_index,
+            ..default()
+        }
+    }
+}
+
+// === Traits ===
+// Picks what a tile of `sprite_type` is drawn with, given its neighborhood. Returning `None` leaves
+// the tile blank. Resolvers are registered per terrain in `AutotileResolvers`, so they can hold
+// whatever state they need to decide, such as gameplay state shared with other systems.
+pub trait AutotileResolver: Send + Sync {
+    fn resolve(
+        &self,
+        neighborhood: &Neighborhood,
+        sprite_type: SpriteType,
+        rules: &Rules,
+        sprite_registry: &SpriteRegistry,
+    ) -> Option<TileOutput>;
+}
+
+// The first rule of the terrain that matches picks the sprite. This is how legalizing and filling
+// the selection read the rules, whichever resolver draws the tiles.
+pub struct FirstMatchResolver {}
+
+impl AutotileResolver for FirstMatchResolver {
+    fn resolve(
+        &self,
+        neighborhood: &Neighborhood,
+        sprite_type: SpriteType,
+        rules: &Rules,
+        sprite_registry: &SpriteRegistry,
+    ) -> Option<TileOutput> {
+        let possible_rules = rules.rules.get(&sprite_type)?;
+        let sprite_name = get_matching_sprite(&neighborhood.active_rule, possible_rules)?;
+        sprite_registry
+            .get_atlas_index(sprite_name)
+            .map(TileOutput::from_atlas_index)
+    }
+}
+
+// Looks the sprite up by the neighbor mask of the tile, ignoring which other terrain is next to it.
+// Corners only count when both sides next to them are set, which leaves the 47 masks of a blob
+// tileset.
+pub struct BitmaskResolver {
+    pub sprites: HashMap<u8, String>,
+    // Used for masks missing from `sprites`.
+    pub fallback: Option<String>,
+}
+
+impl BitmaskResolver {
+    // Fills the table with the sprite the first matching rule picks for each mask, with empty tiles
+    // where the mask is not set. The table is built again when the rules change, see
+    // `update_autotile_resolvers`.
+    pub fn from_rules(
+        possible_rules: &[(Rule, String)],
+        sprite_type: SpriteType,
+    ) -> BitmaskResolver {
+        let mut sprites = HashMap::new();
+        for mask in 0..=u8::MAX {
+            let mask = get_reduced_mask(mask);
+            if sprites.contains_key(&mask) {
+                continue;
+            }
+            let get_slot = |bit: u8| {
+                if mask & bit != 0 {
+                    Slot::Filled { sprite_type }
+                } else {
+                    Slot::Empty
+                }
+            };
+            let active_rule = Rule {
+                nw_slot: get_slot(MASK_NW),
+                n_slot: get_slot(MASK_N),
+                ne_slot: get_slot(MASK_NE),
+                w_slot: get_slot(MASK_W),
+                c_slot: Slot::Filled { sprite_type },
+                e_slot: get_slot(MASK_E),
+                sw_slot: get_slot(MASK_SW),
+                s_slot: get_slot(MASK_S),
+                se_slot: get_slot(MASK_SE),
+            };
+            if let Some(sprite_name) = get_matching_sprite(&active_rule, possible_rules) {
+                sprites.insert(mask, sprite_name.to_string());
+            }
+        }
+        BitmaskResolver {
+            sprites,
+            fallback: None,
+        }
+    }
+}
+
+impl AutotileResolver for BitmaskResolver {
+    fn resolve(
+        &self,
+        neighborhood: &Neighborhood,
+        sprite_type: SpriteType,
+        _rules: &Rules,
+        sprite_registry: &SpriteRegistry,
+    ) -> Option<TileOutput> {
+        let sprite_name = self
+            .sprites
+            .get(&neighborhood.get_mask(sprite_type))
+            .or(self.fallback.as_ref())?;
+        sprite_registry
+            .get_atlas_index(sprite_name)
+            .map(TileOutput::from_atlas_index)
+    }
+}
+
+// Picks the rule with the fewest slots that differ from the tile, so a tile is only left blank
+// when its terrain has no rules. Ties go to the rule with more exact slots, then to the first one.
+pub struct BestScoreResolver {}
+
+impl AutotileResolver for BestScoreResolver {
+    fn resolve(
+        &self,
+        neighborhood: &Neighborhood,
+        sprite_type: SpriteType,
+        rules: &Rules,
+        sprite_registry: &SpriteRegistry,
+    ) -> Option<TileOutput> {
+        let possible_rules = rules.rules.get(&sprite_type)?;
+        let (_, sprite_name) = possible_rules
+            .iter()
+            .enumerate()
+            .min_by_key(|(index, (rule, _))| {
+                let (mismatches, exact_matches) = get_score(&neighborhood.active_rule, rule);
+                (mismatches, std::cmp::Reverse(exact_matches), *index)
+            })
+            .map(|(_, rule)| rule)?;
+        sprite_registry
+            .get_atlas_index(sprite_name)
+            .map(TileOutput::from_atlas_index)
+    }
+}
+
+// === Resources ===
+// The resolver of each terrain. Terrains without one are resolved by `FirstMatchResolver`.
+pub struct AutotileResolvers {
+    resolvers: HashMap<SpriteType, Box<dyn AutotileResolver>>,
+    // Terrains whose resolver was registered by another system, rather than picked with
+    // `--resolver`. They are kept when the built in ones are set up again.
+    registered_sprite_types: HashSet<SpriteType>,
+    default_resolver: FirstMatchResolver,
+}
+
+impl AutotileResolvers {
+    // The built in resolvers of `resolver_kind`, for the rules as they are.
+    pub fn new(resolver_kind: ResolverKind, rules: &Rules) -> AutotileResolvers {
+        let mut autotile_resolvers = AutotileResolvers {
+            resolvers: HashMap::new(),
+            registered_sprite_types: HashSet::new(),
+            default_resolver: FirstMatchResolver {},
+        };
+        autotile_resolvers.set_builtin_resolvers(resolver_kind, rules);
+        autotile_resolvers
+    }
+
+    pub fn register(&mut self, sprite_type: SpriteType, resolver: impl AutotileResolver + 'static) {
+        self.resolvers.insert(sprite_type, Box::new(resolver));
+        self.registered_sprite_types.insert(sprite_type);
+    }
+
+    // Sets up the built in resolver of every terrain from the rules, apart from the registered ones.
+    pub fn set_builtin_resolvers(&mut self, resolver_kind: ResolverKind, rules: &Rules) {
+        for sprite_type in TERRAIN_TYPES {
+            if self.registered_sprite_types.contains(&sprite_type) {
+                continue;
+            }
+            self.resolvers.remove(&sprite_type);
+            let possible_rules = match rules.rules.get(&sprite_type) {
+                Some(possible_rules) => possible_rules,
+                None => continue,
+            };
+            let resolver: Box<dyn AutotileResolver> = match resolver_kind {
+                ResolverKind::FirstMatch => continue,
+                ResolverKind::Bitmask => {
+                    Box::new(BitmaskResolver::from_rules(possible_rules, sprite_type))
+                }
+                ResolverKind::BestScore => Box::new(BestScoreResolver {}),
+            };
+            self.resolvers.insert(sprite_type, resolver);
+        }
+    }
+
+    pub fn get(&self, sprite_type: SpriteType) -> &dyn AutotileResolver {
+        match self.resolvers.get(&sprite_type) {
+            Some(resolver) => resolver.as_ref(),
+            None => &self.default_resolver,
+        }
+    }
+}
+
+// Which built in resolver terrains use, picked with `--resolver <first-match|bitmask|best-score>`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResolverKind {
+    FirstMatch,
+    Bitmask,
+    BestScore,
+}
+
+impl ResolverKind {
+    pub fn from_args(mut args: impl Iterator<Item = String>) -> ResolverKind {
+        let mut resolver_kind = ResolverKind::FirstMatch;
+        while let Some(arg) = args.next() {
+            if arg != "--resolver" {
+                continue;
+            }
+            match args.next().as_deref() {
+                Some("first-match") => resolver_kind = ResolverKind::FirstMatch,
+                Some("bitmask") => resolver_kind = ResolverKind::Bitmask,
+                Some("best-score") => resolver_kind = ResolverKind::BestScore,
+                other => println!("Unknown resolver {:?}, using first-match", other),
+            }
+        }
+        resolver_kind
+    }
+}
+
+// === Startup Systems ===
+// Resolvers registered later, by any system, replace the built in ones.
+pub fn setup_autotile_resolvers(
+    mut commands: Commands,
+    resolver_kind: Res<ResolverKind>,
+    rules: Res<Rules>,
+) {
+    commands.insert_resource(AutotileResolvers::new(*resolver_kind, &rules));
+}
+
+// === Systems ===
+// Tables built from the rules, such as the one of `BitmaskResolver`, follow the rules edited in
+// the rule editor.
+pub fn update_autotile_resolvers(
+    mut autotile_resolvers: ResMut<AutotileResolvers>,
+    resolver_kind: Res<ResolverKind>,
+    rules: Res<Rules>,
+) {
+    if rules.is_changed() {
+        autotile_resolvers.set_builtin_resolvers(*resolver_kind, &rules);
+    }
+}
+
+// === Helper Functions ===
+// The index of the first rule whose sprite a tile is drawn with, so the tools that reason about
+// rules follow whichever resolver drew it. `None` when no rule has that sprite.
+pub fn get_drawn_rule_index(
+    tile_output: &TileOutput,
+    possible_rules: &[(Rule, String)],
+    sprite_registry: &SpriteRegistry,
+) -> Option<usize> {
+    possible_rules.iter().position(|(_, sprite_name)| {
+        sprite_registry.get_atlas_index(sprite_name) == Some(tile_output.atlas_index)
+    })
+}
+
+// The mask of the neighbors that have the same terrain as a tile of `sprite_type`, with corners
+// reduced as in `get_reduced_mask`.
+pub fn get_neighbor_mask(active_rule: &Rule, sprite_type: SpriteType) -> u8 {
+    let same = Slot::Filled { sprite_type };
+    let mut mask = 0;
+    for (slot, bit) in [
+        (active_rule.n_slot, MASK_N),
+        (active_rule.ne_slot, MASK_NE),
+        (active_rule.e_slot, MASK_E),
+        (active_rule.se_slot, MASK_SE),
+        (active_rule.s_slot, MASK_S),
+        (active_rule.sw_slot, MASK_SW),
+        (active_rule.w_slot, MASK_W),
+        (active_rule.nw_slot, MASK_NW),
+    ] {
+        if slot == same {
+            mask |= bit;
+        }
+    }
+    get_reduced_mask(mask)
+}
+
+// Clears the corners whose two sides are not both set, a corner does not change how such a tile
+// looks.
+pub fn get_reduced_mask(mask: u8) -> u8 {
+    let mut reduced_mask = mask;
+    for (corner, first_side, second_side) in [
+        (MASK_NE, MASK_N, MASK_E),
+        (MASK_SE, MASK_S, MASK_E),
+        (MASK_SW, MASK_S, MASK_W),
+        (MASK_NW, MASK_N, MASK_W),
+    ] {
+        if mask & first_side == 0 || mask & second_side == 0 {
+            reduced_mask &= !corner;
+        }
+    }
+    reduced_mask
+}
+
+// How many slots of the tile differ from the rule, and how many match it exactly rather than
+// through `Slot::Any`.
+fn get_score(active_rule: &Rule, rule: &Rule) -> (usize, usize) {
+    let mut mismatches = 0;
+    let mut exact_matches = 0;
+    for (slot, other) in get_slots(active_rule).into_iter().zip(get_slots(rule)) {
+        if slot == Slot::Any || other == Slot::Any {
+            continue;
+        }
+        if slot == other {
+            exact_matches += 1;
+        } else {
+            mismatches += 1;
+        }
+    }
+    (mismatches, exact_matches)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::get_builtin_rules;
+
+    // A tile of `sprite_type` with the neighbors of `mask` set to the same terrain and the others
+    // empty.
+    fn get_active_rule(mask: u8, sprite_type: SpriteType) -> Rule {
+        let get_slot = |bit: u8| {
+            if mask & bit != 0 {
+                Slot::Filled { sprite_type }
+            } else {
+                Slot::Empty
+            }
+        };
+        Rule {
+            nw_slot: get_slot(MASK_NW),
+            n_slot: get_slot(MASK_N),
+            ne_slot: get_slot(MASK_NE),
+            w_slot: get_slot(MASK_W),
+            c_slot: Slot::Filled { sprite_type },
+            e_slot: get_slot(MASK_E),
+            sw_slot: get_slot(MASK_SW),
+            s_slot: get_slot(MASK_S),
+            se_slot: get_slot(MASK_SE),
+        }
+    }
+
+    fn get_rules(possible_rules: Vec<(Rule, &str)>) -> Rules {
+        let possible_rules = possible_rules
+            .into_iter()
+            .map(|(rule, sprite_name)| (rule, sprite_name.to_string()))
+            .collect();
+        Rules {
+            rules: HashMap::from([(SpriteType::Grass, possible_rules)]),
+            rule_set: "test".to_string(),
+            replaced_rules: HashMap::new(),
+        }
+    }
+
+    fn resolve(
+        resolver: &dyn AutotileResolver,
+        active_rule: Rule,
+        sprite_type: SpriteType,
+        rules: &Rules,
+        sprite_registry: &SpriteRegistry,
+    ) -> Option<u32> {
+        let neighborhood = Neighborhood {
+            tile_position: TilePos { x: 0, y: 0 },
+            active_rule,
+        };
+        resolver
+            .resolve(&neighborhood, sprite_type, rules, sprite_registry)
+            .map(|tile_output| tile_output.atlas_index)
+    }
+
+    #[test]
+    fn the_bitmask_table_agrees_with_the_first_match_for_every_mask() {
+        let rules = get_builtin_rules();
+        let sprite_registry = SpriteRegistry::from_rules(&rules);
+        for sprite_type in [SpriteType::Grass, SpriteType::Dirt] {
+            let bitmask_resolver =
+                BitmaskResolver::from_rules(&rules.rules[&sprite_type], sprite_type);
+            for mask in 0..=u8::MAX {
+                let active_rule = get_active_rule(mask, sprite_type);
+                assert_eq!(
+                    resolve(
+                        &bitmask_resolver,
+                        active_rule,
+                        sprite_type,
+                        &rules,
+                        &sprite_registry
+                    ),
+                    resolve(
+                        &FirstMatchResolver {},
+                        active_rule,
+                        sprite_type,
+                        &rules,
+                        &sprite_registry
+                    ),
+                    "{:?} with mask {:#010b}",
+                    sprite_type,
+                    mask
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn reduced_masks_are_the_47_blob_masks() {
+        let reduced_masks: HashSet<u8> = (0..=u8::MAX).map(get_reduced_mask).collect();
+        assert_eq!(reduced_masks.len(), 47);
+        for mask in reduced_masks {
+            assert_eq!(get_reduced_mask(mask), mask);
+        }
+    }
+
+    #[test]
+    fn best_score_prefers_fewer_mismatches_then_more_exact_slots_then_the_first_rule() {
+        let grass = Slot::Filled {
+            sprite_type: SpriteType::Grass,
+        };
+        let active_rule = get_active_rule(u8::MAX, SpriteType::Grass);
+        let loose_rule = Rule {
+            nw_slot: Slot::Any,
+            n_slot: Slot::Any,
+            ne_slot: Slot::Any,
+            w_slot: Slot::Any,
+            c_slot: grass,
+            e_slot: Slot::Any,
+            sw_slot: Slot::Any,
+            s_slot: Slot::Any,
+            se_slot: Slot::Any,
+        };
+        let mut one_mismatch_rule = loose_rule;
+        one_mismatch_rule.n_slot = Slot::Empty;
+        let mut two_mismatches_rule = one_mismatch_rule;
+        two_mismatches_rule.e_slot = Slot::Empty;
+        let exact_rule = active_rule;
+
+        let cases = [
+            (
+                vec![(two_mismatches_rule, "two"), (one_mismatch_rule, "one")],
+                "one",
+            ),
+            (vec![(loose_rule, "loose"), (exact_rule, "exact")], "exact"),
+            (vec![(exact_rule, "first"), (exact_rule, "second")], "first"),
+        ];
+        for (possible_rules, expected_sprite) in cases {
+            let rules = get_rules(possible_rules);
+            let sprite_registry = SpriteRegistry::from_rules(&rules);
+            assert_eq!(
+                resolve(
+                    &BestScoreResolver {},
+                    active_rule,
+                    SpriteType::Grass,
+                    &rules,
+                    &sprite_registry
+                ),
+                sprite_registry.get_atlas_index(expected_sprite)
+            );
+        }
+    }
+}
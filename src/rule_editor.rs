@@ -0,0 +1,414 @@
+use crate::atlas::get_asset_root;
+use crate::editor::get_atlas_uv;
+use crate::{
+    Rule, Rules, Slot, SpriteRegistry, SpriteType, UpdateTilemapEvent, AUTOTILED_TYPES,
+    BLANK_SPRITE, TERRAIN_TYPES,
+};
+use bevy::prelude::*;
+use bevy_inspector_egui::bevy_egui::EguiContext;
+use bevy_inspector_egui::egui;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+// Relative to the asset root. Once saved from the rule editor, it replaces the built in rules.
+pub const RULE_FILE_PATH: &str = "rules.ron";
+pub const RULE_FILE_VERSION: u32 = 1;
+// Maps painted with edited rules record this rule set.
+pub const RULE_FILE_RULE_SET: &str = "rule_file";
+
+const SLOT_SIZE: f32 = 28.0;
+const SPRITE_SIZE: f32 = 32.0;
+
+// === Enums ===
+#[derive(Debug)]
+pub enum RuleFileError {
+    Io {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+    Format {
+        path: PathBuf,
+        message: String,
+    },
+    UnknownVersion {
+        path: PathBuf,
+        version: u32,
+    },
+}
+
+impl fmt::Display for RuleFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuleFileError::Io { path, error } => {
+                write!(f, "could not access {:?}: {}", path, error)
+            }
+            RuleFileError::Format { path, message } => {
+                write!(f, "invalid rule file {:?}: {}", path, message)
+            }
+            RuleFileError::UnknownVersion { path, version } => {
+                write!(f, "{:?} has unknown version {}", path, version)
+            }
+        }
+    }
+}
+
+impl std::error::Error for RuleFileError {}
+
+// === Structs ===
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RuleFile {
+    pub version: u32,
+    pub rule_set: String,
+    pub terrains: Vec<TerrainRules>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TerrainRules {
+    pub terrain: SpriteType,
+    // Matched in order, the first match picks the sprite.
+    pub rules: Vec<(Rule, String)>,
+}
+
+// === Resources ===
+pub struct RuleEditor {
+    pub is_open: bool,
+    pub sprite_type: SpriteType,
+    // The rule being edited, in the rules of `sprite_type`.
+    pub rule_index: usize,
+    // Whether the atlas picker only lists sprites named after `sprite_type`.
+    pub only_terrain_sprites: bool,
+}
+
+// === Startup Systems ===
+pub fn setup_rule_editor(mut commands: Commands) {
+    commands.insert_resource(RuleEditor {
+        is_open: false,
+        sprite_type: SpriteType::Grass,
+        rule_index: 0,
+        only_terrain_sprites: true,
+    });
+}
+
+// === Systems ===
+// Every change is applied to the live map straight away, saving writes `RULE_FILE_PATH`.
+pub fn update_rule_editor(
+    mut egui_context: ResMut<EguiContext>,
+    mut update_tilemap_event_writer: EventWriter<UpdateTilemapEvent>,
+    mut rule_editor: ResMut<RuleEditor>,
+    mut rules: ResMut<Rules>,
+    sprite_registry: Res<SpriteRegistry>,
+) {
+    if !rule_editor.is_open {
+        return;
+    }
+    let atlas_texture = egui_context.add_image(sprite_registry.texture.clone_weak());
+    let mut is_open = true;
+    let mut sprite_type = rule_editor.sprite_type;
+    let mut rule_index = rule_editor.rule_index;
+    let mut only_terrain_sprites = rule_editor.only_terrain_sprites;
+    // Edited on a copy, so the rules are only marked as changed when they are.
+    let mut possible_rules = rules.rules.get(&sprite_type).cloned().unwrap_or_default();
+    let is_imported = rules.replaced_rules.contains_key(&sprite_type);
+    let mut is_changed = false;
+    let mut is_saved = false;
+
+    let terrain_prefix = format!("{:?}/", sprite_type).to_lowercase();
+    let mut sprite_names: Vec<&String> = sprite_registry
+        .atlas_indices
+        .keys()
+        .filter(|sprite_name| !only_terrain_sprites || sprite_name.starts_with(&terrain_prefix))
+        .collect();
+    sprite_names.sort();
+
+    egui::Window::new("Rule Editor")
+        .open(&mut is_open)
+        .show(egui_context.ctx_mut(), |ui| {
+            let previous_sprite_type = sprite_type;
+            egui::ComboBox::from_label("Terrain")
+                .selected_text(format!("{:?}", sprite_type))
+                .show_ui(ui, |ui| {
+                    // Only autotiled terrains pick their sprite with the rules.
+                    for terrain in AUTOTILED_TYPES {
+                        ui.selectable_value(&mut sprite_type, terrain, format!("{:?}", terrain));
+                    }
+                });
+            if sprite_type != previous_sprite_type {
+                // The other terrain is edited from the next frame on.
+                rule_index = 0;
+                return;
+            }
+
+            ui.label(format!(
+                "{} rules, the first one matching a tile picks its sprite",
+                possible_rules.len()
+            ));
+            egui::ScrollArea::vertical()
+                .id_source("rule_list")
+                .max_height(200.0)
+                .show(ui, |ui| {
+                    for (index, (_, sprite_name)) in possible_rules.iter().enumerate() {
+                        let label = format!("{} {}", index, sprite_name);
+                        if ui.selectable_label(index == rule_index, label).clicked() {
+                            rule_index = index;
+                        }
+                    }
+                });
+
+            // The rule file is saved with the rules from before the import, so edits to imported
+            // rules would be lost.
+            if is_imported {
+                ui.label(format!(
+                    "The rules of {:?} are imported and cannot be edited",
+                    sprite_type
+                ));
+            }
+            ui.add_enabled_ui(!is_imported, |ui| {
+                ui.horizontal(|ui| {
+                    // New rules copy the selected one, so they do not change what the map resolves
+                    // to until they are edited.
+                    if ui.button("Add").clicked() {
+                        let rule = match possible_rules.get(rule_index) {
+                            Some(rule) => rule.clone(),
+                            None => (get_catch_all_rule(sprite_type), BLANK_SPRITE.to_string()),
+                        };
+                        rule_index = usize::min(rule_index + 1, possible_rules.len());
+                        possible_rules.insert(rule_index, rule);
+                        is_changed = true;
+                    }
+                    if ui.button("Remove").clicked() && rule_index < possible_rules.len() {
+                        possible_rules.remove(rule_index);
+                        rule_index = rule_index.saturating_sub(1);
+                        is_changed = true;
+                    }
+                    if ui.button("Move up").clicked() && rule_index > 0 {
+                        possible_rules.swap(rule_index, rule_index - 1);
+                        rule_index -= 1;
+                        is_changed = true;
+                    }
+                    if ui.button("Move down").clicked() && rule_index + 1 < possible_rules.len() {
+                        possible_rules.swap(rule_index, rule_index + 1);
+                        rule_index += 1;
+                        is_changed = true;
+                    }
+                });
+
+                let (rule, sprite_name) = match possible_rules.get_mut(rule_index) {
+                    Some(rule) => rule,
+                    None => return,
+                };
+                ui.separator();
+                ui.horizontal(|ui| {
+                    // Clicking a neighbor cycles it through empty, each terrain and any.
+                    egui::Grid::new("rule_slots").show(ui, |ui| {
+                        for (row_index, row) in get_slot_rows(rule).into_iter().enumerate() {
+                            for (column_index, slot) in row.into_iter().enumerate() {
+                                let is_center = row_index == 1 && column_index == 1;
+                                let button = egui::Button::new(get_slot_label(slot))
+                                    .min_size(egui::vec2(SLOT_SIZE, SLOT_SIZE));
+                                let response = ui
+                                    .add_enabled(!is_center, button)
+                                    .on_hover_text(format!("{:?}", slot));
+                                if response.clicked() {
+                                    *slot = get_next_slot(*slot);
+                                    is_changed = true;
+                                }
+                            }
+                            ui.end_row();
+                        }
+                    });
+                    if let Some(uv) = get_atlas_uv(&sprite_registry, sprite_name) {
+                        ui.add(egui::Image::new(atlas_texture, [SPRITE_SIZE * 2.0; 2]).uv(uv));
+                    }
+                    ui.label(sprite_name.as_str());
+                });
+
+                ui.separator();
+                ui.checkbox(
+                    &mut only_terrain_sprites,
+                    format!("Only {:?} sprites", sprite_type),
+                );
+                egui::ScrollArea::vertical()
+                    .id_source("atlas_picker")
+                    .max_height(240.0)
+                    .show(ui, |ui| {
+                        ui.horizontal_wrapped(|ui| {
+                            for name in &sprite_names {
+                                let uv = match get_atlas_uv(&sprite_registry, name) {
+                                    Some(uv) => uv,
+                                    None => continue,
+                                };
+                                let button =
+                                    egui::ImageButton::new(atlas_texture, [SPRITE_SIZE; 2])
+                                        .uv(uv)
+                                        .selected(name.as_str() == sprite_name.as_str());
+                                if ui.add(button).on_hover_text(name.as_str()).clicked() {
+                                    *sprite_name = name.to_string();
+                                    is_changed = true;
+                                }
+                            }
+                        });
+                    });
+            });
+
+            ui.separator();
+            if ui.button("Save").clicked() {
+                is_saved = true;
+            }
+        });
+
+    rule_editor.is_open = is_open;
+    rule_editor.sprite_type = sprite_type;
+    rule_editor.rule_index = rule_index;
+    rule_editor.only_terrain_sprites = only_terrain_sprites;
+    if is_changed && !is_imported {
+        rules.rules.insert(rule_editor.sprite_type, possible_rules);
+        rules.rule_set = RULE_FILE_RULE_SET.to_string();
+        update_tilemap_event_writer.send(UpdateTilemapEvent {});
+    }
+    if is_saved {
+        for sprite_type in rules.replaced_rules.keys() {
+            println!(
+                "The rules of {:?} are imported, they are saved as they were before the import",
+                sprite_type
+            );
+        }
+        let path = get_asset_root().join(RULE_FILE_PATH);
+        match write_rule_file(&path, &rules) {
+            Ok(()) => println!("Saved the rules to {:?}", path),
+            Err(error) => println!("Failed to save the rules: {}", error),
+        }
+    }
+}
+
+// === Helper Functions ===
+// The slots of a rule from north to south, west to east, as they are laid out on the map.
+fn get_slot_rows(rule: &mut Rule) -> [[&mut Slot; 3]; 3] {
+    let Rule {
+        nw_slot,
+        n_slot,
+        ne_slot,
+        w_slot,
+        c_slot,
+        e_slot,
+        sw_slot,
+        s_slot,
+        se_slot,
+    } = rule;
+    [
+        [nw_slot, n_slot, ne_slot],
+        [w_slot, c_slot, e_slot],
+        [sw_slot, s_slot, se_slot],
+    ]
+}
+
+pub fn get_slot_label(slot: &Slot) -> String {
+    match slot {
+        Slot::Empty => "-".to_string(),
+        Slot::Any => "*".to_string(),
+        Slot::Filled { sprite_type } => format!("{:?}", sprite_type)[..1].to_string(),
+    }
+}
+
+pub fn get_next_slot(slot: Slot) -> Slot {
+    match slot {
+        Slot::Empty => Slot::Filled {
+            sprite_type: TERRAIN_TYPES[0],
+        },
+        Slot::Filled { sprite_type } => {
+            match TERRAIN_TYPES
+                .iter()
+                .position(|terrain| *terrain == sprite_type)
+                .and_then(|index| TERRAIN_TYPES.get(index + 1))
+            {
+                Some(next_sprite_type) => Slot::Filled {
+                    sprite_type: *next_sprite_type,
+                },
+                None => Slot::Any,
+            }
+        }
+        Slot::Any => Slot::Empty,
+    }
+}
+
+// Matches any tile of `sprite_type`, whatever its neighbors are.
+fn get_catch_all_rule(sprite_type: SpriteType) -> Rule {
+    Rule {
+        nw_slot: Slot::Any,
+        n_slot: Slot::Any,
+        ne_slot: Slot::Any,
+        w_slot: Slot::Any,
+        c_slot: Slot::Filled { sprite_type },
+        e_slot: Slot::Any,
+        sw_slot: Slot::Any,
+        s_slot: Slot::Any,
+        se_slot: Slot::Any,
+    }
+}
+
+pub fn read_rule_file(path: &Path) -> Result<Rules, RuleFileError> {
+    let contents = std::fs::read_to_string(path).map_err(|error| RuleFileError::Io {
+        path: path.to_path_buf(),
+        error,
+    })?;
+    let rule_file: RuleFile =
+        ron::de::from_str(&contents).map_err(|error| RuleFileError::Format {
+            path: path.to_path_buf(),
+            message: error.to_string(),
+        })?;
+    if rule_file.version != RULE_FILE_VERSION {
+        return Err(RuleFileError::UnknownVersion {
+            path: path.to_path_buf(),
+            version: rule_file.version,
+        });
+    }
+    Ok(Rules {
+        rules: rule_file
+            .terrains
+            .into_iter()
+            .map(|terrain_rules| (terrain_rules.terrain, terrain_rules.rules))
+            .collect(),
+        rule_set: rule_file.rule_set,
+        replaced_rules: HashMap::new(),
+    })
+}
+
+// Terrains are written in the order of `TERRAIN_TYPES`, so the file diffs cleanly. Terrains replaced
+// by an import are written with the rules they had before it.
+pub fn write_rule_file(path: &Path, rules: &Rules) -> Result<(), RuleFileError> {
+    let rule_set = if rules.replaced_rules.is_empty() {
+        rules.rule_set.clone()
+    } else {
+        RULE_FILE_RULE_SET.to_string()
+    };
+    let rule_file = RuleFile {
+        version: RULE_FILE_VERSION,
+        rule_set,
+        terrains: TERRAIN_TYPES
+            .iter()
+            .filter_map(|terrain| {
+                let possible_rules = rules
+                    .replaced_rules
+                    .get(terrain)
+                    .or_else(|| rules.rules.get(terrain))?;
+                if possible_rules.is_empty() {
+                    return None;
+                }
+                Some(TerrainRules {
+                    terrain: *terrain,
+                    rules: possible_rules.clone(),
+                })
+            })
+            .collect(),
+    };
+    let contents = ron::ser::to_string_pretty(&rule_file, ron::ser::PrettyConfig::default())
+        .map_err(|error| RuleFileError::Format {
+            path: path.to_path_buf(),
+            message: error.to_string(),
+        })?;
+    std::fs::write(path, contents).map_err(|error| RuleFileError::Io {
+        path: path.to_path_buf(),
+        error,
+    })
+}
@@ -0,0 +1,737 @@
+use crate::history::EditHistory;
+use crate::objects::{ConnectableTile, ConnectableType, ObjectLayer, UpdateObjectLayerEvent};
+use crate::structures::{
+    spawn_structure, StampType, Stamps, Structure, StructureFootprint, StructureLayer,
+};
+use crate::{
+    is_typing, paint_terrain, DirtTile, GrassTile, GroundLayer, Rules, SpriteRegistry, SpriteType,
+    UpdateTilemapEvent, WaterTile,
+};
+use bevy::prelude::*;
+use bevy_ecs_tilemap::prelude::*;
+use bevy_inspector_egui::bevy_egui::EguiContext;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+pub const SAVE_DIRECTORY: &str = "saves";
+pub const SAVE_FILE_STEM: &str = "map";
+pub const MAP_FILE_VERSION: u32 = 1;
+
+// Binary files start with these bytes, followed by the map file.
+const MAP_FILE_MAGIC: &[u8; 4] = b"ATMF";
+
+// Saved terrain uses one character per tile.
+const EMPTY_CHARACTER: char = '.';
+const GRASS_CHARACTER: char = 'g';
+const DIRT_CHARACTER: char = 'd';
+const WATER_CHARACTER: char = 'w';
+
+// === Enums ===
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MapFormat {
+    // Readable and diffable, for maps being worked on.
+    Ron,
+    // Compact, for maps being shipped.
+    Binary,
+}
+
+impl MapFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            MapFormat::Ron => "ron",
+            MapFormat::Binary => "map",
+        }
+    }
+
+    // Maps saved as anything but RON are read as binary, which checks for its magic bytes.
+    pub fn from_path(path: &Path) -> MapFormat {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("ron") => MapFormat::Ron,
+            _ => MapFormat::Binary,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum MapFileError {
+    Io {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+    Format {
+        path: PathBuf,
+        message: String,
+    },
+    UnknownVersion {
+        path: PathBuf,
+        version: u32,
+    },
+    Invalid {
+        path: PathBuf,
+        message: String,
+    },
+}
+
+impl fmt::Display for MapFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MapFileError::Io { path, error } => write!(f, "could not access {:?}: {}", path, error),
+            MapFileError::Format { path, message } => {
+                write!(f, "could not read {:?}: {}", path, message)
+            }
+            MapFileError::UnknownVersion { path, version } => write!(
+                f,
+                "{:?} is version {}, only versions up to {} can be loaded",
+                path, version, MAP_FILE_VERSION
+            ),
+            MapFileError::Invalid { path, message } => write!(f, "{:?} {}", path, message),
+        }
+    }
+}
+
+impl std::error::Error for MapFileError {}
+
+// Every version that can be read, each as the struct it was saved as. Once the format changes, the
+// previous `MapFile` is kept here under its version and migrated in `migrate`.
+enum VersionedMapFile {
+    V1(MapFile),
+}
+
+impl VersionedMapFile {
+    fn migrate(self) -> MapFile {
+        match self {
+            VersionedMapFile::V1(map_file) => map_file,
+        }
+    }
+}
+
+// A map file without its magic bytes, so every version is read the same way from either format.
+enum MapFileContents<'a> {
+    Ron(&'a str),
+    Binary(&'a [u8]),
+}
+
+impl MapFileContents<'_> {
+    fn deserialize<T: DeserializeOwned>(&self) -> Result<T, String> {
+        match self {
+            MapFileContents::Ron(contents) => {
+                ron::de::from_str(contents).map_err(|error| error.to_string())
+            }
+            MapFileContents::Binary(contents) => {
+                bincode::deserialize(contents).map_err(|error| error.to_string())
+            }
+        }
+    }
+}
+
+// === Structs ===
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct MapFile {
+    pub version: u32,
+    pub width: u32,
+    pub height: u32,
+    // The rules the map was painted with, see `Rules::rule_set`.
+    pub rule_set: String,
+    // Rows from north to south, one character per tile.
+    pub terrain: Vec<String>,
+    pub objects: Vec<SavedObject>,
+    pub structures: Vec<SavedStructure>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct SavedObject {
+    pub connectable_type: ConnectableType,
+    pub x: u32,
+    pub y: u32,
+    pub links: u8,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct SavedStructure {
+    pub stamp_type: StampType,
+    // The south-west corner of the structure.
+    pub x: u32,
+    pub y: u32,
+}
+
+// Every version starts with its version number, it is read first to pick the version to read the
+// rest of the file as.
+#[derive(Debug, Deserialize)]
+struct MapFileVersion {
+    version: u32,
+}
+
+// === Systems ===
+// F5 saves the map as RON, Shift+F5 as binary.
+pub fn save_map(
+    keyboard: Res<Input<KeyCode>>,
+    mut egui_context: ResMut<EguiContext>,
+    rules: Res<Rules>,
+    ground_layer_query: Query<(&TilemapSize, &TileStorage), With<GroundLayer>>,
+    terrain_query: Query<(Option<&GrassTile>, Option<&DirtTile>, Option<&WaterTile>)>,
+    object_layers_query: Query<(&ObjectLayer, &TileStorage)>,
+    connectable_tiles_query: Query<&ConnectableTile>,
+    structures_query: Query<&Structure>,
+) {
+    if !keyboard.just_pressed(KeyCode::F5) || is_typing(&mut egui_context) {
+        return;
+    }
+    let map_format = get_map_format(&keyboard);
+    let (map_size, ground_storage) = match ground_layer_query.get_single() {
+        Ok(ground_layer) => ground_layer,
+        Err(_) => return,
+    };
+
+    let mut terrain = Vec::new();
+    for y in (0..map_size.y).rev() {
+        let mut row = String::new();
+        for x in 0..map_size.x {
+            let sprite_type = match ground_storage
+                .get(&TilePos { x, y })
+                .and_then(|tile_entity| terrain_query.get(tile_entity).ok())
+            {
+                Some((Some(_), _, _)) => Some(SpriteType::Grass),
+                Some((_, Some(_), _)) => Some(SpriteType::Dirt),
+                Some((_, _, Some(_))) => Some(SpriteType::Water),
+                _ => None,
+            };
+            row.push(get_terrain_character(sprite_type));
+        }
+        terrain.push(row);
+    }
+
+    let mut objects = Vec::new();
+    for (object_layer, tile_storage) in object_layers_query.iter() {
+        for y in 0..map_size.y {
+            for x in 0..map_size.x {
+                let connectable_tile = tile_storage
+                    .get(&TilePos { x, y })
+                    .and_then(|tile_entity| connectable_tiles_query.get(tile_entity).ok());
+                if let Some(connectable_tile) = connectable_tile {
+                    objects.push(SavedObject {
+                        connectable_type: object_layer.connectable_type,
+                        x,
+                        y,
+                        links: connectable_tile.links,
+                    });
+                }
+            }
+        }
+    }
+
+    let structures = structures_query
+        .iter()
+        .map(|structure| SavedStructure {
+            stamp_type: structure.stamp_type,
+            x: structure.origin.x,
+            y: structure.origin.y,
+        })
+        .collect();
+
+    let map_file = MapFile {
+        version: MAP_FILE_VERSION,
+        width: map_size.x,
+        height: map_size.y,
+        rule_set: rules.rule_set.clone(),
+        terrain,
+        objects,
+        structures,
+    };
+    let path = get_save_path(map_format);
+    match write_map_file(&path, &map_file, map_format) {
+        Ok(_) => println!("Saved the map to {:?}", path),
+        Err(error) => println!("Failed to save the map: {}", error),
+    }
+}
+
+// F9 loads the map saved as RON, Shift+F9 the one saved as binary. Everything on the map is
+// replaced and autotiled again.
+pub fn load_map(
+    mut commands: Commands,
+    mut update_tilemap_event_writer: EventWriter<UpdateTilemapEvent>,
+    mut update_object_layer_event_writer: EventWriter<UpdateObjectLayerEvent>,
+    mut edit_history: ResMut<EditHistory>,
+    keyboard: Res<Input<KeyCode>>,
+    mut egui_context: ResMut<EguiContext>,
+    rules: Res<Rules>,
+    sprite_registry: Res<SpriteRegistry>,
+    stamps: Res<Stamps>,
+    ground_layer_query: Query<(&TilemapSize, &TileStorage), With<GroundLayer>>,
+    object_layers_query: Query<(&ObjectLayer, &TileStorage)>,
+    structure_layer_query: Query<&TileStorage, With<StructureLayer>>,
+    structures_query: Query<Entity, With<Structure>>,
+) {
+    if !keyboard.just_pressed(KeyCode::F9) || is_typing(&mut egui_context) {
+        return;
+    }
+    let map_format = get_map_format(&keyboard);
+    let (map_size, ground_storage) = match ground_layer_query.get_single() {
+        Ok(ground_layer) => ground_layer,
+        Err(_) => return,
+    };
+    let structure_storage = match structure_layer_query.get_single() {
+        Ok(structure_storage) => structure_storage,
+        Err(_) => return,
+    };
+
+    let path = get_save_path(map_format);
+    let (map_file, terrain) = match read_map_file(&path, map_format)
+        .and_then(|map_file| validate_map_file(&path, map_file, map_size, &stamps))
+    {
+        Ok(map_file) => map_file,
+        Err(error) => {
+            println!("Failed to load the map: {}", error);
+            return;
+        }
+    };
+    if map_file.rule_set != rules.rule_set {
+        println!(
+            "{:?} was painted with the {:?} rules, it is autotiled with the {:?} rules",
+            path, map_file.rule_set, rules.rule_set
+        );
+    }
+
+    // Clear the map. Erased terrain keeps its texture otherwise, as only painted tiles are
+    // autotiled.
+    let blank_index = sprite_registry.get_blank_index();
+    for y in 0..map_size.y {
+        for x in 0..map_size.x {
+            let tile_position = TilePos { x, y };
+            if let Some(tile_entity) = ground_storage.get(&tile_position) {
+                commands
+                    .entity(tile_entity)
+                    .remove::<GrassTile>()
+                    .remove::<DirtTile>()
+                    .remove::<WaterTile>()
+                    .remove::<StructureFootprint>()
+                    .insert(TileTexture(blank_index));
+            }
+            for (_, tile_storage) in object_layers_query.iter() {
+                if let Some(tile_entity) = tile_storage.get(&tile_position) {
+                    commands.entity(tile_entity).remove::<ConnectableTile>();
+                }
+            }
+            if let Some(tile_entity) = structure_storage.get(&tile_position) {
+                commands.entity(tile_entity).insert(TileVisible(false));
+            }
+        }
+    }
+    for structure in structures_query.iter() {
+        commands.entity(structure).despawn();
+    }
+    // Strokes painted on the previous map would undo onto this one.
+    edit_history.clear();
+
+    paint_terrain(
+        &mut commands,
+        map_size,
+        ground_storage,
+        map_file.width,
+        map_file.height,
+        &terrain,
+    );
+    for saved_object in &map_file.objects {
+        let tile_storage = object_layers_query
+            .iter()
+            .find(|(object_layer, _)| {
+                object_layer.connectable_type == saved_object.connectable_type
+            })
+            .map(|(_, tile_storage)| tile_storage);
+        let tile_entity = tile_storage.and_then(|tile_storage| {
+            tile_storage.get(&TilePos {
+                x: saved_object.x,
+                y: saved_object.y,
+            })
+        });
+        if let Some(tile_entity) = tile_entity {
+            commands.entity(tile_entity).insert(ConnectableTile {
+                links: saved_object.links,
+            });
+        }
+    }
+    for saved_structure in &map_file.structures {
+        spawn_structure(
+            &mut commands,
+            saved_structure.stamp_type,
+            &stamps.stamps[&saved_structure.stamp_type],
+            TilePos {
+                x: saved_structure.x,
+                y: saved_structure.y,
+            },
+            ground_storage,
+            structure_storage,
+        );
+    }
+
+    update_tilemap_event_writer.send(UpdateTilemapEvent {});
+    update_object_layer_event_writer.send(UpdateObjectLayerEvent {});
+    println!("Loaded the map from {:?}", path);
+}
+
+// === Helper Functions ===
+pub fn get_save_path(map_format: MapFormat) -> PathBuf {
+    Path::new(SAVE_DIRECTORY).join(format!("{}.{}", SAVE_FILE_STEM, map_format.extension()))
+}
+
+fn get_map_format(keyboard: &Input<KeyCode>) -> MapFormat {
+    if keyboard.pressed(KeyCode::LShift) || keyboard.pressed(KeyCode::RShift) {
+        MapFormat::Binary
+    } else {
+        MapFormat::Ron
+    }
+}
+
+pub fn get_terrain_character(sprite_type: Option<SpriteType>) -> char {
+    match sprite_type {
+        Some(SpriteType::Grass) => GRASS_CHARACTER,
+        Some(SpriteType::Dirt) => DIRT_CHARACTER,
+        Some(SpriteType::Water) => WATER_CHARACTER,
+        _ => EMPTY_CHARACTER,
+    }
+}
+
+pub fn get_terrain_by_character(character: char) -> Option<Option<SpriteType>> {
+    match character {
+        EMPTY_CHARACTER => Some(None),
+        GRASS_CHARACTER => Some(Some(SpriteType::Grass)),
+        DIRT_CHARACTER => Some(Some(SpriteType::Dirt)),
+        WATER_CHARACTER => Some(Some(SpriteType::Water)),
+        _ => None,
+    }
+}
+
+pub fn write_map_file(
+    path: &Path,
+    map_file: &MapFile,
+    map_format: MapFormat,
+) -> Result<(), MapFileError> {
+    let format_error = |message: String| MapFileError::Format {
+        path: path.to_path_buf(),
+        message,
+    };
+    let bytes = match map_format {
+        MapFormat::Ron => ron::ser::to_string_pretty(map_file, ron::ser::PrettyConfig::default())
+            .map_err(|error| format_error(error.to_string()))?
+            .into_bytes(),
+        MapFormat::Binary => {
+            let mut bytes = MAP_FILE_MAGIC.to_vec();
+            bincode::serialize_into(&mut bytes, map_file)
+                .map_err(|error| format_error(error.to_string()))?;
+            bytes
+        }
+    };
+    if let Some(directory) = path.parent() {
+        std::fs::create_dir_all(directory).map_err(|error| MapFileError::Io {
+            path: directory.to_path_buf(),
+            error,
+        })?;
+    }
+    std::fs::write(path, bytes).map_err(|error| MapFileError::Io {
+        path: path.to_path_buf(),
+        error,
+    })
+}
+
+// Reads a map file of any known version. Its version is read first, then the rest of the file as
+// the struct that version was saved as, which is migrated to the current `MapFile`.
+pub fn read_map_file(path: &Path, map_format: MapFormat) -> Result<MapFile, MapFileError> {
+    let bytes = std::fs::read(path).map_err(|error| MapFileError::Io {
+        path: path.to_path_buf(),
+        error,
+    })?;
+    let format_error = |message: String| MapFileError::Format {
+        path: path.to_path_buf(),
+        message,
+    };
+    let contents = match map_format {
+        MapFormat::Ron => MapFileContents::Ron(
+            std::str::from_utf8(&bytes).map_err(|error| format_error(error.to_string()))?,
+        ),
+        MapFormat::Binary => MapFileContents::Binary(
+            bytes
+                .strip_prefix(MAP_FILE_MAGIC)
+                .ok_or_else(|| format_error("is not a map file".to_string()))?,
+        ),
+    };
+
+    // The version is the first field of every version, and stays part of the contents.
+    let map_file_version: MapFileVersion = contents.deserialize().map_err(format_error)?;
+    let versioned_map_file = match map_file_version.version {
+        1 => VersionedMapFile::V1(contents.deserialize().map_err(format_error)?),
+        version => {
+            return Err(MapFileError::UnknownVersion {
+                path: path.to_path_buf(),
+                version,
+            })
+        }
+    };
+    Ok(versioned_map_file.migrate())
+}
+
+// Checks the terrain is as large as the map says, and returns it listed row by row from the
+// top-left corner.
+pub fn get_map_file_terrain(
+    path: &Path,
+    map_file: &MapFile,
+) -> Result<Vec<Option<SpriteType>>, MapFileError> {
+    let invalid = |message: String| MapFileError::Invalid {
+        path: path.to_path_buf(),
+        message,
+    };
+    if map_file.terrain.len() != map_file.height as usize
+        || map_file
+            .terrain
+            .iter()
+            .any(|row| row.chars().count() != map_file.width as usize)
+    {
+        return Err(invalid(format!(
+            "has terrain that is not {}x{} tiles",
+            map_file.width, map_file.height
+        )));
+    }
+    let mut terrain = Vec::new();
+    for character in map_file.terrain.iter().flat_map(|row| row.chars()) {
+        match get_terrain_by_character(character) {
+            Some(sprite_type) => terrain.push(sprite_type),
+            None => return Err(invalid(format!("has unknown terrain {:?}", character))),
+        }
+    }
+    Ok(terrain)
+}
+
+// Checks the map fits the tilemap, and that its objects and structures could have been placed
+// there, then returns it along with its terrain.
+fn validate_map_file(
+    path: &Path,
+    map_file: MapFile,
+    map_size: &TilemapSize,
+    stamps: &Stamps,
+) -> Result<(MapFile, Vec<Option<SpriteType>>), MapFileError> {
+    let invalid = |message: String| MapFileError::Invalid {
+        path: path.to_path_buf(),
+        message,
+    };
+    if map_file.width != map_size.x || map_file.height != map_size.y {
+        return Err(invalid(format!(
+            "is {}x{} tiles, the map is {}x{} tiles",
+            map_file.width, map_file.height, map_size.x, map_size.y
+        )));
+    }
+    let terrain = get_map_file_terrain(path, &map_file)?;
+    let is_inside = |x: u32, y: u32| x < map_file.width && y < map_file.height;
+
+    for saved_object in &map_file.objects {
+        if !is_inside(saved_object.x, saved_object.y) {
+            return Err(invalid(format!(
+                "has a {:?} at ({}, {}), outside of the map",
+                saved_object.connectable_type, saved_object.x, saved_object.y
+            )));
+        }
+    }
+
+    // Structures are checked the same way as when they are placed, see `place_structure`.
+    let mut covered_cells = HashSet::new();
+    for saved_structure in &map_file.structures {
+        let stamp_type = saved_structure.stamp_type;
+        let stamp = &stamps.stamps[&stamp_type];
+        if !is_inside(saved_structure.x, saved_structure.y) {
+            return Err(invalid(format!(
+                "has a {:?} at ({}, {}), outside of the map",
+                stamp_type, saved_structure.x, saved_structure.y
+            )));
+        }
+        let origin = TilePos {
+            x: saved_structure.x,
+            y: saved_structure.y,
+        };
+        for (cell_position, _) in stamp.get_cells(&origin) {
+            if !is_inside(cell_position.x, cell_position.y) {
+                return Err(invalid(format!(
+                    "has a {:?} at ({}, {}) reaching outside of the map",
+                    stamp_type, origin.x, origin.y
+                )));
+            }
+            if !covered_cells.insert((cell_position.x, cell_position.y)) {
+                return Err(invalid(format!(
+                    "has a {:?} at ({}, {}) overlapping another structure at ({}, {})",
+                    stamp_type, origin.x, origin.y, cell_position.x, cell_position.y
+                )));
+            }
+            // Terrain is listed from the top-left corner, tile positions count rows from the
+            // bottom.
+            let index = (map_file.height - 1 - cell_position.y) * map_file.width + cell_position.x;
+            let cell_terrain = terrain[index as usize].unwrap_or(SpriteType::Blank);
+            if !stamp.allowed_terrain.contains(&cell_terrain) {
+                return Err(invalid(format!(
+                    "has a {:?} at ({}, {}) standing on {:?} at ({}, {})",
+                    stamp_type, origin.x, origin.y, cell_terrain, cell_position.x, cell_position.y
+                )));
+            }
+        }
+    }
+    Ok((map_file, terrain))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A folder of its own for each test, as tests run in parallel.
+    fn get_test_save_directory(test_name: &str) -> PathBuf {
+        let save_directory = std::env::temp_dir().join(format!(
+            "autotile_save_{}_{}",
+            test_name,
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&save_directory).unwrap();
+        save_directory
+    }
+
+    // A 6x10 map of grass with a pond in its north-east corner, and a house on the grass.
+    fn get_map_file() -> MapFile {
+        let mut terrain = vec!["gggggg".to_string(); 10];
+        terrain[0] = "ggggww".to_string();
+        MapFile {
+            version: MAP_FILE_VERSION,
+            width: 6,
+            height: 10,
+            rule_set: "builtin".to_string(),
+            terrain,
+            objects: vec![SavedObject {
+                connectable_type: ConnectableType::Path,
+                x: 5,
+                y: 0,
+                links: 0b0101,
+            }],
+            structures: vec![SavedStructure {
+                stamp_type: StampType::WoodenHouse,
+                x: 0,
+                y: 0,
+            }],
+        }
+    }
+
+    fn get_invalid_message(map_file: MapFile) -> String {
+        let map_size = TilemapSize { x: 6, y: 10 };
+        match validate_map_file(
+            Path::new("map.ron"),
+            map_file,
+            &map_size,
+            &Stamps::default(),
+        ) {
+            Err(MapFileError::Invalid { message, .. }) => message,
+            result => panic!("expected the map to be invalid, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn map_files_read_back_the_same_in_both_formats() {
+        let save_directory = get_test_save_directory("round_trip");
+        for map_format in [MapFormat::Ron, MapFormat::Binary] {
+            let path = save_directory.join(format!("map.{}", map_format.extension()));
+            write_map_file(&path, &get_map_file(), map_format).unwrap();
+            assert_eq!(MapFormat::from_path(&path), map_format);
+            assert_eq!(read_map_file(&path, map_format).unwrap(), get_map_file());
+        }
+        std::fs::remove_dir_all(save_directory).unwrap();
+    }
+
+    #[test]
+    fn newer_versions_are_rejected() {
+        let save_directory = get_test_save_directory("version");
+        for map_format in [MapFormat::Ron, MapFormat::Binary] {
+            let path = save_directory.join(format!("map.{}", map_format.extension()));
+            let map_file = MapFile {
+                version: MAP_FILE_VERSION + 1,
+                ..get_map_file()
+            };
+            write_map_file(&path, &map_file, map_format).unwrap();
+            match read_map_file(&path, map_format) {
+                Err(MapFileError::UnknownVersion { version, .. }) => {
+                    assert_eq!(version, MAP_FILE_VERSION + 1)
+                }
+                result => panic!("expected an unknown version, got {:?}", result),
+            }
+        }
+        std::fs::remove_dir_all(save_directory).unwrap();
+    }
+
+    #[test]
+    fn binary_files_without_the_magic_bytes_are_rejected() {
+        let save_directory = get_test_save_directory("magic");
+        let path = save_directory.join("map.map");
+        std::fs::write(&path, b"not a map").unwrap();
+        assert!(matches!(
+            read_map_file(&path, MapFormat::Binary),
+            Err(MapFileError::Format { .. })
+        ));
+        std::fs::remove_dir_all(save_directory).unwrap();
+    }
+
+    #[test]
+    fn valid_maps_return_their_terrain_from_the_top_left_corner() {
+        let map_size = TilemapSize { x: 6, y: 10 };
+        let (_, terrain) = validate_map_file(
+            Path::new("map.ron"),
+            get_map_file(),
+            &map_size,
+            &Stamps::default(),
+        )
+        .unwrap();
+        assert_eq!(terrain.len(), 60);
+        assert_eq!(terrain[0], Some(SpriteType::Grass));
+        assert_eq!(terrain[5], Some(SpriteType::Water));
+        assert_eq!(terrain[59], Some(SpriteType::Grass));
+    }
+
+    #[test]
+    fn maps_of_another_size_are_invalid() {
+        let map_file = MapFile {
+            width: 5,
+            terrain: vec!["ggggg".to_string(); 10],
+            ..get_map_file()
+        };
+        assert_eq!(
+            get_invalid_message(map_file),
+            "is 5x10 tiles, the map is 6x10 tiles"
+        );
+    }
+
+    #[test]
+    fn objects_and_structures_outside_of_the_map_are_invalid() {
+        let mut map_file = get_map_file();
+        map_file.objects[0].y = 10;
+        assert!(get_invalid_message(map_file).ends_with("outside of the map"));
+
+        // The house is 3 tiles wide, so it reaches 2 tiles past its origin.
+        let mut map_file = get_map_file();
+        map_file.structures[0].x = 4;
+        assert!(get_invalid_message(map_file).ends_with("reaching outside of the map"));
+    }
+
+    #[test]
+    fn overlapping_structures_are_invalid() {
+        let mut map_file = get_map_file();
+        map_file.structures.push(SavedStructure {
+            stamp_type: StampType::WoodenHouse,
+            x: 2,
+            y: 1,
+        });
+        assert!(get_invalid_message(map_file).contains("overlapping another structure at (2, 7)"));
+    }
+
+    #[test]
+    fn structures_on_terrain_they_do_not_allow_are_invalid() {
+        // The house is 8 tiles tall, so a house at (3, 2) reaches the pond on the top row.
+        let mut map_file = get_map_file();
+        map_file.structures[0].x = 3;
+        map_file.structures[0].y = 2;
+        assert_eq!(
+            get_invalid_message(map_file),
+            "has a WoodenHouse at (3, 2) standing on Water at (4, 9)"
+        );
+    }
+}
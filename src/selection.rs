@@ -0,0 +1,747 @@
+use crate::history::{set_tile_terrain, EditHistory};
+use crate::save::{get_terrain_by_character, get_terrain_character, MapFileError};
+use crate::tools::{get_line, get_rectangle, Tool, ToolState};
+use crate::{
+    get_tile_terrain, is_typing, world_position_to_tile_position, DirtTile, GrassTile, GroundLayer,
+    Mouse, SpriteRegistry, SpriteType, UpdateTilemapEvent, WaterTile,
+};
+use bevy::prelude::*;
+use bevy_ecs_tilemap::prelude::*;
+use bevy_inspector_egui::bevy_egui::EguiContext;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+pub const STAMP_DIRECTORY: &str = "stamps";
+pub const STAMP_FILE_VERSION: u32 = 1;
+
+// Stamp files mark cells outside of the selection with this character, pasting leaves them alone.
+const UNSELECTED_CHARACTER: char = ' ';
+
+// === Structs ===
+// A piece of terrain cut out of the map. Rows count from the bottom like `TilePos`, cells outside
+// of the selection are `None`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TerrainStamp {
+    pub name: String,
+    pub width: u32,
+    pub height: u32,
+    cells: Vec<Option<Option<SpriteType>>>,
+}
+
+impl TerrainStamp {
+    pub fn get(&self, x: u32, y: u32) -> Option<Option<SpriteType>> {
+        self.cells[(y * self.width + x) as usize]
+    }
+
+    // Builds a stamp of the same size from where every one of its cells comes from in `self`.
+    fn remap(
+        &self,
+        width: u32,
+        height: u32,
+        get_source: impl Fn(u32, u32) -> (u32, u32),
+    ) -> TerrainStamp {
+        let mut cells = Vec::with_capacity(self.cells.len());
+        for y in 0..height {
+            for x in 0..width {
+                let (source_x, source_y) = get_source(x, y);
+                cells.push(self.get(source_x, source_y));
+            }
+        }
+        TerrainStamp {
+            name: self.name.clone(),
+            width,
+            height,
+            cells,
+        }
+    }
+
+    pub fn rotate_clockwise(&self) -> TerrainStamp {
+        self.remap(self.height, self.width, |x, y| (self.width - 1 - y, x))
+    }
+
+    pub fn rotate_counter_clockwise(&self) -> TerrainStamp {
+        self.remap(self.height, self.width, |x, y| (y, self.height - 1 - x))
+    }
+
+    pub fn flip_horizontally(&self) -> TerrainStamp {
+        self.remap(self.width, self.height, |x, y| (self.width - 1 - x, y))
+    }
+
+    pub fn flip_vertically(&self) -> TerrainStamp {
+        self.remap(self.width, self.height, |x, y| (x, self.height - 1 - y))
+    }
+}
+
+// How stamps are written to `STAMP_DIRECTORY`, the name is the file stem.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StampFile {
+    pub version: u32,
+    pub width: u32,
+    pub height: u32,
+    // One row per string from north to south, one character per tile.
+    pub terrain: Vec<String>,
+}
+
+// === Resources ===
+#[derive(Default)]
+pub struct Selection {
+    pub cells: HashSet<TilePos>,
+    // Where the selection being dragged started, `None` while the mouse button is up.
+    drag_start: Option<TilePos>,
+    // The cells the cursor went through while drawing a lasso.
+    lasso_points: Vec<IVec2>,
+}
+
+#[derive(Default)]
+pub struct Clipboard {
+    pub stamp: Option<TerrainStamp>,
+    // Whether the stamp follows the cursor, waiting to be pasted.
+    pub is_pasting: bool,
+    // The stamp file loaded last, F7 loads the one after it.
+    stamp_file_index: usize,
+}
+
+// === Startup Systems ===
+pub fn setup_selection(mut commands: Commands) {
+    commands.insert_resource(Selection::default());
+    commands.insert_resource(Clipboard::default());
+}
+
+// === Systems ===
+// Drags out a rectangle or draws a lasso around the cells to select.
+pub fn select_region(
+    mouse: Res<Mouse>,
+    tool_state: Res<ToolState>,
+    clipboard: Res<Clipboard>,
+    mut selection: ResMut<Selection>,
+    ground_layer_query: Query<
+        (&TilemapSize, &TilemapGridSize, &TilemapType, &Transform),
+        With<GroundLayer>,
+    >,
+) {
+    if !tool_state.tool.is_selection() || clipboard.is_pasting {
+        return;
+    }
+    let (map_size, grid_size, map_type, map_transform) = match ground_layer_query.get_single() {
+        Ok(ground_layer) => ground_layer,
+        Err(_) => return,
+    };
+
+    if mouse.holding_lmb {
+        if let Some(tile_position) = world_position_to_tile_position(
+            mouse.world_position,
+            map_size,
+            grid_size,
+            map_type,
+            map_transform,
+        ) {
+            if selection.drag_start.is_none() {
+                selection.drag_start = Some(tile_position);
+                selection.lasso_points.clear();
+            }
+            let point = IVec2::new(tile_position.x as i32, tile_position.y as i32);
+            if selection.lasso_points.last() != Some(&point) {
+                selection.lasso_points.push(point);
+            }
+        }
+        return;
+    }
+
+    let drag_start = match selection.drag_start.take() {
+        Some(drag_start) => drag_start,
+        None => return,
+    };
+    let lasso_points = std::mem::take(&mut selection.lasso_points);
+    let cells = match tool_state.tool {
+        Tool::LassoSelect => get_lasso(&lasso_points),
+        _ => {
+            let start = IVec2::new(drag_start.x as i32, drag_start.y as i32);
+            let end = lasso_points.last().copied().unwrap_or(start);
+            get_rectangle(start, end, true)
+        }
+    };
+    selection.cells = cells
+        .into_iter()
+        .filter(|cell| {
+            cell.x >= 0 && cell.y >= 0 && cell.x < map_size.x as i32 && cell.y < map_size.y as i32
+        })
+        .map(|cell| TilePos {
+            x: cell.x as u32,
+            y: cell.y as u32,
+        })
+        .collect();
+    println!("Selection Updated: {} tiles", selection.cells.len());
+}
+
+// Ctrl+C copies the selection, Ctrl+X cuts it to move it and Ctrl+V pastes it. While pasting, Q
+// and E rotate the stamp, X and Y flip it, and Escape stops. F6 saves the stamp to
+// `STAMP_DIRECTORY` under the name typed in the editor panel, F7 loads the next one saved there.
+pub fn edit_selection(
+    mut commands: Commands,
+    mut update_tilemap_event_writer: EventWriter<UpdateTilemapEvent>,
+    mut edit_history: ResMut<EditHistory>,
+    mut selection: ResMut<Selection>,
+    mut clipboard: ResMut<Clipboard>,
+    keyboard: Res<Input<KeyCode>>,
+    sprite_registry: Res<SpriteRegistry>,
+    mut egui_context: ResMut<EguiContext>,
+    ground_layer_query: Query<&TileStorage, With<GroundLayer>>,
+    terrain_query: Query<(Option<&GrassTile>, Option<&DirtTile>, Option<&WaterTile>)>,
+) {
+    if is_typing(&mut egui_context) {
+        return;
+    }
+    let tile_storage = match ground_layer_query.get_single() {
+        Ok(tile_storage) => tile_storage,
+        Err(_) => return,
+    };
+    let is_control_pressed =
+        keyboard.pressed(KeyCode::LControl) || keyboard.pressed(KeyCode::RControl);
+
+    if is_control_pressed
+        && (keyboard.just_pressed(KeyCode::C) || keyboard.just_pressed(KeyCode::X))
+    {
+        let stamp = match get_selection_stamp(&selection.cells, |tile_position| {
+            tile_storage
+                .get(tile_position)
+                .and_then(|tile_entity| get_tile_terrain(tile_entity, &terrain_query))
+        }) {
+            Some(stamp) => stamp,
+            None => {
+                println!("Nothing is selected");
+                return;
+            }
+        };
+        println!("Copied {}x{} tiles", stamp.width, stamp.height);
+        clipboard.stamp = Some(stamp);
+        // Cutting erases the selection and picks it up, so it can be moved.
+        if keyboard.just_pressed(KeyCode::X) {
+            for tile_position in &selection.cells {
+                if let Some(tile_entity) = tile_storage.get(tile_position) {
+                    let previous_sprite_type = get_tile_terrain(tile_entity, &terrain_query);
+                    edit_history.record(*tile_position, previous_sprite_type, None);
+                    set_tile_terrain(&mut commands, tile_entity, None, &sprite_registry);
+                }
+            }
+            selection.cells.clear();
+            clipboard.is_pasting = true;
+            update_tilemap_event_writer.send(UpdateTilemapEvent {});
+        }
+        return;
+    }
+    if is_control_pressed && keyboard.just_pressed(KeyCode::V) && clipboard.stamp.is_some() {
+        clipboard.is_pasting = true;
+        return;
+    }
+
+    if keyboard.just_pressed(KeyCode::Escape) {
+        if clipboard.is_pasting {
+            clipboard.is_pasting = false;
+        } else {
+            selection.cells.clear();
+        }
+        return;
+    }
+
+    if keyboard.just_pressed(KeyCode::F6) {
+        if let Some(stamp) = &mut clipboard.stamp {
+            let path = match get_stamp_path(&stamp.name) {
+                Some(path) => path,
+                None => {
+                    println!("{:?} cannot be used as a stamp name", stamp.name);
+                    return;
+                }
+            };
+            match write_stamp_file(&path, stamp) {
+                Ok(()) => {
+                    stamp.name = get_stamp_name(&path);
+                    println!("Saved the stamp to {:?}", path);
+                }
+                Err(error) => println!("Failed to save the stamp: {}", error),
+            }
+        }
+        return;
+    }
+    if keyboard.just_pressed(KeyCode::F7) {
+        let paths = get_stamp_paths();
+        if paths.is_empty() {
+            println!("No stamps are saved in {:?}", STAMP_DIRECTORY);
+            return;
+        }
+        let index = clipboard.stamp_file_index % paths.len();
+        clipboard.stamp_file_index = index + 1;
+        match read_stamp_file(&paths[index]) {
+            Ok(stamp) => {
+                println!("Loaded the stamp {:?}", stamp.name);
+                clipboard.stamp = Some(stamp);
+                clipboard.is_pasting = true;
+            }
+            Err(error) => println!("Failed to load the stamp: {}", error),
+        }
+        return;
+    }
+
+    if !clipboard.is_pasting || is_control_pressed {
+        return;
+    }
+    if let Some(stamp) = &mut clipboard.stamp {
+        if keyboard.just_pressed(KeyCode::Q) {
+            *stamp = stamp.rotate_counter_clockwise();
+        } else if keyboard.just_pressed(KeyCode::E) {
+            *stamp = stamp.rotate_clockwise();
+        } else if keyboard.just_pressed(KeyCode::X) {
+            *stamp = stamp.flip_horizontally();
+        } else if keyboard.just_pressed(KeyCode::Y) {
+            *stamp = stamp.flip_vertically();
+        }
+    }
+}
+
+// Pastes the stamp centered on the cursor. The borders are autotiled along with the rest of the
+// map, so pasted terrain blends into its surroundings.
+pub fn paste_selection(
+    mut commands: Commands,
+    mut update_tilemap_event_writer: EventWriter<UpdateTilemapEvent>,
+    mut edit_history: ResMut<EditHistory>,
+    clipboard: Res<Clipboard>,
+    mouse: Res<Mouse>,
+    mouse_input: Res<Input<MouseButton>>,
+    sprite_registry: Res<SpriteRegistry>,
+    ground_layer_query: Query<
+        (
+            &TilemapSize,
+            &TilemapGridSize,
+            &TilemapType,
+            &TileStorage,
+            &Transform,
+        ),
+        With<GroundLayer>,
+    >,
+    terrain_query: Query<(Option<&GrassTile>, Option<&DirtTile>, Option<&WaterTile>)>,
+) {
+    if !clipboard.is_pasting || !mouse_input.just_pressed(MouseButton::Left) || mouse.is_over_ui {
+        return;
+    }
+    let stamp = match &clipboard.stamp {
+        Some(stamp) => stamp,
+        None => return,
+    };
+    let (map_size, grid_size, map_type, tile_storage, map_transform) =
+        match ground_layer_query.get_single() {
+            Ok(ground_layer) => ground_layer,
+            Err(_) => return,
+        };
+    let tile_position = match world_position_to_tile_position(
+        mouse.world_position,
+        map_size,
+        grid_size,
+        map_type,
+        map_transform,
+    ) {
+        Some(tile_position) => tile_position,
+        None => return,
+    };
+
+    let origin = get_paste_origin(stamp, &tile_position);
+    for y in 0..stamp.height {
+        for x in 0..stamp.width {
+            let sprite_type = match stamp.get(x, y) {
+                Some(sprite_type) => sprite_type,
+                None => continue,
+            };
+            let cell = origin + IVec2::new(x as i32, y as i32);
+            if cell.x < 0
+                || cell.y < 0
+                || cell.x >= map_size.x as i32
+                || cell.y >= map_size.y as i32
+            {
+                continue;
+            }
+            let cell_position = TilePos {
+                x: cell.x as u32,
+                y: cell.y as u32,
+            };
+            if let Some(tile_entity) = tile_storage.get(&cell_position) {
+                let previous_sprite_type = get_tile_terrain(tile_entity, &terrain_query);
+                edit_history.record(cell_position, previous_sprite_type, sprite_type);
+                set_tile_terrain(&mut commands, tile_entity, sprite_type, &sprite_registry);
+            }
+        }
+    }
+    update_tilemap_event_writer.send(UpdateTilemapEvent {});
+}
+
+// === Helper Functions ===
+// Clicks select or paste rather than place anything while a selection tool or a paste is active.
+pub fn is_selecting(tool_state: &ToolState, clipboard: &Clipboard) -> bool {
+    tool_state.tool.is_selection() || clipboard.is_pasting
+}
+
+// The map position of the bottom-left cell of `stamp` when pasted centered on `tile_position`.
+pub fn get_paste_origin(stamp: &TerrainStamp, tile_position: &TilePos) -> IVec2 {
+    IVec2::new(
+        tile_position.x as i32 - (stamp.width / 2) as i32,
+        tile_position.y as i32 - (stamp.height / 2) as i32,
+    )
+}
+
+// Copies the terrain of the selected cells into a stamp as large as the selection.
+pub fn get_selection_stamp(
+    cells: &HashSet<TilePos>,
+    get_terrain: impl Fn(&TilePos) -> Option<SpriteType>,
+) -> Option<TerrainStamp> {
+    let min_x = cells.iter().map(|cell| cell.x).min()?;
+    let min_y = cells.iter().map(|cell| cell.y).min()?;
+    let max_x = cells.iter().map(|cell| cell.x).max()?;
+    let max_y = cells.iter().map(|cell| cell.y).max()?;
+    let mut stamp_cells = Vec::new();
+    for y in min_y..=max_y {
+        for x in min_x..=max_x {
+            let tile_position = TilePos { x, y };
+            if cells.contains(&tile_position) {
+                stamp_cells.push(Some(get_terrain(&tile_position)));
+            } else {
+                stamp_cells.push(None);
+            }
+        }
+    }
+    Some(TerrainStamp {
+        name: String::new(),
+        width: max_x - min_x + 1,
+        height: max_y - min_y + 1,
+        cells: stamp_cells,
+    })
+}
+
+// The cells along the lasso, closed back to its first point, and every cell whose center lies
+// inside of it.
+pub fn get_lasso(points: &[IVec2]) -> Vec<IVec2> {
+    let (first, last) = match (points.first(), points.last()) {
+        (Some(first), Some(last)) => (*first, *last),
+        _ => return Vec::new(),
+    };
+    let mut cells: HashSet<IVec2> = HashSet::new();
+    for pair in points.windows(2) {
+        cells.extend(get_line(pair[0], pair[1]));
+    }
+    cells.extend(get_line(last, first));
+
+    let min = points.iter().fold(first, |min, point| min.min(*point));
+    let max = points.iter().fold(first, |max, point| max.max(*point));
+    for y in min.y..=max.y {
+        for x in min.x..=max.x {
+            // Even-odd rule, casting a ray towards the east.
+            let mut is_inside = false;
+            for (index, start) in points.iter().enumerate() {
+                let end = points[(index + 1) % points.len()];
+                if (start.y > y) != (end.y > y) {
+                    let crossing_x = start.x as f32
+                        + (y - start.y) as f32 / (end.y - start.y) as f32
+                            * (end.x - start.x) as f32;
+                    if (x as f32) < crossing_x {
+                        is_inside = !is_inside;
+                    }
+                }
+            }
+            if is_inside {
+                cells.insert(IVec2::new(x, y));
+            }
+        }
+    }
+    cells.into_iter().collect()
+}
+
+pub fn get_stamp_name(path: &Path) -> String {
+    path.file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+// Saved stamps sorted by name.
+pub fn get_stamp_paths() -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = match std::fs::read_dir(STAMP_DIRECTORY) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.extension()
+                    .map_or(false, |extension| extension == "ron")
+            })
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    paths.sort();
+    paths
+}
+
+// Stamps are saved under their name, which is typed in the editor panel. Unnamed stamps are
+// numbered in the order they were saved. Names that are not plain file names return `None`.
+pub fn get_stamp_path(name: &str) -> Option<PathBuf> {
+    if name.is_empty() {
+        return Some(get_free_stamp_path());
+    }
+    if !is_valid_stamp_name(name) {
+        return None;
+    }
+    Some(Path::new(STAMP_DIRECTORY).join(format!("{}.ron", name)))
+}
+
+// Letters, digits, spaces, `-` and `_`, so names stay inside of `STAMP_DIRECTORY`.
+pub fn is_valid_stamp_name(name: &str) -> bool {
+    name.trim() == name
+        && name
+            .chars()
+            .all(|character| character.is_alphanumeric() || " -_".contains(character))
+}
+
+fn get_free_stamp_path() -> PathBuf {
+    (1..)
+        .map(|number| Path::new(STAMP_DIRECTORY).join(format!("stamp_{:03}.ron", number)))
+        .find(|path| !path.exists())
+        .unwrap_or_default()
+}
+
+pub fn write_stamp_file(path: &Path, stamp: &TerrainStamp) -> Result<(), MapFileError> {
+    let terrain = (0..stamp.height)
+        .rev()
+        .map(|y| {
+            (0..stamp.width)
+                .map(|x| match stamp.get(x, y) {
+                    Some(sprite_type) => get_terrain_character(sprite_type),
+                    None => UNSELECTED_CHARACTER,
+                })
+                .collect()
+        })
+        .collect();
+    let stamp_file = StampFile {
+        version: STAMP_FILE_VERSION,
+        width: stamp.width,
+        height: stamp.height,
+        terrain,
+    };
+    let contents = ron::ser::to_string_pretty(&stamp_file, ron::ser::PrettyConfig::default())
+        .map_err(|error| MapFileError::Format {
+            path: path.to_path_buf(),
+            message: error.to_string(),
+        })?;
+    if let Some(directory) = path.parent() {
+        std::fs::create_dir_all(directory).map_err(|error| MapFileError::Io {
+            path: directory.to_path_buf(),
+            error,
+        })?;
+    }
+    std::fs::write(path, contents).map_err(|error| MapFileError::Io {
+        path: path.to_path_buf(),
+        error,
+    })
+}
+
+pub fn read_stamp_file(path: &Path) -> Result<TerrainStamp, MapFileError> {
+    let contents = std::fs::read_to_string(path).map_err(|error| MapFileError::Io {
+        path: path.to_path_buf(),
+        error,
+    })?;
+    let stamp_file: StampFile =
+        ron::de::from_str(&contents).map_err(|error| MapFileError::Format {
+            path: path.to_path_buf(),
+            message: error.to_string(),
+        })?;
+    if stamp_file.version != STAMP_FILE_VERSION {
+        return Err(MapFileError::UnknownVersion {
+            path: path.to_path_buf(),
+            version: stamp_file.version,
+        });
+    }
+    let invalid = |message: String| MapFileError::Invalid {
+        path: path.to_path_buf(),
+        message,
+    };
+    if stamp_file.width == 0
+        || stamp_file.terrain.len() != stamp_file.height as usize
+        || stamp_file
+            .terrain
+            .iter()
+            .any(|row| row.chars().count() != stamp_file.width as usize)
+    {
+        return Err(invalid(format!(
+            "has terrain that is not {}x{} tiles",
+            stamp_file.width, stamp_file.height
+        )));
+    }
+
+    // Rows are stored from the north, stamps count them from the south.
+    let mut cells = Vec::with_capacity((stamp_file.width * stamp_file.height) as usize);
+    for row in stamp_file.terrain.iter().rev() {
+        for character in row.chars() {
+            if character == UNSELECTED_CHARACTER {
+                cells.push(None);
+                continue;
+            }
+            match get_terrain_by_character(character) {
+                Some(sprite_type) => cells.push(Some(sprite_type)),
+                None => return Err(invalid(format!("has unknown terrain {:?}", character))),
+            }
+        }
+    }
+    Ok(TerrainStamp {
+        name: get_stamp_name(path),
+        width: stamp_file.width,
+        height: stamp_file.height,
+        cells,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Rows from the top, terrain as saved in map files and a space for unselected cells.
+    fn get_stamp(rows: &[&str]) -> TerrainStamp {
+        let cells = rows
+            .iter()
+            .rev()
+            .flat_map(|row| row.chars())
+            .map(|character| match character {
+                UNSELECTED_CHARACTER => None,
+                _ => get_terrain_by_character(character),
+            })
+            .collect();
+        TerrainStamp {
+            name: String::new(),
+            width: rows[0].len() as u32,
+            height: rows.len() as u32,
+            cells,
+        }
+    }
+
+    fn get_set(cells: &[IVec2]) -> HashSet<IVec2> {
+        cells.iter().copied().collect()
+    }
+
+    #[test]
+    fn rotating_and_flipping_moves_every_cell() {
+        let stamp = get_stamp(&["gd", "w "]);
+        assert_eq!(stamp.rotate_clockwise(), get_stamp(&["wg", " d"]));
+        assert_eq!(stamp.rotate_counter_clockwise(), get_stamp(&["d ", "gw"]));
+        assert_eq!(stamp.flip_horizontally(), get_stamp(&["dg", " w"]));
+        assert_eq!(stamp.flip_vertically(), get_stamp(&["w ", "gd"]));
+    }
+
+    #[test]
+    fn rotating_four_times_gives_back_the_stamp() {
+        let stamp = get_stamp(&["gd.", "w g"]);
+        let rotated = stamp.rotate_clockwise();
+        assert_eq!((rotated.width, rotated.height), (2, 3));
+        assert_eq!(
+            rotated
+                .rotate_clockwise()
+                .rotate_clockwise()
+                .rotate_clockwise(),
+            stamp
+        );
+        let rotated = stamp.rotate_counter_clockwise();
+        assert_eq!(
+            rotated
+                .rotate_counter_clockwise()
+                .rotate_counter_clockwise()
+                .rotate_counter_clockwise(),
+            stamp
+        );
+        assert_eq!(stamp.rotate_clockwise().rotate_counter_clockwise(), stamp);
+        assert_eq!(stamp.flip_horizontally().flip_horizontally(), stamp);
+        assert_eq!(stamp.flip_vertically().flip_vertically(), stamp);
+        // Flipping both ways is the same as turning around.
+        assert_eq!(
+            stamp.flip_horizontally().flip_vertically(),
+            stamp.rotate_clockwise().rotate_clockwise()
+        );
+    }
+
+    #[test]
+    fn lassos_select_their_outline_and_inside() {
+        assert!(get_lasso(&[]).is_empty());
+        assert_eq!(get_lasso(&[IVec2::new(2, 3)]), vec![IVec2::new(2, 3)]);
+
+        // The lasso is closed back to its first point.
+        let triangle = get_set(&get_lasso(&[
+            IVec2::new(0, 0),
+            IVec2::new(4, 0),
+            IVec2::new(4, 4),
+        ]));
+        assert!(triangle.contains(&IVec2::new(2, 2)));
+        assert!(triangle.contains(&IVec2::new(3, 1)));
+        assert!(!triangle.contains(&IVec2::new(1, 3)));
+        assert!(!triangle.contains(&IVec2::new(0, 4)));
+
+        let square = get_lasso(&[
+            IVec2::new(0, 0),
+            IVec2::new(4, 0),
+            IVec2::new(4, 4),
+            IVec2::new(0, 4),
+        ]);
+        assert_eq!(square.len(), 25);
+        assert_eq!(
+            get_set(&square),
+            get_set(&get_rectangle(IVec2::ZERO, IVec2::new(4, 4), true))
+        );
+    }
+
+    #[test]
+    fn stamp_files_read_back_the_same() {
+        let stamp_directory =
+            std::env::temp_dir().join(format!("autotile_stamps_{}", std::process::id()));
+        let path = stamp_directory.join("pond.ron");
+        let stamp = get_stamp(&["gw.", " dg"]);
+        write_stamp_file(&path, &stamp).unwrap();
+        let read_stamp = read_stamp_file(&path).unwrap();
+        assert_eq!(read_stamp.name, "pond");
+        assert_eq!(
+            read_stamp,
+            TerrainStamp {
+                name: "pond".to_string(),
+                ..stamp
+            }
+        );
+
+        let read_stamp_file_contents = |stamp_file: StampFile| {
+            let contents =
+                ron::ser::to_string_pretty(&stamp_file, ron::ser::PrettyConfig::default()).unwrap();
+            std::fs::write(&path, contents).unwrap();
+            read_stamp_file(&path)
+        };
+        let result = read_stamp_file_contents(StampFile {
+            version: STAMP_FILE_VERSION + 1,
+            width: 1,
+            height: 1,
+            terrain: vec!["g".to_string()],
+        });
+        assert!(matches!(result, Err(MapFileError::UnknownVersion { .. })));
+        let result = read_stamp_file_contents(StampFile {
+            version: STAMP_FILE_VERSION,
+            width: 2,
+            height: 1,
+            terrain: vec!["g".to_string()],
+        });
+        assert!(matches!(result, Err(MapFileError::Invalid { .. })));
+        let result = read_stamp_file_contents(StampFile {
+            version: STAMP_FILE_VERSION,
+            width: 1,
+            height: 1,
+            terrain: vec!["?".to_string()],
+        });
+        assert!(matches!(result, Err(MapFileError::Invalid { .. })));
+        std::fs::remove_dir_all(stamp_directory).unwrap();
+    }
+
+    #[test]
+    fn stamp_names_stay_inside_of_the_stamp_directory() {
+        assert_eq!(
+            get_stamp_path("small pond"),
+            Some(Path::new(STAMP_DIRECTORY).join("small pond.ron"))
+        );
+        assert!(get_stamp_path("").is_some());
+        for name in ["../pond", "ponds/pond", "pond.ron", " pond"] {
+            assert_eq!(get_stamp_path(name), None, "{:?}", name);
+        }
+    }
+}
@@ -0,0 +1,335 @@
+use crate::objects::ConnectableType;
+use crate::selection::{is_selecting, Clipboard};
+use crate::tools::ToolState;
+use crate::{
+    world_position_to_tile_position, DirtTile, GameState, GrassTile, GroundLayer, Mouse,
+    SpriteType, UpdateTilemapEvent, WaterTile, MAP_HEIGHT, MAP_WIDTH, TILE_SIZE,
+};
+use bevy::prelude::*;
+use bevy_ecs_tilemap::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+pub const STRUCTURE_TEXTURE_PATH: &str =
+    "sprites/Sprout Lands - Sprites - Basic pack/Tilesets/Wooden House.png";
+
+// === Components ===
+#[derive(Component, Debug)]
+pub struct StructureLayer {}
+
+// A placed stamp. `origin` is the south-west corner of its footprint.
+#[derive(Component, Debug)]
+pub struct Structure {
+    pub stamp_type: StampType,
+    pub origin: TilePos,
+}
+
+// Placed on the ground tiles covered by a structure.
+#[derive(Component, Debug)]
+pub struct StructureFootprint {
+    pub structure: Entity,
+}
+
+// === Enums ===
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum StampType {
+    WoodenHouse,
+}
+
+impl StampType {
+    pub fn from_sprite_type(sprite_type: SpriteType) -> Option<StampType> {
+        match sprite_type {
+            SpriteType::WoodenHouse => Some(StampType::WoodenHouse),
+            _ => None,
+        }
+    }
+
+    pub fn to_sprite_type(self) -> SpriteType {
+        match self {
+            StampType::WoodenHouse => SpriteType::WoodenHouse,
+        }
+    }
+}
+
+// === Structs ===
+pub struct Stamp {
+    // Atlas indices into `STRUCTURE_TEXTURE_PATH`, rows are listed from north to south. `None`
+    // cells are not part of the footprint.
+    pub footprint: Vec<Vec<Option<u32>>>,
+    pub allowed_terrain: Vec<SpriteType>,
+}
+
+impl Stamp {
+    // Returns the positions covered by the stamp when placed at `origin`, along with their atlas
+    // index.
+    pub fn get_cells(&self, origin: &TilePos) -> Vec<(TilePos, u32)> {
+        let height = self.footprint.len() as u32;
+        let mut cells = Vec::new();
+        for (row, atlas_indices) in self.footprint.iter().enumerate() {
+            for (column, atlas_index) in atlas_indices.iter().enumerate() {
+                if let Some(atlas_index) = atlas_index {
+                    let tile_position = TilePos {
+                        x: origin.x + column as u32,
+                        y: origin.y + height - 1 - row as u32,
+                    };
+                    cells.push((tile_position, *atlas_index));
+                }
+            }
+        }
+        cells
+    }
+}
+
+// === Resources ===
+pub struct Stamps {
+    pub stamps: HashMap<StampType, Stamp>,
+}
+
+impl Default for Stamps {
+    fn default() -> Stamps {
+        Stamps {
+            stamps: HashMap::from([(
+                StampType::WoodenHouse,
+                Stamp {
+                    // The roof of the house over its front wall, with the door in the middle of
+                    // the bottom row. Wooden House.png is 7 tiles wide, the roof is in its right 3
+                    // columns, the wall in its left 3 and the door in the one between them.
+                    footprint: Vec::from([
+                        Vec::from([Some(4), Some(5), Some(6)]),
+                        Vec::from([Some(11), Some(12), Some(13)]),
+                        Vec::from([Some(18), Some(19), Some(20)]),
+                        Vec::from([Some(25), Some(26), Some(27)]),
+                        Vec::from([Some(32), Some(33), Some(34)]),
+                        Vec::from([Some(7), Some(8), Some(9)]),
+                        Vec::from([Some(14), Some(15), Some(16)]),
+                        Vec::from([Some(21), Some(10), Some(23)]),
+                    ]),
+                    allowed_terrain: Vec::from([SpriteType::Grass, SpriteType::Dirt]),
+                },
+            )]),
+        }
+    }
+}
+
+// === Startup Systems ===
+pub fn setup_stamps(mut commands: Commands) {
+    commands.insert_resource(Stamps::default());
+}
+
+pub fn setup_structure_layer(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let tilemap_size = TilemapSize {
+        x: MAP_WIDTH as u32,
+        y: MAP_HEIGHT as u32,
+    };
+    let tilemap_entity = commands.spawn().id();
+    let mut tile_storage = TileStorage::empty(tilemap_size);
+
+    // Spawn the elements of the tilemap, hidden until a structure covers them.
+    for y in 0..tilemap_size.y {
+        for x in 0..tilemap_size.x {
+            let tile_position = TilePos { x, y };
+            let tile_entity = commands
+                .spawn()
+                .insert_bundle(TileBundle {
+                    position: tile_position,
+                    tilemap_id: TilemapId(tilemap_entity),
+                    visible: TileVisible(false),
+                    ..default()
+                })
+                .id();
+            tile_storage.set(&tile_position, tile_entity);
+        }
+    }
+
+    let grid_size = TilemapGridSize {
+        x: TILE_SIZE as f32,
+        y: TILE_SIZE as f32,
+    };
+    let tile_size = TilemapTileSize {
+        x: TILE_SIZE as f32,
+        y: TILE_SIZE as f32,
+    };
+    let image_handle: Handle<Image> = asset_server.load(STRUCTURE_TEXTURE_PATH);
+    let tilemap_texture = TilemapTexture::Single(image_handle);
+
+    commands
+        .entity(tilemap_entity)
+        .insert_bundle(TilemapBundle {
+            grid_size,
+            size: tilemap_size,
+            storage: tile_storage,
+            texture: tilemap_texture,
+            map_type: TilemapType::Square {
+                diagonal_neighbors: false,
+            },
+            tile_size,
+            // Structures are drawn above every object layer.
+            transform: Transform::from_xyz(0.0, 0.0, 1.0 + ConnectableType::ALL.len() as f32),
+            ..Default::default()
+        })
+        .insert(StructureLayer {});
+}
+
+// === Systems ===
+pub fn place_structure(
+    mut commands: Commands,
+    mut update_tilemap_event_writer: EventWriter<UpdateTilemapEvent>,
+    game_state: Res<GameState>,
+    mouse: Res<Mouse>,
+    mouse_input: Res<Input<MouseButton>>,
+    stamps: Res<Stamps>,
+    tool_state: Res<ToolState>,
+    clipboard: Res<Clipboard>,
+    ground_layer_query: Query<
+        (
+            &TilemapSize,
+            &TilemapGridSize,
+            &TilemapType,
+            &TileStorage,
+            &Transform,
+        ),
+        With<GroundLayer>,
+    >,
+    structure_layer_query: Query<&TileStorage, With<StructureLayer>>,
+    terrain_query: Query<(Option<&GrassTile>, Option<&DirtTile>, Option<&WaterTile>)>,
+    structure_footprints_query: Query<&StructureFootprint>,
+    structures_query: Query<&Structure>,
+) {
+    if is_selecting(&tool_state, &clipboard) {
+        return;
+    }
+    let (map_size, grid_size, map_type, ground_storage, map_transform) =
+        match ground_layer_query.get_single() {
+            Ok(ground_layer) => ground_layer,
+            Err(_) => return,
+        };
+    let structure_storage = match structure_layer_query.get_single() {
+        Ok(structure_storage) => structure_storage,
+        Err(_) => return,
+    };
+    let tile_position = match world_position_to_tile_position(
+        mouse.world_position,
+        map_size,
+        grid_size,
+        map_type,
+        map_transform,
+    ) {
+        Some(tile_position) => tile_position,
+        None => return,
+    };
+
+    // Blank erases the whole structure underneath the cursor.
+    if mouse.holding_lmb && game_state.selection == SpriteType::Blank {
+        let footprint = ground_storage
+            .get(&tile_position)
+            .and_then(|tile_entity| structure_footprints_query.get(tile_entity).ok());
+        if let Some(footprint) = footprint {
+            if let Ok(structure) = structures_query.get(footprint.structure) {
+                for (cell_position, _) in
+                    stamps.stamps[&structure.stamp_type].get_cells(&structure.origin)
+                {
+                    if let Some(ground_entity) = ground_storage.get(&cell_position) {
+                        commands
+                            .entity(ground_entity)
+                            .remove::<StructureFootprint>();
+                    }
+                    if let Some(structure_tile_entity) = structure_storage.get(&cell_position) {
+                        commands
+                            .entity(structure_tile_entity)
+                            .insert(TileVisible(false));
+                    }
+                }
+                commands.entity(footprint.structure).despawn();
+                update_tilemap_event_writer.send(UpdateTilemapEvent {});
+            }
+        }
+        return;
+    }
+
+    // Stamps are placed with a single click rather than painted.
+    if !mouse_input.just_pressed(MouseButton::Left) || mouse.is_over_ui {
+        return;
+    }
+    let stamp_type = match StampType::from_sprite_type(game_state.selection) {
+        Some(stamp_type) => stamp_type,
+        None => return,
+    };
+    let stamp = &stamps.stamps[&stamp_type];
+    let cells = stamp.get_cells(&tile_position);
+
+    // Validate the whole footprint before touching anything.
+    for (cell_position, _) in &cells {
+        if cell_position.x >= map_size.x || cell_position.y >= map_size.y {
+            println!(
+                "Cannot place {:?} at {:?}: {:?} is outside of the map",
+                stamp_type, tile_position, cell_position
+            );
+            return;
+        }
+        let ground_entity = match ground_storage.get(cell_position) {
+            Some(ground_entity) => ground_entity,
+            None => return,
+        };
+        if structure_footprints_query.contains(ground_entity) {
+            println!(
+                "Cannot place {:?} at {:?}: {:?} is already covered by a structure",
+                stamp_type, tile_position, cell_position
+            );
+            return;
+        }
+        let terrain = match terrain_query.get(ground_entity) {
+            Ok((Some(_), _, _)) => SpriteType::Grass,
+            Ok((_, Some(_), _)) => SpriteType::Dirt,
+            Ok((_, _, Some(_))) => SpriteType::Water,
+            _ => SpriteType::Blank,
+        };
+        if !stamp.allowed_terrain.contains(&terrain) {
+            println!(
+                "Cannot place {:?} at {:?}: {:?} is {:?}",
+                stamp_type, tile_position, cell_position, terrain
+            );
+            return;
+        }
+    }
+
+    spawn_structure(
+        &mut commands,
+        stamp_type,
+        stamp,
+        tile_position,
+        ground_storage,
+        structure_storage,
+    );
+    update_tilemap_event_writer.send(UpdateTilemapEvent {});
+}
+
+// === Helper Functions ===
+// Spawns a structure without validating its footprint, see `place_structure`.
+pub fn spawn_structure(
+    commands: &mut Commands,
+    stamp_type: StampType,
+    stamp: &Stamp,
+    origin: TilePos,
+    ground_storage: &TileStorage,
+    structure_storage: &TileStorage,
+) -> Entity {
+    let structure = commands
+        .spawn()
+        .insert(Structure { stamp_type, origin })
+        .insert(Name::new(format!("{:?}", stamp_type)))
+        .id();
+    for (cell_position, atlas_index) in stamp.get_cells(&origin) {
+        if let Some(ground_entity) = ground_storage.get(&cell_position) {
+            commands
+                .entity(ground_entity)
+                .insert(StructureFootprint { structure });
+        }
+        if let Some(structure_tile_entity) = structure_storage.get(&cell_position) {
+            commands
+                .entity(structure_tile_entity)
+                .insert(TileTexture(atlas_index))
+                .insert(TileVisible(true));
+        }
+    }
+    structure
+}
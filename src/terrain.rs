@@ -0,0 +1,179 @@
+use crate::resolver::{AutotileResolvers, Neighborhood, TileOutput};
+use crate::{get_matching_sprite, Rule, Rules, Slot, SpriteRegistry, SpriteType, AUTOTILED_TYPES};
+use bevy_ecs_tilemap::prelude::*;
+
+// === Structs ===
+// The terrain of every tile, apart from the ECS so that maps can be resolved without an app.
+// Positions match `TilePos`, counting rows from the bottom.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TerrainGrid {
+    pub width: u32,
+    pub height: u32,
+    cells: Vec<Option<SpriteType>>,
+    // Tiles covered by a structure, see `get_active_rule`.
+    structure_cells: Vec<bool>,
+}
+
+impl TerrainGrid {
+    pub fn new(width: u32, height: u32) -> TerrainGrid {
+        TerrainGrid {
+            width,
+            height,
+            cells: vec![None; (width * height) as usize],
+            structure_cells: vec![false; (width * height) as usize],
+        }
+    }
+
+    // `terrain` is listed row by row starting from the top-left corner, like files store it.
+    pub fn from_rows(width: u32, height: u32, terrain: &[Option<SpriteType>]) -> TerrainGrid {
+        let mut terrain_grid = TerrainGrid::new(width, height);
+        for (index, sprite_type) in terrain.iter().enumerate() {
+            let column = index as u32 % width;
+            let row = index as u32 / width;
+            if row < height {
+                terrain_grid.set(column, height - 1 - row, *sprite_type);
+            }
+        }
+        terrain_grid
+    }
+
+    // Tiles outside of the grid are empty.
+    pub fn get(&self, x: i32, y: i32) -> Option<SpriteType> {
+        if x < 0 || y < 0 || x >= self.width as i32 || y >= self.height as i32 {
+            return None;
+        }
+        self.cells[(y as u32 * self.width + x as u32) as usize]
+    }
+
+    pub fn set(&mut self, x: u32, y: u32, sprite_type: Option<SpriteType>) {
+        if x < self.width && y < self.height {
+            self.cells[(y * self.width + x) as usize] = sprite_type;
+        }
+    }
+
+    pub fn has_structure(&self, x: i32, y: i32) -> bool {
+        if x < 0 || y < 0 || x >= self.width as i32 || y >= self.height as i32 {
+            return false;
+        }
+        self.structure_cells[(y as u32 * self.width + x as u32) as usize]
+    }
+
+    pub fn set_structure(&mut self, x: u32, y: u32, has_structure: bool) {
+        if x < self.width && y < self.height {
+            self.structure_cells[(y * self.width + x) as usize] = has_structure;
+        }
+    }
+
+    // Builds the rule a tile is matched against, the same way `update_active_rules` does for the
+    // tilemap. Empty tiles have no rule, and tiles covered by a structure count as more of the
+    // terrain of their neighbors.
+    pub fn get_active_rule(&self, x: u32, y: u32) -> Option<Rule> {
+        let sprite_type = self.get(x as i32, y as i32)?;
+        let get_slot = |dx: i32, dy: i32| {
+            let (neighbor_x, neighbor_y) = (x as i32 + dx, y as i32 + dy);
+            if self.has_structure(neighbor_x, neighbor_y) {
+                return Slot::Filled { sprite_type };
+            }
+            match self.get(neighbor_x, neighbor_y) {
+                Some(sprite_type) => Slot::Filled { sprite_type },
+                None => Slot::Empty,
+            }
+        };
+        Some(Rule {
+            nw_slot: get_slot(-1, 1),
+            n_slot: get_slot(0, 1),
+            ne_slot: get_slot(1, 1),
+            w_slot: get_slot(-1, 0),
+            c_slot: Slot::Filled { sprite_type },
+            e_slot: get_slot(1, 0),
+            sw_slot: get_slot(-1, -1),
+            s_slot: get_slot(0, -1),
+            se_slot: get_slot(1, -1),
+        })
+    }
+
+    // Returns the sprite the first matching rule picks for a single tile, which is what the rules
+    // allow rather than what the tile is drawn with, see `resolve_tile`. Tiles without terrain, or
+    // whose terrain has no rules, match nothing.
+    pub fn match_tile<'a>(&self, x: u32, y: u32, rules: &'a Rules) -> Option<&'a str> {
+        let active_rule = self.get_active_rule(x, y)?;
+        let possible_rules = rules.rules.get(&self.get(x as i32, y as i32)?)?;
+        get_matching_sprite(&active_rule, possible_rules)
+    }
+
+    // Returns what a single tile is drawn with, picked by the resolver of its terrain like
+    // `update_tilemap` does. Tiles that are not autotiled, or that the resolver leaves blank,
+    // resolve to `None`.
+    pub fn resolve_tile(
+        &self,
+        x: u32,
+        y: u32,
+        rules: &Rules,
+        autotile_resolvers: &AutotileResolvers,
+        sprite_registry: &SpriteRegistry,
+    ) -> Option<TileOutput> {
+        let sprite_type = self.get(x as i32, y as i32)?;
+        if !AUTOTILED_TYPES.contains(&sprite_type) {
+            return None;
+        }
+        let neighborhood = Neighborhood {
+            tile_position: TilePos { x, y },
+            active_rule: self.get_active_rule(x, y)?,
+        };
+        autotile_resolvers.get(sprite_type).resolve(
+            &neighborhood,
+            sprite_type,
+            rules,
+            sprite_registry,
+        )
+    }
+
+    // Returns what every tile is drawn with, indexed like the cells.
+    pub fn resolve(
+        &self,
+        rules: &Rules,
+        autotile_resolvers: &AutotileResolvers,
+        sprite_registry: &SpriteRegistry,
+    ) -> Vec<Option<TileOutput>> {
+        let mut tile_outputs = Vec::with_capacity(self.cells.len());
+        for y in 0..self.height {
+            for x in 0..self.width {
+                tile_outputs.push(self.resolve_tile(
+                    x,
+                    y,
+                    rules,
+                    autotile_resolvers,
+                    sprite_registry,
+                ));
+            }
+        }
+        tile_outputs
+    }
+}
+
+// Lets tests draw the maps they start from.
+#[cfg(test)]
+impl TerrainGrid {
+    // Rows from the top, `g` is grass, `d` dirt, `~` water and anything else no terrain.
+    pub fn from_ascii(rows: &[&str]) -> TerrainGrid {
+        let terrain: Vec<Option<SpriteType>> = rows
+            .iter()
+            .flat_map(|row| row.chars())
+            .map(|character| match character {
+                'g' => Some(SpriteType::Grass),
+                'd' => Some(SpriteType::Dirt),
+                '~' => Some(SpriteType::Water),
+                _ => None,
+            })
+            .collect();
+        TerrainGrid::from_rows(rows[0].len() as u32, rows.len() as u32, &terrain)
+    }
+
+    // Every cell of the grid, row by row from the bottom.
+    pub fn cells(&self) -> Vec<bevy::math::IVec2> {
+        let width = self.width as i32;
+        (0..self.height as i32)
+            .flat_map(|y| (0..width).map(move |x| bevy::math::IVec2::new(x, y)))
+            .collect()
+    }
+}
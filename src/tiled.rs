@@ -0,0 +1,828 @@
+use crate::atlas::{SheetManifest, TileManifest};
+use crate::{
+    get_sprite_type_by_name, paint_terrain, GroundLayer, Rule, Slot, SpriteType,
+    UpdateTilemapEvent, TILE_SIZE,
+};
+use bevy::prelude::*;
+use bevy_ecs_tilemap::prelude::*;
+use roxmltree::Node;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+// Wang ids list 8 colors clockwise, starting with the top edge. 0 means the color is unset.
+const WANG_TOP: usize = 0;
+const WANG_TOP_RIGHT: usize = 1;
+const WANG_RIGHT: usize = 2;
+const WANG_BOTTOM_RIGHT: usize = 3;
+const WANG_BOTTOM: usize = 4;
+const WANG_BOTTOM_LEFT: usize = 5;
+const WANG_LEFT: usize = 6;
+const WANG_TOP_LEFT: usize = 7;
+
+// The upper bits of a gid store how the tile is flipped.
+const GID_FLAGS: u32 = 0xF000_0000;
+
+// === Enums ===
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WangSetType {
+    Corner,
+    Edge,
+    Mixed,
+}
+
+#[derive(Debug)]
+pub enum TiledError {
+    Io {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+    Xml {
+        path: PathBuf,
+        message: String,
+    },
+    Missing {
+        path: PathBuf,
+        what: String,
+    },
+    Unsupported {
+        path: PathBuf,
+        what: String,
+    },
+}
+
+impl fmt::Display for TiledError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TiledError::Io { path, error } => write!(f, "could not read {:?}: {}", path, error),
+            TiledError::Xml { path, message } => {
+                write!(f, "invalid XML in {:?}: {}", path, message)
+            }
+            TiledError::Missing { path, what } => write!(f, "{:?} is missing {}", path, what),
+            TiledError::Unsupported { path, what } => {
+                write!(f, "{:?} uses {}, which is not supported", path, what)
+            }
+        }
+    }
+}
+
+impl std::error::Error for TiledError {}
+
+// === Structs ===
+#[derive(Clone, Debug)]
+pub struct TiledTileset {
+    pub name: String,
+    pub image_path: PathBuf,
+    pub columns: u32,
+    pub wang_sets: Vec<WangSet>,
+    // Tiles given a `terrain` property, like the terrain layer of exported maps.
+    pub tile_terrain: HashMap<u32, SpriteType>,
+}
+
+#[derive(Clone, Debug)]
+pub struct WangSet {
+    pub wang_set_type: WangSetType,
+    // Color names, wang ids refer to them starting from 1.
+    pub colors: Vec<String>,
+    pub tiles: Vec<WangTile>,
+}
+
+#[derive(Clone, Debug)]
+pub struct WangTile {
+    pub tile_id: u32,
+    pub wang_id: [u32; 8],
+}
+
+pub struct TiledMap {
+    pub width: u32,
+    pub height: u32,
+    // Tilesets along with the first gid they cover.
+    pub tilesets: Vec<(u32, TiledTileset)>,
+    pub layers: Vec<TiledLayer>,
+}
+
+pub struct TiledLayer {
+    // Row by row, starting from the top-left corner of the map. 0 is an empty cell.
+    pub gids: Vec<u32>,
+}
+
+impl TiledTileset {
+    pub fn get_sprite_name(&self, tile_id: u32) -> String {
+        format!("{}/{}", self.name, tile_id)
+    }
+
+    // Every tile used by a wang set is packed into the atlas.
+    pub fn to_sheet_manifest(&self) -> SheetManifest {
+        let mut tile_ids: Vec<u32> = self
+            .wang_sets
+            .iter()
+            .flat_map(|wang_set| wang_set.tiles.iter().map(|wang_tile| wang_tile.tile_id))
+            .collect();
+        tile_ids.sort_unstable();
+        tile_ids.dedup();
+        SheetManifest {
+            path: self.image_path.to_string_lossy().into_owned(),
+            tiles: tile_ids
+                .into_iter()
+                .map(|tile_id| TileManifest {
+                    name: self.get_sprite_name(tile_id),
+                    column: tile_id % self.columns,
+                    row: tile_id / self.columns,
+                })
+                .collect(),
+        }
+    }
+
+    // Turns every wang color named after a terrain into the rules of that terrain.
+    pub fn to_rules(&self) -> HashMap<SpriteType, Vec<(Rule, String)>> {
+        let mut rules: HashMap<SpriteType, Vec<(Rule, String)>> = HashMap::new();
+        for wang_set in &self.wang_sets {
+            for (color_index, color_name) in wang_set.colors.iter().enumerate() {
+                let sprite_type = match get_sprite_type_by_name(color_name) {
+                    Some(sprite_type) => sprite_type,
+                    None => continue,
+                };
+                let color = color_index as u32 + 1;
+                let mut color_rules: Vec<(usize, Rule, String)> = wang_set
+                    .tiles
+                    .iter()
+                    // Wang ids don't cover the center of a tile, tiles without the color at all
+                    // (like a lone island) can't be told apart from other terrains and are skipped.
+                    .filter(|wang_tile| wang_tile.wang_id.contains(&color))
+                    .map(|wang_tile| {
+                        let specificity = wang_tile
+                            .wang_id
+                            .iter()
+                            .filter(|wang_color| **wang_color == color)
+                            .count();
+                        (
+                            specificity,
+                            wang_tile.to_rule(wang_set.wang_set_type, color, sprite_type),
+                            self.get_sprite_name(wang_tile.tile_id),
+                        )
+                    })
+                    .collect();
+                // Rules are matched in order, tiles covering more of the terrain go first.
+                color_rules.sort_by_key(|(specificity, _, _)| Reverse(*specificity));
+                rules.entry(sprite_type).or_default().extend(
+                    color_rules
+                        .into_iter()
+                        .map(|(_, rule, sprite_name)| (rule, sprite_name)),
+                );
+            }
+        }
+        rules
+    }
+
+    // The terrain a tile belongs to is its `terrain` property, or else the terrain that covers most
+    // of it.
+    pub fn get_tile_terrain(&self, tile_id: u32) -> Option<SpriteType> {
+        if let Some(sprite_type) = self.tile_terrain.get(&tile_id) {
+            return Some(*sprite_type);
+        }
+        for wang_set in &self.wang_sets {
+            if let Some(wang_tile) = wang_set
+                .tiles
+                .iter()
+                .find(|wang_tile| wang_tile.tile_id == tile_id)
+            {
+                let mut color_counts: HashMap<u32, usize> = HashMap::new();
+                for color in wang_tile.wang_id {
+                    if color != 0 {
+                        *color_counts.entry(color).or_default() += 1;
+                    }
+                }
+                let mut colors: Vec<(u32, usize)> = color_counts.into_iter().collect();
+                colors.sort_by_key(|(color, count)| (Reverse(*count), *color));
+                for (color, _) in colors {
+                    let sprite_type = wang_set
+                        .colors
+                        .get(color as usize - 1)
+                        .and_then(|color_name| get_sprite_type_by_name(color_name));
+                    if sprite_type.is_some() {
+                        return sprite_type;
+                    }
+                }
+            }
+        }
+        None
+    }
+}
+
+impl WangTile {
+    // Builds the rule of a tile for the terrain painted with `color`. Other colors count as empty.
+    pub fn to_rule(&self, wang_set_type: WangSetType, color: u32, sprite_type: SpriteType) -> Rule {
+        let filled = Slot::Filled { sprite_type };
+        let is_color = |index: usize| self.wang_id[index] == color;
+        let get_edge_slot = |index: usize| {
+            if self.wang_id[index] == 0 {
+                Slot::Any
+            } else if is_color(index) {
+                filled
+            } else {
+                Slot::Empty
+            }
+        };
+
+        // Slots listed in wang id order.
+        let mut slots = [Slot::Any; 8];
+        match wang_set_type {
+            WangSetType::Edge => {
+                for edge in [WANG_TOP, WANG_RIGHT, WANG_BOTTOM, WANG_LEFT] {
+                    slots[edge] = get_edge_slot(edge);
+                }
+            }
+            WangSetType::Corner => {
+                // A corner is painted when the three neighbors around it are. Unpainted corners
+                // can't be expressed by a single rule, they are left as Any instead and the
+                // rules are ordered so that tiles painting more corners are tried first.
+                for corner in [
+                    WANG_TOP_RIGHT,
+                    WANG_BOTTOM_RIGHT,
+                    WANG_BOTTOM_LEFT,
+                    WANG_TOP_LEFT,
+                ] {
+                    if is_color(corner) {
+                        slots[corner] = filled;
+                        slots[(corner + 7) % 8] = filled;
+                        slots[(corner + 1) % 8] = filled;
+                    }
+                }
+            }
+            WangSetType::Mixed => {
+                for edge in [WANG_TOP, WANG_RIGHT, WANG_BOTTOM, WANG_LEFT] {
+                    slots[edge] = get_edge_slot(edge);
+                }
+                // Like the blob tiles, a corner only matters when both edges next to it are
+                // painted.
+                for corner in [
+                    WANG_TOP_RIGHT,
+                    WANG_BOTTOM_RIGHT,
+                    WANG_BOTTOM_LEFT,
+                    WANG_TOP_LEFT,
+                ] {
+                    if self.wang_id[corner] == 0 {
+                        continue;
+                    }
+                    if is_color(corner) {
+                        slots[corner] = filled;
+                    } else if is_color((corner + 7) % 8) && is_color((corner + 1) % 8) {
+                        slots[corner] = Slot::Empty;
+                    }
+                }
+            }
+        }
+
+        Rule {
+            nw_slot: slots[WANG_TOP_LEFT],
+            n_slot: slots[WANG_TOP],
+            ne_slot: slots[WANG_TOP_RIGHT],
+            w_slot: slots[WANG_LEFT],
+            c_slot: filled,
+            e_slot: slots[WANG_RIGHT],
+            sw_slot: slots[WANG_BOTTOM_LEFT],
+            s_slot: slots[WANG_BOTTOM],
+            se_slot: slots[WANG_BOTTOM_RIGHT],
+        }
+    }
+}
+
+impl TiledMap {
+    // Combines every tile layer into a single terrain per cell, later layers paint over earlier
+    // ones. Row by row, starting from the top-left corner of the map.
+    pub fn get_terrain(&self) -> Vec<Option<SpriteType>> {
+        let mut terrain = vec![None; (self.width * self.height) as usize];
+        for layer in &self.layers {
+            for (index, gid) in layer.gids.iter().enumerate() {
+                let gid = gid & !GID_FLAGS;
+                if gid == 0 {
+                    continue;
+                }
+                // Tilesets are sorted by their first gid, the last one starting at or before the gid
+                // holds the tile.
+                let tileset = self
+                    .tilesets
+                    .iter()
+                    .rev()
+                    .find(|(first_gid, _)| *first_gid <= gid);
+                if let Some((first_gid, tileset)) = tileset {
+                    if let Some(sprite_type) = tileset.get_tile_terrain(gid - first_gid) {
+                        terrain[index] = Some(sprite_type);
+                    }
+                }
+            }
+        }
+        terrain
+    }
+}
+
+// === Resources ===
+pub struct TiledImport {
+    pub tilesets: Vec<TiledTileset>,
+    pub map: Option<TiledMap>,
+}
+
+impl TiledImport {
+    // Reads `--tmx <map>` and `--tsx <tileset>` from the command line. Maps bring along the
+    // tilesets they use.
+    pub fn from_args(mut args: impl Iterator<Item = String>) -> Result<TiledImport, TiledError> {
+        let mut tiled_import = TiledImport {
+            tilesets: Vec::new(),
+            map: None,
+        };
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--tsx" => {
+                    if let Some(path) = args.next() {
+                        tiled_import.add_tileset(load_tiled_tileset(Path::new(&path))?);
+                    }
+                }
+                "--tmx" => {
+                    if let Some(path) = args.next() {
+                        let map = load_tiled_map(Path::new(&path))?;
+                        for (_, tileset) in &map.tilesets {
+                            tiled_import.add_tileset(tileset.clone());
+                        }
+                        tiled_import.map = Some(map);
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(tiled_import)
+    }
+
+    // A tileset given with `--tsx` may also be used by a map given with `--tmx`, it is only packed
+    // into the atlas once.
+    fn add_tileset(&mut self, tileset: TiledTileset) {
+        let is_added = self
+            .tilesets
+            .iter()
+            .any(|other| other.name == tileset.name && other.image_path == tileset.image_path);
+        if !is_added {
+            self.tilesets.push(tileset);
+        }
+    }
+}
+
+// === Startup Systems ===
+// Paints the terrain of the imported map onto the ground, the autotiling systems take it from
+// there.
+pub fn apply_tiled_map(
+    mut commands: Commands,
+    mut update_tilemap_event_writer: EventWriter<UpdateTilemapEvent>,
+    tiled_import: Res<TiledImport>,
+    ground_layer_query: Query<(&TilemapSize, &TileStorage), With<GroundLayer>>,
+) {
+    let map = match &tiled_import.map {
+        Some(map) => map,
+        None => return,
+    };
+    let (map_size, tile_storage) = match ground_layer_query.get_single() {
+        Ok(ground_layer) => ground_layer,
+        Err(_) => return,
+    };
+    paint_terrain(
+        &mut commands,
+        map_size,
+        tile_storage,
+        map.width,
+        map.height,
+        &map.get_terrain(),
+    );
+    update_tilemap_event_writer.send(UpdateTilemapEvent {});
+}
+
+// === Helper Functions ===
+pub fn load_tiled_tileset(path: &Path) -> Result<TiledTileset, TiledError> {
+    let contents = read_file(path)?;
+    let document = parse_document(path, &contents)?;
+    parse_tileset(path, document.root_element())
+}
+
+pub fn load_tiled_map(path: &Path) -> Result<TiledMap, TiledError> {
+    let contents = read_file(path)?;
+    let document = parse_document(path, &contents)?;
+    let map_node = document.root_element();
+    if map_node.attribute("infinite") == Some("1") {
+        return Err(unsupported(path, "infinite maps"));
+    }
+    if map_node.attribute("orientation").unwrap_or("orthogonal") != "orthogonal" {
+        return Err(unsupported(path, "a non-orthogonal orientation"));
+    }
+    let width: u32 = get_attribute(path, map_node, "width")?;
+    let height: u32 = get_attribute(path, map_node, "height")?;
+
+    let mut tilesets = Vec::new();
+    for tileset_node in map_node
+        .children()
+        .filter(|node| node.has_tag_name("tileset"))
+    {
+        let first_gid: u32 = get_attribute(path, tileset_node, "firstgid")?;
+        let tileset = match tileset_node.attribute("source") {
+            // External tilesets are relative to the map.
+            Some(source) => load_tiled_tileset(&get_relative_path(path, source))?,
+            None => parse_tileset(path, tileset_node)?,
+        };
+        tilesets.push((first_gid, tileset));
+    }
+    tilesets.sort_by_key(|(first_gid, _)| *first_gid);
+
+    let mut layers = Vec::new();
+    for layer_node in map_node
+        .children()
+        .filter(|node| node.has_tag_name("layer"))
+    {
+        let data_node = layer_node
+            .children()
+            .find(|node| node.has_tag_name("data"))
+            .ok_or_else(|| missing(path, "the data of a layer"))?;
+        let gids = parse_layer_data(path, data_node)?;
+        if gids.len() != (width * height) as usize {
+            return Err(unsupported(path, "layers of a different size than the map"));
+        }
+        layers.push(TiledLayer { gids });
+    }
+
+    Ok(TiledMap {
+        width,
+        height,
+        tilesets,
+        layers,
+    })
+}
+
+fn parse_tileset(path: &Path, tileset_node: Node) -> Result<TiledTileset, TiledError> {
+    let name: String = get_attribute(path, tileset_node, "name")?;
+    let tile_width: i32 = get_attribute(path, tileset_node, "tilewidth")?;
+    let tile_height: i32 = get_attribute(path, tileset_node, "tileheight")?;
+    let columns: u32 = get_attribute(path, tileset_node, "columns")?;
+    if tile_width != TILE_SIZE || tile_height != TILE_SIZE {
+        return Err(unsupported(
+            path,
+            "a tile size other than the one of the map",
+        ));
+    }
+    if tileset_node.attribute("spacing").unwrap_or("0") != "0"
+        || tileset_node.attribute("margin").unwrap_or("0") != "0"
+    {
+        return Err(unsupported(path, "spacing or margins between tiles"));
+    }
+    let image_node = tileset_node
+        .children()
+        .find(|node| node.has_tag_name("image"))
+        .ok_or_else(|| unsupported(path, "a collection of images instead of a single image"))?;
+    let source: String = get_attribute(path, image_node, "source")?;
+
+    let mut tile_terrain = HashMap::new();
+    for tile_node in tileset_node
+        .children()
+        .filter(|node| node.has_tag_name("tile"))
+    {
+        let tile_id: u32 = get_attribute(path, tile_node, "id")?;
+        let terrain = tile_node
+            .children()
+            .filter(|node| node.has_tag_name("properties"))
+            .flat_map(|node| node.children())
+            .find(|node| node.has_tag_name("property") && node.attribute("name") == Some("terrain"))
+            .and_then(|node| node.attribute("value"))
+            .and_then(get_sprite_type_by_name);
+        if let Some(sprite_type) = terrain {
+            tile_terrain.insert(tile_id, sprite_type);
+        }
+    }
+
+    let mut wang_sets = Vec::new();
+    let wang_set_nodes = tileset_node
+        .children()
+        .filter(|node| node.has_tag_name("wangsets"))
+        .flat_map(|node| node.children())
+        .filter(|node| node.has_tag_name("wangset"));
+    for wang_set_node in wang_set_nodes {
+        let wang_set_type = match wang_set_node.attribute("type") {
+            Some("corner") => WangSetType::Corner,
+            Some("edge") => WangSetType::Edge,
+            Some("mixed") => WangSetType::Mixed,
+            _ => return Err(unsupported(path, "a wang set from before Tiled 1.5")),
+        };
+        let colors = wang_set_node
+            .children()
+            .filter(|node| node.has_tag_name("wangcolor"))
+            .map(|node| node.attribute("name").unwrap_or_default().to_string())
+            .collect();
+        let mut tiles = Vec::new();
+        for wang_tile_node in wang_set_node
+            .children()
+            .filter(|node| node.has_tag_name("wangtile"))
+        {
+            let tile_id: u32 = get_attribute(path, wang_tile_node, "tileid")?;
+            let wang_id: String = get_attribute(path, wang_tile_node, "wangid")?;
+            let colors: Vec<u32> = wang_id
+                .split(',')
+                .map(|color| color.trim().parse())
+                .collect::<Result<_, _>>()
+                .map_err(|_| unsupported(path, "a wang id from before Tiled 1.5"))?;
+            let wang_id: [u32; 8] = colors
+                .try_into()
+                .map_err(|_| unsupported(path, "a wang id from before Tiled 1.5"))?;
+            tiles.push(WangTile { tile_id, wang_id });
+        }
+        wang_sets.push(WangSet {
+            wang_set_type,
+            colors,
+            tiles,
+        });
+    }
+
+    Ok(TiledTileset {
+        name,
+        // Images are relative to the file the tileset is defined in.
+        image_path: get_absolute_path(path, &source),
+        columns,
+        wang_sets,
+        tile_terrain,
+    })
+}
+
+fn parse_layer_data(path: &Path, data_node: Node) -> Result<Vec<u32>, TiledError> {
+    if data_node.attribute("compression").is_some() {
+        return Err(unsupported(path, "compressed layer data"));
+    }
+    let text = data_node.text().unwrap_or_default();
+    match data_node.attribute("encoding") {
+        Some("csv") => text
+            .split(',')
+            .map(|gid| gid.trim().parse())
+            .collect::<Result<_, _>>()
+            .map_err(|_| unsupported(path, "malformed CSV layer data")),
+        Some("base64") => {
+            let bytes = decode_base64(text.trim())
+                .ok_or_else(|| unsupported(path, "malformed base64 layer data"))?;
+            Ok(bytes
+                .chunks_exact(4)
+                .map(|gid| u32::from_le_bytes([gid[0], gid[1], gid[2], gid[3]]))
+                .collect())
+        }
+        Some(encoding) => Err(unsupported(path, &format!("the {} encoding", encoding))),
+        // Empty tiles are written without a gid.
+        None => data_node
+            .children()
+            .filter(|node| node.has_tag_name("tile"))
+            .map(|node| {
+                node.attribute("gid")
+                    .unwrap_or("0")
+                    .parse()
+                    .map_err(|_| unsupported(path, "malformed XML layer data"))
+            })
+            .collect(),
+    }
+}
+
+fn decode_base64(text: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(text.len() * 3 / 4);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for character in text
+        .bytes()
+        .filter(|character| !character.is_ascii_whitespace())
+    {
+        let value = match character {
+            b'A'..=b'Z' => character - b'A',
+            b'a'..=b'z' => character - b'a' + 26,
+            b'0'..=b'9' => character - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            b'=' => break,
+            _ => return None,
+        };
+        buffer = (buffer << 6) | u32::from(value);
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+        }
+    }
+    Some(bytes)
+}
+
+fn read_file(path: &Path) -> Result<String, TiledError> {
+    std::fs::read_to_string(path).map_err(|error| TiledError::Io {
+        path: path.to_path_buf(),
+        error,
+    })
+}
+
+fn parse_document<'a>(
+    path: &Path,
+    contents: &'a str,
+) -> Result<roxmltree::Document<'a>, TiledError> {
+    roxmltree::Document::parse(contents).map_err(|error| TiledError::Xml {
+        path: path.to_path_buf(),
+        message: error.to_string(),
+    })
+}
+
+fn get_attribute<T: FromStr>(path: &Path, node: Node, name: &str) -> Result<T, TiledError> {
+    node.attribute(name)
+        .and_then(|value| value.parse().ok())
+        .ok_or_else(|| {
+            missing(
+                path,
+                &format!("a valid {:?} on <{}>", name, node.tag_name().name()),
+            )
+        })
+}
+
+fn get_relative_path(path: &Path, relative_path: &str) -> PathBuf {
+    path.parent()
+        .unwrap_or_else(|| Path::new(""))
+        .join(relative_path)
+}
+
+// Sheets are looked up from the asset root, which would be prepended to a path relative to the
+// working directory. Joining an absolute path onto it leaves the path as it is.
+pub fn get_absolute_path(path: &Path, relative_path: &str) -> PathBuf {
+    let relative_path = get_relative_path(path, relative_path);
+    std::fs::canonicalize(&relative_path).unwrap_or_else(|_| {
+        std::env::current_dir()
+            .unwrap_or_default()
+            .join(&relative_path)
+    })
+}
+
+fn missing(path: &Path, what: &str) -> TiledError {
+    TiledError::Missing {
+        path: path.to_path_buf(),
+        what: what.to_string(),
+    }
+}
+
+fn unsupported(path: &Path, what: &str) -> TiledError {
+    TiledError::Unsupported {
+        path: path.to_path_buf(),
+        what: what.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::debug::get_slots;
+
+    const EXAMPLE_MAP_PATH: &str = "assets/tiled/example.tmx";
+    const FLIPPED_HORIZONTALLY: u32 = 0x8000_0000;
+    const FLIPPED_VERTICALLY: u32 = 0x4000_0000;
+
+    fn parse_data(data: &str) -> Result<Vec<u32>, TiledError> {
+        let document = roxmltree::Document::parse(data).unwrap();
+        parse_layer_data(Path::new("test.tmx"), document.root_element())
+    }
+
+    fn get_rule(slots: [Slot; 9]) -> Rule {
+        Rule {
+            nw_slot: slots[0],
+            n_slot: slots[1],
+            ne_slot: slots[2],
+            w_slot: slots[3],
+            c_slot: slots[4],
+            e_slot: slots[5],
+            sw_slot: slots[6],
+            s_slot: slots[7],
+            se_slot: slots[8],
+        }
+    }
+
+    #[test]
+    fn layer_data_decodes_the_same_from_every_encoding() {
+        let gids = vec![0, 3, 3 | FLIPPED_HORIZONTALLY, 1];
+        let csv = parse_data(
+            r#"<data encoding="csv">0,3,
+            2147483651,1</data>"#,
+        );
+        let base64 = parse_data(r#"<data encoding="base64"> AAAAAAMAAAADAACAAQAAAA== </data>"#);
+        let xml = parse_data(
+            r#"<data><tile/><tile gid="3"/><tile gid="2147483651"/><tile gid="1"/></data>"#,
+        );
+        assert_eq!(csv.unwrap(), gids);
+        assert_eq!(base64.unwrap(), gids);
+        assert_eq!(xml.unwrap(), gids);
+    }
+
+    #[test]
+    fn malformed_or_compressed_layer_data_is_rejected() {
+        for data in [
+            r#"<data encoding="csv">0,grass,1</data>"#,
+            r#"<data encoding="base64">AAAA*AAA</data>"#,
+            r#"<data><tile gid="grass"/></data>"#,
+            r#"<data encoding="base64" compression="zlib">AAAAAA==</data>"#,
+        ] {
+            assert!(matches!(
+                parse_data(data),
+                Err(TiledError::Unsupported { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn base64_decodes_with_and_without_padding() {
+        assert_eq!(decode_base64("TWFu").unwrap(), b"Man");
+        assert_eq!(decode_base64("TWE=").unwrap(), b"Ma");
+        assert_eq!(decode_base64("TQ==").unwrap(), b"M");
+        assert_eq!(decode_base64("TW\nFu").unwrap(), b"Man");
+        assert_eq!(decode_base64("").unwrap(), b"");
+        assert!(decode_base64("TW-u").is_none());
+    }
+
+    #[test]
+    fn edge_wang_ids_become_edge_slots() {
+        let grass = Slot::Filled {
+            sprite_type: SpriteType::Grass,
+        };
+        // Grass on top and bottom, another color on the right and nothing on the left.
+        let wang_tile = WangTile {
+            tile_id: 0,
+            wang_id: [1, 0, 2, 0, 1, 0, 0, 0],
+        };
+        let rule = wang_tile.to_rule(WangSetType::Edge, 1, SpriteType::Grass);
+        let expected_rule = get_rule([
+            Slot::Any,
+            grass,
+            Slot::Any,
+            Slot::Any,
+            grass,
+            Slot::Empty,
+            Slot::Any,
+            grass,
+            Slot::Any,
+        ]);
+        assert_eq!(get_slots(&rule), get_slots(&expected_rule));
+    }
+
+    #[test]
+    fn corner_wang_ids_fill_the_slots_around_painted_corners() {
+        let grass = Slot::Filled {
+            sprite_type: SpriteType::Grass,
+        };
+        // Only the top right corner is grass.
+        let wang_tile = WangTile {
+            tile_id: 0,
+            wang_id: [0, 1, 0, 2, 0, 2, 0, 2],
+        };
+        let rule = wang_tile.to_rule(WangSetType::Corner, 1, SpriteType::Grass);
+        let expected_rule = get_rule([
+            Slot::Any,
+            grass,
+            grass,
+            Slot::Any,
+            grass,
+            grass,
+            Slot::Any,
+            Slot::Any,
+            Slot::Any,
+        ]);
+        assert_eq!(get_slots(&rule), get_slots(&expected_rule));
+    }
+
+    #[test]
+    fn flipped_gids_keep_their_terrain() {
+        let example_map = load_tiled_map(Path::new(EXAMPLE_MAP_PATH)).unwrap();
+        let map = TiledMap {
+            width: 4,
+            height: 1,
+            tilesets: example_map.tilesets,
+            // The first gid of the tileset is 1, tile 2 is covered with grass and tile 0 is not in
+            // the wang set.
+            layers: vec![TiledLayer {
+                gids: vec![
+                    3 | FLIPPED_HORIZONTALLY,
+                    3 | FLIPPED_VERTICALLY,
+                    FLIPPED_HORIZONTALLY,
+                    1 | FLIPPED_HORIZONTALLY,
+                ],
+            }],
+        };
+        assert_eq!(
+            map.get_terrain(),
+            vec![Some(SpriteType::Grass), Some(SpriteType::Grass), None, None]
+        );
+    }
+
+    #[test]
+    fn the_example_map_loads_with_its_tileset_rules() {
+        let map = load_tiled_map(Path::new(EXAMPLE_MAP_PATH)).unwrap();
+        assert_eq!((map.width, map.height), (16, 12));
+        let terrain = map.get_terrain();
+        assert_eq!(terrain.len(), 16 * 12);
+        assert_eq!(terrain[0], None);
+        assert_eq!(terrain[2 * 16 + 4], Some(SpriteType::Grass));
+
+        let (first_gid, tileset) = &map.tilesets[0];
+        assert_eq!(*first_gid, 1);
+        let rules = tileset.to_rules();
+        // The tile covered with grass on every side is the most specific, so it is tried first.
+        let (_, sprite_name) = &rules[&SpriteType::Grass][0];
+        assert_eq!(sprite_name, "grass_blob/2");
+    }
+}
@@ -0,0 +1,417 @@
+use crate::is_typing;
+use crate::terrain::TerrainGrid;
+use bevy::prelude::*;
+use bevy_ecs_tilemap::prelude::*;
+use bevy_inspector_egui::bevy_egui::EguiContext;
+use std::collections::HashSet;
+
+pub const MAX_BRUSH_RADIUS: u32 = 8;
+
+// === Enums ===
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Tool {
+    // Paints under the cursor while the mouse button is held.
+    Freehand,
+    // Shapes span from where the mouse button is pressed to where it is released.
+    Line,
+    Rectangle,
+    Ellipse,
+    // Fills the area of the same terrain as the clicked cell.
+    FloodFill,
+    // Select cells instead of painting them, see `selection.rs`.
+    RectangleSelect,
+    LassoSelect,
+}
+
+impl Tool {
+    pub const ALL: [Tool; 7] = [
+        Tool::Freehand,
+        Tool::Line,
+        Tool::Rectangle,
+        Tool::Ellipse,
+        Tool::FloodFill,
+        Tool::RectangleSelect,
+        Tool::LassoSelect,
+    ];
+
+    pub fn is_selection(self) -> bool {
+        matches!(self, Tool::RectangleSelect | Tool::LassoSelect)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BrushShape {
+    Square,
+    Round,
+}
+
+// === Resources ===
+pub struct ToolState {
+    pub tool: Tool,
+    pub brush_shape: BrushShape,
+    // Cells painted around the cursor by freehand and lines, 0 paints a single cell.
+    pub brush_radius: u32,
+    // Whether rectangles and ellipses are filled or only outlined.
+    pub filled: bool,
+    // Where the current stroke started, `None` while the mouse button is up.
+    pub stroke_start: Option<TilePos>,
+    // The cell under the cursor when it was last sampled during the current stroke.
+    pub last_tile_position: Option<TilePos>,
+}
+
+impl ToolState {
+    // Returns every cell the tool paints for a stroke from `start` to `end`, inside the map or
+    // not. Flood fills start from `end`.
+    pub fn get_cells(&self, start: IVec2, end: IVec2, terrain_grid: &TerrainGrid) -> Vec<IVec2> {
+        match self.tool {
+            Tool::Freehand | Tool::Line => {
+                apply_brush(&get_line(start, end), self.brush_shape, self.brush_radius)
+            }
+            Tool::Rectangle => get_rectangle(start, end, self.filled),
+            Tool::Ellipse => get_ellipse(start, end, self.filled),
+            Tool::FloodFill => get_flood_fill(terrain_grid, end),
+            Tool::RectangleSelect | Tool::LassoSelect => Vec::new(),
+        }
+    }
+
+    // Shapes are only painted once the mouse button is released.
+    pub fn is_shape(&self) -> bool {
+        matches!(self.tool, Tool::Line | Tool::Rectangle | Tool::Ellipse)
+    }
+}
+
+// === Startup Systems ===
+pub fn setup_tools(mut commands: Commands) {
+    commands.insert_resource(ToolState {
+        tool: Tool::Freehand,
+        brush_shape: BrushShape::Square,
+        brush_radius: 0,
+        filled: true,
+        stroke_start: None,
+        last_tile_position: None,
+    });
+}
+
+// === Systems ===
+pub fn update_tool(
+    keyboard: Res<Input<KeyCode>>,
+    mut tool_state: ResMut<ToolState>,
+    mut egui_context: ResMut<EguiContext>,
+) {
+    if is_typing(&mut egui_context) {
+        return;
+    }
+    let tool = if keyboard.just_pressed(KeyCode::B) {
+        Some(Tool::Freehand)
+    } else if keyboard.just_pressed(KeyCode::L) {
+        Some(Tool::Line)
+    } else if keyboard.just_pressed(KeyCode::R) {
+        Some(Tool::Rectangle)
+    } else if keyboard.just_pressed(KeyCode::O) {
+        Some(Tool::Ellipse)
+    } else if keyboard.just_pressed(KeyCode::F) {
+        Some(Tool::FloodFill)
+    } else if keyboard.just_pressed(KeyCode::M) {
+        Some(Tool::RectangleSelect)
+    } else if keyboard.just_pressed(KeyCode::N) {
+        Some(Tool::LassoSelect)
+    } else {
+        None
+    };
+    // Switching tools halfway through a stroke would paint a shape the stroke was not drawn for.
+    if let Some(tool) = tool {
+        if tool_state.stroke_start.is_none() {
+            tool_state.tool = tool;
+            println!("Tool Updated: {:?}", tool_state.tool);
+        }
+    }
+
+    if keyboard.just_pressed(KeyCode::H) {
+        tool_state.filled = !tool_state.filled;
+        println!("Filled Shapes Updated: {:?}", tool_state.filled);
+    }
+    if keyboard.just_pressed(KeyCode::T) {
+        tool_state.brush_shape = match tool_state.brush_shape {
+            BrushShape::Square => BrushShape::Round,
+            BrushShape::Round => BrushShape::Square,
+        };
+        println!("Brush Shape Updated: {:?}", tool_state.brush_shape);
+    }
+    if keyboard.just_pressed(KeyCode::LBracket) && tool_state.brush_radius > 0 {
+        tool_state.brush_radius -= 1;
+        println!("Brush Radius Updated: {}", tool_state.brush_radius);
+    } else if keyboard.just_pressed(KeyCode::RBracket) && tool_state.brush_radius < MAX_BRUSH_RADIUS
+    {
+        tool_state.brush_radius += 1;
+        println!("Brush Radius Updated: {}", tool_state.brush_radius);
+    }
+}
+
+// === Helper Functions ===
+// Bresenham's line from `start` to `end`, both ends included.
+pub fn get_line(start: IVec2, end: IVec2) -> Vec<IVec2> {
+    let delta_x = (end.x - start.x).abs();
+    let delta_y = -(end.y - start.y).abs();
+    let step_x = if start.x < end.x { 1 } else { -1 };
+    let step_y = if start.y < end.y { 1 } else { -1 };
+    let mut error = delta_x + delta_y;
+    let mut position = start;
+    let mut line = vec![start];
+    while position != end {
+        let doubled_error = 2 * error;
+        if doubled_error >= delta_y {
+            error += delta_y;
+            position.x += step_x;
+        }
+        if doubled_error <= delta_x {
+            error += delta_x;
+            position.y += step_y;
+        }
+        line.push(position);
+    }
+    line
+}
+
+// The rectangle with `start` and `end` as opposite corners.
+pub fn get_rectangle(start: IVec2, end: IVec2, filled: bool) -> Vec<IVec2> {
+    let min = start.min(end);
+    let max = start.max(end);
+    let mut cells = Vec::new();
+    for y in min.y..=max.y {
+        for x in min.x..=max.x {
+            if filled || x == min.x || x == max.x || y == min.y || y == max.y {
+                cells.push(IVec2::new(x, y));
+            }
+        }
+    }
+    cells
+}
+
+// The ellipse fitting in the rectangle with `start` and `end` as opposite corners. A cell is
+// inside if its center is, the outline is every inside cell next to an outside one.
+pub fn get_ellipse(start: IVec2, end: IVec2, filled: bool) -> Vec<IVec2> {
+    let min = start.min(end);
+    let max = start.max(end);
+    let center = (min.as_vec2() + max.as_vec2()) / 2.0;
+    let radii = (max - min).as_vec2() / 2.0 + Vec2::splat(0.5);
+    let is_inside = |x: i32, y: i32| {
+        let offset = (Vec2::new(x as f32, y as f32) - center) / radii;
+        offset.length_squared() <= 1.0
+    };
+    let mut cells = Vec::new();
+    for y in min.y..=max.y {
+        for x in min.x..=max.x {
+            if !is_inside(x, y) {
+                continue;
+            }
+            let is_edge = !is_inside(x - 1, y)
+                || !is_inside(x + 1, y)
+                || !is_inside(x, y - 1)
+                || !is_inside(x, y + 1);
+            if filled || is_edge {
+                cells.push(IVec2::new(x, y));
+            }
+        }
+    }
+    cells
+}
+
+// Every cell connected to `start` through its north, east, south and west neighbors that has the
+// same terrain as `start`.
+pub fn get_flood_fill(terrain_grid: &TerrainGrid, start: IVec2) -> Vec<IVec2> {
+    if start.x < 0
+        || start.y < 0
+        || start.x >= terrain_grid.width as i32
+        || start.y >= terrain_grid.height as i32
+    {
+        return Vec::new();
+    }
+    let sprite_type = terrain_grid.get(start.x, start.y);
+    let mut visited = HashSet::from([start]);
+    let mut pending = vec![start];
+    let mut cells = Vec::new();
+    while let Some(cell) = pending.pop() {
+        cells.push(cell);
+        for offset in [IVec2::Y, IVec2::X, -IVec2::Y, -IVec2::X] {
+            let neighbor = cell + offset;
+            let is_inside = neighbor.x >= 0
+                && neighbor.y >= 0
+                && neighbor.x < terrain_grid.width as i32
+                && neighbor.y < terrain_grid.height as i32;
+            if is_inside
+                && terrain_grid.get(neighbor.x, neighbor.y) == sprite_type
+                && visited.insert(neighbor)
+            {
+                pending.push(neighbor);
+            }
+        }
+    }
+    cells
+}
+
+// Grows every cell into a brush of `radius` cells around it, without duplicates.
+pub fn apply_brush(cells: &[IVec2], brush_shape: BrushShape, radius: u32) -> Vec<IVec2> {
+    let radius = radius as i32;
+    let mut seen = HashSet::new();
+    let mut brushed_cells = Vec::new();
+    for cell in cells {
+        for y in -radius..=radius {
+            for x in -radius..=radius {
+                // The extra `radius` rounds the circle out, so small brushes are not plus shaped.
+                if brush_shape == BrushShape::Round && x * x + y * y > radius * radius + radius {
+                    continue;
+                }
+                let brushed_cell = *cell + IVec2::new(x, y);
+                if seen.insert(brushed_cell) {
+                    brushed_cells.push(brushed_cell);
+                }
+            }
+        }
+    }
+    brushed_cells
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_set(cells: &[IVec2]) -> HashSet<IVec2> {
+        cells.iter().copied().collect()
+    }
+
+    #[test]
+    fn lines_include_both_ends_without_gaps_in_every_octant() {
+        let start = IVec2::new(1, -2);
+        let offsets = [
+            IVec2::new(5, 2),
+            IVec2::new(2, 5),
+            IVec2::new(-2, 5),
+            IVec2::new(-5, 2),
+            IVec2::new(-5, -2),
+            IVec2::new(-2, -5),
+            IVec2::new(2, -5),
+            IVec2::new(5, -2),
+            IVec2::new(4, 0),
+            IVec2::new(0, -4),
+            IVec2::new(3, 3),
+            IVec2::ZERO,
+        ];
+        for offset in offsets {
+            let end = start + offset;
+            let line = get_line(start, end);
+            assert_eq!(line.first(), Some(&start));
+            assert_eq!(line.last(), Some(&end));
+            // One cell per step along the longer axis, each touching the one before it.
+            let length = offset.x.abs().max(offset.y.abs()) as usize + 1;
+            assert_eq!(line.len(), length, "line to {:?}", offset);
+            for (cell, next_cell) in line.iter().zip(line.iter().skip(1)) {
+                let step = *next_cell - *cell;
+                assert!(
+                    step.x.abs() <= 1 && step.y.abs() <= 1,
+                    "gap in line to {:?}",
+                    offset
+                );
+                assert_ne!(step, IVec2::ZERO);
+            }
+        }
+    }
+
+    #[test]
+    fn outlined_rectangles_leave_out_their_inside() {
+        // The corners can be given in any order.
+        let start = IVec2::new(3, 1);
+        let end = IVec2::new(0, 4);
+        let filled = get_set(&get_rectangle(start, end, true));
+        let outlined = get_set(&get_rectangle(start, end, false));
+        assert_eq!(filled, get_set(&get_rectangle(end, start, true)));
+        assert_eq!(filled.len(), 16);
+        assert_eq!(outlined.len(), 12);
+        assert!(outlined.is_subset(&filled));
+        for inside in [(1, 2), (2, 2), (1, 3), (2, 3)] {
+            assert!(!outlined.contains(&IVec2::from(inside)));
+        }
+    }
+
+    #[test]
+    fn outlined_ellipses_are_the_edge_of_filled_ones() {
+        let start = IVec2::new(0, 0);
+        let end = IVec2::new(6, 4);
+        let filled = get_set(&get_ellipse(start, end, true));
+        let outlined = get_set(&get_ellipse(start, end, false));
+        assert!(outlined.is_subset(&filled));
+        // The ellipse reaches the middle of every side of its rectangle, but not its corners.
+        for edge in [(0, 2), (6, 2), (3, 0), (3, 4)] {
+            assert!(outlined.contains(&IVec2::from(edge)));
+        }
+        for corner in [(0, 0), (6, 0), (0, 4), (6, 4)] {
+            assert!(!filled.contains(&IVec2::from(corner)));
+        }
+        assert!(filled.contains(&IVec2::new(3, 2)));
+        assert!(!outlined.contains(&IVec2::new(3, 2)));
+        // Mirrored across both of its axes, the ellipse stays the same.
+        for cell in &filled {
+            assert!(filled.contains(&IVec2::new(6 - cell.x, cell.y)));
+            assert!(filled.contains(&IVec2::new(cell.x, 4 - cell.y)));
+        }
+    }
+
+    #[test]
+    fn one_cell_wide_ellipses_are_straight_lines() {
+        for length in 1..=6 {
+            for end in [IVec2::new(length - 1, 0), IVec2::new(0, length - 1)] {
+                let line = get_set(&get_line(IVec2::ZERO, end));
+                assert_eq!(get_set(&get_ellipse(IVec2::ZERO, end, true)), line);
+                assert_eq!(get_set(&get_ellipse(IVec2::ZERO, end, false)), line);
+                assert_eq!(get_set(&get_ellipse(end, IVec2::ZERO, false)), line);
+            }
+        }
+    }
+
+    #[test]
+    fn flood_fills_stop_at_other_terrain_and_the_map_edge() {
+        // The grass on the right edge would be reached if rows wrapped around.
+        let terrain_grid = TerrainGrid::from_ascii(&["gg.dg", "g.ddg", "ggd.g"]);
+        let get_fill = |x: i32, y: i32| get_set(&get_flood_fill(&terrain_grid, IVec2::new(x, y)));
+        let grass = get_set(&[
+            IVec2::new(0, 0),
+            IVec2::new(1, 0),
+            IVec2::new(0, 1),
+            IVec2::new(0, 2),
+            IVec2::new(1, 2),
+        ]);
+        assert_eq!(get_fill(0, 0), grass);
+        let dirt = get_set(&[
+            IVec2::new(2, 0),
+            IVec2::new(2, 1),
+            IVec2::new(3, 1),
+            IVec2::new(3, 2),
+        ]);
+        assert_eq!(get_fill(3, 1), dirt);
+        // Empty cells are filled the same way, and corners do not connect.
+        assert_eq!(get_fill(1, 1), get_set(&[IVec2::new(1, 1)]));
+        assert_eq!(get_fill(4, 0).len(), 3);
+        assert!(get_fill(5, 0).is_empty());
+        assert!(get_fill(-1, 0).is_empty());
+    }
+
+    #[test]
+    fn round_brushes_cut_the_corners_of_square_ones() {
+        let cell = [IVec2::new(2, 3)];
+        let get_count = |brush_shape: BrushShape, radius: u32| {
+            get_set(&apply_brush(&cell, brush_shape, radius)).len()
+        };
+        assert_eq!(apply_brush(&cell, BrushShape::Round, 0), cell);
+        assert_eq!(apply_brush(&cell, BrushShape::Square, 0), cell);
+        // Small round brushes are still squares rather than plus shaped.
+        assert_eq!(get_count(BrushShape::Round, 1), 9);
+        assert_eq!(get_count(BrushShape::Square, 2), 25);
+        assert_eq!(get_count(BrushShape::Round, 2), 21);
+        assert_eq!(get_count(BrushShape::Square, 3), 49);
+        assert_eq!(get_count(BrushShape::Round, 3), 37);
+        assert!(!get_set(&apply_brush(&cell, BrushShape::Round, 2)).contains(&IVec2::new(4, 5)));
+
+        // Overlapping brushes along a line are only listed once.
+        let line = [IVec2::new(0, 0), IVec2::new(1, 0)];
+        assert_eq!(apply_brush(&line, BrushShape::Square, 1).len(), 12);
+    }
+}
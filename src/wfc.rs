@@ -0,0 +1,616 @@
+use crate::generator::Generator;
+use crate::history::{set_tile_terrain, EditHistory};
+use crate::selection::{Clipboard, Selection};
+use crate::terrain::TerrainGrid;
+use crate::{
+    get_matching_sprite, get_terrain_grid, get_tile_terrain, is_typing, DirtTile, GrassTile,
+    GroundLayer, Rule, Rules, Slot, SpriteRegistry, SpriteType, UpdateTilemapEvent, WaterTile,
+    BLANK_SPRITE, TERRAIN_TYPES,
+};
+use bevy::prelude::*;
+use bevy_ecs_tilemap::prelude::*;
+use bevy_inspector_egui::bevy_egui::EguiContext;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+// How many times an attempt may step back before it starts over with fresh random choices.
+pub const MAX_BACKTRACKS: usize = 4096;
+pub const MAX_RESTARTS: u32 = 8;
+
+// === Events ===
+// Fills the selection with terrain collapsed from the rules, see `collapse_terrain`.
+pub struct FillSelectionEvent {
+    // Whether the painted cells of the selection are kept, only the empty ones are filled.
+    pub keep_painted: bool,
+}
+
+// === Enums ===
+#[derive(Debug)]
+pub enum CollapseError {
+    // None of the terrains to fill with have rules.
+    NoTerrains,
+    // Every attempt ran into a cell no terrain fits in.
+    Contradiction { attempts: u32 },
+}
+
+impl fmt::Display for CollapseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CollapseError::NoTerrains => write!(f, "none of the terrains have rules"),
+            CollapseError::Contradiction { attempts } => write!(
+                f,
+                "the rules could not be satisfied after {} attempts",
+                attempts
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CollapseError {}
+
+// === Structs ===
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CollapseOptions {
+    pub seed: u64,
+    // The terrains cells may collapse to, those without rules are left out.
+    pub terrains: Vec<SpriteType>,
+    // When set, the cells along the edge of the region are fixed to this terrain.
+    pub border: Option<SpriteType>,
+    // Whether cells of the region that already have terrain keep it.
+    pub keep_painted: bool,
+}
+
+// SplitMix64, small and seeded so the same options always collapse the same way.
+pub struct Random {
+    state: u64,
+}
+
+impl Random {
+    pub fn new(seed: u64) -> Random {
+        Random { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut value = self.state;
+        value = (value ^ (value >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        value = (value ^ (value >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        value ^ (value >> 31)
+    }
+
+    // A number from 0 up to, but not including, `bound`.
+    pub fn next_below(&mut self, bound: usize) -> usize {
+        (self.next_u64() % bound.max(1) as u64) as usize
+    }
+}
+
+// A cell picked during a collapse, along with the terrains left to try if it leads nowhere.
+struct Decision {
+    cell_index: usize,
+    untried: Vec<SpriteType>,
+}
+
+// The state of one collapse. Cells of the region are `None` until collapsed, everything outside of
+// the region keeps the terrain it has in the grid.
+struct Collapse<'a> {
+    terrain_grid: &'a TerrainGrid,
+    rules: &'a Rules,
+    terrains: Vec<SpriteType>,
+    cells: Vec<IVec2>,
+    cell_indices: HashMap<IVec2, usize>,
+    values: Vec<Option<Option<SpriteType>>>,
+    is_fixed: Vec<bool>,
+    // The tiles that have to resolve to a sprite once the region is collapsed.
+    checked_cells: HashSet<IVec2>,
+    // Whether a terrain resolves with its 8 neighbors, and whether a partly collapsed neighborhood
+    // can still resolve. Both are remembered, as the same neighborhoods come up over and over.
+    resolved_neighborhoods: HashMap<(SpriteType, [Option<SpriteType>; 8]), bool>,
+    satisfiable_neighborhoods: HashMap<[Option<Option<SpriteType>>; 9], bool>,
+}
+
+impl<'a> Collapse<'a> {
+    fn get_value(&self, cell: IVec2) -> Option<Option<SpriteType>> {
+        match self.cell_indices.get(&cell) {
+            Some(cell_index) => self.values[*cell_index],
+            None => Some(self.terrain_grid.get(cell.x, cell.y)),
+        }
+    }
+
+    fn is_resolved(&mut self, sprite_type: SpriteType, neighbors: [Option<SpriteType>; 8]) -> bool {
+        if let Some(is_resolved) = self.resolved_neighborhoods.get(&(sprite_type, neighbors)) {
+            return *is_resolved;
+        }
+        let get_slot = |neighbor: Option<SpriteType>| match neighbor {
+            Some(sprite_type) => Slot::Filled { sprite_type },
+            None => Slot::Empty,
+        };
+        let active_rule = Rule {
+            nw_slot: get_slot(neighbors[0]),
+            n_slot: get_slot(neighbors[1]),
+            ne_slot: get_slot(neighbors[2]),
+            w_slot: get_slot(neighbors[3]),
+            c_slot: Slot::Filled { sprite_type },
+            e_slot: get_slot(neighbors[4]),
+            sw_slot: get_slot(neighbors[5]),
+            s_slot: get_slot(neighbors[6]),
+            se_slot: get_slot(neighbors[7]),
+        };
+        let is_resolved = self
+            .rules
+            .rules
+            .get(&sprite_type)
+            .and_then(|possible_rules| get_matching_sprite(&active_rule, possible_rules))
+            .map_or(false, |sprite_name| sprite_name != BLANK_SPRITE);
+        self.resolved_neighborhoods
+            .insert((sprite_type, neighbors), is_resolved);
+        is_resolved
+    }
+
+    // Whether the tile at `center` can still resolve, trying every terrain for the cells around it
+    // that are not collapsed yet.
+    fn is_satisfiable(&mut self, center: IVec2) -> bool {
+        if !self.checked_cells.contains(&center) {
+            return true;
+        }
+        let mut values = [None; 9];
+        for (index, value) in values.iter_mut().enumerate() {
+            // From north-west to south-east, like the slots of a rule.
+            let offset = IVec2::new(index as i32 % 3 - 1, 1 - index as i32 / 3);
+            *value = self.get_value(center + offset);
+        }
+        if let Some(is_satisfiable) = self.satisfiable_neighborhoods.get(&values) {
+            return *is_satisfiable;
+        }
+        let is_satisfiable = self.is_any_resolved(values);
+        self.satisfiable_neighborhoods
+            .insert(values, is_satisfiable);
+        is_satisfiable
+    }
+
+    fn is_any_resolved(&mut self, values: [Option<Option<SpriteType>>; 9]) -> bool {
+        let mut neighborhood = [None; 9];
+        let mut open_slots = Vec::new();
+        for (index, value) in values.iter().enumerate() {
+            match value {
+                Some(sprite_type) => neighborhood[index] = *sprite_type,
+                None => open_slots.push(index),
+            }
+        }
+        // Counts through every combination of terrains for the open slots.
+        let mut choices = vec![0; open_slots.len()];
+        loop {
+            for (slot_index, choice) in open_slots.iter().zip(&choices) {
+                neighborhood[*slot_index] = Some(self.terrains[*choice]);
+            }
+            if let Some(sprite_type) = neighborhood[4] {
+                let neighbors = [
+                    neighborhood[0],
+                    neighborhood[1],
+                    neighborhood[2],
+                    neighborhood[3],
+                    neighborhood[5],
+                    neighborhood[6],
+                    neighborhood[7],
+                    neighborhood[8],
+                ];
+                if self.is_resolved(sprite_type, neighbors) {
+                    return true;
+                }
+            }
+            let mut position = 0;
+            loop {
+                if position == choices.len() {
+                    return false;
+                }
+                choices[position] += 1;
+                if choices[position] < self.terrains.len() {
+                    break;
+                }
+                choices[position] = 0;
+                position += 1;
+            }
+        }
+    }
+
+    // The terrains a cell can collapse to without leaving a tile around it that cannot resolve.
+    fn get_options(&mut self, cell_index: usize) -> Vec<SpriteType> {
+        let cell = self.cells[cell_index];
+        let mut options = Vec::new();
+        for terrain_index in 0..self.terrains.len() {
+            let sprite_type = self.terrains[terrain_index];
+            self.values[cell_index] = Some(Some(sprite_type));
+            let mut is_option = true;
+            'neighborhood: for y in -1..=1 {
+                for x in -1..=1 {
+                    if !self.is_satisfiable(cell + IVec2::new(x, y)) {
+                        is_option = false;
+                        break 'neighborhood;
+                    }
+                }
+            }
+            if is_option {
+                options.push(sprite_type);
+            }
+        }
+        self.values[cell_index] = None;
+        options
+    }
+
+    // Collapsing a cell changes what the cells up to two tiles away can collapse to, as they share
+    // tiles that have to resolve.
+    fn update_options(&mut self, cell: IVec2, options: &mut [Vec<SpriteType>]) {
+        for y in -2..=2 {
+            for x in -2..=2 {
+                if let Some(cell_index) = self.cell_indices.get(&(cell + IVec2::new(x, y))) {
+                    let cell_index = *cell_index;
+                    if self.values[cell_index].is_none() {
+                        options[cell_index] = self.get_options(cell_index);
+                    }
+                }
+            }
+        }
+    }
+
+    // Collapses every open cell, lowest number of options first. Returns `false` on giving up.
+    fn run(&mut self, random: &mut Random) -> bool {
+        for cell_index in 0..self.cells.len() {
+            if !self.is_fixed[cell_index] {
+                self.values[cell_index] = None;
+            }
+        }
+        let mut options = vec![Vec::new(); self.cells.len()];
+        for cell_index in 0..self.cells.len() {
+            if self.values[cell_index].is_none() {
+                options[cell_index] = self.get_options(cell_index);
+            }
+        }
+
+        let mut decisions: Vec<Decision> = Vec::new();
+        let mut backtracks = 0;
+        loop {
+            // The open cells with the fewest options, one of them is picked at random.
+            let mut fewest_options = usize::MAX;
+            let mut candidates = Vec::new();
+            for cell_index in 0..self.cells.len() {
+                if self.values[cell_index].is_some() {
+                    continue;
+                }
+                let option_count = options[cell_index].len();
+                if option_count < fewest_options {
+                    fewest_options = option_count;
+                    candidates.clear();
+                }
+                if option_count == fewest_options {
+                    candidates.push(cell_index);
+                }
+            }
+            if candidates.is_empty() {
+                return true;
+            }
+
+            if fewest_options > 0 {
+                let cell_index = candidates[random.next_below(candidates.len())];
+                let mut untried = options[cell_index].clone();
+                let sprite_type = untried.swap_remove(random.next_below(untried.len()));
+                self.values[cell_index] = Some(Some(sprite_type));
+                decisions.push(Decision {
+                    cell_index,
+                    untried,
+                });
+                self.update_options(self.cells[cell_index], &mut options);
+                continue;
+            }
+
+            // A cell has nothing left to collapse to, step back to the last choice with terrains
+            // left to try.
+            loop {
+                backtracks += 1;
+                if backtracks > MAX_BACKTRACKS {
+                    return false;
+                }
+                let mut decision = match decisions.pop() {
+                    Some(decision) => decision,
+                    None => return false,
+                };
+                self.values[decision.cell_index] = None;
+                let cell = self.cells[decision.cell_index];
+                if decision.untried.is_empty() {
+                    self.update_options(cell, &mut options);
+                    continue;
+                }
+                let sprite_type = decision
+                    .untried
+                    .swap_remove(random.next_below(decision.untried.len()));
+                self.values[decision.cell_index] = Some(Some(sprite_type));
+                decisions.push(decision);
+                self.update_options(cell, &mut options);
+                break;
+            }
+        }
+    }
+}
+
+// === Systems ===
+// G fills the selection with terrain collapsed from the rules, Shift+G only fills its empty cells.
+pub fn fill_selection(
+    mut commands: Commands,
+    mut fill_selection_event_reader: EventReader<FillSelectionEvent>,
+    mut update_tilemap_event_writer: EventWriter<UpdateTilemapEvent>,
+    mut edit_history: ResMut<EditHistory>,
+    mut fill_count: Local<u64>,
+    keyboard: Res<Input<KeyCode>>,
+    selection: Res<Selection>,
+    clipboard: Res<Clipboard>,
+    generator: Res<Generator>,
+    rules: Res<Rules>,
+    sprite_registry: Res<SpriteRegistry>,
+    mut egui_context: ResMut<EguiContext>,
+    ground_layer_query: Query<(&TilemapSize, &TileStorage), With<GroundLayer>>,
+    terrain_query: Query<(Option<&GrassTile>, Option<&DirtTile>, Option<&WaterTile>)>,
+) {
+    let mut keep_painted = None;
+    for fill_selection_event in fill_selection_event_reader.iter() {
+        keep_painted = Some(fill_selection_event.keep_painted);
+    }
+    let is_control_pressed =
+        keyboard.pressed(KeyCode::LControl) || keyboard.pressed(KeyCode::RControl);
+    if keyboard.just_pressed(KeyCode::G)
+        && !is_control_pressed
+        && !clipboard.is_pasting
+        && !is_typing(&mut egui_context)
+    {
+        keep_painted = Some(keyboard.pressed(KeyCode::LShift) || keyboard.pressed(KeyCode::RShift));
+    }
+    let keep_painted = match keep_painted {
+        Some(keep_painted) => keep_painted,
+        None => return,
+    };
+    if selection.cells.is_empty() {
+        println!("Nothing is selected");
+        return;
+    }
+    let (map_size, tile_storage) = match ground_layer_query.get_single() {
+        Ok(ground_layer) => ground_layer,
+        Err(_) => return,
+    };
+
+    // Every fill picks differently, starting from the seed of the generator.
+    let options = CollapseOptions {
+        seed: generator.options.seed as u64 + *fill_count,
+        terrains: TERRAIN_TYPES.to_vec(),
+        border: generator.fill_border,
+        keep_painted,
+    };
+    *fill_count += 1;
+    let terrain_grid = get_terrain_grid(map_size, tile_storage, &terrain_query);
+    let cells: Vec<IVec2> = selection
+        .cells
+        .iter()
+        .map(|tile_position| IVec2::new(tile_position.x as i32, tile_position.y as i32))
+        .collect();
+    let collapsed_grid = match collapse_terrain(&terrain_grid, &cells, &options, &rules) {
+        Ok(collapsed_grid) => collapsed_grid,
+        Err(error) => {
+            println!("Failed to fill the selection: {}", error);
+            return;
+        }
+    };
+
+    edit_history.finish_stroke();
+    for tile_position in &selection.cells {
+        if let Some(tile_entity) = tile_storage.get(tile_position) {
+            let sprite_type = collapsed_grid.get(tile_position.x as i32, tile_position.y as i32);
+            let previous_sprite_type = get_tile_terrain(tile_entity, &terrain_query);
+            edit_history.record(*tile_position, previous_sprite_type, sprite_type);
+            set_tile_terrain(&mut commands, tile_entity, sprite_type, &sprite_registry);
+        }
+    }
+    edit_history.finish_stroke();
+    update_tilemap_event_writer.send(UpdateTilemapEvent {});
+    println!("Filled {} tiles", selection.cells.len());
+}
+
+// === Helper Functions ===
+// Fills `cells` with terrain so that every one of them, and every tile around them that resolved
+// before, resolves to a sprite other than blank where any terrain could make it. Collapses the
+// cell with the fewest terrains left first, steps back on running into a cell no terrain fits in,
+// and starts over with other random choices when stepping back does not get anywhere.
+pub fn collapse_terrain(
+    terrain_grid: &TerrainGrid,
+    cells: &[IVec2],
+    options: &CollapseOptions,
+    rules: &Rules,
+) -> Result<TerrainGrid, CollapseError> {
+    let terrains: Vec<SpriteType> = options
+        .terrains
+        .iter()
+        .copied()
+        .filter(|sprite_type| rules.rules.contains_key(sprite_type))
+        .collect();
+    if terrains.is_empty() {
+        return Err(CollapseError::NoTerrains);
+    }
+    let is_inside = |cell: &IVec2| {
+        cell.x >= 0
+            && cell.y >= 0
+            && cell.x < terrain_grid.width as i32
+            && cell.y < terrain_grid.height as i32
+    };
+    let mut region = Vec::new();
+    let mut cell_indices = HashMap::new();
+    for cell in cells.iter().filter(|cell| is_inside(cell)) {
+        if !cell_indices.contains_key(cell) {
+            cell_indices.insert(*cell, region.len());
+            region.push(*cell);
+        }
+    }
+
+    // Painted cells and the border are fixed before collapsing starts.
+    let mut values = vec![None; region.len()];
+    let mut is_fixed = vec![false; region.len()];
+    for (cell_index, cell) in region.iter().enumerate() {
+        let is_edge = (-1..=1)
+            .flat_map(|y| (-1..=1).map(move |x| IVec2::new(x, y)))
+            .any(|offset| !cell_indices.contains_key(&(*cell + offset)));
+        let painted = terrain_grid.get(cell.x, cell.y);
+        if let (Some(border), true) = (options.border, is_edge) {
+            values[cell_index] = Some(Some(border));
+            is_fixed[cell_index] = true;
+        } else if let (Some(painted), true) = (painted, options.keep_painted) {
+            values[cell_index] = Some(Some(painted));
+            is_fixed[cell_index] = true;
+        }
+    }
+
+    // The tiles around the region only have to keep resolving if they did before.
+    let mut checked_cells: HashSet<IVec2> = region.iter().copied().collect();
+    for cell in &region {
+        for y in -1..=1 {
+            for x in -1..=1 {
+                let neighbor = *cell + IVec2::new(x, y);
+                if is_inside(&neighbor)
+                    && !cell_indices.contains_key(&neighbor)
+                    && terrain_grid
+                        .match_tile(neighbor.x as u32, neighbor.y as u32, rules)
+                        .map_or(false, |sprite_name| sprite_name != BLANK_SPRITE)
+                {
+                    checked_cells.insert(neighbor);
+                }
+            }
+        }
+    }
+
+    let mut collapse = Collapse {
+        terrain_grid,
+        rules,
+        terrains,
+        cells: region,
+        cell_indices,
+        values,
+        is_fixed,
+        checked_cells,
+        resolved_neighborhoods: HashMap::new(),
+        satisfiable_neighborhoods: HashMap::new(),
+    };
+    // Tiles that cannot resolve whatever the region is filled with, like painted water, which has
+    // no rules, and the tiles next to it, are not checked, or every attempt would fail on them.
+    let checked_cells: Vec<IVec2> = collapse.checked_cells.iter().copied().collect();
+    for cell in checked_cells {
+        if !collapse.is_satisfiable(cell) {
+            collapse.checked_cells.remove(&cell);
+        }
+    }
+    let mut random = Random::new(options.seed);
+    for _ in 0..=MAX_RESTARTS {
+        if collapse.run(&mut random) {
+            let mut collapsed_grid = terrain_grid.clone();
+            for (cell, value) in collapse.cells.iter().zip(&collapse.values) {
+                collapsed_grid.set(cell.x as u32, cell.y as u32, value.flatten());
+            }
+            return Ok(collapsed_grid);
+        }
+    }
+    Err(CollapseError::Contradiction {
+        attempts: MAX_RESTARTS + 1,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::get_builtin_rules;
+
+    // The cells of `terrain_grid` at least `margin` tiles away from its edges.
+    fn get_inner_cells(terrain_grid: &TerrainGrid, margin: i32) -> Vec<IVec2> {
+        let max = IVec2::new(terrain_grid.width as i32, terrain_grid.height as i32) - margin;
+        terrain_grid
+            .cells()
+            .into_iter()
+            .filter(|cell| cell.cmpge(IVec2::splat(margin)).all() && cell.cmplt(max).all())
+            .collect()
+    }
+
+    #[test]
+    fn collapse_terrain_is_deterministic_per_seed() {
+        let rules = get_builtin_rules();
+        let terrain_grid = TerrainGrid::from_ascii(&["............"; 12]);
+        let cells = get_inner_cells(&terrain_grid, 2);
+        let options = CollapseOptions {
+            seed: 7,
+            terrains: TERRAIN_TYPES.to_vec(),
+            border: None,
+            keep_painted: false,
+        };
+        let collapsed_grid = collapse_terrain(&terrain_grid, &cells, &options, &rules).unwrap();
+        assert_eq!(
+            collapsed_grid,
+            collapse_terrain(&terrain_grid, &cells, &options, &rules).unwrap()
+        );
+        for cell in &cells {
+            assert!(collapsed_grid
+                .match_tile(cell.x as u32, cell.y as u32, &rules)
+                .map_or(false, |sprite_name| sprite_name != BLANK_SPRITE));
+        }
+    }
+
+    #[test]
+    fn keep_painted_keeps_water_without_rules() {
+        let rules = get_builtin_rules();
+        let terrain_grid = TerrainGrid::from_ascii(&[
+            "............",
+            "............",
+            "............",
+            "............",
+            "............",
+            "............",
+            ".....~......",
+            "............",
+            "............",
+            "............",
+            "............",
+            "............",
+        ]);
+        let cells = get_inner_cells(&terrain_grid, 2);
+        let options = CollapseOptions {
+            seed: 1,
+            terrains: TERRAIN_TYPES.to_vec(),
+            border: None,
+            keep_painted: true,
+        };
+        let collapsed_grid = collapse_terrain(&terrain_grid, &cells, &options, &rules).unwrap();
+        assert_eq!(collapsed_grid.get(5, 5), Some(SpriteType::Water));
+        for cell in &cells {
+            assert!(collapsed_grid.get(cell.x, cell.y).is_some());
+        }
+    }
+
+    #[test]
+    fn a_border_keeps_the_whole_edge_of_the_region() {
+        let rules = get_builtin_rules();
+        let terrain_grid = TerrainGrid::from_ascii(&["............"; 12]);
+        let cells = get_inner_cells(&terrain_grid, 2);
+        for seed in 0..4 {
+            let options = CollapseOptions {
+                seed,
+                terrains: TERRAIN_TYPES.to_vec(),
+                border: Some(SpriteType::Grass),
+                keep_painted: false,
+            };
+            let collapsed_grid = collapse_terrain(&terrain_grid, &cells, &options, &rules).unwrap();
+            for cell in &cells {
+                let is_edge = cell.x == 2 || cell.y == 2 || cell.x == 9 || cell.y == 9;
+                if is_edge {
+                    assert_eq!(
+                        collapsed_grid.get(cell.x, cell.y),
+                        Some(SpriteType::Grass),
+                        "seed {} at {:?}",
+                        seed,
+                        cell
+                    );
+                }
+            }
+            // Cells outside of the region are left alone.
+            assert_eq!(collapsed_grid.get(1, 1), None);
+        }
+    }
+}